@@ -0,0 +1,47 @@
+use std::{collections::BTreeMap, hint::black_box};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use pippy::{parse_wheel_filename, DistFilename, Package, PackageName, Release, Version};
+
+fn filename_parsing(c: &mut Criterion) {
+    c.bench_function("parse_wheel_filename", |b| {
+        b.iter(|| {
+            parse_wheel_filename(black_box(
+                "numpy-2.1.3-cp312-cp312-manylinux_2_17_x86_64.whl",
+            ))
+        })
+    });
+}
+
+fn synthetic_index(packages: usize, releases: usize) -> BTreeMap<PackageName, Package> {
+    (0..packages)
+        .map(|p| {
+            let name = PackageName::new(format!("package_{p}")).unwrap();
+            let mut package = Package::new(name.clone());
+            package.releases = (0..releases)
+                .map(|r| {
+                    Release::new(
+                        Version::new(format!("1.{r}.0")).unwrap(),
+                        DistFilename::new(format!("{name}-1.{r}.0-py3-none-any.whl")).unwrap(),
+                    )
+                })
+                .collect();
+            (name, package)
+        })
+        .collect()
+}
+
+fn index_serialization(c: &mut Criterion) {
+    let index = synthetic_index(1000, 10);
+    let json = serde_json::to_string_pretty(&index).unwrap();
+
+    c.bench_function("serialize_index_1000x10", |b| {
+        b.iter(|| serde_json::to_string_pretty(black_box(&index)).unwrap())
+    });
+    c.bench_function("deserialize_index_1000x10", |b| {
+        b.iter(|| serde_json::from_str::<BTreeMap<PackageName, Package>>(black_box(&json)).unwrap())
+    });
+}
+
+criterion_group!(benches, filename_parsing, index_serialization);
+criterion_main!(benches);
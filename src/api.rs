@@ -0,0 +1,1099 @@
+use std::{
+    collections::BTreeMap,
+    io::{Seek, SeekFrom},
+};
+
+use axum::{
+    body::Body,
+    extract::{Multipart, Path, Query, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use chrono::{DateTime, Utc};
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::io::AsyncWriteExt;
+use tokio_util::io::ReaderStream;
+use tracing::info;
+
+use crate::{
+    auth::{ApiToken, Identity, Scope, TokenStore, User},
+    bundle::{write_bundle, BundleSelection, Pin},
+    compat::{CompatibilityQuery, TargetEnvironment},
+    diff::{IndexDiff, Manifest, Side},
+    fsck::{self, FsckReport},
+    handlers::{body_too_large, limit_size, normalizing_redirect},
+    inspect::{self, Member},
+    metadata::{FileUpdate, OwnersUpdate, ProjectUpdate, ReleaseUpdate},
+    quota::UsageReport,
+    reindex::{self, ReindexReport},
+    stats::{CapacityReport, ProjectStats},
+    webhooks::{Delivery, Webhook, WebhookEvent},
+    AppError, AppState, Change, Channel, DistFilename, LocalFile, PackageIndex, PackageName,
+    Provenance, Snapshot, SnapshotName, UrlBuilder, Version,
+};
+
+#[derive(Debug, Serialize)]
+pub(crate) struct ProjectFiles {
+    name: PackageName,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    summary: Option<String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    project_urls: BTreeMap<String, String>,
+    files: Vec<ProjectFile>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct ProjectFile {
+    filename: DistFilename,
+    version: Version,
+    channel: Channel,
+    url: String,
+    upload_time: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    requires_python: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    provenance: Option<Provenance>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    deprecated: Option<String>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    yanked: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    yanked_reason: Option<String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    enrichments: BTreeMap<String, Value>,
+}
+
+/// Lists a project's files, optionally restricted to those compatible with
+/// a target interpreter and platform.
+pub(crate) async fn project_files(
+    State(index): State<PackageIndex>,
+    State(urls): State<UrlBuilder>,
+    Path(name): Path<PackageName>,
+    Query(query): Query<CompatibilityQuery>,
+) -> Result<Json<ProjectFiles>, AppError> {
+    let target = TargetEnvironment::from_query(&query)?;
+    let packages = index.project(&name).await?;
+    let package = packages
+        .get(name.as_str())
+        .ok_or_else(|| AppError::NotFound(name.to_string()))?;
+
+    let files = package
+        .releases
+        .iter()
+        .filter(|r| target.accepts(r.filename.as_str()))
+        .map(|r| ProjectFile {
+            filename: r.filename.clone(),
+            version: r.version.clone(),
+            channel: r.channel(),
+            url: urls.file(package.name.as_str(), r.filename.as_str()),
+            upload_time: r.upload_time,
+            requires_python: r.requires_python.clone(),
+            provenance: r.provenance.clone(),
+            deprecated: r.deprecated.clone(),
+            yanked: r.yanked,
+            yanked_reason: r.yanked_reason.clone(),
+            enrichments: r.enrichments.clone(),
+        })
+        .collect();
+
+    Ok(Json(ProjectFiles {
+        name: package.name.clone(),
+        summary: package.summary.clone(),
+        project_urls: package.project_urls.clone(),
+        files,
+    }))
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct ProjectMetadata {
+    name: PackageName,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    summary: Option<String>,
+    project_urls: BTreeMap<String, String>,
+}
+
+/// Edits a project's summary and links without re-uploading anything.
+pub(crate) async fn update_project(
+    State(index): State<PackageIndex>,
+    Path(name): Path<PackageName>,
+    identity: Option<Extension<Identity>>,
+    Json(update): Json<ProjectUpdate>,
+) -> Result<Json<ProjectMetadata>, AppError> {
+    index.check_owner(&name, identity.as_deref()).await?;
+    let package = index.update_project(&name, update).await?;
+    Ok(Json(ProjectMetadata {
+        name: package.name,
+        summary: package.summary,
+        project_urls: package.project_urls,
+    }))
+}
+
+/// Removes a project with all its files and docs. Deleting takes an
+/// owner or admin even on an open index.
+pub(crate) async fn delete_project(
+    State(index): State<PackageIndex>,
+    Path(name): Path<PackageName>,
+    identity: Option<Extension<Identity>>,
+) -> Result<StatusCode, AppError> {
+    let identity = ensure_identified(identity)?;
+    index.check_owner(&name, Some(&identity)).await?;
+    index.delete_project(&name).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub(crate) async fn project_owners(
+    State(index): State<PackageIndex>,
+    Path(name): Path<PackageName>,
+) -> Result<Json<OwnersUpdate>, AppError> {
+    let packages = index.project(&name).await?;
+    let package = packages
+        .get(name.as_str())
+        .ok_or_else(|| AppError::NotFound(name.to_string()))?;
+    Ok(Json(OwnersUpdate {
+        owners: package.owners.clone(),
+    }))
+}
+
+/// Replaces a project's owners. Only admins may, since owners could
+/// otherwise hand a project to anyone.
+pub(crate) async fn set_project_owners(
+    State(index): State<PackageIndex>,
+    Path(name): Path<PackageName>,
+    identity: Option<Extension<Identity>>,
+    Json(update): Json<OwnersUpdate>,
+) -> Result<Json<OwnersUpdate>, AppError> {
+    ensure_admin(identity)?;
+    let package = index.set_owners(&name, update).await?;
+    Ok(Json(OwnersUpdate {
+        owners: package.owners,
+    }))
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct ReleaseMetadata {
+    name: PackageName,
+    version: Version,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    deprecated: Option<String>,
+    channel: Channel,
+    files: Vec<DistFilename>,
+}
+
+/// Edits the deprecation note and channel of every file of a release.
+pub(crate) async fn update_release(
+    State(index): State<PackageIndex>,
+    Path((name, version)): Path<(PackageName, Version)>,
+    identity: Option<Extension<Identity>>,
+    Json(update): Json<ReleaseUpdate>,
+) -> Result<Json<ReleaseMetadata>, AppError> {
+    index.check_owner(&name, identity.as_deref()).await?;
+    let releases = index.update_release(&name, &version, update).await?;
+    let first = &releases[0];
+    Ok(Json(ReleaseMetadata {
+        deprecated: first.deprecated.clone(),
+        channel: first.channel(),
+        files: releases.iter().map(|r| r.filename.clone()).collect(),
+        name,
+        version,
+    }))
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct FileMetadata {
+    name: PackageName,
+    filename: DistFilename,
+    version: Version,
+    yanked: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    yanked_reason: Option<String>,
+}
+
+/// Yanks or unyanks one file.
+pub(crate) async fn update_file(
+    State(index): State<PackageIndex>,
+    Path((name, filename)): Path<(PackageName, DistFilename)>,
+    identity: Option<Extension<Identity>>,
+    Json(update): Json<FileUpdate>,
+) -> Result<Json<FileMetadata>, AppError> {
+    index.check_yanker(&name, identity.as_deref()).await?;
+    let release = index.update_file(&name, &filename, update).await?;
+    Ok(Json(FileMetadata {
+        name,
+        filename,
+        version: release.version,
+        yanked: release.yanked,
+        yanked_reason: release.yanked_reason,
+    }))
+}
+
+/// Removes one file for good. Yanking is the gentler alternative for
+/// files that should merely stop being installed. As for projects,
+/// deleting takes an owner or admin.
+pub(crate) async fn delete_file(
+    State(index): State<PackageIndex>,
+    Path((name, filename)): Path<(PackageName, DistFilename)>,
+    identity: Option<Extension<Identity>>,
+) -> Result<StatusCode, AppError> {
+    let identity = ensure_identified(identity)?;
+    index.check_owner(&name, Some(&identity)).await?;
+    index.delete_file(&name, &filename).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct ChecksumsQuery {
+    version: Option<Version>,
+    /// Hash the stored bytes instead of listing the recorded digests.
+    #[serde(default)]
+    rehash: bool,
+}
+
+/// A `SHA256SUMS`-style manifest of a project's files, optionally limited to
+/// one version, from the digests recorded on arrival. Rehashing reads every
+/// stored file, so, as for the index manifest, it takes an admin. With a
+/// signing key configured, the detached signature of the body is sent in
+/// `X-Checksums-Signature`.
+pub(crate) async fn project_checksums(
+    State(state): State<AppState>,
+    Path(name): Path<PackageName>,
+    identity: Option<Extension<Identity>>,
+    Query(query): Query<ChecksumsQuery>,
+) -> Result<Response, AppError> {
+    if query.rehash {
+        ensure_admin(identity)?;
+    }
+    let mut files: Vec<(DistFilename, Option<String>)> = {
+        let packages = state.index.project(&name).await?;
+        let package = packages
+            .get(name.as_str())
+            .ok_or_else(|| AppError::NotFound(name.to_string()))?;
+        package
+            .releases
+            .iter()
+            .filter(|r| query.version.as_ref().is_none_or(|v| r.version == *v))
+            .map(|r| (r.filename.clone(), r.sha256().map(str::to_string)))
+            .collect()
+    };
+    if files.is_empty() {
+        return Err(AppError::NotFound(format!(
+            "{name}=={}",
+            query.version.map(String::from).unwrap_or_default()
+        )));
+    }
+    files.sort();
+
+    let mut manifest = String::new();
+    for (filename, recorded) in &files {
+        let digest = match recorded {
+            _ if query.rehash => state.index.storage.sha256(&name, filename).await?,
+            Some(digest) => digest.clone(),
+            None => {
+                return Err(AppError::Conflict(format!(
+                    "No digest was recorded for {filename}; an admin can ask for ?rehash=true"
+                )))
+            }
+        };
+        manifest.push_str(&format!("{digest}  {filename}\n"));
+    }
+
+    let mut response = (
+        [(header::CONTENT_TYPE, "text/plain; charset=utf-8")],
+        manifest.clone(),
+    )
+        .into_response();
+    if let Some(key) = &state.config.signing_key {
+        response.headers_mut().insert(
+            "x-checksums-signature",
+            header::HeaderValue::from_str(&format!("ed25519={}", key.sign(manifest.as_bytes())))
+                .expect("base64 is a valid header value"),
+        );
+    }
+    Ok(response)
+}
+
+/// The public half of the server's signing key, as PEM.
+pub(crate) async fn signing_key(State(state): State<AppState>) -> Result<String, AppError> {
+    state
+        .config
+        .signing_key
+        .as_ref()
+        .map(|key| key.public_key_pem())
+        .ok_or_else(|| AppError::NotFound("No signing key is configured".into()))
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct SnapshotSummary {
+    name: SnapshotName,
+    created: DateTime<Utc>,
+    projects: usize,
+    files: usize,
+    url: String,
+}
+
+impl SnapshotSummary {
+    fn new(snapshot: &Snapshot, urls: &UrlBuilder) -> Self {
+        let active = || {
+            snapshot
+                .packages
+                .values()
+                .filter(|p| p.renamed_to.is_none())
+        };
+        Self {
+            name: snapshot.name.clone(),
+            created: snapshot.created,
+            projects: active().count(),
+            files: active().map(|p| p.releases.len()).sum(),
+            url: urls.snapshot_index(snapshot.name.as_str()),
+        }
+    }
+}
+
+/// Freezes the index under a name. Only admins may, since a snapshot keeps
+/// every file it lists from being deleted, renamed, pruned or overwritten.
+pub(crate) async fn create_snapshot(
+    State(index): State<PackageIndex>,
+    State(urls): State<UrlBuilder>,
+    Path(name): Path<SnapshotName>,
+    identity: Option<Extension<Identity>>,
+) -> Result<(StatusCode, Json<SnapshotSummary>), AppError> {
+    ensure_admin(identity)?;
+    let snapshot = index.create_snapshot(name).await?;
+    Ok((
+        StatusCode::CREATED,
+        Json(SnapshotSummary::new(&snapshot, &urls)),
+    ))
+}
+
+pub(crate) async fn list_snapshots(
+    State(index): State<PackageIndex>,
+    State(urls): State<UrlBuilder>,
+) -> Result<Json<Vec<SnapshotSummary>>, AppError> {
+    let snapshots = index.snapshots().await?;
+    Ok(Json(
+        snapshots
+            .iter()
+            .map(|snapshot| SnapshotSummary::new(snapshot, &urls))
+            .collect(),
+    ))
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct BundleQuery {
+    /// Comma-separated `name==version` pins; omitted means latest of each project.
+    pins: Option<String>,
+    #[serde(flatten)]
+    compat: CompatibilityQuery,
+}
+
+/// A bundle built on request, for identified callers only, as it reads
+/// every file selected, within the configured limits.
+pub(crate) async fn bundle(
+    State(state): State<AppState>,
+    Query(query): Query<BundleQuery>,
+    identity: Option<Extension<Identity>>,
+) -> Result<Response, AppError> {
+    ensure_identified(identity)?;
+    let selection = match query.pins.as_deref() {
+        None | Some("") => BundleSelection::Latest,
+        Some(pins) => BundleSelection::Pins(
+            pins.split(',')
+                .map(str::parse)
+                .collect::<Result<Vec<Pin>, _>>()?,
+        ),
+    };
+    let target = TargetEnvironment::from_query(&query.compat)?;
+
+    // The archive is spooled to an anonymous temp file rather than memory.
+    let mut file = write_bundle(
+        &state.index,
+        &selection,
+        &target,
+        state.config.bundle_limits,
+        tempfile::tempfile()?,
+    )
+    .await?;
+    file.seek(SeekFrom::Start(0))?;
+    let length = file.metadata()?.len();
+    let body = Body::from_stream(ReaderStream::new(tokio::fs::File::from_std(file)));
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/x-tar".to_string()),
+            (header::CONTENT_LENGTH, length.to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"pippy-bundle.tar\"".to_string(),
+            ),
+        ],
+        body,
+    )
+        .into_response())
+}
+
+/// Accepts a zip of built HTML documentation in the `content` field, from
+/// the project's owners or an admin. The archive is spooled to a temporary
+/// file, held to the upload size limit, rather than buffered.
+pub(crate) async fn upload_docs(
+    State(state): State<AppState>,
+    Path((name, version)): Path<(PackageName, Version)>,
+    identity: Option<Extension<Identity>>,
+    mut multipart: Multipart,
+) -> Result<StatusCode, AppError> {
+    let (index, config) = (&state.index, &state.config);
+    if !index.project(&name).await?.contains_key(name.as_str()) {
+        return Err(AppError::NotFound(name.into()));
+    }
+    let identity = ensure_identified(identity)?;
+    index.check_owner(&name, Some(&identity)).await?;
+
+    let too_large = |e: AppError| body_too_large(e, config.max_upload_size);
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(|e| too_large(e.into()))?
+    {
+        if field.name() == Some("content") {
+            let label = format!("The docs archive for {name} {version}");
+            let mut chunks = std::pin::pin!(limit_size(field, &label, config.max_upload_size));
+            let mut archive = tokio::fs::File::from_std(tempfile::tempfile()?);
+            while let Some(chunk) = chunks.next().await {
+                archive.write_all(&chunk?).await?;
+            }
+            archive.flush().await?;
+            let archive = archive.into_std().await;
+            index
+                .storage
+                .store_docs(&name, &version, archive, config.max_docs_size)
+                .await?;
+            index.add_docs(&name, &version).await?;
+            info!("Stored docs for {} {}", name, version);
+            return Ok(StatusCode::OK);
+        }
+    }
+
+    Err(AppError::InvalidFormat(
+        "No docs archive found in upload".into(),
+    ))
+}
+
+/// Lets through only requests for docs of a version the index lists, as
+/// `/<name>/<version>/...` under `/docs`, so archives still being
+/// unpacked, and docs left behind by a version's removal, are never served.
+/// Names spelled other than normalized are redirected, as project pages are.
+pub(crate) async fn published_docs(
+    State(index): State<PackageIndex>,
+    State(urls): State<UrlBuilder>,
+    request: axum::extract::Request,
+    next: Next,
+) -> Response {
+    let mut segments = request.uri().path().trim_start_matches('/').splitn(3, '/');
+    let published = match (segments.next(), segments.next()) {
+        (Some(raw), Some(version)) => match PackageName::new(raw) {
+            Ok(name) => {
+                let rest = segments.next().unwrap_or_default();
+                if let Some(redirect) = normalizing_redirect(raw, &name, request.uri(), || {
+                    format!("{}{rest}", urls.docs(name.as_str(), version))
+                }) {
+                    return redirect.into_response();
+                }
+                match index.project(&name).await {
+                    Ok(packages) => packages
+                        .get(raw)
+                        .is_some_and(|p| p.docs.iter().any(|v| v.as_str() == version)),
+                    Err(e) => return e.into_response(),
+                }
+            }
+            Err(_) => false,
+        },
+        _ => false,
+    };
+    if !published {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    next.run(request).await
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct FileContents {
+    filename: DistFilename,
+    members: Vec<Member>,
+}
+
+/// A project's stored file on disk, if the index lists it.
+async fn stored_file(
+    index: &PackageIndex,
+    name: &PackageName,
+    filename: &DistFilename,
+) -> Result<LocalFile, AppError> {
+    let listed = index
+        .project(name)
+        .await?
+        .get(name.as_str())
+        .is_some_and(|p| p.releases.iter().any(|r| r.filename == *filename));
+    if !listed {
+        return Err(AppError::NotFound(filename.to_string()));
+    }
+    index.storage.local_file(name, filename).await
+}
+
+/// Lists the members of a stored wheel or sdist, for any identified
+/// caller.
+pub(crate) async fn file_contents(
+    State(index): State<PackageIndex>,
+    Path((name, filename)): Path<(PackageName, DistFilename)>,
+    identity: Option<Extension<Identity>>,
+) -> Result<Json<FileContents>, AppError> {
+    ensure_identified(identity)?;
+    let file = stored_file(&index, &name, &filename).await?;
+    let members = inspect::list_members(file.path().to_path_buf(), filename.as_str()).await?;
+    Ok(Json(FileContents { filename, members }))
+}
+
+/// Streams one member of a stored wheel or sdist, e.g.
+/// `.../contents/demo-1.0.dist-info/METADATA`.
+pub(crate) async fn file_member(
+    State(index): State<PackageIndex>,
+    Path((name, filename, member)): Path<(PackageName, DistFilename, String)>,
+    identity: Option<Extension<Identity>>,
+) -> Result<Response, AppError> {
+    ensure_identified(identity)?;
+    let file = stored_file(&index, &name, &filename).await?;
+    let content_type = if inspect::is_text(&member) {
+        "text/plain; charset=utf-8"
+    } else {
+        "application/octet-stream"
+    };
+    let (size, stream) =
+        inspect::read_member(file.path().to_path_buf(), filename.as_str(), member).await?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (header::CONTENT_LENGTH, size.to_string()),
+            (header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+        ],
+        Body::from_stream(stream),
+    )
+        .into_response())
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct NewWebhook {
+    url: String,
+    #[serde(default = "default_webhook_events")]
+    events: Vec<WebhookEvent>,
+}
+
+fn default_webhook_events() -> Vec<WebhookEvent> {
+    vec![WebhookEvent::Release]
+}
+
+/// A registered hook as listed, without its secret.
+#[derive(Debug, Serialize)]
+pub(crate) struct WebhookSummary {
+    id: String,
+    url: String,
+    events: Vec<WebhookEvent>,
+    created: DateTime<Utc>,
+}
+
+impl From<&Webhook> for WebhookSummary {
+    fn from(hook: &Webhook) -> Self {
+        Self {
+            id: hook.id.clone(),
+            url: hook.url.clone(),
+            events: hook.events.clone(),
+            created: hook.created,
+        }
+    }
+}
+
+/// Registers a hook on a project, for its owners and admins only, as the
+/// server sends requests wherever a hook points. The response is the only
+/// place the hook's signing secret is ever shown.
+pub(crate) async fn create_webhook(
+    State(index): State<PackageIndex>,
+    Path(name): Path<PackageName>,
+    identity: Option<Extension<Identity>>,
+    Json(new): Json<NewWebhook>,
+) -> Result<(StatusCode, Json<Webhook>), AppError> {
+    let identity = ensure_identified(identity)?;
+    index.check_owner(&name, Some(&identity)).await?;
+    let webhook = Webhook::new(new.url, new.events)?;
+    index.add_webhook(&name, webhook.clone()).await?;
+    info!("Registered webhook {} on {}", webhook.id, name);
+    Ok((StatusCode::CREATED, Json(webhook)))
+}
+
+/// A project's hooks, for its owners and admins only, since hook URLs
+/// often carry secrets.
+pub(crate) async fn list_webhooks(
+    State(index): State<PackageIndex>,
+    Path(name): Path<PackageName>,
+    identity: Option<Extension<Identity>>,
+) -> Result<Json<Vec<WebhookSummary>>, AppError> {
+    let identity = ensure_identified(identity)?;
+    index.check_owner(&name, Some(&identity)).await?;
+    let packages = index.project(&name).await?;
+    let package = packages
+        .get(name.as_str())
+        .ok_or_else(|| AppError::NotFound(name.to_string()))?;
+    Ok(Json(package.webhooks.iter().map(Into::into).collect()))
+}
+
+pub(crate) async fn delete_webhook(
+    State(index): State<PackageIndex>,
+    Path((name, hook)): Path<(PackageName, String)>,
+    identity: Option<Extension<Identity>>,
+) -> Result<StatusCode, AppError> {
+    let identity = ensure_identified(identity)?;
+    index.check_owner(&name, Some(&identity)).await?;
+    index.remove_webhook(&name, &hook).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Delivery attempts listed per hook, most recent kept.
+const DELIVERY_LOG_LEN: usize = 50;
+
+/// Fails unless `hook` is registered on the project.
+async fn ensure_webhook(
+    index: &PackageIndex,
+    name: &PackageName,
+    hook: &str,
+) -> Result<(), AppError> {
+    let registered = index
+        .project(name)
+        .await?
+        .get(name.as_str())
+        .is_some_and(|p| p.webhooks.iter().any(|h| h.id == hook));
+    if registered {
+        Ok(())
+    } else {
+        Err(AppError::NotFound(format!("Webhook {hook}")))
+    }
+}
+
+/// Recent delivery attempts to one of a project's hooks, oldest first.
+/// Like the hooks, they are shown to owners and admins only, as they hold
+/// payloads and what the receiving services answered.
+pub(crate) async fn webhook_deliveries(
+    State(index): State<PackageIndex>,
+    Path((name, hook)): Path<(PackageName, String)>,
+    identity: Option<Extension<Identity>>,
+) -> Result<Json<Vec<Delivery>>, AppError> {
+    let identity = ensure_identified(identity)?;
+    index.check_owner(&name, Some(&identity)).await?;
+    ensure_webhook(&index, &name, &hook).await?;
+    let mut deliveries: Vec<Delivery> = index
+        .storage
+        .read_deliveries()
+        .await?
+        .into_iter()
+        .filter(|d| d.hook == hook)
+        .collect();
+    let skip = deliveries.len().saturating_sub(DELIVERY_LOG_LEN);
+    deliveries.drain(..skip);
+    Ok(Json(deliveries))
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct Redelivery {
+    id: String,
+}
+
+/// Queues a past delivery to be sent again with its original payload, as
+/// a new delivery.
+pub(crate) async fn redeliver_webhook(
+    State(index): State<PackageIndex>,
+    Path((name, hook, delivery)): Path<(PackageName, String, String)>,
+    identity: Option<Extension<Identity>>,
+) -> Result<(StatusCode, Json<Redelivery>), AppError> {
+    let identity = ensure_identified(identity)?;
+    index.check_owner(&name, Some(&identity)).await?;
+    ensure_webhook(&index, &name, &hook).await?;
+    let original = index
+        .storage
+        .read_deliveries()
+        .await?
+        .into_iter()
+        .rev()
+        .find(|d| d.hook == hook && d.id == delivery)
+        .ok_or_else(|| AppError::NotFound(format!("Delivery {delivery}")))?;
+    let id = index
+        .webhooks
+        .enqueue(&name, hook, original.event, original.payload)
+        .await?;
+    Ok((StatusCode::ACCEPTED, Json(Redelivery { id })))
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct ChangesQuery {
+    /// Only changes after this serial are listed.
+    #[serde(default)]
+    since: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct ChangedProject {
+    name: PackageName,
+    /// Serial of the project's latest change.
+    serial: u64,
+    time: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct ChangedProjects {
+    /// Serial to pass as `since` next time.
+    serial: u64,
+    projects: Vec<ChangedProject>,
+}
+
+/// The projects changed since a journal serial, each listed once with its
+/// latest change, so mirrors can refresh only those pages.
+pub(crate) async fn changes(
+    State(index): State<PackageIndex>,
+    Query(query): Query<ChangesQuery>,
+) -> Result<Json<ChangedProjects>, AppError> {
+    let journal = index.storage.changes_after(query.since).await?;
+    let serial = match journal.last() {
+        Some(change) => change.serial,
+        None => index.serial().await,
+    };
+    let mut latest: BTreeMap<PackageName, Change> = BTreeMap::new();
+    for change in journal {
+        latest.insert(change.project.clone(), change);
+    }
+    let mut projects: Vec<ChangedProject> = latest
+        .into_values()
+        .map(|change| ChangedProject {
+            name: change.project,
+            serial: change.serial,
+            time: change.time,
+        })
+        .collect();
+    projects.sort_by_key(|p| p.serial);
+    Ok(Json(ChangedProjects { serial, projects }))
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct StatsResponse {
+    name: PackageName,
+    #[serde(flatten)]
+    stats: ProjectStats,
+}
+
+/// Upload and download counts of a project, hourly for the recent past
+/// and daily and monthly further back.
+pub(crate) async fn project_stats(
+    State(index): State<PackageIndex>,
+    Path(name): Path<PackageName>,
+) -> Result<Json<StatsResponse>, AppError> {
+    if !index.project(&name).await?.contains_key(name.as_str()) {
+        return Err(AppError::NotFound(name.into()));
+    }
+    let stats = index.stats.project(&name).await?;
+    Ok(Json(StatsResponse { name, stats }))
+}
+
+/// Runs a read-only consistency check; repairs are left to `pippy fsck`.
+pub(crate) async fn fsck(
+    State(index): State<PackageIndex>,
+    identity: Option<Extension<Identity>>,
+) -> Result<Json<FsckReport>, AppError> {
+    ensure_admin(identity)?;
+    Ok(Json(fsck::check(&index, false).await?))
+}
+
+/// Rebuilds the index from stored files, as `pippy reindex` does.
+pub(crate) async fn reindex(
+    State(index): State<PackageIndex>,
+    identity: Option<Extension<Identity>>,
+) -> Result<Json<ReindexReport>, AppError> {
+    ensure_admin(identity)?;
+    Ok(Json(reindex::rebuild(&index).await?))
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct ManifestQuery {
+    snapshot: Option<SnapshotName>,
+    /// Hash the stored bytes instead of reporting the recorded digests.
+    #[serde(default)]
+    rehash: bool,
+}
+
+/// Projects, files and digests of the live index or a snapshot, as
+/// compared by `pippy diff`. Rehashing reads every stored file, so, like
+/// fsck, it takes an admin.
+pub(crate) async fn manifest(
+    State(index): State<PackageIndex>,
+    identity: Option<Extension<Identity>>,
+    Query(query): Query<ManifestQuery>,
+) -> Result<Json<Manifest>, AppError> {
+    if query.rehash {
+        ensure_admin(identity)?;
+    }
+    let side = query.snapshot.map_or(Side::Live, Side::Snapshot);
+    Ok(Json(Manifest::of(&index, &side, query.rehash).await?))
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct DiffQuery {
+    from: String,
+    #[serde(default = "live")]
+    to: String,
+    #[serde(default)]
+    rehash: bool,
+}
+
+fn live() -> String {
+    "live".to_string()
+}
+
+/// Differences between two of this instance's states, each `live` or a
+/// snapshot name. Comparing with other instances is left to `pippy diff`,
+/// so the server never fetches URLs on a client's behalf. Rehashing takes
+/// an admin, as for the manifest.
+pub(crate) async fn diff(
+    State(index): State<PackageIndex>,
+    identity: Option<Extension<Identity>>,
+    Query(query): Query<DiffQuery>,
+) -> Result<Json<IndexDiff>, AppError> {
+    if query.rehash {
+        ensure_admin(identity)?;
+    }
+    let (from, to): (Side, Side) = (query.from.parse()?, query.to.parse()?);
+    if from.is_remote() || to.is_remote() {
+        return Err(AppError::InvalidFormat(
+            "Only live and snapshot names can be compared here".to_string(),
+        ));
+    }
+    let a = Manifest::of(&index, &from, query.rehash).await?;
+    let b = Manifest::of(&index, &to, query.rehash).await?;
+    Ok(Json(IndexDiff::compare(from, to, &a, &b)))
+}
+
+/// Stored size, growth and size distributions, as in `pippy report capacity`.
+pub(crate) async fn capacity(
+    State(index): State<PackageIndex>,
+    identity: Option<Extension<Identity>>,
+) -> Result<Json<CapacityReport>, AppError> {
+    ensure_scope(identity, Scope::StatsRead)?;
+    Ok(Json(CapacityReport::build(index.storage()).await?))
+}
+
+/// Bytes stored per project and in total, against the quotas.
+pub(crate) async fn usage(
+    State(index): State<PackageIndex>,
+) -> Result<Json<UsageReport>, AppError> {
+    Ok(Json(index.usage().await?))
+}
+
+/// Fails unless the request came with credentials, handing back who it
+/// acts as.
+fn ensure_identified(identity: Option<Extension<Identity>>) -> Result<Identity, AppError> {
+    let Extension(identity) =
+        identity.ok_or_else(|| AppError::Unauthorized("Credentials are required".into()))?;
+    Ok(identity)
+}
+
+/// Fails unless the request came with an unscoped admin token.
+fn ensure_admin(identity: Option<Extension<Identity>>) -> Result<(), AppError> {
+    identity
+        .ok_or_else(|| AppError::Unauthorized("An admin token is required".into()))?
+        .ensure_admin()
+}
+
+/// Fails unless the request came with an admin token that is unscoped or
+/// has `scope`, handing back its identity.
+fn ensure_scope(identity: Option<Extension<Identity>>, scope: Scope) -> Result<Identity, AppError> {
+    let Extension(identity) =
+        identity.ok_or_else(|| AppError::Unauthorized("An admin token is required".into()))?;
+    identity.ensure_scope(scope)?;
+    Ok(identity)
+}
+
+pub(crate) async fn list_users(
+    State(index): State<PackageIndex>,
+    identity: Option<Extension<Identity>>,
+) -> Result<Json<Vec<User>>, AppError> {
+    ensure_scope(identity, Scope::TokenAdmin)?;
+    Ok(Json(
+        TokenStore::new(index.storage().clone()).users().await?,
+    ))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct NewUser {
+    name: String,
+}
+
+pub(crate) async fn create_user(
+    State(index): State<PackageIndex>,
+    identity: Option<Extension<Identity>>,
+    Json(new): Json<NewUser>,
+) -> Result<(StatusCode, Json<User>), AppError> {
+    ensure_scope(identity, Scope::TokenAdmin)?;
+    let user = TokenStore::new(index.storage().clone())
+        .create_user(&new.name)
+        .await?;
+    info!("Created user {}", user.name);
+    Ok((StatusCode::CREATED, Json(user)))
+}
+
+/// A token as listed, without its digest.
+#[derive(Debug, Serialize)]
+pub(crate) struct TokenSummary {
+    id: String,
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    user: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    projects: Option<Vec<PackageName>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    scopes: Option<Vec<Scope>>,
+    created: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expires: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_used: Option<DateTime<Utc>>,
+}
+
+impl From<ApiToken> for TokenSummary {
+    fn from(token: ApiToken) -> Self {
+        Self {
+            id: token.id,
+            name: token.name,
+            user: token.user,
+            projects: token.projects,
+            scopes: token.scopes,
+            created: token.created,
+            expires: token.expires,
+            last_used: token.last_used,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct TokensQuery {
+    user: Option<String>,
+}
+
+pub(crate) async fn list_tokens(
+    State(index): State<PackageIndex>,
+    identity: Option<Extension<Identity>>,
+    Query(query): Query<TokensQuery>,
+) -> Result<Json<Vec<TokenSummary>>, AppError> {
+    ensure_scope(identity, Scope::TokenAdmin)?;
+    let tokens = TokenStore::new(index.storage().clone()).list().await?;
+    Ok(Json(
+        tokens
+            .into_iter()
+            .filter(|t| query.user.is_none() || t.user == query.user)
+            .map(Into::into)
+            .collect(),
+    ))
+}
+
+/// A token to issue: an admin token unless it names a user, limited to
+/// `projects` and, for admin tokens, `scopes` when given.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct NewToken {
+    name: String,
+    #[serde(default)]
+    user: Option<String>,
+    #[serde(default)]
+    projects: Option<Vec<PackageName>>,
+    #[serde(default)]
+    scopes: Option<Vec<Scope>>,
+    #[serde(default)]
+    expires_in_days: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct IssuedToken {
+    #[serde(flatten)]
+    token: TokenSummary,
+    secret: String,
+}
+
+/// Issues a token. The response is the only place the token itself is
+/// ever shown.
+pub(crate) async fn create_token(
+    State(index): State<PackageIndex>,
+    identity: Option<Extension<Identity>>,
+    Json(new): Json<NewToken>,
+) -> Result<(StatusCode, Json<IssuedToken>), AppError> {
+    let identity = ensure_scope(identity, Scope::TokenAdmin)?;
+    // Tokens with `token-admin` issue tokens for users, never admin tokens
+    // that could outrank their own.
+    if new.user.is_none() {
+        identity.ensure_admin()?;
+    }
+    match &new.scopes {
+        Some(_) if new.user.is_some() => {
+            return Err(AppError::InvalidFormat(
+                "Scopes limit admin tokens; user tokens act as their user".into(),
+            ))
+        }
+        Some(scopes) if scopes.is_empty() => {
+            return Err(AppError::InvalidFormat("Scoped tokens need a scope".into()))
+        }
+        _ => {}
+    }
+    let tokens = TokenStore::new(index.storage().clone());
+    if let Some(user) = &new.user {
+        if !tokens.users().await?.iter().any(|u| u.name == *user) {
+            return Err(AppError::NotFound(format!("User {user}")));
+        }
+    }
+    if new.name.trim().is_empty() {
+        return Err(AppError::InvalidFormat("Tokens need a name".into()));
+    }
+    let expires = match new.expires_in_days {
+        Some(days) if days <= 0 => {
+            return Err(AppError::InvalidFormat(
+                "expires_in_days must be positive".into(),
+            ))
+        }
+        Some(days) => Some(Utc::now() + chrono::Duration::days(days)),
+        None => None,
+    };
+    let (mut token, secret) = ApiToken::generate(new.name, expires)?;
+    token.user = new.user;
+    token.projects = new.projects;
+    token.scopes = new.scopes;
+    let (token, secret) = tokens.add((token, secret)).await?;
+    info!("Issued token {} ({})", token.id, token.name);
+    Ok((
+        StatusCode::CREATED,
+        Json(IssuedToken {
+            token: token.into(),
+            secret,
+        }),
+    ))
+}
+
+pub(crate) async fn revoke_token(
+    State(index): State<PackageIndex>,
+    identity: Option<Extension<Identity>>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, AppError> {
+    let identity = ensure_scope(identity, Scope::TokenAdmin)?;
+    let tokens = TokenStore::new(index.storage().clone());
+    if tokens
+        .list()
+        .await?
+        .iter()
+        .any(|token| token.id == id && token.user.is_none())
+    {
+        identity.ensure_admin()?;
+    }
+    let token = tokens.revoke(&id).await?;
+    info!("Revoked token {} ({})", token.id, token.name);
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,69 @@
+//! CSS and other static assets, embedded in the binary and served under
+//! content-hashed names so browsers can cache them indefinitely.
+
+use axum::{
+    extract::Path,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+use rust_embed::RustEmbed;
+
+#[derive(RustEmbed)]
+#[folder = "assets/"]
+struct Assets;
+
+/// Characters of the content hash included in asset URLs.
+const HASH_LEN: usize = 12;
+
+/// The hashed file name an asset is served under, e.g.
+/// `style.3f2a9c81d0e4.css`.
+pub(crate) fn hashed_name(path: &str) -> String {
+    let asset = Assets::get(path).unwrap_or_else(|| panic!("no embedded asset {path}"));
+    let hash = hex_prefix(&asset.metadata.sha256_hash());
+    match path.rsplit_once('.') {
+        Some((stem, extension)) => format!("{stem}.{hash}.{extension}"),
+        None => format!("{path}.{hash}"),
+    }
+}
+
+fn hex_prefix(digest: &[u8]) -> String {
+    digest
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect::<String>()[..HASH_LEN]
+        .to_string()
+}
+
+/// Serves an asset by its hashed name. A name whose hash does not match
+/// the embedded content is a stale link and gets a 404, never different
+/// content cached under an old URL.
+pub(crate) async fn serve(Path(file): Path<String>) -> Response {
+    let Some(asset) = lookup(&file) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    (
+        [
+            (header::CONTENT_TYPE, asset.metadata.mimetype().to_string()),
+            (
+                header::CACHE_CONTROL,
+                "public, max-age=31536000, immutable".to_string(),
+            ),
+        ],
+        asset.data.into_owned(),
+    )
+        .into_response()
+}
+
+fn lookup(file: &str) -> Option<rust_embed::EmbeddedFile> {
+    let (rest, extension) = match file.rsplit_once('.') {
+        Some((rest, extension)) if rest.contains('.') => (rest, Some(extension)),
+        _ => (file, None),
+    };
+    let (stem, hash) = rest.rsplit_once('.')?;
+    let path = match extension {
+        Some(extension) => format!("{stem}.{extension}"),
+        None => stem.to_string(),
+    };
+    let asset = Assets::get(&path)?;
+    (hex_prefix(&asset.metadata.sha256_hash()) == hash).then_some(asset)
+}
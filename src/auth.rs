@@ -0,0 +1,776 @@
+//! Credentials guarding the index: API tokens for uploads and every other
+//! change, shown once when created and kept only as SHA-256 digests in
+//! `data/tokens.json`, and optional Basic auth users for reads and writes.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    num::NonZeroU32,
+    path::Path,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderMap, Method, Uri},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use base64::{
+    engine::general_purpose::{STANDARD, STANDARD_NO_PAD},
+    Engine,
+};
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use hmac::{Hmac, Mac};
+use ring::pbkdf2;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::warn;
+
+use crate::{server::ClientCertificate, AppError, AppState, Package, PackageName, PackageStorage};
+
+/// Starts every token, so leaked ones are easy to scan for.
+const TOKEN_PREFIX: &str = "pippy-";
+
+/// The username twine and other publishing clients send a token under.
+const TOKEN_USERNAME: &str = "__token__";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiToken {
+    pub id: String,
+    /// What the token is for, e.g. `ci`.
+    pub name: String,
+    /// Hex SHA-256 of the token.
+    pub sha256: String,
+    /// The user the token acts for, limited to the projects they own;
+    /// tokens without one are admin tokens.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    /// Projects the token is limited to, on top of its user's.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub projects: Option<Vec<PackageName>>,
+    /// Admin capabilities an admin token is limited to, instead of all.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scopes: Option<Vec<Scope>>,
+    pub created: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires: Option<DateTime<Utc>>,
+    /// When the token last got a request through, to within
+    /// [`LAST_USED_RESOLUTION`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_used: Option<DateTime<Utc>>,
+}
+
+impl ApiToken {
+    /// A new token record and the token itself, which is not kept.
+    pub fn generate(
+        name: impl Into<String>,
+        expires: Option<DateTime<Utc>>,
+    ) -> Result<(Self, String), AppError> {
+        let secret = format!("{TOKEN_PREFIX}{}", random_hex(32)?);
+        let token = Self {
+            id: random_hex(8)?,
+            name: name.into(),
+            sha256: digest(&secret),
+            user: None,
+            projects: None,
+            scopes: None,
+            created: Utc::now(),
+            expires,
+            last_used: None,
+        };
+        Ok((token, secret))
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires.is_some_and(|expires| expires <= Utc::now())
+    }
+
+    pub fn identity(&self) -> Identity {
+        Identity {
+            user: self.user.clone(),
+            projects: self.projects.clone(),
+            scopes: self.scopes.clone(),
+        }
+    }
+}
+
+/// One of the admin capabilities a token can be limited to, so bots get
+/// only what they need rather than a full admin token.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Scope {
+    /// Yank and unyank files, of any project or of the token's projects.
+    Yank,
+    /// Read the capacity report.
+    StatsRead,
+    /// Manage users and the tokens acting for them.
+    TokenAdmin,
+}
+
+impl Scope {
+    fn as_str(self) -> &'static str {
+        match self {
+            Scope::Yank => "yank",
+            Scope::StatsRead => "stats-read",
+            Scope::TokenAdmin => "token-admin",
+        }
+    }
+
+    /// Whether requests to `path` with `method` are among those this scope
+    /// lets a token make.
+    fn covers(self, method: &Method, path: &str) -> bool {
+        match self {
+            Scope::Yank => {
+                let file = path
+                    .strip_prefix("/api/v1/projects/")
+                    .map(|rest| rest.split('/').collect::<Vec<_>>());
+                *method == Method::PATCH && matches!(file.as_deref(), Some([_, "files", _]))
+            }
+            Scope::StatsRead => *method == Method::GET && path == "/api/v1/admin/capacity",
+            Scope::TokenAdmin => {
+                path.starts_with("/api/v1/users") || path.starts_with("/api/v1/tokens")
+            }
+        }
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Scopes as listed in errors and by `pippy token list`, e.g. `yank, stats-read`.
+pub fn scope_list(scopes: &[Scope]) -> String {
+    scopes
+        .iter()
+        .map(|scope| scope.as_str())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Who a request that passed [`authorize`] acts as, available to handlers
+/// as a request extension.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Identity {
+    /// The user a token or Basic auth login acts for; `None` for admin
+    /// tokens, which may change any project.
+    pub user: Option<String>,
+    /// Projects a scoped token is limited to.
+    pub projects: Option<Vec<PackageName>>,
+    /// Admin capabilities a scoped admin token is limited to.
+    pub scopes: Option<Vec<Scope>>,
+}
+
+impl Identity {
+    pub fn user(name: impl Into<String>) -> Self {
+        Self {
+            user: Some(name.into()),
+            projects: None,
+            scopes: None,
+        }
+    }
+
+    fn has_scope(&self, scope: Scope) -> bool {
+        self.scopes
+            .as_ref()
+            .is_some_and(|scopes| scopes.contains(&scope))
+    }
+
+    /// Fails unless this may upload to, edit or delete `package`. Users
+    /// may change only the projects they own; projects without owners,
+    /// such as those published before ownership existed, are left to
+    /// admins.
+    pub(crate) fn may_change(&self, package: &Package) -> Result<(), AppError> {
+        if let Some(scopes) = &self.scopes {
+            return Err(AppError::Forbidden(format!(
+                "This token is limited to {}",
+                scope_list(scopes)
+            )));
+        }
+        self.within_projects(package)?;
+        match &self.user {
+            None => Ok(()),
+            Some(user) if package.owners.contains(user) => Ok(()),
+            Some(user) => Err(AppError::Forbidden(format!(
+                "{user} is not an owner of {}",
+                package.name
+            ))),
+        }
+    }
+
+    /// Fails unless this may yank and unyank files of `package`: as anyone
+    /// who may change it, or with the `yank` scope.
+    pub(crate) fn may_yank(&self, package: &Package) -> Result<(), AppError> {
+        if self.has_scope(Scope::Yank) {
+            return self.within_projects(package);
+        }
+        self.may_change(package)
+    }
+
+    fn within_projects(&self, package: &Package) -> Result<(), AppError> {
+        if let Some(projects) = &self.projects {
+            if !projects.contains(&package.name) {
+                return Err(AppError::Forbidden(format!(
+                    "This token is not scoped to {}",
+                    package.name
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Fails unless this is an unscoped admin token, as managing owners,
+    /// users and tokens needs.
+    pub(crate) fn ensure_admin(&self) -> Result<(), AppError> {
+        match (&self.user, &self.projects, &self.scopes) {
+            (None, None, None) => Ok(()),
+            (Some(user), _, _) => Err(AppError::Forbidden(format!("{user} is not an admin"))),
+            (None, Some(_), _) => Err(AppError::Forbidden(
+                "Scoped tokens cannot act as admins".into(),
+            )),
+            (None, None, Some(scopes)) => Err(AppError::Forbidden(format!(
+                "This token is limited to {}",
+                scope_list(scopes)
+            ))),
+        }
+    }
+
+    /// Fails unless this is an admin token, unscoped or with `scope`, and
+    /// not limited to projects.
+    pub(crate) fn ensure_scope(&self, scope: Scope) -> Result<(), AppError> {
+        if self.has_scope(scope) && self.user.is_none() && self.projects.is_none() {
+            return Ok(());
+        }
+        self.ensure_admin()
+    }
+}
+
+/// Someone tokens can be issued to and projects owned by.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct User {
+    pub name: String,
+    pub created: DateTime<Utc>,
+}
+
+/// How stale a token's `last_used` may get, so busy tokens do not
+/// rewrite `data/tokens.json` on every request.
+pub const LAST_USED_RESOLUTION: chrono::Duration = chrono::Duration::minutes(1);
+
+fn digest(secret: &str) -> String {
+    format!("{:x}", Sha256::digest(secret.as_bytes()))
+}
+
+fn random_hex(bytes: usize) -> Result<String, AppError> {
+    let mut buf = vec![0; bytes];
+    getrandom::getrandom(&mut buf).map_err(|e| AppError::Io(e.into()))?;
+    Ok(buf.iter().map(|b| format!("{b:02x}")).collect())
+}
+
+/// The tokens of one data directory and the users they act for. Every
+/// check rereads the file, so tokens created or revoked by `pippy token`
+/// apply to running servers.
+#[derive(Debug, Clone)]
+pub struct TokenStore {
+    storage: PackageStorage,
+}
+
+impl TokenStore {
+    pub fn new(storage: PackageStorage) -> Self {
+        Self { storage }
+    }
+
+    pub async fn list(&self) -> Result<Vec<ApiToken>, AppError> {
+        self.storage.load_tokens().await
+    }
+
+    /// Adds an admin token, returning its record and the token to hand
+    /// out.
+    pub async fn create(
+        &self,
+        name: &str,
+        expires: Option<DateTime<Utc>>,
+    ) -> Result<(ApiToken, String), AppError> {
+        self.add(ApiToken::generate(name, expires)?).await
+    }
+
+    /// Adds an admin token limited to `scopes`.
+    pub async fn create_scoped(
+        &self,
+        name: &str,
+        scopes: Vec<Scope>,
+        expires: Option<DateTime<Utc>>,
+    ) -> Result<(ApiToken, String), AppError> {
+        let (mut token, secret) = ApiToken::generate(name, expires)?;
+        token.scopes = Some(scopes);
+        self.add((token, secret)).await
+    }
+
+    /// Adds a token acting for `user`.
+    pub async fn create_for(
+        &self,
+        user: &str,
+        name: &str,
+        expires: Option<DateTime<Utc>>,
+    ) -> Result<(ApiToken, String), AppError> {
+        let (mut token, secret) = ApiToken::generate(name, expires)?;
+        token.user = Some(user.to_string());
+        self.add((token, secret)).await
+    }
+
+    /// Stores a token made with [`ApiToken::generate`]. Expired tokens are
+    /// dropped on the way, so minted ones do not pile up.
+    pub async fn add(
+        &self,
+        (token, secret): (ApiToken, String),
+    ) -> Result<(ApiToken, String), AppError> {
+        let _lock = self.storage.lock_tokens().await?;
+        let mut tokens = self.storage.load_tokens().await?;
+        tokens.retain(|t| !t.is_expired());
+        tokens.push(token.clone());
+        self.storage.save_tokens(&tokens).await?;
+        Ok((token, secret))
+    }
+
+    pub async fn revoke(&self, id: &str) -> Result<ApiToken, AppError> {
+        let _lock = self.storage.lock_tokens().await?;
+        let mut tokens = self.storage.load_tokens().await?;
+        let position = tokens
+            .iter()
+            .position(|t| t.id == id)
+            .ok_or_else(|| AppError::NotFound(format!("Token {id}")))?;
+        let revoked = tokens.remove(position);
+        self.storage.save_tokens(&tokens).await?;
+        Ok(revoked)
+    }
+
+    /// The unexpired token whose secret is `secret`, noting that it was
+    /// used.
+    pub async fn authenticate(&self, secret: &str) -> Result<ApiToken, AppError> {
+        let sha256 = digest(secret);
+        let token = self
+            .storage
+            .load_tokens()
+            .await?
+            .into_iter()
+            .find(|t| t.sha256 == sha256 && !t.is_expired())
+            .ok_or_else(|| AppError::Unauthorized("Invalid or expired token".into()))?;
+        let now = Utc::now();
+        if token
+            .last_used
+            .is_none_or(|used| now - used >= LAST_USED_RESOLUTION)
+        {
+            if let Err(e) = self.touch(&token.id, now).await {
+                warn!("Could not record use of token {}: {}", token.id, e);
+            }
+        }
+        Ok(token)
+    }
+
+    async fn touch(&self, id: &str, now: DateTime<Utc>) -> Result<(), AppError> {
+        let _lock = self.storage.lock_tokens().await?;
+        let mut tokens = self.storage.load_tokens().await?;
+        if let Some(token) = tokens.iter_mut().find(|t| t.id == id) {
+            token.last_used = Some(now);
+            self.storage.save_tokens(&tokens).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn users(&self) -> Result<Vec<User>, AppError> {
+        self.storage.load_users().await
+    }
+
+    pub async fn create_user(&self, name: &str) -> Result<User, AppError> {
+        let valid = !name.is_empty()
+            && name.len() <= 128
+            && !name.chars().any(|c| c.is_whitespace() || c.is_control())
+            && name != TOKEN_USERNAME;
+        if !valid {
+            return Err(AppError::InvalidFormat(format!(
+                "Invalid user name: {name:?}"
+            )));
+        }
+        let _lock = self.storage.lock_tokens().await?;
+        let mut users = self.storage.load_users().await?;
+        if users.iter().any(|u| u.name == name) {
+            return Err(AppError::Conflict(format!("User {name} already exists")));
+        }
+        let user = User {
+            name: name.to_string(),
+            created: Utc::now(),
+        };
+        users.push(user.clone());
+        self.storage.save_users(&users).await?;
+        Ok(user)
+    }
+}
+
+/// PBKDF2-HMAC-SHA256 rounds for new password hashes, as OWASP recommends.
+const PASSWORD_ITERATIONS: u32 = 600_000;
+
+/// A password kept as `$pbkdf2-sha256$<iterations>$<salt>$<hash>`, with
+/// the salt and hash in unpadded base64.
+#[derive(Debug, Clone)]
+struct PasswordHash {
+    iterations: NonZeroU32,
+    salt: Vec<u8>,
+    hash: Vec<u8>,
+}
+
+impl PasswordHash {
+    fn new(password: &str) -> Result<Self, AppError> {
+        let mut salt = vec![0; 16];
+        getrandom::getrandom(&mut salt).map_err(|e| AppError::Io(e.into()))?;
+        let iterations = NonZeroU32::new(PASSWORD_ITERATIONS).expect("nonzero");
+        let mut hash = vec![0; 32];
+        pbkdf2::derive(
+            pbkdf2::PBKDF2_HMAC_SHA256,
+            iterations,
+            &salt,
+            password.as_bytes(),
+            &mut hash,
+        );
+        Ok(Self {
+            iterations,
+            salt,
+            hash,
+        })
+    }
+
+    /// Checks `password` in constant time.
+    fn verify(&self, password: &str) -> bool {
+        pbkdf2::verify(
+            pbkdf2::PBKDF2_HMAC_SHA256,
+            self.iterations,
+            &self.salt,
+            password.as_bytes(),
+            &self.hash,
+        )
+        .is_ok()
+    }
+}
+
+impl fmt::Display for PasswordHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "$pbkdf2-sha256${}${}${}",
+            self.iterations,
+            STANDARD_NO_PAD.encode(&self.salt),
+            STANDARD_NO_PAD.encode(&self.hash)
+        )
+    }
+}
+
+impl FromStr for PasswordHash {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let ["", "pbkdf2-sha256", iterations, salt, hash] = s.split('$').collect::<Vec<_>>()[..]
+        else {
+            return Err(());
+        };
+        let iterations = iterations.parse().map_err(|_| ())?;
+        let salt = STANDARD_NO_PAD.decode(salt).map_err(|_| ())?;
+        let hash = STANDARD_NO_PAD.decode(hash).map_err(|_| ())?;
+        if salt.is_empty() || hash.is_empty() {
+            return Err(());
+        }
+        Ok(Self {
+            iterations,
+            salt,
+            hash,
+        })
+    }
+}
+
+/// A `--read-users` or `--write-users` line for `username` and `password`.
+pub fn hash_password(username: &str, password: &str) -> Result<String, AppError> {
+    Ok(format!("{username}:{}", PasswordHash::new(password)?))
+}
+
+/// Passwords that already passed the slow hash, so clients sending Basic
+/// auth with every request only pay for it once. They are kept as HMACs
+/// under a key made for the process, never as the passwords themselves.
+#[derive(Debug)]
+struct Verified {
+    key: [u8; 32],
+    macs: Mutex<HashMap<String, Vec<u8>>>,
+}
+
+impl Verified {
+    fn new() -> Self {
+        let mut key = [0; 32];
+        getrandom::getrandom(&mut key).expect("the OS random source works");
+        Self {
+            key,
+            macs: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn mac(&self, password: &str) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts keys of any length");
+        mac.update(password.as_bytes());
+        mac
+    }
+
+    fn contains(&self, username: &str, password: &str) -> bool {
+        let macs = self.macs.lock().unwrap();
+        macs.get(username)
+            .is_some_and(|known| self.mac(password).verify_slice(known).is_ok())
+    }
+
+    fn insert(&self, username: &str, password: &str) {
+        let mac = self.mac(password).finalize().into_bytes().to_vec();
+        self.macs.lock().unwrap().insert(username.to_string(), mac);
+    }
+}
+
+/// Basic auth users, read from a file of `username:$pbkdf2-sha256$...`
+/// lines as printed by `pippy hash-password`. Blank lines and `#` comments
+/// are skipped.
+#[derive(Debug, Clone)]
+pub struct Credentials {
+    users: BTreeMap<String, PasswordHash>,
+    verified: Arc<Verified>,
+}
+
+impl Default for Credentials {
+    fn default() -> Self {
+        Self {
+            users: BTreeMap::new(),
+            verified: Arc::new(Verified::new()),
+        }
+    }
+}
+
+impl Credentials {
+    pub fn from_file(path: &Path) -> Result<Self, AppError> {
+        std::fs::read_to_string(path)?.parse()
+    }
+
+    /// Adds a user with a plain-text password.
+    pub fn user(mut self, username: impl Into<String>, password: &str) -> Self {
+        let hash = PasswordHash::new(password).expect("the OS random source works");
+        self.users.insert(username.into(), hash);
+        self
+    }
+
+    async fn accepts(&self, username: &str, password: &str) -> bool {
+        let Some(hash) = self.users.get(username).cloned() else {
+            return false;
+        };
+        if self.verified.contains(username, password) {
+            return true;
+        }
+        let attempt = password.to_string();
+        let accepted = tokio::task::spawn_blocking(move || hash.verify(&attempt))
+            .await
+            .unwrap_or(false);
+        if accepted {
+            self.verified.insert(username, password);
+        }
+        accepted
+    }
+}
+
+impl FromStr for Credentials {
+    type Err = AppError;
+
+    fn from_str(contents: &str) -> Result<Self, Self::Err> {
+        let mut users = BTreeMap::new();
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = |expected: &str| {
+                AppError::InvalidFormat(format!("Line {}: {expected}", number + 1))
+            };
+            let (username, hash) = line
+                .split_once(':')
+                .filter(|(username, _)| !username.is_empty())
+                .ok_or_else(|| invalid("expected username:password-hash"))?;
+            if hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return Err(invalid(
+                    "unsalted SHA-256 digests are no longer accepted; \
+                     rehash the password with `pippy hash-password`",
+                ));
+            }
+            let hash = hash.parse().map_err(|()| {
+                invalid("expected a $pbkdf2-sha256$ hash from `pippy hash-password`")
+            })?;
+            users.insert(username.to_string(), hash);
+        }
+        Ok(Self {
+            users,
+            ..Self::default()
+        })
+    }
+}
+
+/// What a request authenticates with.
+enum Presented {
+    Token(String),
+    Basic { username: String, password: String },
+}
+
+/// The credentials in a request's `Authorization` header: a token as
+/// `token <value>` or `Bearer <value>`, or Basic auth. Basic auth as
+/// `__token__`, with the token as the password, is how twine sends a
+/// token.
+fn presented(headers: &HeaderMap) -> Option<Presented> {
+    let value = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, credentials) = value.trim().split_once(' ')?;
+    let credentials = credentials.trim();
+    match scheme.to_ascii_lowercase().as_str() {
+        "token" | "bearer" => Some(Presented::Token(credentials.to_string())),
+        "basic" => {
+            let decoded = String::from_utf8(STANDARD.decode(credentials).ok()?).ok()?;
+            let (username, password) = decoded.split_once(':')?;
+            if username == TOKEN_USERNAME {
+                return Some(Presented::Token(password.to_string()));
+            }
+            Some(Presented::Basic {
+                username: username.to_string(),
+                password: password.to_string(),
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Whether the query has `key`, as asking for a rehash or a forced
+/// download does. The handlers check the parsed query themselves; this
+/// only makes sure the caller is identified.
+fn query_has(uri: &Uri, key: &str) -> bool {
+    uri.query().is_some_and(|query| {
+        query
+            .split('&')
+            .any(|pair| pair.split('=').next() == Some(key))
+    })
+}
+
+/// Checks credentials on the requests the server is configured to guard:
+/// changes when tokens, client certificates or any users are configured,
+/// reads other than static assets when read users are configured, and
+/// deletions, docs uploads, a project's webhooks and their deliveries,
+/// the contents of stored files, bundles, user and token management,
+/// admin endpoints, snapshot creation and rehashing manifests, diffs and
+/// checksums always, as are forced downloads when yanked downloads are
+/// refused. Tokens and write users may also read. Basic auth users act as themselves, as do clients
+/// without an `Authorization` header that connected with a verified
+/// certificate, named by its common name. Forge webhooks are left alone,
+/// since they only make the server pull from sources it is configured
+/// with, as is trusted publishing, which checks its own credentials.
+pub(crate) async fn authorize(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let config = &state.config;
+    let path = request.uri().path();
+    let read = matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS
+    );
+    // What of a project `/api/v1/projects/<name>/...` is about, if anything.
+    let project_part = path
+        .strip_prefix("/api/v1/projects/")
+        .and_then(|rest| rest.split('/').nth(1));
+    // `/api/v1/projects/<name>/files/<filename>/contents[/<member>]`.
+    let inspected = project_part == Some("files") && path.split('/').nth(7) == Some("contents");
+    let rehashable = path == "/api/v1/manifest"
+        || path == "/api/v1/diff"
+        || (path.starts_with("/api/v1/projects/") && path.ends_with("/checksums"));
+    let guarded = if *request.method() == Method::DELETE
+        || project_part == Some("webhooks")
+        || inspected
+        || path == "/api/v1/bundle"
+        || (!read && project_part == Some("docs"))
+        || path.starts_with("/api/v1/users")
+        || path.starts_with("/api/v1/tokens")
+        || path.starts_with("/api/v1/admin/")
+        || (path.starts_with("/api/v1/snapshots/") && !read)
+        || (rehashable && query_has(request.uri(), "rehash"))
+        || (config.refuse_yanked_downloads
+            && path.starts_with("/packages/")
+            && query_has(request.uri(), "force"))
+    {
+        true
+    } else if read {
+        config.read_credentials.is_some() && !path.starts_with("/static/")
+    } else {
+        config.guards_writes()
+            && !path.starts_with("/api/v1/ingest/")
+            && !path.starts_with("/_/oidc/")
+    };
+    if !guarded {
+        return next.run(request).await;
+    }
+    let Some(presented) = presented(request.headers()) else {
+        let Some(client) = request.extensions().get::<ClientCertificate>() else {
+            return AppError::Unauthorized("Credentials are required".into()).into_response();
+        };
+        let identity = Identity::user(client.common_name.clone());
+        request.extensions_mut().insert(identity);
+        return next.run(request).await;
+    };
+    let checked = match presented {
+        Presented::Token(secret) => TokenStore::new(state.index.storage.clone())
+            .authenticate(&secret)
+            .await
+            .map(|token| token.identity()),
+        Presented::Basic { username, password } => {
+            let readers = config.read_credentials.as_ref().filter(|_| read);
+            let mut accepted = false;
+            for users in [readers, config.write_credentials.as_ref()]
+                .into_iter()
+                .flatten()
+            {
+                if users.accepts(&username, &password).await {
+                    accepted = true;
+                    break;
+                }
+            }
+            if accepted {
+                Ok(Identity::user(username))
+            } else {
+                Err(AppError::Unauthorized(
+                    "Invalid username or password".into(),
+                ))
+            }
+        }
+    };
+    match checked {
+        Ok(identity) => {
+            if let Some(scopes) = &identity.scopes {
+                let path = request.uri().path();
+                let admin = path.starts_with("/api/v1/users")
+                    || path.starts_with("/api/v1/tokens")
+                    || path.starts_with("/api/v1/admin/");
+                // Scoped tokens read what anyone may, and change only what
+                // their scopes cover.
+                let allowed = (read && !admin)
+                    || scopes
+                        .iter()
+                        .any(|scope| scope.covers(request.method(), path));
+                if !allowed {
+                    return AppError::Forbidden(format!(
+                        "This token is limited to {}",
+                        scope_list(scopes)
+                    ))
+                    .into_response();
+                }
+            }
+            request.extensions_mut().insert(identity);
+            next.run(request).await
+        }
+        Err(e) => e.into_response(),
+    }
+}
@@ -0,0 +1,459 @@
+//! Where distribution files, and the core metadata extracted from them,
+//! are kept. Objects are named by keys of the form `<project>/<filename>`.
+//! The index, tokens and other bookkeeping stay in the data directory
+//! whichever backend holds the files.
+
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use async_trait::async_trait;
+use axum::body::Bytes;
+use chrono::{DateTime, Utc};
+use futures_util::{stream::BoxStream, StreamExt};
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncRead, AsyncWriteExt};
+
+use crate::cloud::{AzureBackend, GcsBackend};
+
+/// The contents of a stored object.
+pub type ObjectReader = Pin<Box<dyn AsyncRead + Send>>;
+
+/// An entry of [`StorageBackend::list`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredObject {
+    pub key: String,
+    pub size: u64,
+    pub modified: DateTime<Utc>,
+}
+
+#[async_trait]
+pub trait StorageBackend: fmt::Debug + Send + Sync {
+    /// Writes `chunks` as `key`, returning how many bytes were written.
+    /// Readers see the previous object until the new one is complete, and
+    /// a failed write leaves nothing behind.
+    async fn store(&self, key: &str, chunks: BoxStream<'_, io::Result<Bytes>>) -> io::Result<u64>;
+
+    /// Opens an object for reading, with its size, or `None` if there is
+    /// no such object.
+    async fn open(&self, key: &str) -> io::Result<Option<(ObjectReader, u64)>>;
+
+    /// Removes an object. Removing a missing object is not an error.
+    async fn delete(&self, key: &str) -> io::Result<()>;
+
+    async fn exists(&self, key: &str) -> io::Result<bool>;
+
+    /// Every object whose key starts with `prefix`, ordered by key.
+    async fn list(&self, prefix: &str) -> io::Result<Vec<StoredObject>>;
+
+    /// Moves an object to a new key. The default copies and deletes it.
+    async fn rename(&self, from: &str, to: &str) -> io::Result<()> {
+        let Some((reader, _)) = self.open(from).await? else {
+            return Err(io::Error::new(io::ErrorKind::NotFound, from.to_string()));
+        };
+        self.store(to, tokio_util::io::ReaderStream::new(reader).boxed())
+            .await?;
+        self.delete(from).await
+    }
+
+    /// Moves the complete local file at `path`, whose bytes have the hex
+    /// SHA-256 `sha256`, into place as `key`, returning its size. Backends
+    /// keeping objects on the local filesystem rename it, so `path` should
+    /// be beside [`local_path`](Self::local_path); the default streams it
+    /// in with `store`.
+    async fn store_file(&self, key: &str, path: &Path, _sha256: &str) -> io::Result<u64> {
+        let file = tokio::fs::File::open(path).await?;
+        self.store(key, tokio_util::io::ReaderStream::new(file).boxed())
+            .await
+    }
+
+    /// Space the backend keeps besides its objects that none of them needs
+    /// any more, such as blobs no key links to, last modified before
+    /// `before`. With `remove` it is also freed. The default keeps nothing
+    /// besides the objects.
+    async fn collect_garbage(
+        &self,
+        _before: DateTime<Utc>,
+        _remove: bool,
+    ) -> io::Result<Vec<StoredObject>> {
+        Ok(Vec::new())
+    }
+
+    /// The local file holding the bytes with hex SHA-256 `sha256`, for
+    /// backends keeping objects there by content, or `None` if no object
+    /// has those bytes. Uploads naming such bytes by digest alone are
+    /// published from it. The default keeps no objects by content.
+    async fn local_blob(&self, _sha256: &str) -> io::Result<Option<PathBuf>> {
+        Ok(None)
+    }
+
+    /// Where `key` is, or would be, on the local filesystem, for backends
+    /// that keep objects there. Imports link files into place through it,
+    /// and inspecting a file reads it in place rather than from a copy.
+    fn local_path(&self, _key: &str) -> Option<PathBuf> {
+        None
+    }
+}
+
+/// The backend `location` names, as given on the command line:
+///
+/// - a directory, as a path or a `file://` URL, with `?blobs=<dir>`
+///   keeping its objects by content there;
+/// - `gs://<bucket>/<prefix>`, a Google Cloud Storage bucket, with the
+///   access token in `GOOGLE_OAUTH_ACCESS_TOKEN` if set;
+/// - `az://<account>/<container>/<prefix>`, an Azure Storage container,
+///   with the SAS token in `AZURE_STORAGE_SAS_TOKEN`.
+///
+/// Object stores take `?endpoint=<url>` to use an emulator instead.
+pub fn open_backend(location: &str) -> io::Result<Arc<dyn StorageBackend>> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidInput, message);
+    let (location, options) = match location.split_once('?') {
+        Some((location, options)) => (location, options),
+        None => (location, ""),
+    };
+    let mut options: Vec<(&str, &str)> = options
+        .split('&')
+        .filter(|option| !option.is_empty())
+        .map(|option| option.split_once('=').unwrap_or((option, "")))
+        .collect();
+    let mut option = |name: &str| {
+        let found = options.iter().position(|(key, _)| *key == name)?;
+        Some(options.remove(found).1)
+    };
+    let backend: Arc<dyn StorageBackend> = match location.split_once("://") {
+        None | Some(("file", _)) => {
+            let root = location.trim_start_matches("file://");
+            let backend = FileSystemBackend::new(PathBuf::from(root))?;
+            match option("blobs") {
+                Some(blobs) => Arc::new(backend.content_addressed(PathBuf::from(blobs))?),
+                None => Arc::new(backend),
+            }
+        }
+        Some(("gs", path)) => {
+            let (bucket, prefix) = path.split_once('/').unwrap_or((path, ""));
+            let mut backend = GcsBackend::new(bucket, prefix);
+            if let Ok(token) = std::env::var("GOOGLE_OAUTH_ACCESS_TOKEN") {
+                backend = backend.with_token(token);
+            }
+            if let Some(endpoint) = option("endpoint") {
+                backend = backend.with_endpoint(endpoint);
+            }
+            Arc::new(backend)
+        }
+        Some(("az", path)) => {
+            let mut parts = path.splitn(3, '/');
+            let (Some(account), Some(container)) = (parts.next(), parts.next()) else {
+                return Err(invalid(format!("{location} names no container")));
+            };
+            let sas = std::env::var("AZURE_STORAGE_SAS_TOKEN")
+                .map_err(|_| invalid("AZURE_STORAGE_SAS_TOKEN is not set".into()))?;
+            let mut backend =
+                AzureBackend::new(account, container, parts.next().unwrap_or(""), &sas);
+            if let Some(endpoint) = option("endpoint") {
+                backend = backend.with_endpoint(endpoint);
+            }
+            Arc::new(backend)
+        }
+        Some((scheme, _)) => {
+            return Err(invalid(format!("{scheme}:// storage is not supported")));
+        }
+    };
+    match options.first() {
+        Some((name, _)) => Err(invalid(format!("unknown storage option {name}"))),
+        None => Ok(backend),
+    }
+}
+
+/// Keeps objects as files in a directory, one subdirectory per project.
+/// This is the default backend, under `packages/` in the data directory,
+/// with blobs under `blobs/`.
+#[derive(Debug, Clone)]
+pub struct FileSystemBackend {
+    root: PathBuf,
+    blobs: Option<PathBuf>,
+}
+
+impl FileSystemBackend {
+    pub fn new(root: PathBuf) -> io::Result<Self> {
+        std::fs::create_dir_all(&root)?;
+        Ok(Self { root, blobs: None })
+    }
+
+    /// Keeps the bytes of each stored object once, in `blobs` under their
+    /// SHA-256 as `ab/cd/<digest>`, with every key a hard link to its blob.
+    /// Identical files stored under several keys, or by several indexes
+    /// sharing `blobs`, then take up space once. `blobs` should be on the
+    /// same filesystem as the root; where a link cannot be made the blob
+    /// is copied instead. Blobs no key links to any more are left for
+    /// [`StorageBackend::collect_garbage`].
+    pub fn content_addressed(mut self, blobs: PathBuf) -> io::Result<Self> {
+        std::fs::create_dir_all(&blobs)?;
+        self.blobs = Some(blobs);
+        Ok(self)
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Where the blob with hex SHA-256 `digest` is kept, if objects are
+    /// content addressed.
+    pub fn blob_path(&self, digest: &str) -> Option<PathBuf> {
+        let blobs = self.blobs.as_ref()?;
+        Some(blobs.join(&digest[..2]).join(&digest[2..4]).join(digest))
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.root.join(key)
+    }
+
+    /// Makes the complete write at `partial` the blob at `blob`, unless an
+    /// identical blob is already kept, and links `path` to it.
+    async fn link_blob(&self, partial: &Path, blob: &Path, path: &Path) -> io::Result<()> {
+        if let Some(dir) = blob.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        if tokio::fs::try_exists(blob).await? {
+            tokio::fs::remove_file(partial).await?;
+        } else {
+            tokio::fs::rename(partial, blob).await?;
+        }
+        // Linked beside the key and renamed over it, so readers of the key
+        // see the old object or the new one and never neither. The link
+        // shares the blob's modification time, so it is brought up to date:
+        // garbage collection takes a recent one to mean a write in progress.
+        let (blob, link, path) = (
+            blob.to_path_buf(),
+            partial.to_path_buf(),
+            path.to_path_buf(),
+        );
+        tokio::task::spawn_blocking(move || {
+            let linked = std::fs::hard_link(&blob, &link)
+                .or_else(|_| std::fs::copy(&blob, &link).map(|_| ()))
+                .and_then(|()| {
+                    std::fs::File::options()
+                        .write(true)
+                        .open(&link)?
+                        .set_modified(std::time::SystemTime::now())
+                })
+                .and_then(|()| std::fs::rename(&link, &path));
+            if linked.is_err() {
+                let _ = std::fs::remove_file(&link);
+            }
+            linked
+        })
+        .await
+        .map_err(io::Error::other)?
+    }
+
+    /// Removes the directory a key was in once it holds nothing else.
+    async fn prune(&self, path: &Path) {
+        if let Some(dir) = path.parent().filter(|dir| *dir != self.root) {
+            let _ = tokio::fs::remove_dir(dir).await;
+        }
+    }
+}
+
+#[async_trait]
+impl StorageBackend for FileSystemBackend {
+    async fn store(
+        &self,
+        key: &str,
+        mut chunks: BoxStream<'_, io::Result<Bytes>>,
+    ) -> io::Result<u64> {
+        static PARTIALS: AtomicU64 = AtomicU64::new(0);
+        let path = self.path(key);
+        let dir = path.parent().unwrap_or(&self.root);
+        tokio::fs::create_dir_all(dir).await?;
+        // Unique per write, so concurrent writes of one key cannot
+        // interleave their bytes. The leading dot keeps it out of listings.
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let partial = dir.join(format!(
+            ".{name}.{}-{}.partial",
+            std::process::id(),
+            PARTIALS.fetch_add(1, Ordering::Relaxed)
+        ));
+        let written = async {
+            let mut file = tokio::fs::File::create(&partial).await?;
+            let mut hasher = Sha256::new();
+            let mut size = 0;
+            while let Some(chunk) = chunks.next().await {
+                let chunk = chunk?;
+                size += chunk.len() as u64;
+                hasher.update(&chunk);
+                file.write_all(&chunk).await?;
+            }
+            file.flush().await?;
+            match self.blob_path(&format!("{:x}", hasher.finalize())) {
+                Some(blob) => self.link_blob(&partial, &blob, &path).await?,
+                None => tokio::fs::rename(&partial, &path).await?,
+            }
+            Ok(size)
+        }
+        .await;
+        if written.is_err() {
+            let _ = tokio::fs::remove_file(&partial).await;
+        }
+        written
+    }
+
+    async fn open(&self, key: &str) -> io::Result<Option<(ObjectReader, u64)>> {
+        let file = match tokio::fs::File::open(self.path(key)).await {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let size = file.metadata().await?.len();
+        Ok(Some((Box::pin(file), size)))
+    }
+
+    async fn delete(&self, key: &str) -> io::Result<()> {
+        let path = self.path(key);
+        match tokio::fs::remove_file(&path).await {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        self.prune(&path).await;
+        Ok(())
+    }
+
+    async fn exists(&self, key: &str) -> io::Result<bool> {
+        tokio::fs::try_exists(self.path(key)).await
+    }
+
+    async fn list(&self, prefix: &str) -> io::Result<Vec<StoredObject>> {
+        // Only the directory the prefix names, and those under it, can hold
+        // matching keys.
+        let start = prefix.rfind('/').map_or("", |slash| &prefix[..slash]);
+        let mut found = Vec::new();
+        let mut pending = vec![start.to_string()];
+        while let Some(dir) = pending.pop() {
+            let mut entries = match tokio::fs::read_dir(self.root.join(&dir)).await {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            while let Some(entry) = entries.next_entry().await? {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                // Writes in progress.
+                if name.starts_with('.') {
+                    continue;
+                }
+                let key = if dir.is_empty() {
+                    name
+                } else {
+                    format!("{dir}/{name}")
+                };
+                let metadata = entry.metadata().await?;
+                if metadata.is_dir() {
+                    pending.push(key);
+                } else if key.starts_with(prefix) {
+                    found.push(StoredObject {
+                        key,
+                        size: metadata.len(),
+                        modified: metadata.modified()?.into(),
+                    });
+                }
+            }
+        }
+        found.sort_by(|a, b| a.key.cmp(&b.key));
+        Ok(found)
+    }
+
+    async fn store_file(&self, key: &str, path: &Path, sha256: &str) -> io::Result<u64> {
+        let target = self.path(key);
+        if let Some(dir) = target.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        let size = tokio::fs::metadata(path).await?.len();
+        match self.blob_path(sha256) {
+            Some(blob) => self.link_blob(path, &blob, &target).await?,
+            None => tokio::fs::rename(path, &target).await?,
+        }
+        Ok(size)
+    }
+
+    async fn local_blob(&self, sha256: &str) -> io::Result<Option<PathBuf>> {
+        let is_digest = sha256.len() == 64
+            && sha256
+                .bytes()
+                .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b));
+        let Some(blob) = self.blob_path(sha256).filter(|_| is_digest) else {
+            return Ok(None);
+        };
+        Ok(tokio::fs::try_exists(&blob).await?.then_some(blob))
+    }
+
+    async fn rename(&self, from: &str, to: &str) -> io::Result<()> {
+        let (from, to) = (self.path(from), self.path(to));
+        if let Some(dir) = to.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        tokio::fs::rename(&from, &to).await?;
+        self.prune(&from).await;
+        Ok(())
+    }
+
+    /// Blobs with no other link are collected, keyed by their path under
+    /// the blob directory. Link counts are only known on Unix, so elsewhere
+    /// blobs are never collected.
+    async fn collect_garbage(
+        &self,
+        before: DateTime<Utc>,
+        remove: bool,
+    ) -> io::Result<Vec<StoredObject>> {
+        let Some(blobs) = self.blobs.clone() else {
+            return Ok(Vec::new());
+        };
+        let mut found = Vec::new();
+        let mut pending = vec![String::new()];
+        while let Some(dir) = pending.pop() {
+            let mut entries = match tokio::fs::read_dir(blobs.join(&dir)).await {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            while let Some(entry) = entries.next_entry().await? {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                let key = if dir.is_empty() {
+                    name
+                } else {
+                    format!("{dir}/{name}")
+                };
+                let metadata = entry.metadata().await?;
+                if metadata.is_dir() {
+                    pending.push(key);
+                    continue;
+                }
+                #[cfg(unix)]
+                let unlinked = std::os::unix::fs::MetadataExt::nlink(&metadata) == 1;
+                #[cfg(not(unix))]
+                let unlinked = false;
+                let modified: DateTime<Utc> = metadata.modified()?.into();
+                if !unlinked || modified >= before {
+                    continue;
+                }
+                if remove {
+                    tokio::fs::remove_file(entry.path()).await?;
+                }
+                found.push(StoredObject {
+                    key,
+                    size: metadata.len(),
+                    modified,
+                });
+            }
+        }
+        found.sort_by(|a, b| a.key.cmp(&b.key));
+        Ok(found)
+    }
+
+    fn local_path(&self, key: &str) -> Option<PathBuf> {
+        Some(self.path(key))
+    }
+}
@@ -0,0 +1,229 @@
+//! Synthetic load generation against a running pippy instance or an
+//! in-process router bound to an ephemeral port.
+
+use std::{
+    fmt,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use clap::ValueEnum;
+use reqwest::{multipart, Client};
+
+use crate::{router, AppError, PackageIndex};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Scenario {
+    Upload,
+    Download,
+    Simple,
+}
+
+impl fmt::Display for Scenario {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Scenario::Upload => f.pad("upload"),
+            Scenario::Download => f.pad("download"),
+            Scenario::Simple => f.pad("simple"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BenchOptions {
+    /// Base URL of the instance to drive; `None` spins up an in-process server.
+    pub target: Option<String>,
+    pub requests: usize,
+    pub concurrency: usize,
+    pub scenarios: Vec<Scenario>,
+    /// Number of packages uploaded before measuring, used by the read scenarios.
+    pub seed_packages: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct ScenarioReport {
+    pub scenario: Scenario,
+    pub requests: usize,
+    pub errors: usize,
+    pub elapsed: Duration,
+    latencies: Vec<Duration>,
+}
+
+impl ScenarioReport {
+    pub fn throughput(&self) -> f64 {
+        self.requests as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+
+    pub fn percentile(&self, p: f64) -> Duration {
+        percentile(&self.latencies, p)
+    }
+}
+
+/// The `p`th percentile of latencies sorted in ascending order.
+pub(crate) fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    let rank = ((p / 100.0) * (sorted.len() - 1) as f64).round() as usize;
+    sorted[rank.min(sorted.len() - 1)]
+}
+
+impl fmt::Display for ScenarioReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:<9} {:>7} req {:>5} err {:>10.1} req/s  p50 {:>8.2?}  p90 {:>8.2?}  p99 {:>8.2?}  max {:>8.2?}",
+            self.scenario,
+            self.requests,
+            self.errors,
+            self.throughput(),
+            self.percentile(50.0),
+            self.percentile(90.0),
+            self.percentile(99.0),
+            self.percentile(100.0),
+        )
+    }
+}
+
+pub async fn run(options: BenchOptions) -> Result<Vec<ScenarioReport>, AppError> {
+    let client = Client::new();
+    let (target, scratch) = match &options.target {
+        Some(target) => (target.trim_end_matches('/').to_string(), None),
+        None => {
+            let dir = std::env::temp_dir().join(format!("pippy-bench-{}", std::process::id()));
+            (spawn_in_process(dir.clone()).await?, Some(dir))
+        }
+    };
+
+    for i in 0..options.seed_packages {
+        let response = upload(&client, &target, &seed_filename(i)).await?;
+        if !response.status().is_success() {
+            return Err(AppError::InvalidFormat(format!(
+                "seeding {} failed with {}",
+                seed_filename(i),
+                response.status()
+            )));
+        }
+    }
+
+    let mut reports = Vec::new();
+    for scenario in &options.scenarios {
+        reports.push(run_scenario(&client, &target, *scenario, &options).await);
+    }
+
+    if let Some(dir) = scratch {
+        let _ = tokio::fs::remove_dir_all(dir).await;
+    }
+
+    Ok(reports)
+}
+
+async fn spawn_in_process(data_dir: PathBuf) -> Result<String, AppError> {
+    let index = PackageIndex::new(data_dir).await?;
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    tokio::spawn(async move { axum::serve(listener, router(index)).await });
+    Ok(format!("http://{addr}"))
+}
+
+async fn run_scenario(
+    client: &Client,
+    target: &str,
+    scenario: Scenario,
+    options: &BenchOptions,
+) -> ScenarioReport {
+    let next = Arc::new(AtomicUsize::new(0));
+    let errors = Arc::new(AtomicUsize::new(0));
+    let started = Instant::now();
+
+    let workers = (0..options.concurrency.max(1))
+        .map(|_| {
+            let client = client.clone();
+            let target = target.to_string();
+            let next = next.clone();
+            let errors = errors.clone();
+            let total = options.requests;
+            let seeded = options.seed_packages.max(1);
+            tokio::spawn(async move {
+                let mut latencies = Vec::new();
+                loop {
+                    let n = next.fetch_add(1, Ordering::Relaxed);
+                    if n >= total {
+                        break;
+                    }
+                    let start = Instant::now();
+                    let ok = match scenario {
+                        Scenario::Upload => upload(&client, &target, &upload_filename(n))
+                            .await
+                            .is_ok_and(|r| r.status().is_success()),
+                        Scenario::Download => {
+                            let filename = seed_filename(n % seeded);
+                            let name = filename.split('-').next().unwrap_or_default();
+                            get(&client, &format!("{target}/packages/{name}/{filename}")).await
+                        }
+                        Scenario::Simple if n.is_multiple_of(10) => {
+                            get(&client, &format!("{target}/simple/")).await
+                        }
+                        Scenario::Simple => {
+                            let filename = seed_filename(n % seeded);
+                            let name = filename.split('-').next().unwrap_or_default();
+                            get(&client, &format!("{target}/simple/{name}/")).await
+                        }
+                    };
+                    latencies.push(start.elapsed());
+                    if !ok {
+                        errors.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+                latencies
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let mut latencies = Vec::with_capacity(options.requests);
+    for worker in workers {
+        latencies.extend(worker.await.unwrap_or_default());
+    }
+    latencies.sort();
+
+    ScenarioReport {
+        scenario,
+        requests: latencies.len(),
+        errors: errors.load(Ordering::Relaxed),
+        elapsed: started.elapsed(),
+        latencies,
+    }
+}
+
+async fn get(client: &Client, url: &str) -> bool {
+    match client.get(url).send().await {
+        Ok(response) => response.status().is_success() && response.bytes().await.is_ok(),
+        Err(_) => false,
+    }
+}
+
+async fn upload(
+    client: &Client,
+    target: &str,
+    filename: &str,
+) -> Result<reqwest::Response, AppError> {
+    let part = multipart::Part::bytes(vec![0u8; 1024]).file_name(filename.to_string());
+    let form = multipart::Form::new().part("content", part);
+    Ok(client
+        .post(format!("{target}/upload"))
+        .multipart(form)
+        .send()
+        .await?)
+}
+
+fn seed_filename(i: usize) -> String {
+    format!("bench_seed_{i}-1.0.0-py3-none-any.whl")
+}
+
+fn upload_filename(n: usize) -> String {
+    format!("bench_upload-0.0.{n}-py3-none-any.whl")
+}
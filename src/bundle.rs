@@ -0,0 +1,202 @@
+//! Self-contained tarballs of selected files plus a static simple index,
+//! for `pip install --no-index --find-links` in air-gapped environments.
+
+use std::{collections::BTreeMap, io::Write, str::FromStr};
+
+use crate::{compat::TargetEnvironment, AppError, PackageIndex, PackageName, Release, Version};
+
+/// Top-level directory inside every bundle.
+const BUNDLE_ROOT: &str = "pippy-bundle";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pin {
+    pub name: PackageName,
+    pub version: Version,
+}
+
+impl FromStr for Pin {
+    type Err = AppError;
+
+    fn from_str(pin: &str) -> Result<Self, Self::Err> {
+        let (name, version) = pin
+            .split_once("==")
+            .ok_or_else(|| AppError::InvalidFormat(format!("Expected name==version: {pin}")))?;
+        Ok(Self {
+            name: name.trim().parse()?,
+            version: version.trim().parse()?,
+        })
+    }
+}
+
+/// Most one bundle may hold, so building one on request stays bounded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BundleLimits {
+    pub max_projects: Option<usize>,
+    /// Total size of the files bundled, in bytes.
+    pub max_bytes: Option<u64>,
+}
+
+impl BundleLimits {
+    /// No limits, for bundles written from the command line.
+    pub const UNLIMITED: Self = Self {
+        max_projects: None,
+        max_bytes: None,
+    };
+}
+
+#[derive(Debug, Clone)]
+pub enum BundleSelection {
+    /// The most recently uploaded version of every hosted project.
+    Latest,
+    Pins(Vec<Pin>),
+}
+
+/// Writes a bundle as an uncompressed tar archive and returns the writer.
+/// Selections over `limits` fail before any file is read: with too many
+/// projects as invalid, with too many bytes as too large.
+pub async fn write_bundle<W>(
+    index: &PackageIndex,
+    selection: &BundleSelection,
+    target: &TargetEnvironment,
+    limits: BundleLimits,
+    out: W,
+) -> Result<W, AppError>
+where
+    W: Write + Send + 'static,
+{
+    let files = select_files(index, selection, target).await?;
+    if let Some(max) = limits.max_projects.filter(|max| files.len() > *max) {
+        return Err(AppError::InvalidFormat(format!(
+            "A bundle holds at most {max} projects, not {}; pin the ones wanted",
+            files.len()
+        )));
+    }
+    if let Some(max) = limits.max_bytes {
+        let mut total = 0u64;
+        for (name, releases) in &files {
+            for release in releases {
+                let stored = index.storage.stat(name, &release.filename).await?;
+                total = total.saturating_add(stored.map_or(0, |object| object.size));
+            }
+        }
+        if total > max {
+            return Err(AppError::PayloadTooLarge {
+                message: format!(
+                    "A bundle of {total} bytes is larger than the limit of {max} bytes"
+                ),
+                limit: max,
+            });
+        }
+    }
+    let mut local = Vec::new();
+    for (name, releases) in &files {
+        for release in releases {
+            local.push(index.storage.local_file(name, &release.filename).await?);
+        }
+    }
+
+    tokio::task::spawn_blocking(move || {
+        let mut local = local.iter();
+        let mut archive = tar::Builder::new(out);
+        append_text(&mut archive, "simple/index.html", &root_page(&files))?;
+        for (name, releases) in &files {
+            append_text(
+                &mut archive,
+                &format!("simple/{name}/index.html"),
+                &project_page(releases),
+            )?;
+            for release in releases {
+                let filename = &release.filename;
+                let file = local.next().expect("a local file per release");
+                archive.append_path_with_name(
+                    file.path(),
+                    format!("{BUNDLE_ROOT}/packages/{filename}"),
+                )?;
+            }
+        }
+        Ok(archive.into_inner()?)
+    })
+    .await
+    .map_err(|e| AppError::Io(std::io::Error::other(e)))?
+}
+
+async fn select_files(
+    index: &PackageIndex,
+    selection: &BundleSelection,
+    target: &TargetEnvironment,
+) -> Result<BTreeMap<PackageName, Vec<Release>>, AppError> {
+    let packages = index.catalog().await?;
+    let wanted: Vec<(PackageName, Version)> = match selection {
+        // Yanked files are left out unless their version is pinned.
+        BundleSelection::Latest => packages
+            .values()
+            .filter_map(|p| {
+                let latest = p.releases.iter().find(|r| !r.yanked)?;
+                Some((p.name.clone(), latest.version.clone()))
+            })
+            .collect(),
+        BundleSelection::Pins(pins) => pins
+            .iter()
+            .map(|pin| (pin.name.clone(), pin.version.clone()))
+            .collect(),
+    };
+
+    let mut files = BTreeMap::new();
+    for (name, version) in wanted {
+        let package = packages
+            .get(name.as_str())
+            .ok_or_else(|| AppError::NotFound(name.to_string()))?;
+        let releases: Vec<Release> = package
+            .releases
+            .iter()
+            .filter(|r| r.version == version && target.accepts(r.filename.as_str()))
+            .filter(|r| !r.yanked || matches!(selection, BundleSelection::Pins(_)))
+            .cloned()
+            .collect();
+        if releases.is_empty() {
+            return Err(AppError::NotFound(format!("{name}=={version}")));
+        }
+        files.insert(name, releases);
+    }
+    Ok(files)
+}
+
+fn append_text<W: Write>(
+    archive: &mut tar::Builder<W>,
+    path: &str,
+    contents: &str,
+) -> std::io::Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(contents.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(chrono::Utc::now().timestamp() as u64);
+    header.set_cksum();
+    archive.append_data(
+        &mut header,
+        format!("{BUNDLE_ROOT}/{path}"),
+        contents.as_bytes(),
+    )
+}
+
+fn root_page(files: &BTreeMap<PackageName, Vec<Release>>) -> String {
+    let links: String = files
+        .keys()
+        .map(|name| format!("<a href='{name}/'>{name}</a><br>\n"))
+        .collect();
+    format!("<!DOCTYPE html>\n<html><body>\n{links}</body></html>\n")
+}
+
+fn project_page(releases: &[Release]) -> String {
+    let links: String = releases
+        .iter()
+        .map(|r| {
+            format!(
+                "<a href='../../packages/{}{}'>{}</a><br>\n",
+                r.filename,
+                r.hash_fragment(),
+                r.filename
+            )
+        })
+        .collect();
+    format!("<!DOCTYPE html>\n<html><body>\n{links}</body></html>\n")
+}
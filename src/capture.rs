@@ -0,0 +1,245 @@
+//! Recording of read traffic to a file and replaying it against another
+//! instance, for checking upgrades and storage migrations under load
+//! shaped like production's.
+//!
+//! Records are anonymized: only the method, path and query, response
+//! status and timing are kept; client addresses and headers never are.
+
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use reqwest::{Client, Method};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncWriteExt, BufWriter},
+    sync::mpsc,
+};
+use tracing::warn;
+
+use crate::{bench::percentile, AppError};
+
+/// Path prefixes of the read API whose requests are captured.
+const CAPTURED_PREFIXES: &[&str] = &[
+    "/simple/",
+    "/packages/",
+    "/project/",
+    "/channels/",
+    "/snapshots/",
+];
+
+/// One captured request, written as a line of JSON.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CaptureRecord {
+    /// Milliseconds since capturing started.
+    pub offset_ms: u64,
+    pub method: String,
+    /// Path and query, relative to the server root.
+    pub uri: String,
+    pub status: u16,
+}
+
+/// Sink the capture middleware sends records to, written out by a
+/// background task.
+#[derive(Clone)]
+pub struct Capture {
+    started: Instant,
+    records: mpsc::UnboundedSender<CaptureRecord>,
+}
+
+impl Capture {
+    /// Starts appending captured requests to `path`.
+    pub async fn create(path: &Path) -> Result<Self, AppError> {
+        let file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await?;
+        let (records, mut received) = mpsc::unbounded_channel::<CaptureRecord>();
+        tokio::spawn(async move {
+            let mut out = BufWriter::new(file);
+            while let Some(record) = received.recv().await {
+                let mut line = serde_json::to_vec(&record).expect("records always serialize");
+                line.push(b'\n');
+                let written = async {
+                    out.write_all(&line).await?;
+                    // Keep the file usable if the process is killed.
+                    if received.is_empty() {
+                        out.flush().await?;
+                    }
+                    std::io::Result::Ok(())
+                };
+                if let Err(e) = written.await {
+                    warn!("Writing the request capture failed, stopping: {}", e);
+                    break;
+                }
+            }
+        });
+
+        Ok(Self {
+            started: Instant::now(),
+            records,
+        })
+    }
+}
+
+impl fmt::Debug for Capture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Capture").finish_non_exhaustive()
+    }
+}
+
+/// Middleware recording requests to the read API.
+pub(crate) async fn record(
+    State(capture): State<Capture>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let path = request.uri().path();
+    if !CAPTURED_PREFIXES
+        .iter()
+        .any(|prefix| path.starts_with(prefix))
+    {
+        return next.run(request).await;
+    }
+    let offset_ms = capture.started.elapsed().as_millis() as u64;
+    let method = request.method().to_string();
+    let uri = request
+        .uri()
+        .path_and_query()
+        .map_or_else(|| path.to_string(), |pq| pq.to_string());
+
+    let response = next.run(request).await;
+    let _ = capture.records.send(CaptureRecord {
+        offset_ms,
+        method,
+        uri,
+        status: response.status().as_u16(),
+    });
+    response
+}
+
+#[derive(Debug, Clone)]
+pub struct ReplayOptions {
+    pub capture: PathBuf,
+    /// Base URL of the instance to replay against.
+    pub target: String,
+    /// Multiplier applied to the captured pacing; `0` sends every request
+    /// as soon as possible.
+    pub speed: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct ReplayReport {
+    pub requests: usize,
+    /// Requests that failed to get any response.
+    pub errors: usize,
+    /// Responses whose status differs from the captured one.
+    pub mismatches: usize,
+    pub elapsed: Duration,
+    latencies: Vec<Duration>,
+}
+
+impl fmt::Display for ReplayReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} req {} err {} status mismatches in {:.2?}  p50 {:.2?}  p90 {:.2?}  p99 {:.2?}  max {:.2?}",
+            self.requests,
+            self.errors,
+            self.mismatches,
+            self.elapsed,
+            percentile(&self.latencies, 50.0),
+            percentile(&self.latencies, 90.0),
+            percentile(&self.latencies, 99.0),
+            percentile(&self.latencies, 100.0),
+        )
+    }
+}
+
+/// Outcome of one replayed request.
+enum Replayed {
+    Matched(Duration),
+    Mismatched(Duration),
+    Failed,
+}
+
+/// Sends every captured request to the target, keeping their relative
+/// timing scaled by `speed`, and compares the response statuses.
+pub async fn replay(options: ReplayOptions) -> Result<ReplayReport, AppError> {
+    // Read up front, so replaying into an instance that captures to the
+    // same file terminates.
+    let content = tokio::fs::read_to_string(&options.capture).await?;
+    let records = content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(serde_json::from_str)
+        .collect::<Result<Vec<CaptureRecord>, _>>()?;
+    // Redirects are compared as captured, not followed.
+    let client = Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()?;
+    let target: Arc<str> = options.target.trim_end_matches('/').into();
+    let started = Instant::now();
+
+    let mut pending = Vec::new();
+    for record in records {
+        if options.speed > 0.0 {
+            let due = Duration::from_millis(record.offset_ms).div_f64(options.speed);
+            tokio::time::sleep_until((started + due).into()).await;
+        }
+        let client = client.clone();
+        let target = target.clone();
+        pending.push(tokio::spawn(async move {
+            let Ok(method) = Method::from_bytes(record.method.as_bytes()) else {
+                return Replayed::Failed;
+            };
+            let sent = Instant::now();
+            match client
+                .request(method, format!("{target}{}", record.uri))
+                .send()
+                .await
+            {
+                Ok(response) => {
+                    let status = response.status().as_u16();
+                    let _ = response.bytes().await;
+                    if status == record.status {
+                        Replayed::Matched(sent.elapsed())
+                    } else {
+                        Replayed::Mismatched(sent.elapsed())
+                    }
+                }
+                Err(_) => Replayed::Failed,
+            }
+        }));
+    }
+
+    let mut report = ReplayReport {
+        requests: pending.len(),
+        errors: 0,
+        mismatches: 0,
+        elapsed: Duration::ZERO,
+        latencies: Vec::with_capacity(pending.len()),
+    };
+    for request in pending {
+        match request.await.unwrap_or(Replayed::Failed) {
+            Replayed::Matched(latency) => report.latencies.push(latency),
+            Replayed::Mismatched(latency) => {
+                report.mismatches += 1;
+                report.latencies.push(latency);
+            }
+            Replayed::Failed => report.errors += 1,
+        }
+    }
+    report.elapsed = started.elapsed();
+    report.latencies.sort();
+    Ok(report)
+}
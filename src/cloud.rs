@@ -0,0 +1,511 @@
+//! Storage backends keeping objects in a cloud object store, through its
+//! REST API: Google Cloud Storage and Azure Blob Storage. Both only show
+//! an object once it is completely written. Objects are uploaded from a
+//! local file, so streams handed to `store` are spooled to one first.
+
+use std::{
+    fmt, io,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use axum::body::Bytes;
+use chrono::{DateTime, Utc};
+use futures_util::{stream::BoxStream, StreamExt, TryStreamExt};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use reqwest::{header, Body, Client, Response, StatusCode};
+use serde::Deserialize;
+use tokio::{io::AsyncWriteExt, sync::Mutex};
+use tokio_util::io::{ReaderStream, StreamReader};
+
+use crate::backend::{ObjectReader, StorageBackend, StoredObject};
+
+/// Characters left as they are in an object name put in a URL.
+const UNRESERVED: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+/// As `UNRESERVED`, keeping the slashes of a blob path.
+const PATH: &AsciiSet = &UNRESERVED.remove(b'/');
+
+const GCS_ENDPOINT: &str = "https://storage.googleapis.com";
+const GCS_METADATA_TOKEN: &str =
+    "http://metadata.google.internal/computeMetadata/v1/instance/service-accounts/default/token";
+/// How long before it expires a token from the metadata server is renewed.
+const TOKEN_MARGIN: Duration = Duration::from_secs(60);
+const AZURE_VERSION: &str = "2021-08-06";
+
+/// An I/O error for a request that could not be made, transient where a
+/// retry may get through.
+fn request_error(e: reqwest::Error) -> io::Error {
+    let kind = if e.is_timeout() {
+        io::ErrorKind::TimedOut
+    } else if e.is_connect() {
+        io::ErrorKind::ConnectionAborted
+    } else {
+        io::ErrorKind::Other
+    };
+    io::Error::new(kind, e)
+}
+
+/// `response` if it succeeded, or else an I/O error naming `what`,
+/// transient for throttling and server errors.
+fn check(response: Response, what: &str) -> io::Result<Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let kind = match status {
+        StatusCode::NOT_FOUND => io::ErrorKind::NotFound,
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => io::ErrorKind::PermissionDenied,
+        StatusCode::REQUEST_TIMEOUT | StatusCode::TOO_MANY_REQUESTS => io::ErrorKind::ResourceBusy,
+        status if status.is_server_error() => io::ErrorKind::ResourceBusy,
+        _ => io::ErrorKind::Other,
+    };
+    Err(io::Error::new(kind, format!("{what}: {status}")))
+}
+
+/// The body of `response` as an object's contents.
+fn object_reader(response: Response) -> ObjectReader {
+    Box::pin(StreamReader::new(
+        response.bytes_stream().map_err(request_error),
+    ))
+}
+
+/// Writes `chunks` to a temporary file, returning it with its size.
+async fn spool(
+    mut chunks: BoxStream<'_, io::Result<Bytes>>,
+) -> io::Result<(tempfile::TempPath, u64)> {
+    let path = tempfile::NamedTempFile::new()?.into_temp_path();
+    let mut file = tokio::fs::File::create(&path).await?;
+    let mut size = 0;
+    while let Some(chunk) = chunks.next().await {
+        let chunk = chunk?;
+        size += chunk.len() as u64;
+        file.write_all(&chunk).await?;
+    }
+    file.flush().await?;
+    Ok((path, size))
+}
+
+/// The file at `path` as a request body, with its size.
+async fn file_body(path: &Path) -> io::Result<(Body, u64)> {
+    let file = tokio::fs::File::open(path).await?;
+    let size = file.metadata().await?.len();
+    Ok((Body::wrap_stream(ReaderStream::new(file)), size))
+}
+
+/// `key` under `prefix`, as the store names it.
+fn prefixed(prefix: &str, key: &str) -> String {
+    match prefix {
+        "" => key.to_string(),
+        prefix => format!("{prefix}/{key}"),
+    }
+}
+
+/// Keeps objects in a Google Cloud Storage bucket, under a prefix. Requests
+/// carry a fixed OAuth access token if given, or else one from the
+/// metadata server of the Compute Engine, GKE or Cloud Run instance the
+/// index runs on, renewed as it expires.
+pub struct GcsBackend {
+    client: Client,
+    endpoint: String,
+    bucket: String,
+    prefix: String,
+    token: Option<String>,
+    /// A token from the metadata server, with when to renew it.
+    fetched: Mutex<Option<(String, Instant)>>,
+}
+
+impl fmt::Debug for GcsBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GcsBackend")
+            .field("bucket", &self.bucket)
+            .field("prefix", &self.prefix)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Deserialize)]
+struct GcsToken {
+    access_token: String,
+    expires_in: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GcsListing {
+    #[serde(default)]
+    items: Vec<GcsObject>,
+    next_page_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct GcsObject {
+    name: String,
+    /// A decimal string, as the JSON API gives 64-bit numbers.
+    size: String,
+    updated: DateTime<Utc>,
+}
+
+impl GcsBackend {
+    pub fn new(bucket: &str, prefix: &str) -> Self {
+        Self {
+            client: Client::new(),
+            endpoint: GCS_ENDPOINT.to_string(),
+            bucket: bucket.to_string(),
+            prefix: prefix.trim_matches('/').to_string(),
+            token: None,
+            fetched: Mutex::new(None),
+        }
+    }
+
+    /// Authenticates with `token` rather than asking the metadata server.
+    pub fn with_token(mut self, token: String) -> Self {
+        self.token = Some(token);
+        self
+    }
+
+    /// Sends requests to `endpoint` instead of Google's, such as an
+    /// emulator.
+    pub fn with_endpoint(mut self, endpoint: &str) -> Self {
+        self.endpoint = endpoint.trim_end_matches('/').to_string();
+        self
+    }
+
+    async fn token(&self) -> io::Result<String> {
+        if let Some(token) = &self.token {
+            return Ok(token.clone());
+        }
+        let mut fetched = self.fetched.lock().await;
+        if let Some((token, renew)) = &*fetched {
+            if Instant::now() < *renew {
+                return Ok(token.clone());
+            }
+        }
+        let response = self
+            .client
+            .get(GCS_METADATA_TOKEN)
+            .header("Metadata-Flavor", "Google")
+            .send()
+            .await
+            .map_err(request_error)?;
+        let token: GcsToken = check(response, "metadata server token")?
+            .json()
+            .await
+            .map_err(request_error)?;
+        let renew =
+            Instant::now() + Duration::from_secs(token.expires_in).saturating_sub(TOKEN_MARGIN);
+        *fetched = Some((token.access_token.clone(), renew));
+        Ok(token.access_token)
+    }
+
+    fn object_url(&self, key: &str) -> String {
+        format!(
+            "{}/storage/v1/b/{}/o/{}",
+            self.endpoint,
+            self.bucket,
+            utf8_percent_encode(&prefixed(&self.prefix, key), UNRESERVED)
+        )
+    }
+
+    async fn send(&self, request: reqwest::RequestBuilder) -> io::Result<Response> {
+        request
+            .bearer_auth(self.token().await?)
+            .send()
+            .await
+            .map_err(request_error)
+    }
+
+    async fn upload(&self, key: &str, path: &Path) -> io::Result<u64> {
+        let (body, size) = file_body(path).await?;
+        let url = format!(
+            "{}/upload/storage/v1/b/{}/o?uploadType=media&name={}",
+            self.endpoint,
+            self.bucket,
+            utf8_percent_encode(&prefixed(&self.prefix, key), UNRESERVED)
+        );
+        let request = self
+            .client
+            .post(url)
+            .header(header::CONTENT_TYPE, "application/octet-stream")
+            .header(header::CONTENT_LENGTH, size)
+            .body(body);
+        check(self.send(request).await?, key)?;
+        Ok(size)
+    }
+}
+
+#[async_trait]
+impl StorageBackend for GcsBackend {
+    async fn store(&self, key: &str, chunks: BoxStream<'_, io::Result<Bytes>>) -> io::Result<u64> {
+        let (path, _) = spool(chunks).await?;
+        self.upload(key, &path).await
+    }
+
+    async fn open(&self, key: &str) -> io::Result<Option<(ObjectReader, u64)>> {
+        let request = self
+            .client
+            .get(self.object_url(key))
+            .query(&[("alt", "media")]);
+        let response = self.send(request).await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let response = check(response, key)?;
+        let size = response.content_length().unwrap_or_default();
+        Ok(Some((object_reader(response), size)))
+    }
+
+    async fn delete(&self, key: &str) -> io::Result<()> {
+        let response = self.send(self.client.delete(self.object_url(key))).await?;
+        if response.status() != StatusCode::NOT_FOUND {
+            check(response, key)?;
+        }
+        Ok(())
+    }
+
+    async fn exists(&self, key: &str) -> io::Result<bool> {
+        let response = self.send(self.client.get(self.object_url(key))).await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(false);
+        }
+        check(response, key)?;
+        Ok(true)
+    }
+
+    async fn list(&self, prefix: &str) -> io::Result<Vec<StoredObject>> {
+        let base = prefixed(&self.prefix, "");
+        let url = format!("{}/storage/v1/b/{}/o", self.endpoint, self.bucket);
+        let mut found = Vec::new();
+        let mut page: Option<String> = None;
+        loop {
+            let mut request = self.client.get(&url).query(&[
+                ("prefix", format!("{base}{prefix}")),
+                ("fields", "items(name,size,updated),nextPageToken".into()),
+            ]);
+            if let Some(page) = &page {
+                request = request.query(&[("pageToken", page)]);
+            }
+            let listing: GcsListing = check(self.send(request).await?, "object listing")?
+                .json()
+                .await
+                .map_err(request_error)?;
+            for object in listing.items {
+                let Some(key) = object.name.strip_prefix(&base) else {
+                    continue;
+                };
+                found.push(StoredObject {
+                    key: key.to_string(),
+                    size: object.size.parse().map_err(io::Error::other)?,
+                    modified: object.updated,
+                });
+            }
+            match listing.next_page_token {
+                Some(next) => page = Some(next),
+                None => break,
+            }
+        }
+        found.sort_by(|a, b| a.key.cmp(&b.key));
+        Ok(found)
+    }
+
+    async fn store_file(&self, key: &str, path: &Path, _sha256: &str) -> io::Result<u64> {
+        self.upload(key, path).await
+    }
+}
+
+/// Keeps objects as block blobs in an Azure Storage container, under a
+/// prefix, authorized by a shared access signature. A blob is uploaded in
+/// one request, so objects are limited to 5000 MiB.
+pub struct AzureBackend {
+    client: Client,
+    /// The account's blob service, e.g.
+    /// `https://<account>.blob.core.windows.net`.
+    endpoint: String,
+    container: String,
+    prefix: String,
+    /// The SAS token, as the query string it is given as.
+    sas: String,
+}
+
+impl fmt::Debug for AzureBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AzureBackend")
+            .field("endpoint", &self.endpoint)
+            .field("container", &self.container)
+            .field("prefix", &self.prefix)
+            .finish_non_exhaustive()
+    }
+}
+
+impl AzureBackend {
+    pub fn new(account: &str, container: &str, prefix: &str, sas: &str) -> Self {
+        Self {
+            client: Client::new(),
+            endpoint: format!("https://{account}.blob.core.windows.net"),
+            container: container.to_string(),
+            prefix: prefix.trim_matches('/').to_string(),
+            sas: sas.trim_start_matches('?').to_string(),
+        }
+    }
+
+    /// Sends requests to `endpoint`, the account's blob service, instead,
+    /// such as an emulator's `http://127.0.0.1:10000/<account>`.
+    pub fn with_endpoint(mut self, endpoint: &str) -> Self {
+        self.endpoint = endpoint.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// The URL of `path` in the container, with `query` and the SAS token.
+    fn url(&self, path: &str, query: &str) -> String {
+        let query = [query, &self.sas]
+            .into_iter()
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<_>>()
+            .join("&");
+        let path = utf8_percent_encode(path, PATH);
+        match path.to_string().as_str() {
+            "" => format!("{}/{}?{query}", self.endpoint, self.container),
+            path => format!("{}/{}/{path}?{query}", self.endpoint, self.container),
+        }
+    }
+
+    fn blob_url(&self, key: &str) -> String {
+        self.url(&prefixed(&self.prefix, key), "")
+    }
+
+    async fn send(&self, request: reqwest::RequestBuilder) -> io::Result<Response> {
+        request
+            .header("x-ms-version", AZURE_VERSION)
+            .send()
+            .await
+            .map_err(request_error)
+    }
+
+    async fn upload(&self, key: &str, path: &Path) -> io::Result<u64> {
+        let (body, size) = file_body(path).await?;
+        let request = self
+            .client
+            .put(self.blob_url(key))
+            .header("x-ms-blob-type", "BlockBlob")
+            .header(header::CONTENT_TYPE, "application/octet-stream")
+            .header(header::CONTENT_LENGTH, size)
+            .body(body);
+        check(self.send(request).await?, key)?;
+        Ok(size)
+    }
+}
+
+/// The text of each `<tag>` element in `xml`, in order.
+fn elements<'a>(xml: &'a str, tag: &str) -> Vec<&'a str> {
+    let (open, close) = (format!("<{tag}>"), format!("</{tag}>"));
+    let mut found = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find(&open) {
+        rest = &rest[start + open.len()..];
+        let Some(end) = rest.find(&close) else {
+            break;
+        };
+        found.push(&rest[..end]);
+        rest = &rest[end + close.len()..];
+    }
+    found
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[async_trait]
+impl StorageBackend for AzureBackend {
+    async fn store(&self, key: &str, chunks: BoxStream<'_, io::Result<Bytes>>) -> io::Result<u64> {
+        let (path, _) = spool(chunks).await?;
+        self.upload(key, &path).await
+    }
+
+    async fn open(&self, key: &str) -> io::Result<Option<(ObjectReader, u64)>> {
+        let response = self.send(self.client.get(self.blob_url(key))).await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let response = check(response, key)?;
+        let size = response.content_length().unwrap_or_default();
+        Ok(Some((object_reader(response), size)))
+    }
+
+    async fn delete(&self, key: &str) -> io::Result<()> {
+        let response = self.send(self.client.delete(self.blob_url(key))).await?;
+        if response.status() != StatusCode::NOT_FOUND {
+            check(response, key)?;
+        }
+        Ok(())
+    }
+
+    async fn exists(&self, key: &str) -> io::Result<bool> {
+        let response = self.send(self.client.head(self.blob_url(key))).await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(false);
+        }
+        check(response, key)?;
+        Ok(true)
+    }
+
+    async fn list(&self, prefix: &str) -> io::Result<Vec<StoredObject>> {
+        let base = prefixed(&self.prefix, "");
+        let mut found = Vec::new();
+        let mut marker = String::new();
+        loop {
+            let mut query = format!(
+                "restype=container&comp=list&prefix={}",
+                utf8_percent_encode(&format!("{base}{prefix}"), UNRESERVED)
+            );
+            if !marker.is_empty() {
+                query.push_str(&format!(
+                    "&marker={}",
+                    utf8_percent_encode(&marker, UNRESERVED)
+                ));
+            }
+            let response = self.send(self.client.get(self.url("", &query))).await?;
+            let listing = check(response, "blob listing")?
+                .text()
+                .await
+                .map_err(request_error)?;
+            for blob in elements(&listing, "Blob") {
+                let (Some(name), Some(size), Some(modified)) = (
+                    elements(blob, "Name").first().map(|name| unescape(name)),
+                    elements(blob, "Content-Length").first().copied(),
+                    elements(blob, "Last-Modified").first().copied(),
+                ) else {
+                    continue;
+                };
+                let Some(key) = name.strip_prefix(&base) else {
+                    continue;
+                };
+                found.push(StoredObject {
+                    key: key.to_string(),
+                    size: size.parse().map_err(io::Error::other)?,
+                    modified: DateTime::parse_from_rfc2822(modified)
+                        .map_err(io::Error::other)?
+                        .into(),
+                });
+            }
+            match elements(&listing, "NextMarker").first() {
+                Some(next) if !next.is_empty() => marker = unescape(next),
+                _ => break,
+            }
+        }
+        found.sort_by(|a, b| a.key.cmp(&b.key));
+        Ok(found)
+    }
+
+    async fn store_file(&self, key: &str, path: &Path, _sha256: &str) -> io::Result<u64> {
+        self.upload(key, path).await
+    }
+}
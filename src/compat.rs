@@ -0,0 +1,311 @@
+//! Matching wheel tags against a target interpreter and platform, used to
+//! serve views containing only the files installable in one environment.
+
+use serde::Deserialize;
+
+use crate::{AppError, WheelTags};
+
+/// Query parameters accepted by the filtered project views, for example
+/// `?python=3.11&platform=manylinux_2_28_x86_64`.
+#[derive(Debug, Default, Deserialize)]
+pub struct CompatibilityQuery {
+    pub python: Option<String>,
+    pub platform: Option<String>,
+}
+
+/// A CPython interpreter version and platform tag that files must support.
+#[derive(Debug, Clone, Default)]
+pub struct TargetEnvironment {
+    python: Option<(u32, u32)>,
+    platform: Option<String>,
+}
+
+impl TargetEnvironment {
+    pub fn from_query(query: &CompatibilityQuery) -> Result<Self, AppError> {
+        let python = query
+            .python
+            .as_deref()
+            .map(|version| {
+                version
+                    .split_once('.')
+                    .and_then(|(major, minor)| Some((major.parse().ok()?, minor.parse().ok()?)))
+                    .ok_or_else(|| {
+                        AppError::InvalidFormat(format!("Invalid python version: {version}"))
+                    })
+            })
+            .transpose()?;
+
+        Ok(Self {
+            python,
+            platform: query.platform.clone(),
+        })
+    }
+
+    /// Whether a file may be installed in this environment. Files that are
+    /// not wheels carry no tags and are always considered compatible.
+    pub fn accepts(&self, filename: &str) -> bool {
+        let Some(tags) = WheelTags::from_filename(filename) else {
+            return true;
+        };
+        let python_ok = self.python.is_none_or(|target| {
+            tags.python
+                .iter()
+                .any(|python| python_compatible(python, &tags.abi, target))
+        });
+        let platform_ok = self.platform.as_deref().is_none_or(|target| {
+            tags.platform
+                .iter()
+                .any(|platform| platform_compatible(platform, target))
+        });
+        python_ok && platform_ok
+    }
+}
+
+fn python_compatible(tag: &str, abis: &[String], (major, minor): (u32, u32)) -> bool {
+    if tag.len() < 3 || !tag.is_char_boundary(2) {
+        return false;
+    }
+    let (implementation, digits) = tag.split_at(2);
+    let Some(tag_major) = digits.get(..1).and_then(|d| d.parse::<u32>().ok()) else {
+        return false;
+    };
+    if tag_major != major {
+        return false;
+    }
+    let tag_minor = match digits.get(1..).filter(|rest| !rest.is_empty()) {
+        Some(rest) => match rest.parse::<u32>() {
+            Ok(minor) => Some(minor),
+            Err(_) => return false,
+        },
+        None => None,
+    };
+
+    match (implementation, tag_minor) {
+        (_, None) => implementation == "py" || implementation == "cp",
+        // Generic tags such as `py38` are supported by every later minor.
+        ("py", Some(tag_minor)) => tag_minor <= minor,
+        ("cp", Some(tag_minor)) if abis.iter().any(|abi| abi == "abi3") => tag_minor <= minor,
+        ("cp", Some(tag_minor)) => tag_minor == minor,
+        _ => false,
+    }
+}
+
+fn platform_compatible(tag: &str, target: &str) -> bool {
+    if tag == "any" || tag == target {
+        return true;
+    }
+    match (glibc_platform(tag), glibc_platform(target)) {
+        (Some((family, version, arch)), Some((target_family, target_version, target_arch))) => {
+            family == target_family && arch == target_arch && version <= target_version
+        }
+        _ => match (macos_platform(tag), macos_platform(target)) {
+            (Some((version, arch)), Some((target_version, target_arch))) => {
+                version <= target_version && macos_arch_compatible(arch, target_arch)
+            }
+            _ => false,
+        },
+    }
+}
+
+/// Splits `manylinux_2_17_x86_64` / `musllinux_1_2_aarch64` (and the legacy
+/// `manylinux1`, `manylinux2010`, `manylinux2014` aliases) into family,
+/// libc version, and architecture.
+fn glibc_platform(tag: &str) -> Option<(&str, (u32, u32), &str)> {
+    for (alias, version) in [
+        ("manylinux1_", (2, 5)),
+        ("manylinux2010_", (2, 12)),
+        ("manylinux2014_", (2, 17)),
+    ] {
+        if let Some(arch) = tag.strip_prefix(alias) {
+            return Some(("manylinux", version, arch));
+        }
+    }
+    let (family, rest) = tag.split_once('_')?;
+    if family != "manylinux" && family != "musllinux" {
+        return None;
+    }
+    let mut parts = rest.splitn(3, '_');
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    Some((family, (major, minor), parts.next()?))
+}
+
+fn macos_platform(tag: &str) -> Option<((u32, u32), &str)> {
+    let mut parts = tag.strip_prefix("macosx_")?.splitn(3, '_');
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    Some(((major, minor), parts.next()?))
+}
+
+fn macos_arch_compatible(arch: &str, target: &str) -> bool {
+    arch == target || (arch == "universal2" && (target == "x86_64" || target == "arm64"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(python: Option<&str>, platform: Option<&str>) -> TargetEnvironment {
+        TargetEnvironment::from_query(&CompatibilityQuery {
+            python: python.map(str::to_string),
+            platform: platform.map(str::to_string),
+        })
+        .unwrap()
+    }
+
+    /// Checks each `(filename, accepted)` pair against `environment`.
+    fn assert_accepts(environment: &TargetEnvironment, cases: &[(&str, bool)]) {
+        for &(filename, accepted) in cases {
+            assert_eq!(
+                environment.accepts(filename),
+                accepted,
+                "{filename} in {environment:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn pure_python_wheels_and_sdists_fit_everywhere() {
+        for environment in [
+            target(None, None),
+            target(Some("3.12"), Some("manylinux_2_28_x86_64")),
+            target(Some("3.8"), Some("macosx_11_0_arm64")),
+            target(Some("3.10"), Some("win_amd64")),
+        ] {
+            assert_accepts(
+                &environment,
+                &[
+                    ("demo-1.0-py3-none-any.whl", true),
+                    ("demo-1.0-py2.py3-none-any.whl", true),
+                    ("demo-1.0-1-py3-none-any.whl", true),
+                    ("demo-1.0.tar.gz", true),
+                ],
+            );
+        }
+        assert_accepts(
+            &target(Some("3.12"), None),
+            &[
+                ("demo-1.0-py2-none-any.whl", false),
+                ("demo-1.0-py38-none-any.whl", true),
+                ("demo-1.0-py313-none-any.whl", false),
+            ],
+        );
+    }
+
+    #[test]
+    fn cpython_wheels_fit_their_minor_version_unless_abi3() {
+        assert_accepts(
+            &target(Some("3.11"), None),
+            &[
+                ("demo-1.0-cp311-cp311-manylinux_2_17_x86_64.whl", true),
+                ("demo-1.0-cp310-cp310-manylinux_2_17_x86_64.whl", false),
+                ("demo-1.0-cp312-cp312-manylinux_2_17_x86_64.whl", false),
+                ("demo-1.0-cp38-abi3-manylinux_2_17_x86_64.whl", true),
+                ("demo-1.0-cp311-abi3-manylinux_2_17_x86_64.whl", true),
+                ("demo-1.0-cp312-abi3-manylinux_2_17_x86_64.whl", false),
+                ("demo-1.0-cp27-cp27mu-manylinux1_x86_64.whl", false),
+                (
+                    "demo-1.0-pp310-pypy310_pp73-manylinux_2_17_x86_64.whl",
+                    false,
+                ),
+                ("demo-1.0-cp3-none-any.whl", true),
+            ],
+        );
+    }
+
+    #[test]
+    fn manylinux_wheels_fit_the_same_or_newer_glibc() {
+        assert_accepts(
+            &target(None, Some("manylinux_2_28_x86_64")),
+            &[
+                ("demo-1.0-cp311-cp311-manylinux_2_28_x86_64.whl", true),
+                ("demo-1.0-cp311-cp311-manylinux_2_17_x86_64.whl", true),
+                ("demo-1.0-cp311-cp311-manylinux2014_x86_64.whl", true),
+                ("demo-1.0-cp311-cp311-manylinux2010_x86_64.whl", true),
+                ("demo-1.0-cp311-cp311-manylinux1_x86_64.whl", true),
+                ("demo-1.0-cp311-cp311-manylinux_2_31_x86_64.whl", false),
+                ("demo-1.0-cp311-cp311-manylinux_2_17_aarch64.whl", false),
+                ("demo-1.0-cp311-cp311-musllinux_1_1_x86_64.whl", false),
+                (
+                    "demo-1.0-cp311-cp311-manylinux_2_17_aarch64.manylinux_2_17_x86_64.whl",
+                    true,
+                ),
+                ("demo-1.0-cp311-cp311-win_amd64.whl", false),
+            ],
+        );
+        assert_accepts(
+            &target(None, Some("manylinux2014_x86_64")),
+            &[
+                ("demo-1.0-cp311-cp311-manylinux_2_17_x86_64.whl", true),
+                ("demo-1.0-cp311-cp311-manylinux_2_28_x86_64.whl", false),
+            ],
+        );
+    }
+
+    #[test]
+    fn musllinux_wheels_fit_the_same_or_newer_musl() {
+        assert_accepts(
+            &target(None, Some("musllinux_1_2_aarch64")),
+            &[
+                ("demo-1.0-cp311-cp311-musllinux_1_2_aarch64.whl", true),
+                ("demo-1.0-cp311-cp311-musllinux_1_1_aarch64.whl", true),
+                ("demo-1.0-cp311-cp311-musllinux_1_3_aarch64.whl", false),
+                ("demo-1.0-cp311-cp311-musllinux_1_1_x86_64.whl", false),
+                ("demo-1.0-cp311-cp311-manylinux_2_17_aarch64.whl", false),
+            ],
+        );
+    }
+
+    #[test]
+    fn macos_wheels_fit_the_same_or_newer_release_and_their_arch() {
+        assert_accepts(
+            &target(None, Some("macosx_12_0_arm64")),
+            &[
+                ("demo-1.0-cp311-cp311-macosx_12_0_arm64.whl", true),
+                ("demo-1.0-cp311-cp311-macosx_11_0_arm64.whl", true),
+                ("demo-1.0-cp311-cp311-macosx_10_9_universal2.whl", true),
+                ("demo-1.0-cp311-cp311-macosx_13_0_arm64.whl", false),
+                ("demo-1.0-cp311-cp311-macosx_12_1_arm64.whl", false),
+                ("demo-1.0-cp311-cp311-macosx_10_9_x86_64.whl", false),
+            ],
+        );
+        assert_accepts(
+            &target(None, Some("macosx_10_15_x86_64")),
+            &[
+                ("demo-1.0-cp311-cp311-macosx_10_9_x86_64.whl", true),
+                ("demo-1.0-cp311-cp311-macosx_10_9_universal2.whl", true),
+                ("demo-1.0-cp311-cp311-macosx_11_0_x86_64.whl", false),
+                ("demo-1.0-cp311-cp311-macosx_10_9_arm64.whl", false),
+            ],
+        );
+    }
+
+    #[test]
+    fn python_and_platform_must_both_fit() {
+        assert_accepts(
+            &target(Some("3.11"), Some("manylinux_2_28_x86_64")),
+            &[
+                ("demo-1.0-cp38-abi3-manylinux_2_17_x86_64.whl", true),
+                ("demo-1.0-cp38-abi3-macosx_11_0_arm64.whl", false),
+                ("demo-1.0-cp310-cp310-manylinux_2_17_x86_64.whl", false),
+            ],
+        );
+    }
+
+    #[test]
+    fn python_versions_must_be_major_and_minor() {
+        for python in ["3", "three.eleven", "3.x", ""] {
+            let query = CompatibilityQuery {
+                python: Some(python.to_string()),
+                platform: None,
+            };
+            assert!(
+                matches!(
+                    TargetEnvironment::from_query(&query),
+                    Err(AppError::InvalidFormat(_))
+                ),
+                "{python:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use std::time::Duration;
+
+use crate::{
+    auth::Credentials,
+    bundle::BundleLimits,
+    capture::Capture,
+    dependencies::DependencyCheck,
+    ingest::IngestSource,
+    oidc::TrustedPublishing,
+    server::{ConnectionSettings, TlsConfig},
+    signing::ServerKey,
+};
+
+/// Server settings shared by every handler.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Path under which a reverse proxy exposes the index, used in every
+    /// generated link.
+    pub path_prefix: String,
+    /// Scheme and host clients reach the index at, e.g.
+    /// `https://pkg.example.com`, making every generated link absolute
+    /// regardless of the address the server is bound to.
+    pub base_url: Option<String>,
+    /// How long upload results are remembered per `Idempotency-Key`.
+    pub idempotency_window: Duration,
+    /// Forge repositories whose release assets are ingested automatically.
+    pub ingest_sources: Vec<IngestSource>,
+    pub ingest_interval: Duration,
+    pub github_token: Option<String>,
+    pub gitlab_token: Option<String>,
+    pub gitlab_url: String,
+    pub connections: ConnectionSettings,
+    /// Whether other processes may serve from the same data directory.
+    pub shared_storage: bool,
+    /// How often a shared index checks for writes by other processes.
+    pub change_poll_interval: Duration,
+    /// Key signing checksum manifests and upload receipts, when configured.
+    pub signing_key: Option<ServerKey>,
+    /// Where read requests are recorded, when capturing.
+    pub capture: Option<Capture>,
+    /// Whether uploads are checked for dependencies that cannot be found.
+    pub dependencies: DependencyCheck,
+    /// Whether uploads and other changes need an API token, or a write
+    /// user's password.
+    pub require_token: bool,
+    /// Users allowed to read, when reads need credentials.
+    pub read_credentials: Option<Credentials>,
+    /// Users allowed to upload and change the index, besides token holders.
+    pub write_credentials: Option<Credentials>,
+    /// CI workflows allowed to trade OIDC tokens for upload tokens.
+    pub trusted_publishing: TrustedPublishing,
+    /// Largest distribution file accepted, in bytes; `None` for no limit.
+    pub max_upload_size: Option<u64>,
+    /// Most bytes an uploaded docs archive may unpack to.
+    pub max_docs_size: u64,
+    /// Most a bundle built on request may hold.
+    pub bundle_limits: BundleLimits,
+    /// Whether direct downloads of yanked files are refused, unless forced
+    /// with `?force=true` by someone who may yank them, rather than left
+    /// to resolvers to skip.
+    pub refuse_yanked_downloads: bool,
+}
+
+impl Config {
+    /// Whether changes need credentials: when tokens are required, when
+    /// any users are configured, read-only ones included, or when clients
+    /// must present a certificate.
+    pub fn guards_writes(&self) -> bool {
+        self.require_token
+            || self.read_credentials.is_some()
+            || self.write_credentials.is_some()
+            || self
+                .connections
+                .tls
+                .as_ref()
+                .is_some_and(TlsConfig::verifies_clients)
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            path_prefix: String::new(),
+            base_url: None,
+            idempotency_window: Duration::from_secs(24 * 60 * 60),
+            ingest_sources: Vec::new(),
+            ingest_interval: Duration::from_secs(300),
+            github_token: None,
+            gitlab_token: None,
+            gitlab_url: "https://gitlab.com".to_string(),
+            connections: ConnectionSettings::default(),
+            shared_storage: false,
+            change_poll_interval: Duration::from_secs(2),
+            signing_key: None,
+            capture: None,
+            dependencies: DependencyCheck::default(),
+            require_token: false,
+            read_credentials: None,
+            write_credentials: None,
+            trusted_publishing: TrustedPublishing::default(),
+            max_upload_size: None,
+            max_docs_size: 1024 * 1024 * 1024,
+            bundle_limits: BundleLimits {
+                max_projects: Some(100),
+                max_bytes: Some(1024 * 1024 * 1024),
+            },
+            refuse_yanked_downloads: false,
+        }
+    }
+}
@@ -0,0 +1,183 @@
+//! Publish-time checking that a wheel's `Requires-Dist` projects can be
+//! found, on this index or its configured upstreams, so releases that
+//! depend on never-published private packages are caught at upload.
+
+use std::{path::Path, time::Duration};
+
+use clap::ValueEnum;
+use reqwest::{Client, StatusCode};
+use tracing::warn;
+
+use crate::{inspect, AppError, DistFilename, PackageIndex, PackageName};
+
+/// What happens to an upload with dependencies that cannot be found.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum DependencyPolicy {
+    /// Dependencies are not checked.
+    #[default]
+    Off,
+    /// The upload is accepted and the missing projects are logged.
+    Warn,
+    /// The upload is refused.
+    Reject,
+}
+
+/// How long an upstream has to accept a connection, and to answer.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Clone)]
+pub struct DependencyCheck {
+    pub policy: DependencyPolicy,
+    /// Simple index base URLs, e.g. `https://pypi.org/simple`, consulted
+    /// for projects this index does not host.
+    pub upstreams: Vec<String>,
+    client: Client,
+}
+
+impl Default for DependencyCheck {
+    fn default() -> Self {
+        Self::new(DependencyPolicy::default(), Vec::new())
+    }
+}
+
+/// Whether a dependency could be found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Lookup {
+    Found,
+    Missing,
+    /// No upstream had it, and some could not be asked.
+    Unknown,
+}
+
+impl DependencyCheck {
+    pub fn new(policy: DependencyPolicy, upstreams: Vec<String>) -> Self {
+        let client = Client::builder()
+            .connect_timeout(CONNECT_TIMEOUT)
+            .timeout(LOOKUP_TIMEOUT)
+            .build()
+            .expect("an HTTP client with default settings can be built");
+        Self {
+            policy,
+            upstreams,
+            client,
+        }
+    }
+
+    /// Checks the wheel `filename` of `name`, received at `path`, under the
+    /// policy, failing only when it rejects.
+    pub(crate) async fn check(
+        &self,
+        index: &PackageIndex,
+        name: &PackageName,
+        filename: &DistFilename,
+        path: &Path,
+    ) -> Result<(), AppError> {
+        // Sdists may compute their dependencies at build time.
+        if self.policy == DependencyPolicy::Off || !filename.as_str().ends_with(".whl") {
+            return Ok(());
+        }
+        let Some(metadata) = inspect::wheel_metadata(path.to_path_buf()).await? else {
+            return Ok(());
+        };
+        let metadata = String::from_utf8_lossy(&metadata);
+        let (mut missing, mut unknown) = (Vec::new(), Vec::new());
+        for requirement in inspect::metadata_values(&metadata, "Requires-Dist") {
+            let Some(dependency) = requirement_name(requirement) else {
+                continue;
+            };
+            if dependency == *name || missing.contains(&dependency) || unknown.contains(&dependency)
+            {
+                continue;
+            }
+            match self.lookup(index, &dependency).await {
+                Lookup::Found => {}
+                Lookup::Missing => missing.push(dependency),
+                Lookup::Unknown => unknown.push(dependency),
+            }
+        }
+        let list = |names: &[PackageName]| {
+            names
+                .iter()
+                .map(PackageName::as_str)
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let mut problems = Vec::new();
+        if !missing.is_empty() {
+            problems.push(format!(
+                "depends on projects not found on this index or its upstreams: {}",
+                list(&missing)
+            ));
+        }
+        if !unknown.is_empty() {
+            problems.push(format!(
+                "depends on projects that could not be checked, as upstreams did not answer: {}",
+                list(&unknown)
+            ));
+        }
+        if problems.is_empty() {
+            return Ok(());
+        }
+        match self.policy {
+            DependencyPolicy::Reject => Err(AppError::InvalidFormat(format!(
+                "{filename} {}",
+                problems.join("; it also ")
+            ))),
+            _ => {
+                for problem in problems {
+                    warn!("{} {}", filename, problem);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Whether `dependency` is hosted here or on an upstream. Upstreams
+    /// that time out or cannot be reached leave it unknown, unless another
+    /// has it.
+    async fn lookup(&self, index: &PackageIndex, dependency: &PackageName) -> Lookup {
+        let hosted = match index.project(dependency).await {
+            Ok(packages) => packages
+                .get(dependency.as_str())
+                .is_some_and(|p| p.renamed_to.is_some() || !p.releases.is_empty()),
+            Err(e) => {
+                warn!("Reading {} failed: {}", dependency, e);
+                return Lookup::Unknown;
+            }
+        };
+        if hosted {
+            return Lookup::Found;
+        }
+        let mut lookup = Lookup::Missing;
+        for upstream in &self.upstreams {
+            let url = format!("{}/{}/", upstream.trim_end_matches('/'), dependency);
+            match self.client.get(&url).send().await {
+                Ok(response) if response.status() == StatusCode::OK => return Lookup::Found,
+                Ok(_) => {}
+                Err(e) => {
+                    warn!("Checking {} failed: {}", url, e);
+                    lookup = Lookup::Unknown;
+                }
+            }
+        }
+        lookup
+    }
+}
+
+/// The project a PEP 508 requirement names, or `None` for requirements
+/// only needed by an extra, which installers skip unless it is asked for.
+fn requirement_name(requirement: &str) -> Option<PackageName> {
+    let (spec, marker) = match requirement.split_once(';') {
+        Some((spec, marker)) => (spec, marker),
+        None => (requirement, ""),
+    };
+    if marker.contains("extra") {
+        return None;
+    }
+    let spec = spec.trim_start();
+    let end = spec
+        .find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')))
+        .unwrap_or(spec.len());
+    PackageName::new(&spec[..end]).ok()
+}
@@ -0,0 +1,362 @@
+//! Comparison of two index states — the live index, a snapshot, or either
+//! of those on another instance — by the projects, files and digests they
+//! serve, for checking that a replica or migration matches its source.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    str::FromStr,
+};
+
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+
+use crate::{AppError, DistFilename, Package, PackageIndex, PackageName, SnapshotName, Version};
+
+/// What an index serves, reduced to what must match between replicas.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    pub projects: BTreeMap<PackageName, ProjectManifest>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ProjectManifest {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub renamed_to: Option<PackageName>,
+    pub files: BTreeMap<DistFilename, FileManifest>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FileManifest {
+    pub version: Version,
+    /// Unknown for files registered before digests were recorded, unless
+    /// the manifest was built by rehashing.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+}
+
+impl Manifest {
+    /// The manifest of `packages`, whose files are in `index`'s storage.
+    /// With `rehash`, digests come from the stored bytes rather than the
+    /// ones recorded on arrival.
+    pub async fn build<'a>(
+        index: &PackageIndex,
+        packages: impl IntoIterator<Item = &'a Package>,
+        rehash: bool,
+    ) -> Result<Self, AppError> {
+        let mut manifest = Self::default();
+        for package in packages {
+            let mut project = ProjectManifest {
+                renamed_to: package.renamed_to.clone(),
+                files: BTreeMap::new(),
+            };
+            for release in &package.releases {
+                let sha256 = if rehash {
+                    Some(
+                        index
+                            .storage
+                            .sha256(&package.name, &release.filename)
+                            .await?,
+                    )
+                } else {
+                    release.sha256().map(str::to_string)
+                };
+                project.files.insert(
+                    release.filename.clone(),
+                    FileManifest {
+                        version: release.version.clone(),
+                        sha256,
+                    },
+                );
+            }
+            manifest.projects.insert(package.name.clone(), project);
+        }
+        Ok(manifest)
+    }
+
+    /// The manifest of `side`. Remote sides are fetched from the instance's
+    /// `/api/v1/manifest`, with its recorded digests whatever `rehash` says.
+    pub async fn of(index: &PackageIndex, side: &Side, rehash: bool) -> Result<Self, AppError> {
+        match side {
+            Side::Live => {
+                let packages = index.catalog().await?.clone();
+                Self::build(index, packages.values(), rehash).await
+            }
+            Side::Snapshot(name) => {
+                let snapshot = index.snapshot(name).await?;
+                Self::build(index, snapshot.packages.values(), rehash).await
+            }
+            Side::Remote { url, snapshot } => {
+                // Rehashing there takes an admin token, which instances are
+                // not given, so they report the digests they recorded.
+                let query: Vec<_> = snapshot
+                    .iter()
+                    .map(|snapshot| ("snapshot", snapshot.to_string()))
+                    .collect();
+                let manifest = Client::new()
+                    .get(format!("{}/api/v1/manifest", url.trim_end_matches('/')))
+                    .query(&query)
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json()
+                    .await?;
+                Ok(manifest)
+            }
+        }
+    }
+}
+
+/// One of the two states being compared.
+#[derive(Debug, Clone)]
+pub enum Side {
+    /// The current index.
+    Live,
+    Snapshot(SnapshotName),
+    /// Another instance, at its base URL, optionally one of its snapshots.
+    Remote {
+        url: String,
+        snapshot: Option<SnapshotName>,
+    },
+}
+
+impl Side {
+    pub fn is_remote(&self) -> bool {
+        matches!(self, Self::Remote { .. })
+    }
+}
+
+impl FromStr for Side {
+    type Err = AppError;
+
+    /// `live`, a snapshot name, or an instance URL whose fragment may name
+    /// one of its snapshots, e.g. `https://replica.example#2024-06`.
+    fn from_str(side: &str) -> Result<Self, Self::Err> {
+        if side.starts_with("http://") || side.starts_with("https://") {
+            let (url, snapshot) = match side.split_once('#') {
+                Some((url, snapshot)) => (url, Some(snapshot.parse()?)),
+                None => (side, None),
+            };
+            return Ok(Self::Remote {
+                url: url.to_string(),
+                snapshot,
+            });
+        }
+        if side == "live" {
+            return Ok(Self::Live);
+        }
+        Ok(Self::Snapshot(side.parse()?))
+    }
+}
+
+impl fmt::Display for Side {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Live => write!(f, "live"),
+            Self::Snapshot(name) => write!(f, "{name}"),
+            Self::Remote {
+                url,
+                snapshot: None,
+            } => write!(f, "{url}"),
+            Self::Remote {
+                url,
+                snapshot: Some(name),
+            } => write!(f, "{url}#{name}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DifferenceKind {
+    ProjectAdded,
+    ProjectRemoved,
+    /// A rename tombstone differs, including one side having none.
+    RenameChanged,
+    FileAdded,
+    FileRemoved,
+    VersionChanged,
+    /// The file's SHA-256 differs, or is known on only one side.
+    DigestChanged,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Difference {
+    pub kind: DifferenceKind,
+    pub project: PackageName,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filename: Option<DistFilename>,
+    /// The value on the first side, for changes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from: Option<String>,
+    /// The value on the second side, for changes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to: Option<String>,
+}
+
+/// Everything that differs between two manifests, in project and filename
+/// order. Files added or removed with their project are listed too.
+#[derive(Debug, Serialize)]
+pub struct IndexDiff {
+    pub from: String,
+    pub to: String,
+    /// Files compared on both sides.
+    pub files_compared: usize,
+    /// Compared files without a digest on either side, whose bytes could
+    /// not be checked; rehashing gives every file one.
+    pub unverified: usize,
+    pub differences: Vec<Difference>,
+}
+
+impl IndexDiff {
+    pub fn compare(from: impl ToString, to: impl ToString, a: &Manifest, b: &Manifest) -> Self {
+        let mut diff = Self {
+            from: from.to_string(),
+            to: to.to_string(),
+            files_compared: 0,
+            unverified: 0,
+            differences: Vec::new(),
+        };
+        let names: BTreeSet<_> = a.projects.keys().chain(b.projects.keys()).collect();
+        for name in names {
+            diff.compare_project(name, a.projects.get(name), b.projects.get(name));
+        }
+        diff
+    }
+
+    pub fn is_identical(&self) -> bool {
+        self.differences.is_empty()
+    }
+
+    fn push(
+        &mut self,
+        kind: DifferenceKind,
+        project: &PackageName,
+        filename: Option<&DistFilename>,
+        from: Option<String>,
+        to: Option<String>,
+    ) {
+        self.differences.push(Difference {
+            kind,
+            project: project.clone(),
+            filename: filename.cloned(),
+            from,
+            to,
+        });
+    }
+
+    fn compare_project(
+        &mut self,
+        name: &PackageName,
+        a: Option<&ProjectManifest>,
+        b: Option<&ProjectManifest>,
+    ) {
+        let empty = ProjectManifest::default();
+        match (a, b) {
+            (None, Some(_)) => self.push(DifferenceKind::ProjectAdded, name, None, None, None),
+            (Some(_), None) => self.push(DifferenceKind::ProjectRemoved, name, None, None, None),
+            (Some(a), Some(b)) if a.renamed_to != b.renamed_to => self.push(
+                DifferenceKind::RenameChanged,
+                name,
+                None,
+                a.renamed_to.as_ref().map(ToString::to_string),
+                b.renamed_to.as_ref().map(ToString::to_string),
+            ),
+            _ => {}
+        }
+        let (a, b) = (a.unwrap_or(&empty), b.unwrap_or(&empty));
+        let filenames: BTreeSet<_> = a.files.keys().chain(b.files.keys()).collect();
+        for filename in filenames {
+            match (a.files.get(filename), b.files.get(filename)) {
+                (None, Some(_)) => {
+                    self.push(DifferenceKind::FileAdded, name, Some(filename), None, None)
+                }
+                (Some(_), None) => self.push(
+                    DifferenceKind::FileRemoved,
+                    name,
+                    Some(filename),
+                    None,
+                    None,
+                ),
+                (Some(a), Some(b)) => {
+                    self.files_compared += 1;
+                    if a.version != b.version {
+                        self.push(
+                            DifferenceKind::VersionChanged,
+                            name,
+                            Some(filename),
+                            Some(a.version.to_string()),
+                            Some(b.version.to_string()),
+                        );
+                    }
+                    match (&a.sha256, &b.sha256) {
+                        (None, None) => self.unverified += 1,
+                        (x, y) if x != y => self.push(
+                            DifferenceKind::DigestChanged,
+                            name,
+                            Some(filename),
+                            x.clone(),
+                            y.clone(),
+                        ),
+                        _ => {}
+                    }
+                }
+                (None, None) => unreachable!("filenames come from one side or the other"),
+            }
+        }
+    }
+}
+
+impl fmt::Display for IndexDiff {
+    /// One line per difference: `+` for added, `-` for removed and `~` for
+    /// changed, then a summary line.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "--- {}\n+++ {}", self.from, self.to)?;
+        for d in &self.differences {
+            let path = match &d.filename {
+                Some(filename) => format!("{}/{}", d.project, filename),
+                None => d.project.to_string(),
+            };
+            let value = |v: &Option<String>| v.clone().unwrap_or_else(|| "(none)".to_string());
+            match d.kind {
+                DifferenceKind::ProjectAdded => writeln!(f, "+ project {path}")?,
+                DifferenceKind::ProjectRemoved => writeln!(f, "- project {path}")?,
+                DifferenceKind::FileAdded => writeln!(f, "+ {path}")?,
+                DifferenceKind::FileRemoved => writeln!(f, "- {path}")?,
+                DifferenceKind::RenameChanged => writeln!(
+                    f,
+                    "~ {path}: renamed to {} -> {}",
+                    value(&d.from),
+                    value(&d.to)
+                )?,
+                DifferenceKind::VersionChanged => writeln!(
+                    f,
+                    "~ {path}: version {} -> {}",
+                    value(&d.from),
+                    value(&d.to)
+                )?,
+                DifferenceKind::DigestChanged => {
+                    writeln!(f, "~ {path}: sha256 {} -> {}", value(&d.from), value(&d.to))?
+                }
+            }
+        }
+        if self.is_identical() {
+            write!(f, "identical: {} files compared", self.files_compared)?;
+        } else {
+            write!(
+                f,
+                "{} differences, {} files compared",
+                self.differences.len(),
+                self.files_compared
+            )?;
+        }
+        if self.unverified > 0 {
+            write!(
+                f,
+                ", {} without a recorded digest (use --rehash to check their bytes)",
+                self.unverified
+            )?;
+        }
+        writeln!(f)
+    }
+}
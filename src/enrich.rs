@@ -0,0 +1,115 @@
+//! Post-publish enrichment. Enrichers run in the background after a release
+//! is registered, so the upload path never waits on them, and each one's
+//! result is stored on the release under the enricher's name.
+
+use std::{path::PathBuf, sync::Arc};
+
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use tracing::warn;
+
+use crate::{AppError, DistFilename, PackageIndex, PackageName, Version};
+
+/// The release an enricher is asked to describe.
+#[derive(Debug, Clone)]
+pub struct EnrichmentContext {
+    pub name: PackageName,
+    pub version: Version,
+    pub filename: DistFilename,
+    /// The stored distribution file, or a copy of it, on disk.
+    pub path: PathBuf,
+}
+
+#[async_trait]
+pub trait Enricher: Send + Sync {
+    /// Key the result is stored under in `Release::enrichments`.
+    fn name(&self) -> &'static str;
+
+    async fn enrich(&self, context: &EnrichmentContext) -> Result<Value, AppError>;
+}
+
+#[derive(Clone, Default)]
+pub struct EnricherRegistry {
+    enrichers: Vec<Arc<dyn Enricher>>,
+}
+
+impl EnricherRegistry {
+    /// A registry with the built-in enrichers.
+    pub fn with_defaults() -> Self {
+        let mut registry = Self::default();
+        registry.register(SizeEnricher);
+        registry
+    }
+
+    pub fn register(&mut self, enricher: impl Enricher + 'static) {
+        self.enrichers.push(Arc::new(enricher));
+    }
+
+    pub(crate) fn spawn(
+        &self,
+        index: PackageIndex,
+        name: PackageName,
+        version: Version,
+        filename: DistFilename,
+    ) {
+        if self.enrichers.is_empty() {
+            return;
+        }
+        let enrichers = self.enrichers.clone();
+        index.tasks.clone().spawn(async move {
+            let file = match index.storage.local_file(&name, &filename).await {
+                Ok(file) => file,
+                Err(e) => {
+                    warn!("Enriching {} failed: {}", filename, e);
+                    return;
+                }
+            };
+            let context = EnrichmentContext {
+                path: file.path().to_path_buf(),
+                name,
+                version,
+                filename,
+            };
+            for enricher in enrichers {
+                let result = match enricher.enrich(&context).await {
+                    Ok(result) => result,
+                    Err(e) => {
+                        warn!(
+                            "Enricher {} failed for {}: {}",
+                            enricher.name(),
+                            context.filename,
+                            e
+                        );
+                        continue;
+                    }
+                };
+                if let Err(e) = index
+                    .record_enrichment(&context.name, &context.filename, enricher.name(), result)
+                    .await
+                {
+                    warn!(
+                        "Storing {} for {} failed: {}",
+                        enricher.name(),
+                        context.filename,
+                        e
+                    );
+                }
+            }
+        });
+    }
+}
+
+/// Records the stored size of the distribution in bytes.
+pub struct SizeEnricher;
+
+#[async_trait]
+impl Enricher for SizeEnricher {
+    fn name(&self) -> &'static str {
+        "size"
+    }
+
+    async fn enrich(&self, context: &EnrichmentContext) -> Result<Value, AppError> {
+        let metadata = tokio::fs::metadata(&context.path).await?;
+        Ok(json!({ "bytes": metadata.len() }))
+    }
+}
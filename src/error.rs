@@ -0,0 +1,122 @@
+use std::time::Duration;
+
+use axum::{
+    http::{header, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use serde_json::json;
+use thiserror::Error;
+use tracing::error;
+
+#[derive(Error, Debug)]
+pub enum AppError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Package not found: {0}")]
+    NotFound(String),
+    #[error("Conflict: {0}")]
+    Conflict(String),
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
+    #[error("Too many requests: {message}")]
+    TooManyRequests {
+        message: String,
+        retry_after: Duration,
+    },
+    #[error("Invalid package format: {0}")]
+    InvalidFormat(String),
+    #[error("Unprocessable request: {0}")]
+    Unprocessable(String),
+    #[error("Upload too large: {message}")]
+    PayloadTooLarge { message: String, limit: u64 },
+    #[error("Quota exceeded: {message}")]
+    QuotaExceeded {
+        message: String,
+        quota: u64,
+        used: u64,
+    },
+    #[error("Multipart error: {0}")]
+    Multipart(#[from] axum::extract::multipart::MultipartError),
+    #[error("Storage unavailable during {operation}")]
+    StorageUnavailable {
+        operation: String,
+        retry_after: Duration,
+    },
+    #[error("HTTP client error: {0}")]
+    Http(#[from] reqwest::Error),
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> axum::response::Response {
+        let status = match &self {
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::InvalidFormat(_) | AppError::Multipart(_) => StatusCode::BAD_REQUEST,
+            AppError::Conflict(_) => StatusCode::CONFLICT,
+            AppError::Unprocessable(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            AppError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AppError::Http(_) => StatusCode::BAD_GATEWAY,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        error!("Error: {}", self);
+        if let AppError::StorageUnavailable { retry_after, .. } = &self {
+            return (
+                StatusCode::SERVICE_UNAVAILABLE,
+                [(header::RETRY_AFTER, retry_after.as_secs().to_string())],
+                self.to_string(),
+            )
+                .into_response();
+        }
+        if let AppError::PayloadTooLarge { limit, .. } = &self {
+            return (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                Json(json!({
+                    "error": self.to_string(),
+                    "max_upload_size": limit,
+                })),
+            )
+                .into_response();
+        }
+        if let AppError::QuotaExceeded { quota, used, .. } = &self {
+            return (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                Json(json!({
+                    "error": self.to_string(),
+                    "quota": quota,
+                    "used": used,
+                })),
+            )
+                .into_response();
+        }
+        // Lets pip and twine know to prompt for, or send, credentials.
+        if let AppError::Unauthorized(_) = &self {
+            return (
+                status,
+                [(header::WWW_AUTHENTICATE, "Basic realm=\"pippy\"")],
+                self.to_string(),
+            )
+                .into_response();
+        }
+        if let AppError::TooManyRequests { retry_after, .. } = &self {
+            return (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(
+                    header::RETRY_AFTER,
+                    retry_after.as_secs().max(1).to_string(),
+                )],
+                self.to_string(),
+            )
+                .into_response();
+        }
+        // Publishing clients surface the body of 4xx responses to the user.
+        if status.is_client_error() {
+            return (status, self.to_string()).into_response();
+        }
+        status.into_response()
+    }
+}
@@ -0,0 +1,71 @@
+use crate::{AppError, PackageName, Version};
+
+/// Splits a wheel filename into its `(name, version)` components.
+pub fn parse_wheel_filename(filename: &str) -> Result<(PackageName, Version), AppError> {
+    let parts: Vec<&str> = filename.split('-').collect();
+    if parts.len() < 2 {
+        return Err(AppError::InvalidFormat(
+            "Invalid package filename format".into(),
+        ));
+    }
+
+    Ok((parts[0].parse()?, parts[1].parse()?))
+}
+
+/// Splits an sdist filename, `{name}-{version}.tar.gz`, into its
+/// components. Older sdists can have hyphens in the name, so the version
+/// starts after the last one.
+pub fn parse_sdist_filename(filename: &str) -> Result<(PackageName, Version), AppError> {
+    let (name, version) = filename
+        .strip_suffix(".tar.gz")
+        .and_then(|stem| stem.rsplit_once('-'))
+        .filter(|(name, version)| !name.is_empty() && !version.is_empty())
+        .ok_or_else(|| AppError::InvalidFormat("Invalid sdist filename format".into()))?;
+    Ok((name.parse()?, version.parse()?))
+}
+
+/// Whether a file is of a kind the index hosts: a wheel or an sdist.
+pub fn is_distribution(filename: &str) -> bool {
+    filename.ends_with(".whl") || filename.ends_with(".tar.gz")
+}
+
+/// Splits the filename of a wheel or sdist into `(name, version)`.
+pub fn parse_dist_filename(filename: &str) -> Result<(PackageName, Version), AppError> {
+    if filename.ends_with(".tar.gz") {
+        parse_sdist_filename(filename)
+    } else if filename.ends_with(".whl") {
+        parse_wheel_filename(filename)
+    } else {
+        Err(AppError::InvalidFormat(format!(
+            "{filename} is neither a wheel (.whl) nor an sdist (.tar.gz)"
+        )))
+    }
+}
+
+/// The compatibility tags encoded in a wheel filename, with compressed tag
+/// sets (`py2.py3`) already expanded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WheelTags {
+    pub python: Vec<String>,
+    pub abi: Vec<String>,
+    pub platform: Vec<String>,
+}
+
+impl WheelTags {
+    /// Parses `{name}-{version}(-{build})?-{python}-{abi}-{platform}.whl`.
+    pub fn from_filename(filename: &str) -> Option<Self> {
+        let stem = filename.strip_suffix(".whl")?;
+        let parts: Vec<&str> = stem.split('-').collect();
+        if parts.len() != 5 && parts.len() != 6 {
+            return None;
+        }
+        let expand = |tags: &str| tags.split('.').map(str::to_string).collect();
+        let tags = &parts[parts.len() - 3..];
+
+        Some(Self {
+            python: expand(tags[0]),
+            abi: expand(tags[1]),
+            platform: expand(tags[2]),
+        })
+    }
+}
@@ -0,0 +1,443 @@
+//! Cross-checking of the index against what the data directory actually
+//! holds, reporting each inconsistency with the fix that would resolve it
+//! and optionally applying the safe ones.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::{
+    filename::parse_dist_filename,
+    index::{Change, Operation},
+    AppError, DistFilename, Package, PackageIndex, PackageName, Release, Version,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProblemKind {
+    /// A release whose file is not in storage.
+    MissingFile,
+    /// A stored file no release refers to.
+    UntrackedFile,
+    /// A stored file whose digest differs from the one recorded on arrival.
+    DigestMismatch,
+    /// The same filename registered more than once in a project.
+    DuplicateFile,
+    /// A project whose recorded name differs from the key it is indexed by.
+    NameMismatch,
+    /// A docs version listed without its unpacked directory.
+    MissingDocs,
+    /// An unpacked docs directory the project does not list.
+    UntrackedDocs,
+    /// Leftovers of a write interrupted before its final rename.
+    PartialWrite,
+    /// A journal line that does not parse, or is out of order.
+    CorruptJournal,
+    /// A snapshot that cannot be read or refers to files no longer stored.
+    BrokenSnapshot,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Problem {
+    pub kind: ProblemKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub project: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filename: Option<String>,
+    pub detail: String,
+    /// What resolves the problem, whether or not `--repair` can apply it.
+    pub fix: String,
+    pub repaired: bool,
+}
+
+impl Problem {
+    fn new(kind: ProblemKind, detail: impl Into<String>, fix: impl Into<String>) -> Self {
+        Self {
+            kind,
+            project: None,
+            filename: None,
+            detail: detail.into(),
+            fix: fix.into(),
+            repaired: false,
+        }
+    }
+
+    fn project(mut self, project: impl Into<String>) -> Self {
+        self.project = Some(project.into());
+        self
+    }
+
+    fn filename(mut self, filename: impl Into<String>) -> Self {
+        self.filename = Some(filename.into());
+        self
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FsckReport {
+    pub checked_at: DateTime<Utc>,
+    /// Journal serial of the index state that was checked.
+    pub serial: u64,
+    pub projects: usize,
+    pub files: usize,
+    pub problems: Vec<Problem>,
+}
+
+impl FsckReport {
+    /// Whether every problem found was repaired.
+    pub fn is_clean(&self) -> bool {
+        self.problems.iter().all(|p| p.repaired)
+    }
+}
+
+/// Checks the index, stored files and docs, the change journal and
+/// snapshots against each other. With `repair`, index entries are brought in
+/// line with storage where that loses nothing: releases and docs without
+/// files are dropped, untracked wheels and docs are registered, duplicates
+/// collapse to the newest entry and interrupted writes are removed.
+/// Digest mismatches, the journal and snapshots are only ever reported.
+///
+/// A repair holds the index write lock throughout, so uploads wait for it.
+/// A check alone works on a copy of the index, taken under the read lock,
+/// and blocks nothing while the stored files are hashed.
+pub async fn check(index: &PackageIndex, repair: bool) -> Result<FsckReport, AppError> {
+    let mut copy;
+    let (mut guard, _lock);
+    let packages: &mut BTreeMap<PackageName, Package> = if repair {
+        (guard, _lock) = index.write_all().await?;
+        &mut guard
+    } else {
+        copy = index.catalog().await?.clone();
+        &mut copy
+    };
+    let storage = index.storage();
+    let mut problems = Vec::new();
+    let mut changed = BTreeSet::new();
+
+    for (key, package) in packages.iter_mut() {
+        if package.name != *key {
+            let mut problem = Problem::new(
+                ProblemKind::NameMismatch,
+                format!("indexed as {key} but named {}", package.name),
+                format!("rename the entry to {key}"),
+            )
+            .project(key.as_str());
+            if repair {
+                package.name = key.clone();
+                problem.repaired = true;
+                changed.insert(key.clone());
+            }
+            problems.push(problem);
+        }
+
+        // Entries for one file share a version and are kept newest first
+        // within it, so the first of each filename is the one kept.
+        let mut seen = BTreeSet::new();
+        let mut duplicates = Vec::new();
+        for release in &package.releases {
+            if !seen.insert(release.filename.clone()) {
+                duplicates.push(release.filename.clone());
+            }
+        }
+        for filename in duplicates {
+            let mut problem = Problem::new(
+                ProblemKind::DuplicateFile,
+                "registered more than once",
+                "keep only the newest entry",
+            )
+            .project(key.as_str())
+            .filename(filename.as_str());
+            if repair {
+                let mut kept = false;
+                package.releases.retain(|r| {
+                    if r.filename != filename {
+                        return true;
+                    }
+                    !std::mem::replace(&mut kept, true)
+                });
+                problem.repaired = true;
+                changed.insert(key.clone());
+            }
+            problems.push(problem);
+        }
+    }
+
+    let mut stored = storage.stored_files().await?;
+    let mut files = 0;
+    let mut untracked = Vec::new();
+    for (key, package) in packages.iter_mut() {
+        let on_disk: BTreeSet<String> = stored
+            .remove(key.as_str())
+            .unwrap_or_default()
+            .into_iter()
+            .collect();
+        let mut missing = Vec::new();
+        for release in &package.releases {
+            files += 1;
+            if !on_disk.contains(release.filename.as_str()) {
+                missing.push(release.filename.clone());
+                continue;
+            }
+            let Some(recorded) = release
+                .provenance
+                .as_ref()
+                .and_then(|p| p.digests.get("sha256"))
+            else {
+                continue;
+            };
+            let actual = storage.sha256(key, &release.filename).await?;
+            if actual != *recorded {
+                problems.push(
+                    Problem::new(
+                        ProblemKind::DigestMismatch,
+                        format!("sha256 is {actual}, recorded {recorded}"),
+                        "restore the file from a backup or upload it again",
+                    )
+                    .project(key.as_str())
+                    .filename(release.filename.as_str()),
+                );
+            }
+        }
+        for filename in missing {
+            let mut problem = Problem::new(
+                ProblemKind::MissingFile,
+                "indexed but not in storage",
+                "restore the file, or drop the release",
+            )
+            .project(key.as_str())
+            .filename(filename.as_str());
+            if repair {
+                package.releases.retain(|r| r.filename != filename);
+                problem.repaired = true;
+                changed.insert(key.clone());
+            }
+            problems.push(problem);
+        }
+
+        let indexed: BTreeSet<&str> = package
+            .releases
+            .iter()
+            .map(|r| r.filename.as_str())
+            .collect();
+        untracked.extend(
+            on_disk
+                .into_iter()
+                .filter(|f| !indexed.contains(f.as_str()))
+                .map(|f| (key.to_string(), f)),
+        );
+    }
+    // Plus everything under directories of projects the index lacks.
+    untracked.extend(
+        stored.into_iter().flat_map(|(project, filenames)| {
+            filenames.into_iter().map(move |f| (project.clone(), f))
+        }),
+    );
+    for (project, filename) in untracked {
+        let mut problem = Problem::new(
+            ProblemKind::UntrackedFile,
+            "stored but not indexed",
+            "register the file as a release, or delete it",
+        )
+        .project(&project)
+        .filename(&filename);
+        if repair {
+            if let Some(release) = registered_release(index, &project, &filename).await? {
+                let key = release.0;
+                let package = packages
+                    .entry(key.clone())
+                    .or_insert_with(|| Package::new(key.clone()));
+                if package.renamed_to.is_none() {
+                    package.releases.push(release.1);
+                    problem.repaired = true;
+                    changed.insert(key);
+                }
+            }
+        }
+        problems.push(problem);
+    }
+
+    let mut docs = storage.stored_docs().await?;
+    for (key, package) in packages.iter_mut() {
+        let on_disk: BTreeSet<String> = docs
+            .remove(key.as_str())
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|v| !v.starts_with('.'))
+            .collect();
+        let missing: Vec<Version> = package
+            .docs
+            .iter()
+            .filter(|v| !on_disk.contains(v.as_str()))
+            .cloned()
+            .collect();
+        for version in missing {
+            let mut problem = Problem::new(
+                ProblemKind::MissingDocs,
+                format!("docs for {version} are listed but not stored"),
+                "upload the docs again, or drop the listing",
+            )
+            .project(key.as_str());
+            if repair {
+                package.docs.retain(|v| *v != version);
+                problem.repaired = true;
+                changed.insert(key.clone());
+            }
+            problems.push(problem);
+        }
+        for version in on_disk {
+            if package.docs.iter().any(|v| v.as_str() == version) {
+                continue;
+            }
+            let mut problem = Problem::new(
+                ProblemKind::UntrackedDocs,
+                format!("docs for {version} are stored but not listed"),
+                "list the docs version, or delete its directory",
+            )
+            .project(key.as_str());
+            if let (true, Ok(version)) = (repair && package.renamed_to.is_none(), version.parse()) {
+                package.docs.push(version);
+                problem.repaired = true;
+                changed.insert(key.clone());
+            }
+            problems.push(problem);
+        }
+    }
+    for (project, versions) in docs {
+        for version in versions.into_iter().filter(|v| !v.starts_with('.')) {
+            problems.push(
+                Problem::new(
+                    ProblemKind::UntrackedDocs,
+                    format!("docs for {version} are stored for an unknown project"),
+                    "delete the directory, or upload a release of the project first",
+                )
+                .project(&project),
+            );
+        }
+    }
+
+    for path in storage.partial_writes().await? {
+        let mut problem = Problem::new(
+            ProblemKind::PartialWrite,
+            format!("{} was left by an interrupted write", path.display()),
+            "delete it",
+        );
+        if repair {
+            storage.remove_path(&path).await?;
+            problem.repaired = true;
+        }
+        problems.push(problem);
+    }
+
+    let journal = storage.read_journal().await?;
+    let mut kept = String::with_capacity(journal.len());
+    let mut dropped = false;
+    let mut serial = 0;
+    for (number, line) in journal.lines().enumerate() {
+        let line_number = number + 1;
+        match serde_json::from_str::<Change>(line) {
+            Ok(change) => {
+                if change.serial <= serial {
+                    problems.push(Problem::new(
+                        ProblemKind::CorruptJournal,
+                        format!(
+                            "line {line_number} has serial {} after {serial}",
+                            change.serial
+                        ),
+                        "look for a process writing without the index lock",
+                    ));
+                }
+                serial = serial.max(change.serial);
+                kept.push_str(line);
+                kept.push('\n');
+            }
+            Err(e) => {
+                let mut problem = Problem::new(
+                    ProblemKind::CorruptJournal,
+                    format!("line {line_number} does not parse: {e}"),
+                    "remove the line",
+                );
+                problem.repaired = repair;
+                dropped = true;
+                problems.push(problem);
+            }
+        }
+    }
+    if repair && dropped {
+        storage.replace_journal(kept).await?;
+    }
+
+    for name in storage.snapshot_names().await? {
+        let snapshot = match storage.load_snapshot(&name).await {
+            Ok(Some(snapshot)) => snapshot,
+            Ok(None) => continue,
+            Err(e) => {
+                problems.push(Problem::new(
+                    ProblemKind::BrokenSnapshot,
+                    format!("snapshot {name} cannot be read: {e}"),
+                    "restore the snapshot from a backup",
+                ));
+                continue;
+            }
+        };
+        for (project, package) in &snapshot.packages {
+            for release in &package.releases {
+                if !storage.has_package(project, &release.filename).await? {
+                    problems.push(
+                        Problem::new(
+                            ProblemKind::BrokenSnapshot,
+                            format!("snapshot {name} refers to a file no longer stored"),
+                            "restore the file from a backup",
+                        )
+                        .project(project.as_str())
+                        .filename(release.filename.as_str()),
+                    );
+                }
+            }
+        }
+    }
+
+    if !changed.is_empty() {
+        for package in packages.values_mut() {
+            package.sort_releases();
+        }
+        let projects: Vec<&PackageName> = changed.iter().collect();
+        index.commit(packages, &projects, Operation::Repair).await?;
+    }
+
+    Ok(FsckReport {
+        checked_at: Utc::now(),
+        serial: index.serial().await,
+        projects: packages.len(),
+        files,
+        problems,
+    })
+}
+
+/// A release for a file found in storage, dated by its modification time,
+/// if it is a wheel or sdist of the project it is stored under. No provenance is
+/// recorded, since how the file arrived is unknown.
+async fn registered_release(
+    index: &PackageIndex,
+    project: &str,
+    filename: &str,
+) -> Result<Option<(PackageName, Release)>, AppError> {
+    let (Ok(key), Ok(filename)) = (
+        project.parse::<PackageName>(),
+        filename.parse::<DistFilename>(),
+    ) else {
+        return Ok(None);
+    };
+    let Ok((name, version)) = parse_dist_filename(filename.as_str()) else {
+        return Ok(None);
+    };
+    if name != key {
+        return Ok(None);
+    }
+    let Some(stored) = index.storage().stat(&key, &filename).await? else {
+        return Ok(None);
+    };
+    let mut release = Release::new(version, filename);
+    release.upload_time = stored.modified;
+    Ok(Some((key, release)))
+}
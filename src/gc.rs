@@ -0,0 +1,220 @@
+//! Garbage collection: removing stored objects no release refers to, and
+//! releases whose file is gone, as left behind by failed uploads, files
+//! deleted by hand and the like.
+
+use std::{collections::BTreeSet, fmt, time::Duration};
+
+use chrono::Utc;
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::{
+    backend::StoredObject, index::Operation, reindex::distribution, AppError, PackageIndex,
+    PackageName,
+};
+
+#[derive(Debug, Clone, Copy)]
+pub struct GcOptions {
+    /// Remove what is found, rather than only reporting it.
+    pub remove: bool,
+    /// Stored objects modified more recently are left alone, since they may
+    /// belong to an upload still in progress.
+    pub min_age: Duration,
+    /// Leave stored wheels and sdists of the project they are stored under
+    /// alone, for a [`StorageScanner`](crate::reindex::StorageScanner) to
+    /// register.
+    pub spare_distributions: bool,
+}
+
+impl Default for GcOptions {
+    fn default() -> Self {
+        Self {
+            remove: false,
+            min_age: Duration::from_secs(60 * 60),
+            spare_distributions: false,
+        }
+    }
+}
+
+/// A stored object or blob found to be garbage.
+#[derive(Debug, Clone, Serialize)]
+pub struct Orphan {
+    pub key: String,
+    pub size: u64,
+}
+
+impl From<StoredObject> for Orphan {
+    fn from(object: StoredObject) -> Self {
+        Self {
+            key: object.key,
+            size: object.size,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct GcReport {
+    /// Stored objects neither a release nor a snapshot refers to: files,
+    /// core metadata of files no longer indexed, and anything else under a
+    /// project directory.
+    pub orphaned_objects: Vec<Orphan>,
+    /// Releases whose file is not stored, as `<project>/<filename>`.
+    pub missing_files: Vec<String>,
+    /// Blobs no stored object links to, by path under the blob directory.
+    pub orphaned_blobs: Vec<Orphan>,
+    /// Bytes the orphaned objects and blobs take up.
+    pub bytes: u64,
+    /// Whether what was found was removed.
+    pub removed: bool,
+}
+
+impl GcReport {
+    pub fn is_empty(&self) -> bool {
+        self.orphaned_objects.is_empty()
+            && self.missing_files.is_empty()
+            && self.orphaned_blobs.is_empty()
+    }
+}
+
+impl fmt::Display for GcReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let verb = if self.removed { "removed" } else { "found" };
+        writeln!(
+            f,
+            "{verb} {} orphaned objects, {} releases without files and {} orphaned blobs, {} bytes",
+            self.orphaned_objects.len(),
+            self.missing_files.len(),
+            self.orphaned_blobs.len(),
+            self.bytes
+        )?;
+        for orphan in &self.orphaned_objects {
+            writeln!(f, "object: {} ({} bytes)", orphan.key, orphan.size)?;
+        }
+        for key in &self.missing_files {
+            writeln!(f, "missing: {key}")?;
+        }
+        for orphan in &self.orphaned_blobs {
+            writeln!(f, "blob: {} ({} bytes)", orphan.key, orphan.size)?;
+        }
+        Ok(())
+    }
+}
+
+/// Finds stored objects that neither a release nor a snapshot refers to,
+/// releases whose file is not stored, and blobs nothing links to, and with
+/// `options.remove` removes them. Releases are dropped from the index
+/// before any bytes are removed.
+///
+/// Holds the index write lock throughout, so uploads wait for the pass.
+pub async fn collect(index: &PackageIndex, options: GcOptions) -> Result<GcReport, AppError> {
+    let (mut packages, _lock) = index.write_all().await?;
+    let storage = index.storage();
+    let before = chrono::Duration::from_std(options.min_age)
+        .ok()
+        .and_then(|age| Utc::now().checked_sub_signed(age))
+        .unwrap_or(chrono::DateTime::<Utc>::MIN_UTC);
+    let mut report = GcReport {
+        removed: options.remove,
+        ..GcReport::default()
+    };
+
+    let objects = storage.backend().list("").await?;
+    let stored: BTreeSet<&str> = objects.iter().map(|o| o.key.as_str()).collect();
+    let mut changed: Vec<PackageName> = Vec::new();
+    for (name, package) in packages.iter_mut() {
+        let missing: Vec<String> = package
+            .releases
+            .iter()
+            .map(|r| format!("{name}/{}", r.filename))
+            .filter(|key| !stored.contains(key.as_str()))
+            .collect();
+        if missing.is_empty() {
+            continue;
+        }
+        if options.remove {
+            package
+                .releases
+                .retain(|r| !missing.contains(&format!("{name}/{}", r.filename)));
+            changed.push(name.clone());
+        }
+        report.missing_files.extend(missing);
+    }
+    if !changed.is_empty() {
+        let projects: Vec<&PackageName> = changed.iter().collect();
+        index
+            .commit(&packages, &projects, Operation::GarbageCollection)
+            .await?;
+    }
+
+    // Counted after dropping releases, so their core metadata goes too.
+    let mut referenced = BTreeSet::new();
+    let snapshots = index.snapshots().await?;
+    let kept = packages.iter().chain(
+        snapshots
+            .iter()
+            .flat_map(|snapshot| snapshot.packages.iter()),
+    );
+    for (name, package) in kept {
+        for release in &package.releases {
+            let key = format!("{name}/{}", release.filename);
+            referenced.insert(format!("{key}.metadata"));
+            referenced.insert(key);
+        }
+    }
+
+    let mut recount = BTreeSet::new();
+    for object in objects {
+        if referenced.contains(&object.key)
+            || object.modified >= before
+            || (options.spare_distributions && distribution(&object.key).is_some())
+        {
+            continue;
+        }
+        if options.remove {
+            storage.backend().delete(&object.key).await?;
+            if let Some(Ok(name)) = object
+                .key
+                .split_once('/')
+                .map(|(project, _)| project.parse::<PackageName>())
+            {
+                recount.insert(name);
+            }
+        }
+        report.bytes += object.size;
+        report.orphaned_objects.push(object.into());
+    }
+    // Blobs of the objects just removed are collected in the same pass.
+    for blob in storage
+        .backend()
+        .collect_garbage(before, options.remove)
+        .await?
+    {
+        report.bytes += blob.size;
+        report.orphaned_blobs.push(blob.into());
+    }
+    drop(packages);
+
+    for name in &recount {
+        index.recount(name).await;
+    }
+    if !report.is_empty() {
+        info!(
+            "Garbage collection {} {} objects, {} releases and {} blobs",
+            if options.remove { "removed" } else { "found" },
+            report.orphaned_objects.len(),
+            report.missing_files.len(),
+            report.orphaned_blobs.len()
+        );
+    }
+    Ok(report)
+}
+
+/// Collects garbage every `interval` forever.
+pub async fn run(index: PackageIndex, interval: Duration, options: GcOptions) {
+    loop {
+        if let Err(e) = collect(&index, options).await {
+            warn!("Collecting garbage failed: {}", e);
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
@@ -0,0 +1,1088 @@
+use std::{convert::Infallible, sync::Arc};
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{Multipart, Path, Query, State},
+    http::{header, HeaderMap, StatusCode, Uri},
+    response::{Html, IntoResponse, Redirect, Response},
+    Extension, Json,
+};
+use chrono::Utc;
+use futures_util::{stream, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio_util::io::ReaderStream;
+use tracing::info;
+
+use crate::{
+    auth::Identity,
+    compat::{CompatibilityQuery, TargetEnvironment},
+    idempotency::{files_digest, Begin},
+    index::UploadAction,
+    is_distribution,
+    metadata::non_empty,
+    parse_dist_filename,
+    receipt::{Receipt, ReceiptFile, SignedReceipt},
+    simple_json::{self, ListedProject, SimpleFormat},
+    storage::SpooledPackage,
+    AppError, AppState, Channel, Config, DistFilename, Package, PackageIndex, PackageName,
+    Provenance, ProvenanceSource, Release, SnapshotName, UrlBuilder, Version,
+};
+
+/// Rows are rendered this many at a time as the response body is polled.
+const STREAM_CHUNK_ROWS: usize = 512;
+
+fn html_header(urls: &UrlBuilder, title: &str) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<title>{title}</title>
+<link rel="stylesheet" href="{}">
+</head>
+<body>
+    <h1>{title}</h1>
+    "#,
+        urls.asset("style.css")
+    )
+}
+
+/// Escapes text edited through the API for use in element content and
+/// single- or double-quoted attributes.
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+const HTML_FOOTER: &str = "
+</body>
+</html>";
+
+/// Streams a page whose rows are formatted lazily, so listings with tens of
+/// thousands of entries are never materialized as one `String`.
+fn stream_html<I>(header: String, rows: I) -> Response
+where
+    I: IntoIterator<Item = String>,
+    I::IntoIter: Send + 'static,
+{
+    let mut rows = rows.into_iter();
+    let chunks = std::iter::from_fn(move || {
+        let chunk: String = rows.by_ref().take(STREAM_CHUNK_ROWS).collect();
+        (!chunk.is_empty()).then_some(chunk)
+    });
+    let body = stream::iter(
+        std::iter::once(header)
+            .chain(chunks)
+            .chain(std::iter::once(HTML_FOOTER.to_string())),
+    )
+    .map(Ok::<_, Infallible>);
+
+    (
+        [
+            (header::CONTENT_TYPE, "text/html; charset=utf-8"),
+            // The simple pages have a JSON form picked by `Accept`.
+            (header::VARY, "Accept"),
+        ],
+        Body::from_stream(body),
+    )
+        .into_response()
+}
+
+pub(crate) async fn root(State(urls): State<UrlBuilder>) -> Html<String> {
+    Html(format!(
+        "{}<p>Use /simple/ for package listing</p>
+    <p>Upload packages using POST to /upload</p>{HTML_FOOTER}",
+        html_header(&urls, "Simple PyPI Server")
+    ))
+}
+
+/// Narrows an index listing to the projects whose names start with
+/// `prefix`, ignoring case, e.g. `/simple/?prefix=ab`.
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct ListingQuery {
+    prefix: Option<String>,
+}
+
+pub(crate) async fn list_packages(
+    State(index): State<PackageIndex>,
+    State(urls): State<UrlBuilder>,
+    Query(query): Query<ListingQuery>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let projects = active_projects(index.catalog().await?.values(), &query, |_| true);
+    if SimpleFormat::negotiate(&headers) == SimpleFormat::Json {
+        return Ok(simple_json::project_list(projects));
+    }
+    let header = html_header(&urls, "Package Index");
+    let links = projects
+        .into_iter()
+        .map(move |project| project_link(&urls.project(project.name.as_str()), &project));
+
+    Ok(stream_html(header, links))
+}
+
+/// The projects of a listing, with file counts covering the releases
+/// `listed` accepts.
+fn active_projects<'a>(
+    packages: impl Iterator<Item = &'a Package>,
+    query: &ListingQuery,
+    listed: impl Fn(&Release) -> bool,
+) -> Vec<ListedProject> {
+    let prefix = query.prefix.as_deref().map(str::to_ascii_lowercase);
+    packages
+        .filter(|p| p.renamed_to.is_none())
+        .filter(|p| {
+            prefix
+                .as_deref()
+                .is_none_or(|prefix| p.name.as_str().to_ascii_lowercase().starts_with(prefix))
+        })
+        .map(|p| ListedProject::new(p, &listed))
+        .collect()
+}
+
+/// A listing entry, carrying the same counts as the JSON form.
+fn project_link(url: &str, project: &ListedProject) -> String {
+    let last_upload = project
+        .last_upload
+        .map(|time| format!(" data-last-upload='{}'", time.to_rfc3339()))
+        .unwrap_or_default();
+    format!(
+        "<a href='{}' data-files='{}'{}>{}</a><br>\n",
+        url, project.files, last_upload, project.name
+    )
+}
+
+/// A permanent redirect to `canonical`, keeping the query string, if the
+/// URL spelled the project name other than in its normalized form `name`.
+pub(crate) fn normalizing_redirect(
+    raw: &str,
+    name: &PackageName,
+    uri: &Uri,
+    canonical: impl FnOnce() -> String,
+) -> Option<Redirect> {
+    if name.as_str() == raw {
+        return None;
+    }
+    let location = match uri.query() {
+        Some(query) => format!("{}?{query}", canonical()),
+        None => canonical(),
+    };
+    Some(Redirect::permanent(&location))
+}
+
+pub(crate) async fn package_details(
+    State(index): State<PackageIndex>,
+    State(urls): State<UrlBuilder>,
+    Path(raw): Path<String>,
+    Query(query): Query<CompatibilityQuery>,
+    uri: Uri,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let name = PackageName::new(raw.as_str())?;
+    if let Some(redirect) = normalizing_redirect(&raw, &name, &uri, || urls.project(name.as_str()))
+    {
+        return Ok(redirect.into_response());
+    }
+    let target = TargetEnvironment::from_query(&query)?;
+    let package = index
+        .project(&name)
+        .await?
+        .get(name.as_str())
+        .cloned()
+        .ok_or_else(|| AppError::NotFound(name.to_string()))?;
+    if let Some(new_name) = &package.renamed_to {
+        return Ok(Redirect::permanent(&urls.project(new_name.as_str())).into_response());
+    }
+
+    Ok(project_page(package, urls, target, &headers))
+}
+
+fn project_page(
+    package: Package,
+    urls: UrlBuilder,
+    target: TargetEnvironment,
+    headers: &HeaderMap,
+) -> Response {
+    if SimpleFormat::negotiate(headers) == SimpleFormat::Json {
+        return simple_json::project_detail(package, &urls, &target);
+    }
+    let package_name = package.name;
+    let header = html_header(&urls, &format!("{} Versions", package_name));
+    let summary = package
+        .summary
+        .map(|summary| format!("<p>{}</p>\n", escape_html(&summary)));
+    let project_links = (!package.project_urls.is_empty()).then(|| {
+        let links: Vec<String> = package
+            .project_urls
+            .iter()
+            .map(|(label, url)| {
+                format!(
+                    "<a href='{}' rel='nofollow'>{}</a>",
+                    escape_html(url),
+                    escape_html(label)
+                )
+            })
+            .collect();
+        format!("<p>{}</p>\n", links.join(" | "))
+    });
+    let docs_link = package.docs.last().map(|version| {
+        format!(
+            "<p><a href='{}'>docs</a></p>\n",
+            urls.docs(package_name.as_str(), version.as_str())
+        )
+    });
+    let links = package
+        .releases
+        .into_iter()
+        .filter(move |r| target.accepts(r.filename.as_str()))
+        .map(move |r| {
+            let fragment = r.hash_fragment();
+            // PEP 714 renamed the attribute; older pips only read the
+            // original spelling.
+            let core_metadata = r
+                .core_metadata
+                .map(|digest| {
+                    format!(
+                        " data-core-metadata='sha256={digest}' data-dist-info-metadata='sha256={digest}'"
+                    )
+                })
+                .unwrap_or_default();
+            let requires_python = r
+                .requires_python
+                .map(|spec| format!(" data-requires-python='{}'", escape_html(&spec)))
+                .unwrap_or_default();
+            // PEP 592: the attribute's value is the reason, if any.
+            let yanked = if r.yanked {
+                let reason = r.yanked_reason.as_deref().map(escape_html);
+                format!(" data-yanked='{}'", reason.unwrap_or_default())
+            } else {
+                String::new()
+            };
+            let yanked_note = match (r.yanked, &r.yanked_reason) {
+                (true, Some(reason)) => format!(" <strong>Yanked:</strong> {}", escape_html(reason)),
+                (true, None) => " <strong>Yanked</strong>".to_string(),
+                (false, _) => String::new(),
+            };
+            let via = r
+                .provenance
+                .map(|p| format!(" via {}", p.source))
+                .unwrap_or_default();
+            let deprecated = r
+                .deprecated
+                .map(|note| format!(" <strong>Deprecated:</strong> {}", escape_html(&note)))
+                .unwrap_or_default();
+            format!(
+                "<a href='{}{}'{}{}{}>{}</a> Uploaded: {}{}{}{}<br>\n",
+                urls.file(package_name.as_str(), r.filename.as_str()),
+                fragment,
+                requires_python,
+                core_metadata,
+                yanked,
+                r.filename,
+                r.upload_time.format("%Y-%m-%d %H:%M:%S UTC"),
+                via,
+                deprecated,
+                yanked_note
+            )
+        });
+
+    stream_html(
+        header,
+        summary
+            .into_iter()
+            .chain(project_links)
+            .chain(docs_link)
+            .chain(links),
+    )
+}
+
+pub(crate) async fn package_details_redirect(
+    State(urls): State<UrlBuilder>,
+    Path(name): Path<PackageName>,
+) -> Redirect {
+    Redirect::permanent(&urls.project(name.as_str()))
+}
+
+pub(crate) async fn channel_packages(
+    State(index): State<PackageIndex>,
+    State(urls): State<UrlBuilder>,
+    Path(channel): Path<Channel>,
+    Query(query): Query<ListingQuery>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let projects = active_projects(
+        index
+            .catalog()
+            .await?
+            .values()
+            .filter(|p| p.releases.iter().any(|r| channel.includes(r.channel()))),
+        &query,
+        |r| channel.includes(r.channel()),
+    );
+    if SimpleFormat::negotiate(&headers) == SimpleFormat::Json {
+        return Ok(simple_json::project_list(projects));
+    }
+    let header = html_header(&urls, &format!("Package Index ({channel})"));
+    let links = projects.into_iter().map(move |project| {
+        let url = urls.channel_project(&channel.to_string(), project.name.as_str());
+        project_link(&url, &project)
+    });
+
+    Ok(stream_html(header, links))
+}
+
+pub(crate) async fn channel_package_details(
+    State(index): State<PackageIndex>,
+    State(urls): State<UrlBuilder>,
+    Path((channel, raw)): Path<(Channel, String)>,
+    Query(query): Query<CompatibilityQuery>,
+    uri: Uri,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let name = PackageName::new(raw.as_str())?;
+    if let Some(redirect) = normalizing_redirect(&raw, &name, &uri, || {
+        urls.channel_project(&channel.to_string(), name.as_str())
+    }) {
+        return Ok(redirect.into_response());
+    }
+    let target = TargetEnvironment::from_query(&query)?;
+    let mut package = index
+        .project(&name)
+        .await?
+        .get(name.as_str())
+        .filter(|p| p.renamed_to.is_none())
+        .cloned()
+        .ok_or_else(|| AppError::NotFound(name.to_string()))?;
+    package.releases.retain(|r| channel.includes(r.channel()));
+
+    Ok(project_page(package, urls, target, &headers))
+}
+
+pub(crate) async fn channel_package_details_redirect(
+    State(urls): State<UrlBuilder>,
+    Path((channel, name)): Path<(Channel, PackageName)>,
+) -> Redirect {
+    Redirect::permanent(&urls.channel_project(&channel.to_string(), name.as_str()))
+}
+
+pub(crate) async fn snapshot_packages(
+    State(index): State<PackageIndex>,
+    State(urls): State<UrlBuilder>,
+    Path(snapshot): Path<SnapshotName>,
+    Query(query): Query<ListingQuery>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let frozen = index.snapshot(&snapshot).await?;
+    let projects = active_projects(frozen.packages.values(), &query, |_| true);
+    if SimpleFormat::negotiate(&headers) == SimpleFormat::Json {
+        return Ok(simple_json::project_list(projects));
+    }
+    let header = html_header(&urls, &format!("Package Index ({snapshot})"));
+    let links = projects.into_iter().map(move |project| {
+        let url = urls.snapshot_project(snapshot.as_str(), project.name.as_str());
+        project_link(&url, &project)
+    });
+
+    Ok(stream_html(header, links))
+}
+
+pub(crate) async fn snapshot_package_details(
+    State(index): State<PackageIndex>,
+    State(urls): State<UrlBuilder>,
+    Path((snapshot, raw)): Path<(SnapshotName, String)>,
+    Query(query): Query<CompatibilityQuery>,
+    uri: Uri,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let name = PackageName::new(raw.as_str())?;
+    if let Some(redirect) = normalizing_redirect(&raw, &name, &uri, || {
+        urls.snapshot_project(snapshot.as_str(), name.as_str())
+    }) {
+        return Ok(redirect.into_response());
+    }
+    let target = TargetEnvironment::from_query(&query)?;
+    let package = index
+        .snapshot(&snapshot)
+        .await?
+        .packages
+        .get(name.as_str())
+        .filter(|p| p.renamed_to.is_none())
+        .cloned()
+        .ok_or_else(|| AppError::NotFound(name.to_string()))?;
+
+    Ok(project_page(package, urls, target, &headers))
+}
+
+pub(crate) async fn snapshot_package_details_redirect(
+    State(urls): State<UrlBuilder>,
+    Path((snapshot, name)): Path<(SnapshotName, PackageName)>,
+) -> Redirect {
+    Redirect::permanent(&urls.snapshot_project(snapshot.as_str(), name.as_str()))
+}
+
+/// Redirects to the newest stable file of a project installable in the
+/// environment given by the query, for scripts that always want the
+/// current build.
+pub(crate) async fn latest_file(
+    State(index): State<PackageIndex>,
+    State(urls): State<UrlBuilder>,
+    Path(name): Path<PackageName>,
+    Query(query): Query<CompatibilityQuery>,
+) -> Result<Response, AppError> {
+    latest_redirect(&index, &urls, &name, &query, |_| true).await
+}
+
+/// Streams a stored distribution file, as linked from the simple index.
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct DownloadQuery {
+    /// Download a yanked file even where such downloads are refused.
+    #[serde(default)]
+    force: bool,
+}
+
+pub(crate) async fn download_package(
+    State(state): State<AppState>,
+    Path((name, filename)): Path<(PackageName, DistFilename)>,
+    Query(query): Query<DownloadQuery>,
+    identity: Option<Extension<Identity>>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let (index, urls) = (&state.index, &state.urls);
+    if let Some(dist) = filename.as_str().strip_suffix(".metadata") {
+        let (file, size) = index
+            .open_core_metadata(&name, &DistFilename::new(dist)?)
+            .await?;
+        return Ok((
+            [
+                (
+                    header::CONTENT_TYPE,
+                    "text/plain; charset=utf-8".to_string(),
+                ),
+                (header::CONTENT_LENGTH, size.to_string()),
+            ],
+            Body::from_stream(ReaderStream::new(file)),
+        )
+            .into_response());
+    }
+    if state.config.refuse_yanked_downloads {
+        let forced_by = identity.as_deref().filter(|_| query.force);
+        index.check_not_yanked(&name, &filename, forced_by).await?;
+    }
+    let (file, size) = index.open_file(&name, &filename).await?;
+    let user_agent = headers
+        .get(header::USER_AGENT)
+        .and_then(|agent| agent.to_str().ok())
+        .map(str::to_string);
+    index
+        .request_build(
+            &name,
+            &filename,
+            urls.file(name.as_str(), filename.as_str()),
+            user_agent,
+        )
+        .await;
+    let content_type = if filename.as_str().ends_with(".tar.gz") {
+        "application/gzip"
+    } else if filename.as_str().ends_with(".whl") || filename.as_str().ends_with(".zip") {
+        "application/zip"
+    } else {
+        "application/octet-stream"
+    };
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (header::CONTENT_LENGTH, size.to_string()),
+        ],
+        Body::from_stream(ReaderStream::new(file)),
+    )
+        .into_response())
+}
+
+/// Like `latest_file`, restricted to wheels.
+pub(crate) async fn latest_wheel(
+    State(index): State<PackageIndex>,
+    State(urls): State<UrlBuilder>,
+    Path(name): Path<PackageName>,
+    Query(query): Query<CompatibilityQuery>,
+) -> Result<Response, AppError> {
+    latest_redirect(&index, &urls, &name, &query, |filename| {
+        filename.as_str().ends_with(".whl")
+    })
+    .await
+}
+
+async fn latest_redirect(
+    index: &PackageIndex,
+    urls: &UrlBuilder,
+    name: &PackageName,
+    query: &CompatibilityQuery,
+    wanted: impl Fn(&DistFilename) -> bool,
+) -> Result<Response, AppError> {
+    let target = TargetEnvironment::from_query(query)?;
+    let packages = index.project(name).await?;
+    let package = packages
+        .get(name.as_str())
+        .ok_or_else(|| AppError::NotFound(name.to_string()))?;
+    // Releases are kept newest version first.
+    let release = package
+        .releases
+        .iter()
+        .filter(|r| !r.version.is_prerelease() && !r.yanked)
+        .find(|r| wanted(&r.filename) && target.accepts(r.filename.as_str()))
+        .ok_or_else(|| AppError::NotFound(format!("No matching release of {name}")))?;
+
+    Ok((
+        StatusCode::FOUND,
+        [(
+            header::LOCATION,
+            urls.file(package.name.as_str(), release.filename.as_str()),
+        )],
+    )
+        .into_response())
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct UploadQuery {
+    /// Validate the upload and report what would be stored, without storing it.
+    #[serde(default)]
+    dry_run: bool,
+    /// Channel to tag the uploaded releases with; a `channel` form field
+    /// sent before the file takes precedence.
+    channel: Option<Channel>,
+}
+
+/// Response to a dry-run upload.
+#[derive(Debug, Serialize)]
+pub(crate) struct UploadPlan {
+    files: Vec<CheckedUpload>,
+}
+
+/// A file of a dry-run upload that passed every check a real upload
+/// makes.
+#[derive(Debug, Serialize)]
+pub(crate) struct CheckedUpload {
+    #[serde(flatten)]
+    plan: PlannedUpload,
+    sha256: String,
+    action: UploadAction,
+}
+
+/// What an upload of one file would do, as reported by a dry run.
+#[derive(Debug, Serialize)]
+pub(crate) struct PlannedUpload {
+    filename: DistFilename,
+    name: PackageName,
+    version: Version,
+    channel: Channel,
+    /// Whether the project is new to the index.
+    new_project: bool,
+    /// Whether a file with this name is already stored.
+    exists: bool,
+}
+
+pub(crate) async fn upload_package(
+    State(state): State<AppState>,
+    Query(query): Query<UploadQuery>,
+    identity: Option<Extension<Identity>>,
+    headers: HeaderMap,
+    multipart: Multipart,
+) -> Result<Response, AppError> {
+    let index = &state.index;
+    let identity = identity.as_deref();
+    if query.dry_run {
+        let files =
+            simulate_uploads(index, &state.config, query.channel, identity, multipart).await?;
+        return Ok(Json(UploadPlan { files }).into_response());
+    }
+    let key = state.config.signing_key.as_ref();
+
+    let Some(idempotency_key) = headers
+        .get("idempotency-key")
+        .and_then(|value| value.to_str().ok())
+    else {
+        let receipt =
+            store_uploads(index, &state.config, query.channel, identity, multipart).await?;
+        return Ok(SignedReceipt::new(&receipt, key).into_response());
+    };
+
+    let pending = match state.idempotency.begin(identity, idempotency_key) {
+        Begin::Started(pending) => pending,
+        Begin::Replay { receipt, files } => {
+            // Received in full, but only hashed, to tell a retry from
+            // another upload reusing the key.
+            if upload_files_digest(&state.config, multipart).await? != files {
+                return Err(AppError::Unprocessable(format!(
+                    "Idempotency key {idempotency_key} was used for an upload of other files"
+                )));
+            }
+            info!(
+                "Replaying upload result for idempotency key {}",
+                idempotency_key
+            );
+            return Ok(SignedReceipt::clone(&receipt).into_response());
+        }
+        Begin::InFlight => {
+            return Err(AppError::Conflict(format!(
+                "An upload with idempotency key {idempotency_key} is still in progress"
+            )))
+        }
+    };
+    // Should the client go away mid-upload, dropping `pending` releases the
+    // key for its retry.
+    let result = store_uploads(index, &state.config, query.channel, identity, multipart)
+        .await
+        .map(|receipt| {
+            let files = receipt_files_digest(&receipt);
+            (Arc::new(SignedReceipt::new(&receipt, key)), files)
+        });
+    pending.finish(result.as_ref().ok().cloned());
+    Ok(SignedReceipt::clone(&result?.0).into_response())
+}
+
+fn receipt_files_digest(receipt: &Receipt) -> String {
+    files_digest(receipt.files.iter().map(|file| {
+        let sha256 = file.digests.get("sha256").map_or("", String::as_str);
+        (file.filename.as_str(), sha256)
+    }))
+}
+
+/// The [`files_digest`] of the distribution files in an upload, hashed as
+/// they arrive without being stored.
+async fn upload_files_digest(
+    config: &Config,
+    mut multipart: Multipart,
+) -> Result<String, AppError> {
+    let mut files = Vec::new();
+    let too_large = |e: AppError| body_too_large(e, config.max_upload_size);
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(|e| too_large(e.into()))?
+    {
+        let Some(filename) = field.file_name().map(str::to_owned) else {
+            continue;
+        };
+        if !is_distribution(&filename) {
+            continue;
+        }
+        let filename = DistFilename::new(filename)?;
+        let mut hasher = Sha256::new();
+        let mut chunks = std::pin::pin!(limit_size(field, &filename, config.max_upload_size));
+        while let Some(chunk) = chunks.next().await {
+            hasher.update(chunk?);
+        }
+        files.push((filename.clone(), format!("{:x}", hasher.finalize())));
+    }
+    Ok(files_digest(files.iter().map(|(filename, sha256)| {
+        (filename.as_str(), sha256.as_str())
+    })))
+}
+
+/// Form fields sent alongside the files, as in PyPI's upload API that
+/// twine speaks. Each applies to the files after it; `name`, `version`
+/// and `sha256_digest` only to the next one.
+#[derive(Debug, Default)]
+struct UploadFields {
+    channel: Option<Channel>,
+    name: Option<String>,
+    version: Option<String>,
+    sha256_digest: Option<String>,
+}
+
+impl UploadFields {
+    fn new(channel: Option<Channel>) -> Self {
+        Self {
+            channel,
+            ..Self::default()
+        }
+    }
+
+    /// Takes in a non-file field. Metadata pippy does not index, such as
+    /// `summary` or `description`, is ignored.
+    async fn read(&mut self, field: axum::extract::multipart::Field<'_>) -> Result<(), AppError> {
+        let Some(key) = field.name().map(str::to_owned) else {
+            return Ok(());
+        };
+        match key.as_str() {
+            "channel" => self.channel = Some(field.text().await?.trim().parse()?),
+            ":action" => {
+                let action = field.text().await?;
+                if action.trim() != "file_upload" {
+                    return Err(AppError::InvalidFormat(format!(
+                        "Unsupported :action {action:?}"
+                    )));
+                }
+            }
+            "name" => self.name = non_empty(field.text().await?.trim()),
+            "version" => self.version = non_empty(field.text().await?.trim()),
+            "sha256_digest" => {
+                self.sha256_digest = non_empty(&field.text().await?.trim().to_ascii_lowercase())
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Clears the fields that described the file just read.
+    fn file_done(&mut self) {
+        self.name = None;
+        self.version = None;
+        self.sha256_digest = None;
+    }
+}
+
+/// Checks one uploaded file, returning what storing it would do. The
+/// project and version come from the filename and must agree with any
+/// declared in the form, and the project must be the uploader's.
+async fn plan_upload(
+    index: &PackageIndex,
+    filename: &str,
+    fields: &UploadFields,
+    identity: Option<&Identity>,
+) -> Result<PlannedUpload, AppError> {
+    let filename = DistFilename::new(filename)?;
+    let (name, version) = parse_dist_filename(filename.as_str())?;
+    if let Some(declared) = &fields.name {
+        let declared = PackageName::new(declared.as_str())?;
+        if declared != name {
+            return Err(AppError::InvalidFormat(format!(
+                "{filename} is not a distribution of {declared}"
+            )));
+        }
+    }
+    if let Some(declared) = &fields.version {
+        let declared: Version = declared.parse()?;
+        // Clients may send the normalized form, e.g. `1.0rc1` for `1.0-RC1`.
+        if declared != version
+            && (declared.parsed().is_none() || declared.parsed() != version.parsed())
+        {
+            return Err(AppError::InvalidFormat(format!(
+                "{filename} is not version {declared}"
+            )));
+        }
+    }
+    let channel = fields
+        .channel
+        .unwrap_or_else(|| Channel::for_version(&version));
+    let recent_uploads = index.recent_uploads(&name).await?;
+    let new_project = match index.project(&name).await?.get(name.as_str()) {
+        Some(package) => {
+            package.ensure_active()?;
+            if let Some(identity) = identity {
+                identity.may_change(package)?;
+            }
+            package.check_limits(&version, index.limits(), &recent_uploads)?;
+            false
+        }
+        None => true,
+    };
+    let exists = index.has_file(&name, &filename).await?;
+    Ok(PlannedUpload {
+        filename,
+        name,
+        version,
+        channel,
+        new_project,
+        exists,
+    })
+}
+
+/// Checks a received file against everything it must pass before it is
+/// stored: its declared digest, the overwrite policy, the quotas and the
+/// dependency policy.
+async fn check_received(
+    index: &PackageIndex,
+    config: &Config,
+    plan: &PlannedUpload,
+    declared_sha256: Option<&str>,
+    spooled: &SpooledPackage,
+) -> Result<UploadAction, AppError> {
+    let PlannedUpload { filename, name, .. } = plan;
+    let sha256 = spooled.sha256();
+    if let Some(declared) = declared_sha256.filter(|d| *d != sha256) {
+        return Err(AppError::InvalidFormat(format!(
+            "{filename} has sha256 {sha256}, not the declared {declared}"
+        )));
+    }
+    // An upload of a file already stored is only hashed, to tell a retry
+    // from a different file under the same name.
+    let stored = if plan.exists {
+        index.file_sha256(name, filename).await?
+    } else {
+        None
+    };
+    let action = index
+        .upload_action(name, filename, &plan.version, sha256, stored.as_deref())
+        .await?;
+    if action == UploadAction::Unchanged {
+        return Ok(action);
+    }
+    let replaced = match action {
+        UploadAction::Replace => index.storage.stat(name, filename).await?,
+        _ => None,
+    };
+    let extra = spooled.size() as i64 - replaced.map_or(0, |stored| stored.size as i64);
+    index.check_quota(name, extra).await?;
+    config
+        .dependencies
+        .check(index, name, filename, spooled.path())
+        .await?;
+    Ok(action)
+}
+
+/// Runs every check of an upload, receiving each file in full, without
+/// storing anything.
+async fn simulate_uploads(
+    index: &PackageIndex,
+    config: &Config,
+    channel: Option<Channel>,
+    identity: Option<&Identity>,
+    mut multipart: Multipart,
+) -> Result<Vec<CheckedUpload>, AppError> {
+    let mut fields = UploadFields::new(channel);
+    let mut checked = Vec::new();
+    let too_large = |e: AppError| body_too_large(e, config.max_upload_size);
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(|e| too_large(e.into()))?
+    {
+        if let Some(filename) = field.file_name().map(str::to_owned) {
+            if !is_distribution(&filename) {
+                continue;
+            }
+            let plan = plan_upload(index, &filename, &fields, identity).await?;
+            let field = limit_size(field, &plan.filename, config.max_upload_size);
+            let spooled = spool_upload(
+                index,
+                config,
+                identity,
+                &plan,
+                fields.sha256_digest.as_deref(),
+                field,
+            )
+            .await?;
+            let action = check_received(
+                index,
+                config,
+                &plan,
+                fields.sha256_digest.as_deref(),
+                &spooled,
+            )
+            .await?;
+            checked.push(CheckedUpload {
+                plan,
+                sha256: spooled.sha256().to_owned(),
+                action,
+            });
+            fields.file_done();
+        } else {
+            fields.read(field).await.map_err(too_large)?;
+        }
+    }
+
+    if checked.is_empty() {
+        return Err(AppError::InvalidFormat(
+            "No distribution file found in upload".into(),
+        ));
+    }
+    Ok(checked)
+}
+
+/// Room for the form fields sent alongside a file, such as a long
+/// description, in the body limit of uploads.
+pub(crate) const FORM_ALLOWANCE: u64 = 1024 * 1024;
+
+/// Receives an uploaded file in full. A file sent empty with the
+/// `sha256_digest` of one this index lists, such as the same artifact
+/// published to another project, is taken from storage instead, so
+/// republishing it needs no byte transfer. Only files the uploader could
+/// download are taken, never bytes left behind by deleted files or those
+/// of other indexes sharing the blobs.
+async fn spool_upload<S>(
+    index: &PackageIndex,
+    config: &Config,
+    identity: Option<&Identity>,
+    plan: &PlannedUpload,
+    declared_sha256: Option<&str>,
+    chunks: S,
+) -> Result<SpooledPackage, AppError>
+where
+    S: Stream<Item = Result<Bytes, AppError>> + Send,
+{
+    let spooled = index
+        .storage
+        .spool_package(&plan.name, &plan.filename, chunks)
+        .await?;
+    let Some(sha256) = declared_sha256.filter(|_| spooled.size() == 0) else {
+        return Ok(spooled);
+    };
+    let unknown = || {
+        AppError::InvalidFormat(format!(
+            "{} is empty, and no listed file has sha256 {sha256} to publish it from",
+            plan.filename
+        ))
+    };
+    if !index
+        .lists_sha256(sha256, identity, config.refuse_yanked_downloads)
+        .await?
+    {
+        return Err(unknown());
+    }
+    index
+        .storage
+        .spool_blob(&plan.name, &plan.filename, sha256)
+        .await?
+        .ok_or_else(unknown)
+}
+
+/// Counts the bytes of an uploaded file as they arrive, failing once there
+/// are more than `limit`. The request body limit, set a little higher to
+/// leave room for the form, is reported the same way.
+pub(crate) fn limit_size<'a>(
+    field: axum::extract::multipart::Field<'a>,
+    filename: &'a (impl std::fmt::Display + Sync),
+    limit: Option<u64>,
+) -> impl Stream<Item = Result<Bytes, AppError>> + Send + 'a {
+    let too_large = move |limit: u64| AppError::PayloadTooLarge {
+        message: format!("{filename} is larger than the limit of {limit} bytes"),
+        limit,
+    };
+    let mut received = 0;
+    field.map(move |chunk| {
+        let chunk = match (chunk, limit) {
+            (Err(e), Some(limit)) if e.status() == StatusCode::PAYLOAD_TOO_LARGE => {
+                return Err(too_large(limit))
+            }
+            (chunk, _) => chunk?,
+        };
+        received += chunk.len() as u64;
+        match limit {
+            Some(limit) if received > limit => Err(too_large(limit)),
+            _ => Ok(chunk),
+        }
+    })
+}
+
+/// Reports the request body limit being reached, outside of any file, as
+/// the upload being too large.
+pub(crate) fn body_too_large(error: AppError, limit: Option<u64>) -> AppError {
+    match (error, limit) {
+        (AppError::Multipart(e), Some(limit)) if e.status() == StatusCode::PAYLOAD_TOO_LARGE => {
+            AppError::PayloadTooLarge {
+                message: format!(
+                    "the request is larger than the limit of {limit} bytes per file plus {FORM_ALLOWANCE} for the form"
+                ),
+                limit,
+            }
+        }
+        (error, _) => error,
+    }
+}
+
+async fn store_uploads(
+    index: &PackageIndex,
+    config: &Config,
+    channel: Option<Channel>,
+    identity: Option<&Identity>,
+    mut multipart: Multipart,
+) -> Result<Receipt, AppError> {
+    let mut fields = UploadFields::new(channel);
+    let mut files = Vec::new();
+    let too_large = |e: AppError| body_too_large(e, config.max_upload_size);
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(|e| too_large(e.into()))?
+    {
+        if let Some(filename) = field.file_name().map(str::to_owned) {
+            if !is_distribution(&filename) {
+                continue;
+            }
+
+            let plan = plan_upload(index, &filename, &fields, identity).await?;
+            // Received in full beside where it is stored, and checked before
+            // it is moved into place, so a refused upload never touches a
+            // published file.
+            let field = limit_size(field, &plan.filename, config.max_upload_size);
+            let spooled = spool_upload(
+                index,
+                config,
+                identity,
+                &plan,
+                fields.sha256_digest.as_deref(),
+                field,
+            )
+            .await?;
+            let action = check_received(
+                index,
+                config,
+                &plan,
+                fields.sha256_digest.as_deref(),
+                &spooled,
+            )
+            .await?;
+            let PlannedUpload {
+                filename,
+                name: package_name,
+                version,
+                ..
+            } = plan;
+            let provenance = Provenance::new(ProvenanceSource::Upload, spooled.sha256().to_owned());
+            let receipt = ReceiptFile {
+                name: package_name.clone(),
+                version: version.clone(),
+                filename: filename.clone(),
+                digests: provenance.digests.clone(),
+            };
+            // Checked again as it is published, in case a concurrent upload
+            // of the same filename got there first.
+            let action = match action {
+                UploadAction::Unchanged => action,
+                _ => {
+                    index
+                        .publish(
+                            package_name.clone(),
+                            Release::new(version, filename.clone())
+                                .with_channel(fields.channel)
+                                .with_provenance(provenance),
+                            identity,
+                            spooled,
+                        )
+                        .await?
+                }
+            };
+            if action == UploadAction::Unchanged {
+                // A retry of an upload that already succeeded.
+                info!("{} is already stored with the same contents", filename);
+            } else {
+                info!("Successfully uploaded package: {}", package_name);
+            }
+            files.push(receipt);
+            fields.file_done();
+        } else {
+            fields.read(field).await.map_err(too_large)?;
+        }
+    }
+
+    if files.is_empty() {
+        return Err(AppError::InvalidFormat(
+            "No distribution file found in upload".into(),
+        ));
+    }
+
+    Ok(Receipt {
+        files,
+        timestamp: Utc::now(),
+    })
+}
@@ -0,0 +1,148 @@
+//! Replay protection for uploads carrying an `Idempotency-Key` header, so a
+//! client retrying after a timeout gets the original result instead of a
+//! second release entry.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use sha2::{Digest, Sha256};
+
+use crate::{auth::Identity, receipt::SignedReceipt};
+
+/// Longest an upload is taken to be in progress. Entries are removed once
+/// their request finishes or is dropped, so this only bounds those of a
+/// request that hangs.
+const MAX_IN_FLIGHT: Duration = Duration::from_secs(60 * 60);
+
+/// Keys are only ever matched against those of the same caller.
+type CacheKey = (Option<Identity>, String);
+
+#[derive(Debug, Clone)]
+enum Entry {
+    InFlight {
+        since: Instant,
+    },
+    Done {
+        receipt: Arc<SignedReceipt>,
+        files: String,
+        at: Instant,
+    },
+}
+
+#[derive(Debug)]
+pub enum Begin<'a> {
+    /// No live entry for the key; the caller should perform the upload and
+    /// report how it went through the guard.
+    Started(Pending<'a>),
+    /// The key already completed successfully with this receipt, for an
+    /// upload of the files with this [`files_digest`].
+    Replay {
+        receipt: Arc<SignedReceipt>,
+        files: String,
+    },
+    /// Another request with the same key has not finished yet.
+    InFlight,
+}
+
+/// A digest of the files an upload carries, by filename and SHA-256 in the
+/// order sent, telling a retry from another upload reusing its key.
+pub fn files_digest<'a>(files: impl IntoIterator<Item = (&'a str, &'a str)>) -> String {
+    let mut hasher = Sha256::new();
+    for (filename, sha256) in files {
+        hasher.update(filename.as_bytes());
+        hasher.update(b"\0");
+        hasher.update(sha256.as_bytes());
+        hasher.update(b"\n");
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+#[derive(Debug)]
+pub struct IdempotencyCache {
+    window: Duration,
+    entries: Mutex<HashMap<CacheKey, Entry>>,
+}
+
+impl IdempotencyCache {
+    /// Results are remembered for `window`; expired keys are collected
+    /// whenever the cache is touched.
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn begin(&self, caller: Option<&Identity>, key: &str) -> Begin<'_> {
+        let now = Instant::now();
+        let in_flight = self.window.min(MAX_IN_FLIGHT);
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, entry| match entry {
+            Entry::InFlight { since } => now.duration_since(*since) < in_flight,
+            Entry::Done { at, .. } => now.duration_since(*at) < self.window,
+        });
+
+        let key = (caller.cloned(), key.to_string());
+        match entries.get(&key) {
+            Some(Entry::Done { receipt, files, .. }) => Begin::Replay {
+                receipt: receipt.clone(),
+                files: files.clone(),
+            },
+            Some(Entry::InFlight { .. }) => Begin::InFlight,
+            None => {
+                entries.insert(key.clone(), Entry::InFlight { since: now });
+                Begin::Started(Pending {
+                    cache: self,
+                    key: Some(key),
+                })
+            }
+        }
+    }
+}
+
+/// An upload holding its key in flight. Dropped without
+/// [`finish`](Pending::finish), as when the client disconnects and the
+/// request is abandoned, it releases the key for a retry.
+#[derive(Debug)]
+pub struct Pending<'a> {
+    cache: &'a IdempotencyCache,
+    key: Option<CacheKey>,
+}
+
+impl Pending<'_> {
+    /// Records the receipt of an upload of the files with digest `files`.
+    /// Failures, `None`, are forgotten so the client can retry them under
+    /// the same key.
+    pub fn finish(mut self, result: Option<(Arc<SignedReceipt>, String)>) {
+        let Some(key) = self.key.take() else {
+            return;
+        };
+        let mut entries = self.cache.entries.lock().unwrap();
+        match result {
+            Some((receipt, files)) => {
+                entries.insert(
+                    key,
+                    Entry::Done {
+                        receipt,
+                        files,
+                        at: Instant::now(),
+                    },
+                );
+            }
+            None => {
+                entries.remove(&key);
+            }
+        }
+    }
+}
+
+impl Drop for Pending<'_> {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            self.cache.entries.lock().unwrap().remove(&key);
+        }
+    }
+}
@@ -0,0 +1,223 @@
+//! Bulk import of wheels and sdists from a local directory, such as a
+//! mirror being migrated. On the same filesystem, files can be reflinked
+//! or hard linked into the store instead of copied, which is much faster
+//! and, with reflinks or hard links, stores each file's blocks only once.
+
+use std::{
+    fmt,
+    fs::{self, File, OpenOptions},
+    io,
+    path::{Path, PathBuf},
+};
+
+use clap::ValueEnum;
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::{
+    is_distribution, parse_dist_filename, AppError, Channel, DistFilename, PackageIndex,
+    Provenance, ProvenanceSource, Release,
+};
+
+/// How an imported file is placed in the store.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkMode {
+    /// Copy the bytes.
+    Copy,
+    /// Hard link to the source, which must then never be modified in place.
+    Hardlink,
+    /// Share the source's blocks copy-on-write, where the filesystem
+    /// supports it (Btrfs, XFS, bcachefs).
+    Reflink,
+    /// Reflink, else hard link, else copy.
+    Auto,
+}
+
+/// Puts a copy of `source` at `dest`, which must not exist, returning how.
+pub(crate) fn place(source: &Path, dest: &Path, mode: LinkMode) -> io::Result<LinkMode> {
+    match mode {
+        LinkMode::Copy => fs::copy(source, dest).map(|_| LinkMode::Copy),
+        LinkMode::Hardlink => fs::hard_link(source, dest).map(|()| LinkMode::Hardlink),
+        LinkMode::Reflink => reflink(source, dest).map(|()| LinkMode::Reflink),
+        LinkMode::Auto => reflink(source, dest)
+            .map(|()| LinkMode::Reflink)
+            .or_else(|_| fs::hard_link(source, dest).map(|()| LinkMode::Hardlink))
+            .or_else(|_| fs::copy(source, dest).map(|_| LinkMode::Copy)),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn reflink(source: &Path, dest: &Path) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    /// `_IOW(0x94, 9, int)` from `linux/fs.h`.
+    const FICLONE: u32 = 0x4004_9409;
+
+    let source = File::open(source)?;
+    let target = OpenOptions::new().write(true).create_new(true).open(dest)?;
+    // SAFETY: both descriptors stay open for the duration of the call, and
+    // FICLONE takes the source descriptor by value.
+    let cloned = unsafe { libc::ioctl(target.as_raw_fd(), FICLONE as _, source.as_raw_fd()) };
+    if cloned == -1 {
+        let error = io::Error::last_os_error();
+        drop(target);
+        let _ = fs::remove_file(dest);
+        return Err(error);
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn reflink(_source: &Path, _dest: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "reflinks are only supported on Linux",
+    ))
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ImportReport {
+    pub reflinked: usize,
+    pub hardlinked: usize,
+    pub copied: usize,
+    /// Files whose name the index already has.
+    pub skipped: usize,
+    pub failed: Vec<ImportFailure>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportFailure {
+    pub path: PathBuf,
+    pub error: String,
+}
+
+impl ImportReport {
+    pub fn imported(&self) -> usize {
+        self.reflinked + self.hardlinked + self.copied
+    }
+}
+
+impl fmt::Display for ImportReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "imported {} files ({} reflinked, {} hard linked, {} copied), skipped {} already indexed",
+            self.imported(),
+            self.reflinked,
+            self.hardlinked,
+            self.copied,
+            self.skipped
+        )?;
+        for failure in &self.failed {
+            writeln!(f, "failed: {}: {}", failure.path.display(), failure.error)?;
+        }
+        Ok(())
+    }
+}
+
+/// Imports every wheel and sdist under `dir`, recursively. Files that fail
+/// are reported and skipped; the rest are still imported.
+pub async fn import_dir(
+    index: &PackageIndex,
+    dir: &Path,
+    mode: LinkMode,
+    channel: Option<Channel>,
+) -> Result<ImportReport, AppError> {
+    import_files(index, distributions_under(dir).await?, mode, channel).await
+}
+
+/// Imports each of `paths`, which must name wheels or sdists, reporting
+/// failures as [`import_dir`] does.
+pub async fn import_files(
+    index: &PackageIndex,
+    paths: Vec<PathBuf>,
+    mode: LinkMode,
+    channel: Option<Channel>,
+) -> Result<ImportReport, AppError> {
+    let mut report = ImportReport::default();
+    for path in paths {
+        match import_file(index, &path, mode, channel).await {
+            Ok(Some(LinkMode::Reflink)) => report.reflinked += 1,
+            Ok(Some(LinkMode::Hardlink)) => report.hardlinked += 1,
+            Ok(Some(_)) => report.copied += 1,
+            Ok(None) => report.skipped += 1,
+            Err(e) => {
+                warn!("Importing {} failed: {}", path.display(), e);
+                report.failed.push(ImportFailure {
+                    path,
+                    error: e.to_string(),
+                });
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// Imports one distribution, returning how it was placed, or `None` if the index
+/// already has it.
+async fn import_file(
+    index: &PackageIndex,
+    path: &Path,
+    mode: LinkMode,
+    channel: Option<Channel>,
+) -> Result<Option<LinkMode>, AppError> {
+    let filename = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    if !is_distribution(&filename) {
+        return Err(AppError::InvalidFormat(format!(
+            "{filename} is not a wheel or sdist"
+        )));
+    }
+    let filename = DistFilename::new(filename)?;
+    let (name, version) = parse_dist_filename(filename.as_str())?;
+    if index.has_file(&name, &filename).await? {
+        return Ok(None);
+    }
+    let used = index
+        .storage
+        .import_package(&name, &filename, path.to_path_buf(), mode)
+        .await?;
+    let sha256 = index.storage.sha256(&name, &filename).await?;
+    let provenance = Provenance::new(
+        ProvenanceSource::Import {
+            path: path.display().to_string(),
+        },
+        sha256,
+    );
+    let release = Release::new(version, filename.clone())
+        .with_channel(channel)
+        .with_provenance(provenance);
+    if let Err(e) = index.add_release(name.clone(), release, None).await {
+        let _ = index.storage.remove_package(&name, &filename).await;
+        return Err(e);
+    }
+    info!("Imported {} ({:?})", filename, used);
+    Ok(Some(used))
+}
+
+/// Distributions anywhere under `dir`, in a stable order.
+async fn distributions_under(dir: &Path) -> Result<Vec<PathBuf>, AppError> {
+    let mut found = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let mut entries = tokio::fs::read_dir(&dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let file_type = entry.file_type().await?;
+            let path = entry.path();
+            if file_type.is_dir() {
+                pending.push(path);
+            } else if file_type.is_file()
+                && path
+                    .file_name()
+                    .is_some_and(|name| is_distribution(&name.to_string_lossy()))
+            {
+                found.push(path);
+            }
+        }
+    }
+    found.sort();
+    Ok(found)
+}
@@ -0,0 +1,2259 @@
+use chrono::{DateTime, Utc};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    path::PathBuf,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::{Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use tokio_util::task::TaskTracker;
+use tracing::{info, warn};
+
+use crate::{
+    auth::Identity,
+    backend::ObjectReader,
+    enrich::EnricherRegistry,
+    inspect,
+    metadata::{non_empty, AuditEntry, FileUpdate, OwnersUpdate, ProjectUpdate, ReleaseUpdate},
+    quota::{DiskUsage, Quotas, UsageReport},
+    retention::RetentionPolicy,
+    stats::{StatKind, StatsRecorder, StatsRetention},
+    storage::{IndexLock, SpooledPackage},
+    webhooks::{Webhook, WebhookDispatcher, WebhookEvent},
+    AppError, DistFilename, PackageName, PackageStorage, SnapshotName, Version,
+};
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Package {
+    pub name: PackageName,
+    pub releases: Vec<Release>,
+    /// Versions with hosted documentation, most recently uploaded last.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub docs: Vec<Version>,
+    /// Set on the tombstone left behind when a project is renamed; the old
+    /// name redirects here and cannot be registered again.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub renamed_to: Option<PackageName>,
+    /// One-line description, editable after upload.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+    /// Links such as `Homepage` or `Source`, keyed by label.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub project_urls: BTreeMap<String, String>,
+    /// Hooks the project's owners registered, moved along on rename.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub webhooks: Vec<Webhook>,
+    /// Users allowed to change the project, starting with whoever first
+    /// uploaded it.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub owners: Vec<String>,
+}
+
+impl Package {
+    /// Orders releases newest version first by PEP 440, with re-uploads
+    /// of one version newest first among themselves. Versions that do not
+    /// follow PEP 440 sort after all that do.
+    pub(crate) fn sort_releases(&mut self) {
+        self.releases
+            .sort_by_cached_key(|r| std::cmp::Reverse((r.version.parsed(), r.upload_time)));
+    }
+
+    /// Folds in an entry for the same project, as found when names that
+    /// differed only in case or punctuation are merged.
+    pub(crate) fn absorb(&mut self, other: Package) {
+        for release in other.releases {
+            if !self.releases.iter().any(|r| r.filename == release.filename) {
+                self.releases.push(release);
+            }
+        }
+        self.sort_releases();
+        for version in other.docs {
+            if !self.docs.contains(&version) {
+                self.docs.push(version);
+            }
+        }
+        self.renamed_to = self.renamed_to.take().or(other.renamed_to);
+        self.summary = self.summary.take().or(other.summary);
+        for (label, url) in other.project_urls {
+            self.project_urls.entry(label).or_insert(url);
+        }
+        self.webhooks.extend(other.webhooks);
+        for owner in other.owners {
+            if !self.owners.contains(&owner) {
+                self.owners.push(owner);
+            }
+        }
+    }
+
+    pub fn new(name: PackageName) -> Self {
+        Self {
+            name,
+            releases: Vec::new(),
+            docs: Vec::new(),
+            renamed_to: None,
+            summary: None,
+            project_urls: BTreeMap::new(),
+            webhooks: Vec::new(),
+            owners: Vec::new(),
+        }
+    }
+
+    /// Fails if publishing `version` now would exceed `limits`, given the
+    /// times of the project's uploads in the last hour.
+    pub(crate) fn check_limits(
+        &self,
+        version: &Version,
+        limits: &UploadLimits,
+        recent_uploads: &[DateTime<Utc>],
+    ) -> Result<(), AppError> {
+        if let Some(max) = limits.max_versions {
+            let is_new = !self.releases.iter().any(|r| r.version == *version);
+            let mut versions: Vec<&Version> = self.releases.iter().map(|r| &r.version).collect();
+            versions.sort();
+            versions.dedup();
+            if is_new && versions.len() >= max {
+                return Err(AppError::Forbidden(format!(
+                    "{} already has {} versions, the most allowed per project",
+                    self.name,
+                    versions.len()
+                )));
+            }
+        }
+        if let Some(max) = limits.max_uploads_per_hour {
+            let window_start = Utc::now() - chrono::Duration::hours(1);
+            let mut recent: Vec<DateTime<Utc>> = recent_uploads
+                .iter()
+                .copied()
+                .filter(|t| *t > window_start)
+                .collect();
+            recent.sort_by_key(|t| std::cmp::Reverse(*t));
+            if recent.len() >= max {
+                // Room frees up once the max-th newest upload leaves the window.
+                let retry_after = max
+                    .checked_sub(1)
+                    .and_then(|i| recent.get(i))
+                    .and_then(|t| (*t - window_start).to_std().ok())
+                    .unwrap_or(Duration::from_secs(60 * 60));
+                return Err(AppError::TooManyRequests {
+                    message: format!("{} accepts at most {max} uploads per hour", self.name),
+                    retry_after,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Fails if the project was renamed, so nothing new is published under
+    /// its old name.
+    pub(crate) fn ensure_active(&self) -> Result<(), AppError> {
+        match &self.renamed_to {
+            Some(new_name) => Err(AppError::Conflict(format!(
+                "{} was renamed to {new_name}",
+                self.name
+            ))),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Caps on how fast a single project may grow, guarding against runaway
+/// CI jobs. `None` means unlimited.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UploadLimits {
+    /// Distinct versions a project may have.
+    pub max_versions: Option<usize>,
+    /// Files a project may receive in any one-hour window.
+    pub max_uploads_per_hour: Option<usize>,
+}
+
+/// Which published files an upload may replace with different contents.
+#[derive(Debug, Clone, Default)]
+pub enum OverwritePolicy {
+    /// None: files are immutable once published, as on PyPI.
+    #[default]
+    Deny,
+    Allow,
+    /// Files of pre-releases, development releases and local versions,
+    /// such as `2.0.dev3` or `1.0+build.7`.
+    AllowPrereleases,
+    /// Files whose whole version matches the expression.
+    Matching(Regex),
+}
+
+impl OverwritePolicy {
+    pub fn allows(&self, version: &Version) -> bool {
+        match self {
+            OverwritePolicy::Deny => false,
+            OverwritePolicy::Allow => true,
+            OverwritePolicy::AllowPrereleases => version.is_prerelease() || version.is_local(),
+            OverwritePolicy::Matching(pattern) => pattern.is_match(version.as_str()),
+        }
+    }
+}
+
+/// What storing a file does to the file already published under its name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum UploadAction {
+    Store,
+    /// Replaces a published file with different contents, as the
+    /// overwrite policy allows.
+    Replace,
+    /// A retry of a file already stored with the same contents.
+    Unchanged,
+}
+
+/// `deny`, `allow`, `allow-prereleases-only`, or else a regular
+/// expression for the versions that may be overwritten.
+impl FromStr for OverwritePolicy {
+    type Err = AppError;
+
+    fn from_str(policy: &str) -> Result<Self, Self::Err> {
+        match policy {
+            "deny" => Ok(OverwritePolicy::Deny),
+            "allow" => Ok(OverwritePolicy::Allow),
+            "allow-prereleases-only" => Ok(OverwritePolicy::AllowPrereleases),
+            _ => Regex::new(&format!("^(?:{policy})$"))
+                .map(OverwritePolicy::Matching)
+                .map_err(|e| {
+                    AppError::InvalidFormat(format!("Invalid overwrite policy {policy:?}: {e}"))
+                }),
+        }
+    }
+}
+
+/// How mature a release is. Each channel's view also includes the more
+/// stable channels, so `nightly` consumers still get stable releases.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Channel {
+    Stable,
+    Beta,
+    Nightly,
+}
+
+impl Channel {
+    /// The default for releases not tagged at upload: dev releases are
+    /// nightlies and other pre-releases are betas.
+    pub fn for_version(version: &Version) -> Self {
+        if version.is_dev_release() {
+            Channel::Nightly
+        } else if version.is_prerelease() {
+            Channel::Beta
+        } else {
+            Channel::Stable
+        }
+    }
+
+    /// Whether releases on `other` are visible in this channel's view.
+    pub fn includes(self, other: Channel) -> bool {
+        other <= self
+    }
+}
+
+impl FromStr for Channel {
+    type Err = AppError;
+
+    fn from_str(channel: &str) -> Result<Self, Self::Err> {
+        match channel {
+            "stable" => Ok(Channel::Stable),
+            "beta" => Ok(Channel::Beta),
+            "nightly" => Ok(Channel::Nightly),
+            _ => Err(AppError::InvalidFormat(format!(
+                "Unknown channel {channel:?}; expected stable, beta or nightly"
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for Channel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Channel::Stable => "stable",
+            Channel::Beta => "beta",
+            Channel::Nightly => "nightly",
+        })
+    }
+}
+
+/// How a file entered the index.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "source", rename_all = "snake_case")]
+pub enum ProvenanceSource {
+    /// Published directly to this index.
+    Upload,
+    /// Attached to a release of a watched forge repository, written as
+    /// `github:owner/repo`.
+    ForgeRelease { repository: String },
+    /// Imported from a file on the server, with `pippy import`.
+    Import { path: String },
+    /// Found in storage rather than sent to the index, by `pippy reindex`
+    /// or a storage scan.
+    Reindex,
+}
+
+impl fmt::Display for ProvenanceSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProvenanceSource::Upload => f.write_str("upload"),
+            ProvenanceSource::ForgeRelease { repository } => {
+                write!(f, "{repository} release")
+            }
+            ProvenanceSource::Import { path } => write!(f, "import of {path}"),
+            ProvenanceSource::Reindex => f.write_str("reindex"),
+        }
+    }
+}
+
+/// Where a file came from and what it looked like on arrival.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Provenance {
+    #[serde(flatten)]
+    pub source: ProvenanceSource,
+    /// Location the file was fetched from, for files pulled from elsewhere.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upstream_url: Option<String>,
+    /// Digests of the bytes as received, keyed by algorithm.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub digests: BTreeMap<String, String>,
+}
+
+impl Provenance {
+    pub fn new(source: ProvenanceSource, sha256: String) -> Self {
+        Self {
+            source,
+            upstream_url: None,
+            digests: BTreeMap::from([("sha256".to_string(), sha256)]),
+        }
+    }
+
+    pub fn with_upstream_url(mut self, url: impl Into<String>) -> Self {
+        self.upstream_url = Some(url.into());
+        self
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Release {
+    pub version: Version,
+    pub filename: DistFilename,
+    pub upload_time: DateTime<Utc>,
+    /// Channel the release was tagged with at upload, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub channel: Option<Channel>,
+    /// Unknown for releases registered before provenance was recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<Provenance>,
+    /// Why the release should no longer be used, if it is deprecated.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deprecated: Option<String>,
+    /// Results of the post-publish enrichers, keyed by enricher name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub enrichments: BTreeMap<String, Value>,
+    /// Hex SHA-256 of the wheel's `METADATA`, served alongside it at
+    /// `<filename>.metadata` (PEP 658). Unset for sdists and for wheels
+    /// registered before it was extracted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub core_metadata: Option<String>,
+    /// The wheel's `Requires-Python` specifier, e.g. `>=3.9`, which
+    /// installers use to skip releases their interpreter cannot run.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub requires_python: Option<String>,
+    /// Whether the file is yanked (PEP 592): still downloadable, but
+    /// skipped by installers unless a requirement pins its exact version.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub yanked: bool,
+    /// Why the file was yanked, shown to users who install it anyway.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub yanked_reason: Option<String>,
+}
+
+impl Release {
+    /// A release uploaded now.
+    pub fn new(version: Version, filename: DistFilename) -> Self {
+        Self {
+            version,
+            filename,
+            upload_time: Utc::now(),
+            channel: None,
+            provenance: None,
+            deprecated: None,
+            enrichments: BTreeMap::new(),
+            core_metadata: None,
+            requires_python: None,
+            yanked: false,
+            yanked_reason: None,
+        }
+    }
+
+    pub fn with_provenance(mut self, provenance: Provenance) -> Self {
+        self.provenance = Some(provenance);
+        self
+    }
+
+    pub fn with_channel(mut self, channel: Option<Channel>) -> Self {
+        self.channel = channel;
+        self
+    }
+
+    /// The tagged channel, or the one implied by the version.
+    pub fn channel(&self) -> Channel {
+        self.channel
+            .unwrap_or_else(|| Channel::for_version(&self.version))
+    }
+
+    /// Hex SHA-256 of the file as received, if it was recorded.
+    pub fn sha256(&self) -> Option<&str> {
+        self.provenance
+            .as_ref()?
+            .digests
+            .get("sha256")
+            .map(String::as_str)
+    }
+
+    /// The `#sha256=` fragment installers verify downloads against, or
+    /// nothing when no digest was recorded.
+    pub fn hash_fragment(&self) -> String {
+        self.sha256()
+            .map(|digest| format!("#sha256={digest}"))
+            .unwrap_or_default()
+    }
+}
+
+/// A frozen copy of the index, served read-only under `/snapshots/<name>/`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub name: SnapshotName,
+    pub created: DateTime<Utc>,
+    pub packages: BTreeMap<PackageName, Package>,
+}
+
+/// Journaled changes whose project state is kept in the journal before
+/// compaction writes the metadata files out durably.
+const COMPACT_AFTER: usize = 1000;
+
+/// What a journaled change did.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Operation {
+    Release,
+    Docs,
+    Enrichment,
+    Owners,
+    /// An edit to project, release or file metadata, yanking included.
+    Update,
+    DeleteFile,
+    DeleteProject,
+    Webhook,
+    Rename,
+    /// A repair by `pippy fsck`.
+    Repair,
+    /// A rebuild from storage by `pippy reindex`.
+    Reindex,
+    /// Releases removed under the retention policy.
+    Prune,
+    /// Releases whose file was gone, dropped by `pippy gc`.
+    GarbageCollection,
+}
+
+/// A project as a change left it.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum ProjectState {
+    Saved(Box<Package>),
+    Removed,
+}
+
+/// One entry of the change journal, appended and synced to disk before
+/// every index write is acknowledged.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Change {
+    pub serial: u64,
+    pub project: PackageName,
+    pub time: DateTime<Utc>,
+    /// Missing from entries written before operations were recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub operation: Option<Operation>,
+    /// The project after the change, until compaction has made its
+    /// metadata file durable. Loading the index replays these over the
+    /// files, so a change survives a crash before its file was written.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub state: Option<ProjectState>,
+}
+
+/// How far into the change journal the in-memory index reflects.
+#[derive(Debug, Default)]
+struct JournalCursor {
+    offset: u64,
+    serial: u64,
+    /// Entries still carrying a project state.
+    pending: usize,
+}
+
+/// Projects kept in memory by default.
+const DEFAULT_CACHED_PROJECTS: usize = 10_000;
+
+/// Which listed projects are held in memory, and how recently each was
+/// used, so that the least recently used can be dropped again.
+#[derive(Debug)]
+struct ProjectCache {
+    /// Listed projects whose metadata is only in their files: not read
+    /// yet, or evicted since.
+    unloaded: BTreeSet<PackageName>,
+    /// Projects whose latest state is in the journal, kept in memory until
+    /// compaction writes their files out.
+    journaled: BTreeSet<PackageName>,
+    /// Projects in memory by when they were last used, and the reverse.
+    /// Projects read for the whole index count as used before any other.
+    by_use: BTreeMap<i64, PackageName>,
+    last_used: BTreeMap<PackageName, i64>,
+    uses: i64,
+    cold_uses: i64,
+    /// Most projects kept in memory besides the journaled ones.
+    capacity: usize,
+}
+
+impl ProjectCache {
+    fn touch(&mut self, name: &PackageName) {
+        self.uses += 1;
+        self.mark(name, self.uses);
+    }
+
+    fn touch_cold(&mut self, name: &PackageName) {
+        self.cold_uses -= 1;
+        self.mark(name, self.cold_uses);
+    }
+
+    fn mark(&mut self, name: &PackageName, used: i64) {
+        if let Some(previous) = self.last_used.insert(name.clone(), used) {
+            self.by_use.remove(&previous);
+        }
+        self.by_use.insert(used, name.clone());
+    }
+
+    fn forget(&mut self, name: &PackageName) {
+        if let Some(previous) = self.last_used.remove(name) {
+            self.by_use.remove(&previous);
+        }
+    }
+
+    fn over_capacity(&self, packages: &BTreeMap<PackageName, Package>) -> bool {
+        packages.len() > self.capacity.saturating_add(self.journaled.len())
+    }
+
+    /// Drops the least recently used projects from `packages` until it is
+    /// within capacity, other than journaled ones and `keep`.
+    fn evict(&mut self, packages: &mut BTreeMap<PackageName, Package>, keep: &[&PackageName]) {
+        let excess = packages
+            .len()
+            .saturating_sub(self.capacity.saturating_add(self.journaled.len()));
+        let evicted: Vec<PackageName> = self
+            .by_use
+            .values()
+            .filter(|&name| !self.journaled.contains(name) && !keep.contains(&name))
+            .take(excess)
+            .cloned()
+            .collect();
+        for name in evicted {
+            packages.remove(name.as_str());
+            self.forget(&name);
+            self.unloaded.insert(name);
+        }
+    }
+}
+
+/// The index as on startup: the name list, and the journal over it.
+/// Projects the journal saved or removed are as it left them, and kept in
+/// memory; the rest are read from their metadata files on first access.
+async fn read_catalog(
+    storage: &PackageStorage,
+    capacity: usize,
+) -> Result<(BTreeMap<PackageName, Package>, ProjectCache, JournalCursor), AppError> {
+    let names = storage.load_project_names().await?.unwrap_or_default();
+    // Read after the name list, so it is never older than it.
+    let (changes, offset) = storage.read_changes(0).await?;
+    let mut packages = BTreeMap::new();
+    let pending = replay(&mut packages, &changes);
+    let journaled: BTreeSet<PackageName> = changes
+        .iter()
+        .filter(|c| c.state.is_some())
+        .map(|c| c.project.clone())
+        .collect();
+    let mut cache = ProjectCache {
+        unloaded: names
+            .into_iter()
+            .filter(|name| !journaled.contains(name))
+            .collect(),
+        journaled,
+        by_use: BTreeMap::new(),
+        last_used: BTreeMap::new(),
+        uses: 0,
+        cold_uses: 0,
+        capacity,
+    };
+    for name in packages.keys() {
+        cache.touch(name);
+    }
+    let serial = match changes.last() {
+        Some(change) => change.serial,
+        None => storage.history_serial().await?,
+    };
+    let journal = JournalCursor {
+        offset,
+        serial,
+        pending,
+    };
+    Ok((packages, cache, journal))
+}
+
+/// Applies the project states in `changes` to `packages`, in order,
+/// returning how many there were.
+fn replay(packages: &mut BTreeMap<PackageName, Package>, changes: &[Change]) -> usize {
+    let mut pending = 0;
+    for change in changes {
+        match &change.state {
+            Some(ProjectState::Saved(package)) => {
+                packages.insert(change.project.clone(), (**package).clone());
+            }
+            Some(ProjectState::Removed) => {
+                packages.remove(change.project.as_str());
+            }
+            None => continue,
+        }
+        pending += 1;
+    }
+    pending
+}
+
+/// The `METADATA` of the wheel at `path`, or `None`, logged, if it has
+/// none or `filename` is not a wheel.
+async fn wheel_core_metadata(filename: &DistFilename, path: PathBuf) -> Option<Vec<u8>> {
+    if !filename.as_str().ends_with(".whl") {
+        return None;
+    }
+    match inspect::wheel_metadata(path).await {
+        Ok(Some(contents)) => Some(contents),
+        Ok(None) => {
+            warn!("No core metadata for {}: no METADATA member", filename);
+            None
+        }
+        Err(e) => {
+            warn!("No core metadata for {}: {}", filename, e);
+            None
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct PackageIndex {
+    /// Projects in memory. Reads go through `project` or `catalog`, and
+    /// writes through `write` or `write_all`, which read what they need
+    /// first.
+    packages: Arc<RwLock<BTreeMap<PackageName, Package>>>,
+    /// Which listed projects are in memory. Only changed under the write
+    /// lock of `packages`, other than marking projects used.
+    cache: Arc<std::sync::Mutex<ProjectCache>>,
+    pub(crate) storage: PackageStorage,
+    enrichers: EnricherRegistry,
+    journal: Arc<Mutex<JournalCursor>>,
+    snapshots: Arc<RwLock<BTreeMap<SnapshotName, Arc<Snapshot>>>>,
+    limits: UploadLimits,
+    overwrite: OverwritePolicy,
+    quotas: Quotas,
+    usage: DiskUsage,
+    retention: RetentionPolicy,
+    /// Whether changes need credentials, so that anonymous callers own
+    /// nothing.
+    credentials_required: bool,
+    pub(crate) webhooks: WebhookDispatcher,
+    pub(crate) stats: StatsRecorder,
+    /// Work spawned on behalf of requests, finished before shutdown.
+    pub(crate) tasks: TaskTracker,
+}
+
+impl PackageIndex {
+    pub async fn new(base_path: PathBuf) -> Result<Self, AppError> {
+        Self::with_storage(PackageStorage::new(base_path)?).await
+    }
+
+    /// An index over `storage`, for keeping files in a backend other than
+    /// the data directory.
+    pub async fn with_storage(storage: PackageStorage) -> Result<Self, AppError> {
+        if let Some(projects) = storage.split_legacy_index().await? {
+            info!(
+                "Split index.json into metadata files for {} projects",
+                projects
+            );
+        }
+        let moved = storage.normalize_project_names().await?;
+        if moved > 0 {
+            info!("Moved {} project directories to normalized names", moved);
+        }
+        let started = Instant::now();
+        let (packages, cache, journal) = read_catalog(&storage, DEFAULT_CACHED_PROJECTS).await?;
+        info!(
+            "Listed {} projects in {:.1?}, replaying {} journaled changes",
+            packages.len() + cache.unloaded.len(),
+            started.elapsed(),
+            journal.pending
+        );
+        let packages = Arc::new(RwLock::new(packages));
+
+        let tasks = TaskTracker::new();
+        let webhooks = WebhookDispatcher::new(storage.clone(), tasks.clone());
+        let stats = StatsRecorder::new(storage.clone());
+        Ok(Self {
+            packages,
+            cache: Arc::new(std::sync::Mutex::new(cache)),
+            storage,
+            enrichers: EnricherRegistry::with_defaults(),
+            journal: Arc::new(Mutex::new(journal)),
+            snapshots: Arc::default(),
+            limits: UploadLimits::default(),
+            overwrite: OverwritePolicy::default(),
+            quotas: Quotas::default(),
+            usage: DiskUsage::default(),
+            retention: RetentionPolicy::default(),
+            credentials_required: false,
+            webhooks,
+            stats,
+            tasks,
+        })
+    }
+
+    pub fn with_limits(mut self, limits: UploadLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn with_overwrite_policy(mut self, policy: OverwritePolicy) -> Self {
+        self.overwrite = policy;
+        self
+    }
+
+    pub fn with_quotas(mut self, quotas: Quotas) -> Self {
+        self.quotas = quotas;
+        self
+    }
+
+    pub fn with_retention(mut self, retention: RetentionPolicy) -> Self {
+        self.retention = retention;
+        self
+    }
+
+    /// Keeps at most `capacity` projects in memory, besides those whose
+    /// latest state only the journal holds.
+    pub fn with_cached_projects(self, capacity: usize) -> Self {
+        self.cache.lock().unwrap().capacity = capacity;
+        self
+    }
+
+    pub fn with_credentials_required(mut self, required: bool) -> Self {
+        self.credentials_required = required;
+        self
+    }
+
+    pub fn with_stats_retention(mut self, retention: StatsRetention) -> Self {
+        self.stats.set_retention(retention);
+        self
+    }
+
+    pub fn limits(&self) -> &UploadLimits {
+        &self.limits
+    }
+
+    pub fn overwrite_policy(&self) -> &OverwritePolicy {
+        &self.overwrite
+    }
+
+    pub fn quotas(&self) -> &Quotas {
+        &self.quotas
+    }
+
+    pub fn retention(&self) -> &RetentionPolicy {
+        &self.retention
+    }
+
+    /// Fails if `name` would be over quota, or put the index over its
+    /// quota, holding `extra` bytes more than it has stored now.
+    pub(crate) async fn check_quota(&self, name: &PackageName, extra: i64) -> Result<(), AppError> {
+        if self.quotas.project_bytes.is_none() && self.quotas.total_bytes.is_none() {
+            return Ok(());
+        }
+        let (project, total) = self.usage.refresh(&self.storage, name).await?;
+        self.quotas.check(
+            name,
+            project.saturating_add_signed(extra),
+            total.saturating_add_signed(extra),
+        )
+    }
+
+    /// Counts `name`'s stored bytes again after files of it were removed.
+    pub(crate) async fn recount(&self, name: &PackageName) {
+        if let Err(e) = self.usage.refresh(&self.storage, name).await {
+            warn!("Counting the stored bytes of {} failed: {}", name, e);
+        }
+    }
+
+    /// Stored bytes per project and in total, counted from storage.
+    pub async fn usage(&self) -> Result<UsageReport, AppError> {
+        let projects = self.usage.rescan(&self.storage).await?;
+        Ok(UsageReport {
+            total_bytes: projects.values().sum(),
+            quotas: self.quotas,
+            projects,
+        })
+    }
+
+    pub fn storage(&self) -> &PackageStorage {
+        &self.storage
+    }
+
+    /// Serial of the most recent change this process has seen.
+    pub async fn serial(&self) -> u64 {
+        self.journal.lock().await.serial
+    }
+
+    /// The index for reading `name`, after reading its metadata file if
+    /// it is not in memory.
+    pub(crate) async fn project(
+        &self,
+        name: &PackageName,
+    ) -> Result<RwLockReadGuard<'_, BTreeMap<PackageName, Package>>, AppError> {
+        {
+            let packages = self.packages.read().await;
+            let mut cache = self.cache.lock().unwrap();
+            if !cache.unloaded.contains(name) && !cache.over_capacity(&packages) {
+                if packages.contains_key(name.as_str()) {
+                    cache.touch(name);
+                }
+                drop(cache);
+                return Ok(packages);
+            }
+        }
+        let mut packages = self.packages.write().await;
+        self.load(&mut packages, &[name]).await?;
+        Ok(packages.downgrade())
+    }
+
+    /// The index for reading every project, after reading the metadata
+    /// files of those not in memory. They are evicted first once the
+    /// cache is over capacity.
+    pub(crate) async fn catalog(
+        &self,
+    ) -> Result<RwLockReadGuard<'_, BTreeMap<PackageName, Package>>, AppError> {
+        {
+            let packages = self.packages.read().await;
+            if self.cache.lock().unwrap().unloaded.is_empty() {
+                return Ok(packages);
+            }
+        }
+        let mut packages = self.packages.write().await;
+        self.load_all(&mut packages).await?;
+        Ok(packages.downgrade())
+    }
+
+    /// Reads the metadata files of those of `projects` not in memory into
+    /// `packages`, which must be the guarded map, marks them used, and
+    /// evicts the least recently used others beyond the cache's capacity.
+    async fn load(
+        &self,
+        packages: &mut BTreeMap<PackageName, Package>,
+        projects: &[&PackageName],
+    ) -> Result<(), AppError> {
+        let wanted: Vec<PackageName> = {
+            let cache = self.cache.lock().unwrap();
+            projects
+                .iter()
+                .filter(|&&name| cache.unloaded.contains(name))
+                .map(|&name| name.clone())
+                .collect()
+        };
+        if !wanted.is_empty() {
+            packages.extend(self.storage.load_projects(wanted.iter().cloned()).await?);
+        }
+        let mut cache = self.cache.lock().unwrap();
+        for name in &wanted {
+            cache.unloaded.remove(name);
+        }
+        for &name in projects {
+            if packages.contains_key(name.as_str()) {
+                cache.touch(name);
+            }
+        }
+        cache.evict(packages, projects);
+        Ok(())
+    }
+
+    /// Reads the metadata files of every project not in memory into
+    /// `packages`, which must be the guarded map.
+    async fn load_all(
+        &self,
+        packages: &mut BTreeMap<PackageName, Package>,
+    ) -> Result<(), AppError> {
+        let unloaded: Vec<PackageName> = {
+            let cache = self.cache.lock().unwrap();
+            cache.unloaded.iter().cloned().collect()
+        };
+        if unloaded.is_empty() {
+            return Ok(());
+        }
+        packages.extend(self.storage.load_projects(unloaded.iter().cloned()).await?);
+        let mut cache = self.cache.lock().unwrap();
+        for name in &unloaded {
+            cache.unloaded.remove(name);
+            if packages.contains_key(name.as_str()) {
+                cache.touch_cold(name);
+            }
+        }
+        Ok(())
+    }
+
+    /// Takes the index for writing, with `projects` read, first reloading
+    /// it if another process sharing the data directory has written since
+    /// it was last read. Other projects may or may not be in memory, so
+    /// the operation must only look at these; see `write_all`.
+    pub(crate) async fn write(
+        &self,
+        projects: &[&PackageName],
+    ) -> Result<
+        (
+            RwLockWriteGuard<'_, BTreeMap<PackageName, Package>>,
+            IndexLock,
+        ),
+        AppError,
+    > {
+        let mut packages = self.packages.write().await;
+        let lock = self.storage.lock_index().await?;
+        self.follow_journal(&mut packages).await?;
+        self.load(&mut packages, projects).await?;
+        Ok((packages, lock))
+    }
+
+    /// Like `write`, with every project read, for operations over the
+    /// whole index.
+    pub(crate) async fn write_all(
+        &self,
+    ) -> Result<
+        (
+            RwLockWriteGuard<'_, BTreeMap<PackageName, Package>>,
+            IndexLock,
+        ),
+        AppError,
+    > {
+        let mut packages = self.packages.write().await;
+        let lock = self.storage.lock_index().await?;
+        self.follow_journal(&mut packages).await?;
+        self.load_all(&mut packages).await?;
+        Ok((packages, lock))
+    }
+
+    /// Reads the index again, as on startup, if another process sharing
+    /// the data directory has written to the journal since it was last
+    /// read. `packages` must be the guarded map.
+    async fn follow_journal(
+        &self,
+        packages: &mut BTreeMap<PackageName, Package>,
+    ) -> Result<(), AppError> {
+        let mut journal = self.journal.lock().await;
+        if self.storage.journal_len().await? == journal.offset {
+            return Ok(());
+        }
+        let capacity = self.cache.lock().unwrap().capacity;
+        let (reloaded, cache, cursor) = read_catalog(&self.storage, capacity).await?;
+        *packages = reloaded;
+        *self.cache.lock().unwrap() = cache;
+        *journal = cursor;
+        info!("Reloaded index at serial {}", journal.serial);
+        Ok(())
+    }
+
+    /// The projects held in memory, by name.
+    pub async fn cached_projects(&self) -> Vec<PackageName> {
+        self.packages.read().await.keys().cloned().collect()
+    }
+
+    /// Records what `operation` left `projects` as in the change journal,
+    /// synced to disk, then saves their metadata files. Once journaled, the
+    /// change is kept even if saving the files fails, since loading replays
+    /// the journal over them. Must be called while the guard returned by
+    /// `write` is still held.
+    pub(crate) async fn commit(
+        &self,
+        packages: &BTreeMap<PackageName, Package>,
+        projects: &[&PackageName],
+        operation: Operation,
+    ) -> Result<(), AppError> {
+        let mut journal = self.journal.lock().await;
+        let time = Utc::now();
+        let changes: Vec<Change> = projects
+            .iter()
+            .zip(journal.serial + 1..)
+            .map(|(&project, serial)| Change {
+                serial,
+                project: project.clone(),
+                time,
+                operation: Some(operation),
+                state: Some(match packages.get(project.as_str()) {
+                    Some(package) => ProjectState::Saved(Box::new(package.clone())),
+                    None => ProjectState::Removed,
+                }),
+            })
+            .collect();
+        if let Err(e) = self.storage.append_changes(&changes).await {
+            // The caller undoes the change, so none of it may be replayed.
+            let _ = self.storage.truncate_journal(journal.offset).await;
+            return Err(e);
+        }
+        journal.serial += changes.len() as u64;
+        journal.pending += changes.len();
+        {
+            let mut cache = self.cache.lock().unwrap();
+            for &project in projects {
+                cache.journaled.insert(project.clone());
+                if packages.contains_key(project.as_str()) {
+                    cache.touch(project);
+                } else {
+                    cache.forget(project);
+                }
+            }
+        }
+        if let Err(e) = self.storage.save_projects(packages, projects).await {
+            warn!(
+                "Saving metadata failed, the journal keeps it until compaction: {}",
+                e
+            );
+        }
+        if journal.pending >= COMPACT_AFTER {
+            if let Err(e) = self.compact(packages, &mut journal).await {
+                warn!("Compacting the change journal failed: {}", e);
+            }
+        }
+        journal.offset = self.storage.journal_len().await?;
+        Ok(())
+    }
+
+    /// Writes out and syncs the metadata files of every project with a
+    /// state in the journal, then moves its entries, without the states,
+    /// to the bounded history.
+    pub async fn compact_journal(&self) -> Result<(), AppError> {
+        let (packages, _lock) = self.write(&[]).await?;
+        let mut journal = self.journal.lock().await;
+        self.compact(&packages, &mut journal).await?;
+        journal.offset = self.storage.journal_len().await?;
+        Ok(())
+    }
+
+    async fn compact(
+        &self,
+        packages: &BTreeMap<PackageName, Package>,
+        journal: &mut JournalCursor,
+    ) -> Result<(), AppError> {
+        let content = self.storage.read_journal().await?;
+        let mut touched = BTreeSet::new();
+        let mut compacted = Vec::new();
+        let mut kept = String::new();
+        for line in content.lines() {
+            match serde_json::from_str::<Change>(line) {
+                Ok(mut change) => {
+                    if change.state.take().is_some() {
+                        touched.insert(change.project.clone());
+                    }
+                    compacted.push(change);
+                }
+                // Unreadable lines are left for `pippy fsck`.
+                Err(_) => {
+                    kept.push_str(line);
+                    kept.push('\n');
+                }
+            }
+        }
+        let projects: Vec<&PackageName> = touched.iter().collect();
+        self.storage.save_projects(packages, &projects).await?;
+        self.storage.sync_projects(&projects).await?;
+        self.storage.append_history(&compacted).await?;
+        self.storage.replace_journal(kept).await?;
+        info!(
+            "Compacted the change journal, {} projects written out",
+            projects.len()
+        );
+        journal.pending = 0;
+        self.cache.lock().unwrap().journaled.clear();
+        Ok(())
+    }
+
+    /// Reloads the index whenever another process sharing the data
+    /// directory writes to it, checking every `interval`.
+    pub async fn follow_changes(self, interval: Duration) {
+        loop {
+            tokio::time::sleep(interval).await;
+            let offset = self.journal.lock().await.offset;
+            match self.storage.journal_len().await {
+                Ok(len) if len == offset => {}
+                Ok(_) => {
+                    if let Err(e) = self.write(&[]).await {
+                        warn!("Reloading the index failed: {}", e);
+                    }
+                }
+                Err(e) => warn!("Checking the change journal failed: {}", e),
+            }
+        }
+    }
+
+    /// Delivers queued webhook events and retries failed ones, for as long
+    /// as the server runs.
+    pub async fn deliver_webhooks(self) {
+        self.webhooks.clone().run(self).await
+    }
+
+    /// Saves the download and upload counts every `interval`, for as long
+    /// as the server runs.
+    pub async fn flush_stats(self, interval: Duration) {
+        self.stats.run(interval).await
+    }
+
+    /// Finishes buffered work before the process exits: waits for
+    /// enrichment and webhook queueing spawned by requests, then for any
+    /// index and journal write in progress, and saves the pending stats.
+    /// Whatever is still undone at `timeout` is logged. Returns whether
+    /// everything was saved.
+    pub async fn shutdown(&self, timeout: Duration) -> bool {
+        let deadline = tokio::time::Instant::now() + timeout;
+        let mut complete = true;
+        self.tasks.close();
+        if tokio::time::timeout_at(deadline, self.tasks.wait())
+            .await
+            .is_err()
+        {
+            warn!(
+                "Shutdown: {} enrichment or webhook tasks did not finish",
+                self.tasks.len()
+            );
+            complete = false;
+        }
+        // Every index change is saved and journaled under the write lock,
+        // so taking it waits out the one in progress.
+        if tokio::time::timeout_at(deadline, self.packages.write())
+            .await
+            .is_err()
+        {
+            warn!("Shutdown: an index write did not finish");
+            complete = false;
+        }
+        let unsaved = self.stats.pending_projects();
+        match tokio::time::timeout_at(deadline, self.stats.flush()).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => {
+                warn!(
+                    "Shutdown: stats of {} projects were not saved: {}",
+                    unsaved, e
+                );
+                complete = false;
+            }
+            Err(_) => {
+                warn!(
+                    "Shutdown: stats of {} projects were not saved in time",
+                    unsaved
+                );
+                complete = false;
+            }
+        }
+        if complete {
+            info!("Shutdown: all pending state saved");
+        }
+        complete
+    }
+
+    /// Replaces the enrichers run after each new release.
+    pub fn with_enrichers(mut self, enrichers: EnricherRegistry) -> Self {
+        self.enrichers = enrichers;
+        self
+    }
+
+    /// What storing `filename` of `name`, of `version` and with SHA-256
+    /// `sha256`, does given the SHA-256 of the file already stored under
+    /// that name, if any. Unless the overwrite policy says otherwise, files
+    /// are immutable once published, as on PyPI, and files a snapshot keeps
+    /// always are.
+    pub(crate) async fn upload_action(
+        &self,
+        name: &PackageName,
+        filename: &DistFilename,
+        version: &Version,
+        sha256: &str,
+        stored: Option<&str>,
+    ) -> Result<UploadAction, AppError> {
+        match stored {
+            None => return Ok(UploadAction::Store),
+            Some(stored) if stored == sha256 => return Ok(UploadAction::Unchanged),
+            // twine's --skip-existing recognizes the status.
+            Some(_) if !self.overwrite.allows(version) => {
+                return Err(AppError::Conflict(format!(
+                "{filename} already exists with different contents; publish a new version instead"
+            )))
+            }
+            Some(_) => {}
+        }
+        // Snapshots pin the digest of every file they list.
+        let kept_by = self.kept_by_snapshots(name, Some(filename)).await?;
+        if !kept_by.is_empty() {
+            return Err(AppError::Conflict(format!(
+                "{filename} is kept by snapshots {}; publish a new version instead",
+                kept_by.join(", ")
+            )));
+        }
+        Ok(UploadAction::Replace)
+    }
+
+    /// When files were last published to `name` within the hour, as the
+    /// change journal records them, so deleting or overwriting files does
+    /// not make room under `max_uploads_per_hour`. Nothing is read when
+    /// there is no such limit.
+    pub(crate) async fn recent_uploads(
+        &self,
+        name: &PackageName,
+    ) -> Result<Vec<DateTime<Utc>>, AppError> {
+        if self.limits.max_uploads_per_hour.is_none() {
+            return Ok(Vec::new());
+        }
+        let since = Utc::now() - chrono::Duration::hours(1);
+        Ok(self
+            .storage
+            .changes_since(since)
+            .await?
+            .into_iter()
+            .filter(|c| c.project == *name && c.operation == Some(Operation::Release))
+            .map(|c| c.time)
+            .collect())
+    }
+
+    /// Whether `uploader` may add `release` to `package`, and what adding it
+    /// does to the file the project already lists under its name.
+    async fn accept_release(
+        &self,
+        package: &Package,
+        release: &Release,
+        uploader: Option<&Identity>,
+    ) -> Result<UploadAction, AppError> {
+        package.ensure_active()?;
+        if let Some(uploader) = uploader {
+            uploader.may_change(package)?;
+        }
+        let recent_uploads = self.recent_uploads(&package.name).await?;
+        package.check_limits(&release.version, &self.limits, &recent_uploads)?;
+        let Some(listed) = package
+            .releases
+            .iter()
+            .find(|r| r.filename == release.filename)
+        else {
+            return Ok(UploadAction::Store);
+        };
+        let hash = |recorded: Option<&str>| {
+            let recorded = recorded.map(str::to_owned);
+            async move {
+                match recorded {
+                    Some(sha256) => Ok(sha256),
+                    None => self.storage.sha256(&package.name, &release.filename).await,
+                }
+            }
+        };
+        let stored = hash(listed.sha256()).await?;
+        let sha256 = hash(release.sha256()).await?;
+        self.upload_action(
+            &package.name,
+            &release.filename,
+            &release.version,
+            &sha256,
+            Some(&stored),
+        )
+        .await
+    }
+
+    /// Adds a stored file to the index. A new project is claimed by the user
+    /// `uploader` acts as, and an existing one must be theirs to change. A
+    /// file the project already lists with other contents is replaced only
+    /// as the overwrite policy allows, checked under the index lock whatever
+    /// the caller checked before.
+    pub async fn add_release(
+        &self,
+        name: PackageName,
+        release: Release,
+        uploader: Option<&Identity>,
+    ) -> Result<(), AppError> {
+        self.insert_release(name, release, uploader, None)
+            .await
+            .map(|_| ())
+    }
+
+    /// Publishes an upload received into `spooled`, as
+    /// [`add_release`](Self::add_release) does a stored file. It is moved
+    /// into place only once accepted under the index lock, so an upload
+    /// refused there, such as the loser of two racing uploads of one
+    /// filename, never touches the published file.
+    pub(crate) async fn publish(
+        &self,
+        name: PackageName,
+        release: Release,
+        uploader: Option<&Identity>,
+        spooled: SpooledPackage,
+    ) -> Result<UploadAction, AppError> {
+        self.insert_release(name, release, uploader, Some(spooled))
+            .await
+    }
+
+    async fn insert_release(
+        &self,
+        name: PackageName,
+        mut release: Release,
+        uploader: Option<&Identity>,
+        spooled: Option<SpooledPackage>,
+    ) -> Result<UploadAction, AppError> {
+        let size = match &spooled {
+            Some(spooled) => spooled.size(),
+            None => match self.storage.stat(&name, &release.filename).await {
+                Ok(Some(stored)) => stored.size,
+                _ => 0,
+            },
+        };
+        // Read before taking the lock, and stored only once accepted.
+        let core_metadata = match &spooled {
+            _ if release.core_metadata.is_some()
+                || !release.filename.as_str().ends_with(".whl") =>
+            {
+                None
+            }
+            Some(spooled) => {
+                wheel_core_metadata(&release.filename, spooled.path().to_path_buf()).await
+            }
+            None => match self.storage.local_file(&name, &release.filename).await {
+                Ok(file) => wheel_core_metadata(&release.filename, file.path().to_path_buf()).await,
+                Err(e) => {
+                    warn!("No core metadata for {}: {}", release.filename, e);
+                    None
+                }
+            },
+        };
+        let (mut packages, lock) = self.write(&[&name]).await?;
+        let created = !packages.contains_key(name.as_str());
+        let package = packages.entry(name.clone()).or_insert_with(|| {
+            let mut package = Package::new(name.clone());
+            if let Some(user) = uploader.and_then(|u| u.user.as_ref()) {
+                package.owners.push(user.clone());
+            }
+            package
+        });
+        let mut accepted = self.accept_release(package, &release, uploader).await;
+        if let (Ok(UploadAction::Store | UploadAction::Replace), Some(spooled)) =
+            (&accepted, spooled)
+        {
+            if let Err(e) = self
+                .storage
+                .store_spooled(&name, &release.filename, spooled)
+                .await
+            {
+                accepted = Err(e);
+            }
+        }
+        let action = match accepted {
+            Ok(UploadAction::Unchanged) => return Ok(UploadAction::Unchanged),
+            Ok(action) => action,
+            Err(e) => {
+                if created {
+                    packages.remove(name.as_str());
+                }
+                return Err(e);
+            }
+        };
+        let extracted = match core_metadata {
+            Some(contents) => {
+                self.record_core_metadata(&name, &mut release, contents)
+                    .await
+            }
+            None => false,
+        };
+        let Some(package) = packages.get_mut(name.as_str()) else {
+            return Err(AppError::NotFound(name.to_string()));
+        };
+
+        let version = release.version.clone();
+        let filename = release.filename.clone();
+        let upload_time = release.upload_time;
+        // A file overwritten under the overwrite policy keeps one entry.
+        let replaced = package
+            .releases
+            .iter()
+            .position(|r| r.filename == filename)
+            .map(|i| package.releases.remove(i));
+        package.releases.push(release);
+
+        package.sort_releases();
+        if let Err(e) = self.commit(&packages, &[&name], Operation::Release).await {
+            // Keep serving the last persisted state rather than a release
+            // that would vanish on restart.
+            if let Some(package) = packages.get_mut(name.as_str()) {
+                package
+                    .releases
+                    .retain(|r| r.filename != filename || r.upload_time != upload_time);
+                package.releases.extend(replaced);
+                package.sort_releases();
+                if package.releases.is_empty() && package.docs.is_empty() {
+                    packages.remove(name.as_str());
+                }
+            }
+            if extracted {
+                self.remove_core_metadata(&name, &filename).await;
+            }
+            return Err(e);
+        }
+        self.stats.record(&name, StatKind::Upload, size);
+        let hooks = packages
+            .get(name.as_str())
+            .map(|p| p.webhooks.clone())
+            .unwrap_or_default();
+        drop(packages);
+        drop(lock);
+
+        self.webhooks.dispatch(
+            &name,
+            hooks,
+            WebhookEvent::Release,
+            json!({
+                "event": "release",
+                "project": name,
+                "version": version,
+                "filename": filename,
+                "upload_time": upload_time,
+            }),
+        );
+        if !self.retention.is_unlimited() {
+            // The upload itself is never pruned, even when it is of an
+            // older version than the policy keeps.
+            if let Err(e) = self.apply_retention(&name, Some(&version)).await {
+                warn!("Applying the retention policy to {} failed: {}", name, e);
+            }
+        }
+        self.enrichers.spawn(self.clone(), name, version, filename);
+        Ok(action)
+    }
+
+    /// Stores a wheel's `METADATA` next to it and records its digest and
+    /// `Requires-Python` on the release, returning whether it did. A wheel
+    /// without one is still indexed, just without PEP 658 metadata.
+    pub(crate) async fn extract_core_metadata(
+        &self,
+        name: &PackageName,
+        release: &mut Release,
+    ) -> bool {
+        let filename = &release.filename;
+        if !filename.as_str().ends_with(".whl") {
+            return false;
+        }
+        let contents = match self.storage.local_file(name, filename).await {
+            Ok(file) => wheel_core_metadata(filename, file.path().to_path_buf()).await,
+            Err(e) => {
+                warn!("No core metadata for {}: {}", filename, e);
+                None
+            }
+        };
+        match contents {
+            Some(contents) => self.record_core_metadata(name, release, contents).await,
+            None => false,
+        }
+    }
+
+    /// Stores `contents` as the core metadata of `release`, recording its
+    /// digest and `Requires-Python`, and returns whether it did.
+    async fn record_core_metadata(
+        &self,
+        name: &PackageName,
+        release: &mut Release,
+        contents: Vec<u8>,
+    ) -> bool {
+        let filename = &release.filename;
+        let requires_python =
+            inspect::metadata_values(&String::from_utf8_lossy(&contents), "Requires-Python")
+                .next()
+                .and_then(non_empty);
+        match self
+            .storage
+            .store_core_metadata(name, filename, contents)
+            .await
+        {
+            Ok(sha256) => {
+                release.core_metadata = Some(sha256);
+                release.requires_python = requires_python;
+                true
+            }
+            Err(e) => {
+                warn!("No core metadata for {}: {}", filename, e);
+                false
+            }
+        }
+    }
+
+    async fn remove_core_metadata(&self, name: &PackageName, filename: &DistFilename) {
+        let _ = self.storage.remove_core_metadata(name, filename).await;
+    }
+
+    pub(crate) async fn record_enrichment(
+        &self,
+        name: &PackageName,
+        filename: &DistFilename,
+        key: &str,
+        value: Value,
+    ) -> Result<(), AppError> {
+        let (mut packages, _lock) = self.write(&[name]).await?;
+        let release = packages
+            .get_mut(name.as_str())
+            .and_then(|p| p.releases.iter_mut().find(|r| r.filename == *filename))
+            .ok_or_else(|| AppError::NotFound(filename.to_string()))?;
+
+        release.enrichments.insert(key.to_string(), value);
+        self.commit(&packages, &[name], Operation::Enrichment).await
+    }
+
+    /// Opens one of a project's files for download, counting the download.
+    pub async fn open_file(
+        &self,
+        name: &PackageName,
+        filename: &DistFilename,
+    ) -> Result<(ObjectReader, u64), AppError> {
+        let listed = self
+            .project(name)
+            .await?
+            .get(name.as_str())
+            .is_some_and(|p| p.releases.iter().any(|r| r.filename == *filename));
+        if !listed {
+            return Err(AppError::NotFound(filename.to_string()));
+        }
+        let (file, size) = self.storage.open_package(name, filename).await?;
+        self.stats.record(name, StatKind::Download, size);
+        Ok((file, size))
+    }
+
+    /// Fails if `filename` of `name` is yanked, saying why, unless the
+    /// download is forced by `forced_by` and they may yank it. Files that
+    /// are not listed are left to [`open_file`](Self::open_file).
+    pub(crate) async fn check_not_yanked(
+        &self,
+        name: &PackageName,
+        filename: &DistFilename,
+        forced_by: Option<&Identity>,
+    ) -> Result<(), AppError> {
+        let packages = self.project(name).await?;
+        let Some(package) = packages.get(name.as_str()) else {
+            return Ok(());
+        };
+        let Some(release) = package
+            .releases
+            .iter()
+            .find(|r| r.filename == *filename && r.yanked)
+        else {
+            return Ok(());
+        };
+        if let Some(identity) = forced_by {
+            return identity.may_yank(package);
+        }
+        let reason = release
+            .yanked_reason
+            .as_deref()
+            .map_or(String::new(), |reason| format!(" ({reason})"));
+        Err(AppError::Forbidden(format!(
+            "{filename} is yanked{reason}; add ?force=true, with credentials that may yank it, to download it anyway"
+        )))
+    }
+
+    /// Asks the project's `build_requested` hooks for wheels of the
+    /// release an sdist belongs to, after a client that found none it
+    /// could install downloaded it from `url`. Wheels they build come back
+    /// as ordinary uploads.
+    pub(crate) async fn request_build(
+        &self,
+        name: &PackageName,
+        filename: &DistFilename,
+        url: String,
+        user_agent: Option<String>,
+    ) {
+        if !filename.as_str().ends_with(".tar.gz") {
+            return;
+        }
+        let (hooks, version) = {
+            let packages = match self.project(name).await {
+                Ok(packages) => packages,
+                Err(e) => {
+                    warn!("Reading {} for a build request failed: {}", name, e);
+                    return;
+                }
+            };
+            let Some(package) = packages.get(name.as_str()) else {
+                return;
+            };
+            let Some(release) = package.releases.iter().find(|r| r.filename == *filename) else {
+                return;
+            };
+            let hooks: Vec<Webhook> = package
+                .webhooks
+                .iter()
+                .filter(|hook| hook.events.contains(&WebhookEvent::BuildRequested))
+                .cloned()
+                .collect();
+            (hooks, release.version.clone())
+        };
+        if hooks.is_empty() || !self.webhooks.claim_build_request(name, filename.as_str()) {
+            return;
+        }
+        self.webhooks.dispatch(
+            name,
+            hooks,
+            WebhookEvent::BuildRequested,
+            json!({
+                "event": "build_requested",
+                "project": name,
+                "version": version,
+                "filename": filename,
+                "url": url,
+                // pip describes the interpreter and platform here.
+                "user_agent": user_agent,
+            }),
+        );
+    }
+
+    /// Opens the PEP 658 metadata of a listed file, with its size.
+    pub async fn open_core_metadata(
+        &self,
+        name: &PackageName,
+        filename: &DistFilename,
+    ) -> Result<(ObjectReader, u64), AppError> {
+        let extracted = self
+            .project(name)
+            .await?
+            .get(name.as_str())
+            .and_then(|p| p.releases.iter().find(|r| r.filename == *filename))
+            .is_some_and(|r| r.core_metadata.is_some());
+        if !extracted {
+            return Err(AppError::NotFound(format!("{filename}.metadata")));
+        }
+        self.storage.open_core_metadata(name, filename).await
+    }
+
+    /// A copy of every project, tombstones of renamed ones included, by
+    /// name.
+    pub async fn packages(&self) -> Result<Vec<Package>, AppError> {
+        Ok(self.catalog().await?.values().cloned().collect())
+    }
+
+    pub async fn has_file(
+        &self,
+        name: &PackageName,
+        filename: &DistFilename,
+    ) -> Result<bool, AppError> {
+        Ok(self
+            .project(name)
+            .await?
+            .get(name.as_str())
+            .is_some_and(|p| p.releases.iter().any(|r| r.filename == *filename)))
+    }
+
+    /// The SHA-256 of a stored file of `name`, as recorded for its release
+    /// or else hashed from storage, or `None` if the index has no such file.
+    pub(crate) async fn file_sha256(
+        &self,
+        name: &PackageName,
+        filename: &DistFilename,
+    ) -> Result<Option<String>, AppError> {
+        let recorded = match self.project(name).await?.get(name.as_str()) {
+            Some(package) => match package.releases.iter().find(|r| r.filename == *filename) {
+                Some(release) => release.sha256().map(str::to_owned),
+                None => return Ok(None),
+            },
+            None => return Ok(None),
+        };
+        match recorded {
+            Some(sha256) => Ok(Some(sha256)),
+            None => Ok(Some(self.storage.sha256(name, filename).await?)),
+        }
+    }
+
+    /// Whether a file this index lists has the hex SHA-256 `sha256` and
+    /// `reader` may download it, as publishing by digest alone needs.
+    /// Deleted and pruned files do not count, though their bytes may linger
+    /// in storage until garbage collection, nor do yanked ones when yanked
+    /// downloads are refused, unless `reader` may yank them.
+    pub(crate) async fn lists_sha256(
+        &self,
+        sha256: &str,
+        reader: Option<&Identity>,
+        refuse_yanked: bool,
+    ) -> Result<bool, AppError> {
+        let packages = self.catalog().await?;
+        Ok(packages.values().any(|package| {
+            package.releases.iter().any(|release| {
+                release.sha256() == Some(sha256)
+                    && (!release.yanked
+                        || !refuse_yanked
+                        || reader.is_some_and(|reader| reader.may_yank(package).is_ok()))
+            })
+        }))
+    }
+
+    pub async fn add_docs(&self, name: &PackageName, version: &Version) -> Result<(), AppError> {
+        let (mut packages, _lock) = self.write(&[name]).await?;
+        let package = packages
+            .get_mut(name.as_str())
+            .ok_or_else(|| AppError::NotFound(name.to_string()))?;
+        package.ensure_active()?;
+
+        package.docs.retain(|v| v != version);
+        package.docs.push(version.clone());
+        self.commit(&packages, &[name], Operation::Docs).await
+    }
+
+    /// Fails unless `identity` may change `name`, checked before the work
+    /// of an edit. Projects that do not exist yet are open to anyone, and
+    /// without credentials configured, so is everything else.
+    pub(crate) async fn check_owner(
+        &self,
+        name: &PackageName,
+        identity: Option<&Identity>,
+    ) -> Result<(), AppError> {
+        match (identity, self.project(name).await?.get(name.as_str())) {
+            (Some(identity), Some(package)) => identity.may_change(package),
+            (None, _) if self.credentials_required => {
+                Err(AppError::Unauthorized("Credentials are required".into()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Like [`check_owner`](Self::check_owner), for yanking and unyanking
+    /// files, which tokens with the `yank` scope may also do.
+    pub(crate) async fn check_yanker(
+        &self,
+        name: &PackageName,
+        identity: Option<&Identity>,
+    ) -> Result<(), AppError> {
+        match (identity, self.project(name).await?.get(name.as_str())) {
+            (Some(identity), Some(package)) => identity.may_yank(package),
+            (None, _) if self.credentials_required => {
+                Err(AppError::Unauthorized("Credentials are required".into()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Replaces the owners of a project, recording it in the audit log.
+    pub(crate) async fn set_owners(
+        &self,
+        name: &PackageName,
+        update: OwnersUpdate,
+    ) -> Result<Package, AppError> {
+        update.validate()?;
+        let (mut packages, _lock) = self.write(&[name]).await?;
+        let package = packages
+            .get_mut(name.as_str())
+            .ok_or_else(|| AppError::NotFound(name.to_string()))?;
+        package.ensure_active()?;
+
+        let before = serde_json::to_value(OwnersUpdate {
+            owners: package.owners.clone(),
+        })?;
+        let previous = std::mem::replace(&mut package.owners, update.owners.clone());
+        let updated = package.clone();
+        if let Err(e) = self.commit(&packages, &[name], Operation::Owners).await {
+            if let Some(package) = packages.get_mut(name.as_str()) {
+                package.owners = previous;
+            }
+            return Err(e);
+        }
+        self.audit(name, None, before, &update).await;
+        Ok(updated)
+    }
+
+    /// Applies a validated edit to a project's metadata, recording it in the
+    /// audit log.
+    pub(crate) async fn update_project(
+        &self,
+        name: &PackageName,
+        update: ProjectUpdate,
+    ) -> Result<Package, AppError> {
+        update.validate()?;
+        let (mut packages, _lock) = self.write(&[name]).await?;
+        let package = packages
+            .get_mut(name.as_str())
+            .ok_or_else(|| AppError::NotFound(name.to_string()))?;
+        package.ensure_active()?;
+
+        let before = serde_json::to_value(ProjectUpdate {
+            summary: package.summary.clone(),
+            project_urls: Some(package.project_urls.clone()),
+        })?;
+        let previous = package.clone();
+        if let Some(summary) = &update.summary {
+            package.summary = non_empty(summary);
+        }
+        if let Some(urls) = &update.project_urls {
+            package.project_urls = urls.clone();
+        }
+        let updated = package.clone();
+        if let Err(e) = self.commit(&packages, &[name], Operation::Update).await {
+            packages.insert(name.clone(), previous);
+            return Err(e);
+        }
+        self.audit(name, None, before, &update).await;
+        Ok(updated)
+    }
+
+    /// Applies a validated edit to every file of one release, recording it
+    /// in the audit log.
+    pub(crate) async fn update_release(
+        &self,
+        name: &PackageName,
+        version: &Version,
+        update: ReleaseUpdate,
+    ) -> Result<Vec<Release>, AppError> {
+        update.validate()?;
+        let (mut packages, _lock) = self.write(&[name]).await?;
+        let package = packages
+            .get_mut(name.as_str())
+            .ok_or_else(|| AppError::NotFound(name.to_string()))?;
+        package.ensure_active()?;
+        let previous = package.clone();
+        let mut files: Vec<&mut Release> = package
+            .releases
+            .iter_mut()
+            .filter(|r| r.version == *version)
+            .collect();
+        let Some(first) = files.first() else {
+            return Err(AppError::NotFound(format!("{name}=={version}")));
+        };
+
+        let before = serde_json::to_value(ReleaseUpdate {
+            deprecated: first.deprecated.clone(),
+            channel: Some(first.channel()),
+        })?;
+        for release in files.iter_mut() {
+            if let Some(note) = &update.deprecated {
+                release.deprecated = non_empty(note);
+            }
+            if let Some(channel) = update.channel {
+                release.channel = Some(channel);
+            }
+        }
+        let updated = files.into_iter().map(|r| r.clone()).collect();
+        if let Err(e) = self.commit(&packages, &[name], Operation::Update).await {
+            packages.insert(name.clone(), previous);
+            return Err(e);
+        }
+        self.audit(name, Some(version), before, &update).await;
+        Ok(updated)
+    }
+
+    /// Applies a validated edit to one file, recording it in the audit log.
+    pub(crate) async fn update_file(
+        &self,
+        name: &PackageName,
+        filename: &DistFilename,
+        update: FileUpdate,
+    ) -> Result<Release, AppError> {
+        update.validate()?;
+        let (mut packages, _lock) = self.write(&[name]).await?;
+        let package = packages
+            .get_mut(name.as_str())
+            .ok_or_else(|| AppError::NotFound(name.to_string()))?;
+        package.ensure_active()?;
+        let previous = package.clone();
+        let release = package
+            .releases
+            .iter_mut()
+            .find(|r| r.filename == *filename)
+            .ok_or_else(|| AppError::NotFound(format!("{name}/{filename}")))?;
+
+        let before = serde_json::to_value(FileUpdate {
+            yanked: Some(release.yanked),
+            yanked_reason: release.yanked_reason.clone(),
+        })?;
+        if let Some(yanked) = update.yanked {
+            release.yanked = yanked;
+            if !yanked {
+                release.yanked_reason = None;
+            }
+        }
+        if let Some(reason) = &update.yanked_reason {
+            release.yanked_reason = non_empty(reason);
+        }
+        let version = release.version.clone();
+        let updated = release.clone();
+        if let Err(e) = self.commit(&packages, &[name], Operation::Update).await {
+            packages.insert(name.clone(), previous);
+            return Err(e);
+        }
+        self.audit(name, Some(&version), before, &update).await;
+        Ok(updated)
+    }
+
+    /// Removes one file from the index and storage, recording it in the
+    /// audit log. Files kept by a snapshot are refused, since snapshots
+    /// never change; yanking hides those instead.
+    pub async fn delete_file(
+        &self,
+        name: &PackageName,
+        filename: &DistFilename,
+    ) -> Result<Release, AppError> {
+        let (mut packages, _lock) = self.write(&[name]).await?;
+        let package = packages
+            .get_mut(name.as_str())
+            .ok_or_else(|| AppError::NotFound(name.to_string()))?;
+        package.ensure_active()?;
+        let position = package
+            .releases
+            .iter()
+            .position(|r| r.filename == *filename)
+            .ok_or_else(|| AppError::NotFound(format!("{name}/{filename}")))?;
+        let kept_by = self.kept_by_snapshots(name, Some(filename)).await?;
+        if !kept_by.is_empty() {
+            return Err(AppError::Conflict(format!(
+                "{filename} is kept by snapshots {}; yank it instead",
+                kept_by.join(", ")
+            )));
+        }
+
+        let removed = package.releases.remove(position);
+        if let Err(e) = self.commit(&packages, &[name], Operation::DeleteFile).await {
+            if let Some(package) = packages.get_mut(name.as_str()) {
+                package.releases.insert(position, removed);
+            }
+            return Err(e);
+        }
+        // The index no longer lists the file, so failing to remove its bytes
+        // only leaves an untracked file for fsck to report.
+        if let Err(e) = self.storage.remove_package(name, filename).await {
+            warn!("Removing the stored {} failed: {}", filename, e);
+        }
+        self.recount(name).await;
+        if removed.core_metadata.is_some() {
+            self.remove_core_metadata(name, filename).await;
+        }
+        self.audit(
+            name,
+            Some(&removed.version),
+            serde_json::to_value(&removed)?,
+            &serde_json::json!({ "deleted": filename }),
+        )
+        .await;
+        Ok(removed)
+    }
+
+    /// Removes the releases of `name` the retention policy no longer keeps,
+    /// other than those of `keep` and those a snapshot keeps, returning
+    /// them. Their files are removed once the index no longer lists them.
+    pub async fn apply_retention(
+        &self,
+        name: &PackageName,
+        keep: Option<&Version>,
+    ) -> Result<Vec<Release>, AppError> {
+        let (mut packages, _lock) = self.write(&[name]).await?;
+        let Some(package) = packages.get_mut(name.as_str()) else {
+            return Ok(Vec::new());
+        };
+        if package.renamed_to.is_some() {
+            return Ok(Vec::new());
+        }
+        let mut expired = self.retention.expired(package, Utc::now(), keep);
+        if expired.is_empty() {
+            return Ok(Vec::new());
+        }
+        let snapshots = self.snapshots().await?;
+        expired.retain(|filename| {
+            !snapshots.iter().any(|snapshot| {
+                snapshot
+                    .packages
+                    .get(name.as_str())
+                    .is_some_and(|p| p.releases.iter().any(|r| r.filename == *filename))
+            })
+        });
+        if expired.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let (removed, kept): (Vec<Release>, Vec<Release>) = package
+            .releases
+            .drain(..)
+            .partition(|r| expired.contains(&r.filename));
+        package.releases = kept;
+        if let Err(e) = self.commit(&packages, &[name], Operation::Prune).await {
+            if let Some(package) = packages.get_mut(name.as_str()) {
+                package.releases.extend(removed);
+                package.sort_releases();
+            }
+            return Err(e);
+        }
+        drop(packages);
+        for release in &removed {
+            if let Err(e) = self.storage.remove_package(name, &release.filename).await {
+                warn!("Removing the stored {} failed: {}", release.filename, e);
+            }
+            if release.core_metadata.is_some() {
+                self.remove_core_metadata(name, &release.filename).await;
+            }
+            self.audit(
+                name,
+                Some(&release.version),
+                serde_json::to_value(release)?,
+                &json!({ "pruned": release.filename }),
+            )
+            .await;
+        }
+        self.recount(name).await;
+        info!("Pruned {} files of {}", removed.len(), name);
+        Ok(removed)
+    }
+
+    /// Names of the snapshots that list `filename` of `name`, or any file
+    /// of it with `None`, whose bytes must then stay where and as they are.
+    pub(crate) async fn kept_by_snapshots(
+        &self,
+        name: &PackageName,
+        filename: Option<&DistFilename>,
+    ) -> Result<Vec<String>, AppError> {
+        Ok(self
+            .snapshots()
+            .await?
+            .iter()
+            .filter(|snapshot| {
+                snapshot.packages.get(name.as_str()).is_some_and(|p| {
+                    p.releases
+                        .iter()
+                        .any(|r| filename.is_none_or(|f| r.filename == *f))
+                })
+            })
+            .map(|snapshot| snapshot.name.to_string())
+            .collect())
+    }
+
+    /// Removes a project's index entry, files and docs, recording what was
+    /// removed in the audit log. Refused while a snapshot keeps any of its
+    /// files.
+    pub async fn delete_project(&self, name: &PackageName) -> Result<(), AppError> {
+        let (mut packages, _lock) = self.write(&[name]).await?;
+        let package = packages
+            .get(name.as_str())
+            .ok_or_else(|| AppError::NotFound(name.to_string()))?;
+        // The tombstone of a renamed project keeps its old name from being
+        // registered again.
+        package.ensure_active()?;
+        let kept_by = self.kept_by_snapshots(name, None).await?;
+        if !kept_by.is_empty() {
+            return Err(AppError::Conflict(format!(
+                "{name} has files kept by snapshots {}",
+                kept_by.join(", ")
+            )));
+        }
+        // Hooks carry secrets, so only what was served is recorded.
+        let before = serde_json::json!({
+            "files": package.releases.iter().map(|r| &r.filename).collect::<Vec<_>>(),
+            "docs": package.docs,
+            "renamed_to": package.renamed_to,
+        });
+
+        let removed = packages
+            .remove(name.as_str())
+            .expect("the project was found above");
+        if let Err(e) = self
+            .commit(&packages, &[name], Operation::DeleteProject)
+            .await
+        {
+            packages.insert(name.clone(), removed);
+            return Err(e);
+        }
+        // The index no longer lists the project, so leftovers are only
+        // untracked files for fsck to report.
+        if let Err(e) = self.storage.remove_project(name).await {
+            warn!("Removing the stored files of {} failed: {}", name, e);
+        }
+        self.recount(name).await;
+        self.audit(name, None, before, &serde_json::json!({ "deleted": true }))
+            .await;
+        Ok(())
+    }
+
+    /// Appends to the audit log. The edit is already saved, so failing to
+    /// record it is logged rather than reported to the client.
+    async fn audit(
+        &self,
+        project: &PackageName,
+        version: Option<&Version>,
+        before: Value,
+        changes: &impl Serialize,
+    ) {
+        let entry = serde_json::to_value(changes).map(|changes| AuditEntry {
+            time: Utc::now(),
+            project: project.clone(),
+            version: version.cloned(),
+            before,
+            changes,
+        });
+        let recorded = match entry {
+            Ok(entry) => self.storage.append_audit(&entry).await,
+            Err(e) => Err(e.into()),
+        };
+        match recorded {
+            Ok(()) => info!("Updated metadata of {} {:?}", project, version),
+            Err(e) => warn!("Recording the metadata edit of {} failed: {}", project, e),
+        }
+    }
+
+    pub(crate) async fn add_webhook(
+        &self,
+        name: &PackageName,
+        webhook: Webhook,
+    ) -> Result<(), AppError> {
+        let (mut packages, _lock) = self.write(&[name]).await?;
+        let package = packages
+            .get_mut(name.as_str())
+            .ok_or_else(|| AppError::NotFound(name.to_string()))?;
+        package.ensure_active()?;
+
+        package.webhooks.push(webhook);
+        if let Err(e) = self.commit(&packages, &[name], Operation::Webhook).await {
+            if let Some(package) = packages.get_mut(name.as_str()) {
+                package.webhooks.pop();
+            }
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    pub(crate) async fn remove_webhook(
+        &self,
+        name: &PackageName,
+        id: &str,
+    ) -> Result<(), AppError> {
+        let (mut packages, _lock) = self.write(&[name]).await?;
+        let package = packages
+            .get_mut(name.as_str())
+            .ok_or_else(|| AppError::NotFound(name.to_string()))?;
+        let position = package
+            .webhooks
+            .iter()
+            .position(|hook| hook.id == id)
+            .ok_or_else(|| AppError::NotFound(format!("Webhook {id}")))?;
+
+        let removed = package.webhooks.remove(position);
+        if let Err(e) = self.commit(&packages, &[name], Operation::Webhook).await {
+            if let Some(package) = packages.get_mut(name.as_str()) {
+                package.webhooks.insert(position, removed);
+            }
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Moves a project's releases, files and docs to `to`, leaving a
+    /// tombstone that redirects from the old name. Refused while a snapshot
+    /// keeps any of its files.
+    pub async fn rename(&self, from: &PackageName, to: PackageName) -> Result<(), AppError> {
+        let (mut packages, _lock) = self.write(&[from, &to]).await?;
+        let package = packages
+            .get(from.as_str())
+            .ok_or_else(|| AppError::NotFound(from.to_string()))?;
+        package.ensure_active()?;
+        if packages.contains_key(to.as_str()) {
+            return Err(AppError::Conflict(format!("{to} already exists")));
+        }
+        // Snapshots link to files under the name they were taken with.
+        let kept_by = self.kept_by_snapshots(from, None).await?;
+        if !kept_by.is_empty() {
+            return Err(AppError::Conflict(format!(
+                "{from} has files kept by snapshots {}",
+                kept_by.join(", ")
+            )));
+        }
+
+        self.storage.rename_project(from, &to).await?;
+        let mut tombstone = Package::new(from.clone());
+        tombstone.renamed_to = Some(to.clone());
+        let mut moved = packages
+            .insert(from.clone(), tombstone)
+            .expect("checked above");
+        moved.name = to.clone();
+        packages.insert(to.clone(), moved);
+
+        if let Err(e) = self
+            .commit(&packages, &[from, &to], Operation::Rename)
+            .await
+        {
+            let mut moved = packages.remove(to.as_str()).expect("inserted above");
+            moved.name = from.clone();
+            packages.insert(from.clone(), moved);
+            if let Err(undo) = self.storage.rename_project(&to, from).await {
+                warn!("Moving files back from {} to {} failed: {}", to, from, undo);
+            }
+            return Err(e);
+        }
+        self.recount(from).await;
+        self.recount(&to).await;
+        info!("Renamed {} to {}", from, to);
+        Ok(())
+    }
+
+    /// Freezes the current index under `name`. Snapshots are never changed
+    /// or replaced once written.
+    pub async fn create_snapshot(&self, name: SnapshotName) -> Result<Arc<Snapshot>, AppError> {
+        let (packages, _lock) = self.write_all().await?;
+        let snapshot = Arc::new(Snapshot {
+            name: name.clone(),
+            created: Utc::now(),
+            // Hooks and their secrets stay with the live project.
+            packages: packages
+                .iter()
+                .map(|(name, package)| {
+                    let mut package = package.clone();
+                    package.webhooks.clear();
+                    (name.clone(), package)
+                })
+                .collect(),
+        });
+        drop(packages);
+
+        self.storage.save_snapshot(&snapshot).await?;
+        self.snapshots
+            .write()
+            .await
+            .insert(name.clone(), snapshot.clone());
+        info!("Created snapshot {}", name);
+        Ok(snapshot)
+    }
+
+    pub async fn snapshot(&self, name: &SnapshotName) -> Result<Arc<Snapshot>, AppError> {
+        if let Some(snapshot) = self.snapshots.read().await.get(name.as_str()) {
+            return Ok(snapshot.clone());
+        }
+        // Created by another process, or before this one started.
+        let snapshot = Arc::new(
+            self.storage
+                .load_snapshot(name)
+                .await?
+                .ok_or_else(|| AppError::NotFound(format!("snapshot {name}")))?,
+        );
+        self.snapshots
+            .write()
+            .await
+            .insert(name.clone(), snapshot.clone());
+        Ok(snapshot)
+    }
+
+    /// Every snapshot, oldest first.
+    pub async fn snapshots(&self) -> Result<Vec<Arc<Snapshot>>, AppError> {
+        let mut snapshots = Vec::new();
+        for name in self.storage.snapshot_names().await? {
+            snapshots.push(self.snapshot(&name).await?);
+        }
+        snapshots.sort_by_key(|s| s.created);
+        Ok(snapshots)
+    }
+}
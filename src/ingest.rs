@@ -0,0 +1,296 @@
+//! Ingestion of wheels and sdists attached to GitHub and GitLab releases,
+//! either on a polling interval or when a forge webhook announces a new release.
+
+use std::{fmt, str::FromStr};
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+};
+use futures_util::stream;
+use reqwest::{header, Client};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tracing::{info, warn};
+
+use crate::{
+    is_distribution, parse_dist_filename, AppError, AppState, Config, DistFilename, PackageIndex,
+    PackageName, Provenance, ProvenanceSource, Release,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Forge {
+    Github,
+    Gitlab,
+}
+
+/// A watched repository and the one project its assets may publish,
+/// written as `github:owner/repo=project` or `gitlab:group/repo=project`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IngestSource {
+    pub forge: Forge,
+    pub repository: String,
+    pub project: PackageName,
+}
+
+impl FromStr for IngestSource {
+    type Err = AppError;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            AppError::InvalidFormat(format!(
+                "Expected github:owner/repo=project or gitlab:group/repo=project: {spec}"
+            ))
+        };
+        let (forge, rest) = spec.split_once(':').ok_or_else(invalid)?;
+        let (repository, project) = rest.split_once('=').ok_or_else(invalid)?;
+        let forge = match forge {
+            "github" => Forge::Github,
+            "gitlab" => Forge::Gitlab,
+            _ => return Err(invalid()),
+        };
+        if repository.is_empty() {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            forge,
+            repository: repository.to_string(),
+            project: project.parse()?,
+        })
+    }
+}
+
+impl IngestSource {
+    /// The watched repository alone, as `github:owner/repo`.
+    pub fn repository_spec(&self) -> String {
+        let forge = match self.forge {
+            Forge::Github => "github",
+            Forge::Gitlab => "gitlab",
+        };
+        format!("{forge}:{}", self.repository)
+    }
+}
+
+impl fmt::Display for IngestSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.repository_spec(), self.project)
+    }
+}
+
+#[derive(Debug)]
+struct ReleaseAsset {
+    filename: String,
+    url: String,
+    sha256: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct GithubRelease {
+    assets: Vec<GithubAsset>,
+}
+
+#[derive(Deserialize)]
+struct GithubAsset {
+    name: String,
+    browser_download_url: String,
+    digest: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct GitlabRelease {
+    assets: GitlabAssets,
+}
+
+#[derive(Deserialize)]
+struct GitlabAssets {
+    links: Vec<GitlabLink>,
+}
+
+#[derive(Deserialize)]
+struct GitlabLink {
+    name: String,
+    url: String,
+    direct_asset_url: Option<String>,
+}
+
+/// Polls every configured source forever, sleeping `ingest_interval`
+/// between rounds.
+pub async fn poll(index: PackageIndex, config: Config) {
+    let client = Client::new();
+    loop {
+        for source in &config.ingest_sources {
+            if let Err(e) = sync_source(&client, &index, &config, source).await {
+                warn!("Ingesting releases from {} failed: {}", source, e);
+            }
+        }
+        tokio::time::sleep(config.ingest_interval).await;
+    }
+}
+
+/// Fetches the latest releases of one source and registers any
+/// distributions the index does not have yet, returning how many were added.
+pub async fn sync_source(
+    client: &Client,
+    index: &PackageIndex,
+    config: &Config,
+    source: &IngestSource,
+) -> Result<usize, AppError> {
+    let assets = match source.forge {
+        Forge::Github => fetch_github_assets(client, config, source).await?,
+        Forge::Gitlab => fetch_gitlab_assets(client, config, source).await?,
+    };
+
+    let mut added = 0;
+    for asset in assets {
+        if !is_distribution(&asset.filename) {
+            continue;
+        }
+        let filename = match DistFilename::new(asset.filename) {
+            Ok(filename) => filename,
+            Err(e) => {
+                warn!("Skipping asset from {}: {}", source, e);
+                continue;
+            }
+        };
+        let (name, version) = parse_dist_filename(filename.as_str())?;
+        if index.has_file(&name, &filename).await? {
+            continue;
+        }
+        if name != source.project {
+            warn!(
+                "Skipping {}: {} may only publish {}",
+                filename, source, source.project
+            );
+            continue;
+        }
+
+        let contents = client
+            .get(&asset.url)
+            .header(header::ACCEPT, "application/octet-stream")
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
+        let actual = format!("{:x}", Sha256::digest(&contents));
+        if let Some(expected) = &asset.sha256 {
+            if !actual.eq_ignore_ascii_case(expected) {
+                warn!(
+                    "Skipping {}: sha256 {} does not match advertised {}",
+                    filename, actual, expected
+                );
+                continue;
+            }
+        }
+
+        let spooled = index
+            .storage
+            .spool_package(
+                &name,
+                &filename,
+                stream::iter([Ok::<_, AppError>(contents)]),
+            )
+            .await?;
+        let provenance = Provenance::new(
+            ProvenanceSource::ForgeRelease {
+                repository: source.repository_spec(),
+            },
+            actual,
+        )
+        .with_upstream_url(asset.url);
+        index
+            .publish(
+                name,
+                Release::new(version, filename.clone()).with_provenance(provenance),
+                None,
+                spooled,
+            )
+            .await?;
+        info!("Ingested {} from {}", filename, source);
+        added += 1;
+    }
+    Ok(added)
+}
+
+async fn fetch_github_assets(
+    client: &Client,
+    config: &Config,
+    source: &IngestSource,
+) -> Result<Vec<ReleaseAsset>, AppError> {
+    let mut request = client
+        .get(format!(
+            "https://api.github.com/repos/{}/releases?per_page=10",
+            source.repository
+        ))
+        .header(header::ACCEPT, "application/vnd.github+json")
+        .header(header::USER_AGENT, "pippy");
+    if let Some(token) = &config.github_token {
+        request = request.bearer_auth(token);
+    }
+    let releases: Vec<GithubRelease> = request.send().await?.error_for_status()?.json().await?;
+
+    Ok(releases
+        .into_iter()
+        .flat_map(|release| release.assets)
+        .map(|asset| ReleaseAsset {
+            sha256: asset
+                .digest
+                .and_then(|d| d.strip_prefix("sha256:").map(str::to_string)),
+            filename: asset.name,
+            url: asset.browser_download_url,
+        })
+        .collect())
+}
+
+async fn fetch_gitlab_assets(
+    client: &Client,
+    config: &Config,
+    source: &IngestSource,
+) -> Result<Vec<ReleaseAsset>, AppError> {
+    let project = source.repository.replace('/', "%2F");
+    let mut request = client.get(format!(
+        "{}/api/v4/projects/{project}/releases",
+        config.gitlab_url.trim_end_matches('/')
+    ));
+    if let Some(token) = &config.gitlab_token {
+        request = request.header("PRIVATE-TOKEN", token);
+    }
+    let releases: Vec<GitlabRelease> = request.send().await?.error_for_status()?.json().await?;
+
+    // GitLab release links carry no digest, so these are stored unverified.
+    Ok(releases
+        .into_iter()
+        .flat_map(|release| release.assets.links)
+        .map(|link| ReleaseAsset {
+            filename: link.name,
+            url: link.direct_asset_url.unwrap_or(link.url),
+            sha256: None,
+        })
+        .collect())
+}
+
+/// Webhook target for release events. The payload itself is not trusted:
+/// it only triggers an immediate sync of the matching configured source.
+pub(crate) async fn release_webhook(
+    State(state): State<AppState>,
+    Path((forge, repository)): Path<(String, String)>,
+) -> Result<StatusCode, AppError> {
+    let not_found = || AppError::NotFound(format!("{forge}:{repository}"));
+    let forge = match forge.as_str() {
+        "github" => Forge::Github,
+        "gitlab" => Forge::Gitlab,
+        _ => return Err(not_found()),
+    };
+    let source = state
+        .config
+        .ingest_sources
+        .iter()
+        .find(|s| s.forge == forge && s.repository == repository)
+        .ok_or_else(not_found)?
+        .clone();
+
+    let added = sync_source(&Client::new(), &state.index, &state.config, &source).await?;
+    info!("Webhook sync of {} added {} files", source, added);
+    Ok(StatusCode::ACCEPTED)
+}
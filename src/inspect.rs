@@ -0,0 +1,209 @@
+//! Reading the members of stored wheels and sdists in place, so a file's
+//! `METADATA`, `RECORD` or license can be looked at without downloading
+//! the whole artifact.
+
+use std::{
+    fs::File,
+    io::{self, Read},
+    path::PathBuf,
+};
+
+use flate2::read::GzDecoder;
+use serde::Serialize;
+use tokio::sync::oneshot;
+use tokio_util::io::{ReaderStream, SyncIoBridge};
+
+use crate::AppError;
+
+/// Bytes buffered between the blocking archive reader and the response.
+const STREAM_BUFFER: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy)]
+enum ArchiveKind {
+    Zip,
+    TarGz,
+}
+
+impl ArchiveKind {
+    fn for_filename(filename: &str) -> Result<Self, AppError> {
+        if filename.ends_with(".whl") || filename.ends_with(".zip") {
+            Ok(Self::Zip)
+        } else if filename.ends_with(".tar.gz") {
+            Ok(Self::TarGz)
+        } else {
+            Err(AppError::InvalidFormat(format!(
+                "{filename} is not a wheel or sdist archive"
+            )))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct Member {
+    pub name: String,
+    /// Uncompressed size in bytes.
+    pub size: u64,
+}
+
+fn invalid(e: impl std::fmt::Display) -> AppError {
+    AppError::InvalidFormat(format!("Unreadable archive: {e}"))
+}
+
+/// The regular files in the archive at `path`, in archive order.
+pub(crate) async fn list_members(path: PathBuf, filename: &str) -> Result<Vec<Member>, AppError> {
+    let kind = ArchiveKind::for_filename(filename)?;
+    tokio::task::spawn_blocking(move || {
+        let file = File::open(path)?;
+        let mut members = Vec::new();
+        match kind {
+            ArchiveKind::Zip => {
+                let mut zip = zip::ZipArchive::new(file).map_err(invalid)?;
+                for i in 0..zip.len() {
+                    let entry = zip.by_index(i).map_err(invalid)?;
+                    if entry.is_file() {
+                        members.push(Member {
+                            name: entry.name().to_string(),
+                            size: entry.size(),
+                        });
+                    }
+                }
+            }
+            ArchiveKind::TarGz => {
+                let mut tar = tar::Archive::new(GzDecoder::new(file));
+                for entry in tar.entries().map_err(invalid)? {
+                    let entry = entry.map_err(invalid)?;
+                    if entry.header().entry_type().is_file() {
+                        members.push(Member {
+                            name: entry
+                                .path()
+                                .map_err(invalid)?
+                                .to_string_lossy()
+                                .into_owned(),
+                            size: entry.size(),
+                        });
+                    }
+                }
+            }
+        }
+        Ok(members)
+    })
+    .await
+    .map_err(|e| AppError::Io(io::Error::other(e)))?
+}
+
+/// Streams one member of the archive at `path`, decompressing as the
+/// response is sent. Fails with `NotFound` before any bytes are produced if
+/// the archive has no such member.
+pub(crate) async fn read_member(
+    path: PathBuf,
+    filename: &str,
+    member: String,
+) -> Result<(u64, ReaderStream<tokio::io::DuplexStream>), AppError> {
+    let kind = ArchiveKind::for_filename(filename)?;
+    let (writer, reader) = tokio::io::duplex(STREAM_BUFFER);
+    let (found, located) = oneshot::channel::<Result<u64, AppError>>();
+    tokio::task::spawn_blocking(move || {
+        let mut out = SyncIoBridge::new(writer);
+        let copied = match kind {
+            ArchiveKind::Zip => {
+                let opened = File::open(&path)
+                    .map_err(AppError::from)
+                    .and_then(|file| zip::ZipArchive::new(file).map_err(invalid));
+                let mut zip = match opened {
+                    Ok(zip) => zip,
+                    Err(e) => return drop(found.send(Err(e))),
+                };
+                let mut entry = match zip.by_name(&member) {
+                    Ok(entry) if entry.is_file() => entry,
+                    _ => return drop(found.send(Err(AppError::NotFound(member)))),
+                };
+                let _ = found.send(Ok(entry.size()));
+                io::copy(&mut entry, &mut out)
+            }
+            ArchiveKind::TarGz => {
+                let file = match File::open(&path) {
+                    Ok(file) => file,
+                    Err(e) => return drop(found.send(Err(e.into()))),
+                };
+                let mut tar = tar::Archive::new(GzDecoder::new(file));
+                let Some(mut entry) = find_tar_entry(&mut tar, &member) else {
+                    return drop(found.send(Err(AppError::NotFound(member))));
+                };
+                let _ = found.send(Ok(entry.size()));
+                io::copy(&mut entry, &mut out)
+            }
+        };
+        // The client going away mid-stream is not worth reporting.
+        drop(copied);
+    });
+
+    let size = located
+        .await
+        .map_err(|e| AppError::Io(io::Error::other(e)))??;
+    Ok((size, ReaderStream::new(reader)))
+}
+
+/// The `METADATA` of the wheel at `path`, from its top-level
+/// `.dist-info` directory, if it has one.
+pub(crate) async fn wheel_metadata(path: PathBuf) -> Result<Option<Vec<u8>>, AppError> {
+    tokio::task::spawn_blocking(move || {
+        let mut zip = zip::ZipArchive::new(File::open(path)?).map_err(invalid)?;
+        let Some(member) = zip
+            .file_names()
+            .find(|name| {
+                name.split_once('/')
+                    .is_some_and(|(dir, file)| dir.ends_with(".dist-info") && file == "METADATA")
+            })
+            .map(str::to_string)
+        else {
+            return Ok(None);
+        };
+        let mut contents = Vec::new();
+        zip.by_name(&member)
+            .map_err(invalid)?
+            .read_to_end(&mut contents)?;
+        Ok(Some(contents))
+    })
+    .await
+    .map_err(|e| AppError::Io(io::Error::other(e)))?
+}
+
+/// Values of a header field in a core metadata file, in order. Headers
+/// end at the first blank line, where the description begins.
+pub(crate) fn metadata_values<'a>(
+    contents: &'a str,
+    field: &'a str,
+) -> impl Iterator<Item = &'a str> + 'a {
+    contents
+        .lines()
+        .take_while(|line| !line.is_empty())
+        .filter_map(move |line| {
+            let (key, value) = line.split_once(':')?;
+            key.eq_ignore_ascii_case(field).then(|| value.trim())
+        })
+}
+
+fn find_tar_entry<'a, R: Read>(
+    tar: &'a mut tar::Archive<R>,
+    member: &str,
+) -> Option<tar::Entry<'a, R>> {
+    tar.entries().ok()?.filter_map(Result::ok).find(|entry| {
+        entry.header().entry_type().is_file()
+            && entry
+                .path()
+                .is_ok_and(|path| path.to_string_lossy() == member)
+    })
+}
+
+/// Whether a member is likely text, so it can be shown in a browser rather
+/// than downloaded.
+pub(crate) fn is_text(member: &str) -> bool {
+    let name = member.rsplit('/').next().unwrap_or(member);
+    match name.rsplit_once('.') {
+        None => true,
+        Some((_, extension)) => matches!(
+            extension.to_ascii_lowercase().as_str(),
+            "txt" | "md" | "rst" | "py" | "pyi" | "toml" | "cfg" | "ini" | "json" | "in" | "typed"
+        ),
+    }
+}
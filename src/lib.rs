@@ -0,0 +1,271 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{DefaultBodyLimit, FromRef},
+    middleware,
+    routing::{delete, get, patch, post},
+    Router,
+};
+use tower::ServiceBuilder;
+use tower_http::{compression::CompressionLayer, services::ServeDir, trace::TraceLayer};
+
+mod api;
+mod assets;
+pub mod auth;
+pub mod backend;
+pub mod bench;
+pub mod bundle;
+pub mod capture;
+pub mod cloud;
+pub mod compat;
+mod config;
+pub mod dependencies;
+pub mod diff;
+pub mod enrich;
+mod error;
+mod filename;
+pub mod fsck;
+pub mod gc;
+mod handlers;
+pub mod idempotency;
+pub mod import;
+mod index;
+pub mod ingest;
+mod inspect;
+mod metadata;
+pub mod migrate;
+pub mod oidc;
+pub mod pep440;
+mod pypi_json;
+pub mod quota;
+pub mod receipt;
+pub mod reindex;
+pub mod retention;
+pub mod server;
+pub mod signing;
+mod simple_json;
+pub mod stats;
+mod storage;
+pub mod tenants;
+pub mod testing;
+mod types;
+mod urls;
+pub mod webhooks;
+
+pub use config::Config;
+pub use error::AppError;
+pub use filename::{
+    is_distribution, parse_dist_filename, parse_sdist_filename, parse_wheel_filename, WheelTags,
+};
+use idempotency::IdempotencyCache;
+pub use index::{
+    Change, Channel, Operation, OverwritePolicy, Package, PackageIndex, ProjectState, Provenance,
+    ProvenanceSource, Release, Snapshot, UploadLimits,
+};
+pub use storage::{InstanceLock, LocalFile, PackageStorage};
+pub use types::{DistFilename, PackageName, SnapshotName, Version};
+pub use urls::UrlBuilder;
+
+/// State shared by all handlers; handlers that only need the index
+/// extract `State<PackageIndex>` directly.
+#[derive(Clone)]
+pub struct AppState {
+    pub index: PackageIndex,
+    pub config: Arc<Config>,
+    pub urls: UrlBuilder,
+    pub idempotency: Arc<IdempotencyCache>,
+}
+
+impl FromRef<AppState> for PackageIndex {
+    fn from_ref(state: &AppState) -> Self {
+        state.index.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<IdempotencyCache> {
+    fn from_ref(state: &AppState) -> Self {
+        state.idempotency.clone()
+    }
+}
+
+impl FromRef<AppState> for UrlBuilder {
+    fn from_ref(state: &AppState) -> Self {
+        state.urls.clone()
+    }
+}
+
+/// Builds the HTTP router serving the index with default settings.
+pub fn router(index: PackageIndex) -> Router {
+    router_with_config(index, Config::default())
+}
+
+pub fn router_with_config(index: PackageIndex, config: Config) -> Router {
+    let index = index.with_credentials_required(config.guards_writes());
+    let capture = config.capture.clone();
+    let state = AppState {
+        index,
+        urls: match &config.base_url {
+            Some(base_url) => UrlBuilder::new(&config.path_prefix).with_base_url(base_url),
+            None => UrlBuilder::new(&config.path_prefix),
+        },
+        idempotency: Arc::new(IdempotencyCache::new(config.idempotency_window)),
+        config: Arc::new(config),
+    };
+    let docs = ServiceBuilder::new()
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            api::published_docs,
+        ))
+        .service(ServeDir::new(state.index.storage.docs_dir()));
+    // Wheels and docs archives are streamed to disk, so axum's default
+    // 2 MB limit on buffered bodies does not apply to them; only the
+    // configured maximum does.
+    let upload_limit = match state.config.max_upload_size {
+        Some(max) => DefaultBodyLimit::max(
+            usize::try_from(max.saturating_add(handlers::FORM_ALLOWANCE)).unwrap_or(usize::MAX),
+        ),
+        None => DefaultBodyLimit::disable(),
+    };
+    let router = Router::new()
+        .route(
+            "/",
+            get(handlers::root).merge(post(handlers::upload_package).layer(upload_limit)),
+        )
+        .route("/simple/", get(handlers::list_packages))
+        .route(
+            "/static/:file",
+            get(assets::serve).layer(CompressionLayer::new()),
+        )
+        .route("/simple/:package", get(handlers::package_details_redirect))
+        .route("/simple/:package/", get(handlers::package_details))
+        .route(
+            "/snapshots/:snapshot/simple/",
+            get(handlers::snapshot_packages),
+        )
+        .route(
+            "/snapshots/:snapshot/simple/:package",
+            get(handlers::snapshot_package_details_redirect),
+        )
+        .route(
+            "/snapshots/:snapshot/simple/:package/",
+            get(handlers::snapshot_package_details),
+        )
+        .route(
+            "/channels/:channel/simple/",
+            get(handlers::channel_packages),
+        )
+        .route(
+            "/channels/:channel/simple/:package",
+            get(handlers::channel_package_details_redirect),
+        )
+        .route(
+            "/channels/:channel/simple/:package/",
+            get(handlers::channel_package_details),
+        )
+        .route("/pypi/:package/json", get(pypi_json::project))
+        .route("/pypi/:package/:version/json", get(pypi_json::release))
+        .route("/project/:package/latest", get(handlers::latest_file))
+        .route("/packages/:package/latest.whl", get(handlers::latest_wheel))
+        .route(
+            "/packages/:package/:filename",
+            get(handlers::download_package),
+        )
+        // Publishing clients post to whatever repository URL they are given:
+        // the root (routed above), often with a trailing slash, or PyPI's
+        // `/legacy/` path.
+        .route(
+            "/upload",
+            post(handlers::upload_package).layer(upload_limit),
+        )
+        .route(
+            "/upload/",
+            post(handlers::upload_package).layer(upload_limit),
+        )
+        .route(
+            "/legacy/",
+            post(handlers::upload_package).layer(upload_limit),
+        )
+        .route(
+            "/api/v1/projects/:package",
+            patch(api::update_project).delete(api::delete_project),
+        )
+        .route(
+            "/api/v1/projects/:package/owners",
+            get(api::project_owners).put(api::set_project_owners),
+        )
+        .route(
+            "/api/v1/projects/:package/releases/:version",
+            patch(api::update_release),
+        )
+        .route(
+            "/api/v1/projects/:package/webhooks",
+            get(api::list_webhooks).post(api::create_webhook),
+        )
+        .route(
+            "/api/v1/projects/:package/webhooks/:hook",
+            delete(api::delete_webhook),
+        )
+        .route(
+            "/api/v1/projects/:package/webhooks/:hook/deliveries",
+            get(api::webhook_deliveries),
+        )
+        .route(
+            "/api/v1/projects/:package/webhooks/:hook/deliveries/:delivery/redeliver",
+            post(api::redeliver_webhook),
+        )
+        .route("/api/v1/projects/:package/stats", get(api::project_stats))
+        .route("/api/v1/projects/:package/files", get(api::project_files))
+        .route(
+            "/api/v1/projects/:package/files/:filename",
+            patch(api::update_file).delete(api::delete_file),
+        )
+        .route(
+            "/api/v1/projects/:package/checksums",
+            get(api::project_checksums),
+        )
+        .route(
+            "/api/v1/projects/:package/files/:filename/contents",
+            get(api::file_contents),
+        )
+        .route(
+            "/api/v1/projects/:package/files/:filename/contents/*member",
+            get(api::file_member),
+        )
+        .route("/api/v1/users", get(api::list_users).post(api::create_user))
+        .route(
+            "/api/v1/tokens",
+            get(api::list_tokens).post(api::create_token),
+        )
+        .route("/api/v1/tokens/:token", delete(api::revoke_token))
+        .route("/api/v1/signing-key", get(api::signing_key))
+        .route("/api/v1/snapshots", get(api::list_snapshots))
+        .route("/api/v1/snapshots/:snapshot", post(api::create_snapshot))
+        .route("/api/v1/bundle", get(api::bundle))
+        .route("/api/v1/manifest", get(api::manifest))
+        .route("/api/v1/diff", get(api::diff))
+        .route("/api/v1/changes", get(api::changes))
+        .route("/api/v1/usage", get(api::usage))
+        .route("/api/v1/admin/fsck", get(api::fsck))
+        .route("/api/v1/admin/reindex", post(api::reindex))
+        .route("/api/v1/admin/capacity", get(api::capacity))
+        .route(
+            "/api/v1/projects/:package/docs/:version",
+            post(api::upload_docs).layer(upload_limit),
+        )
+        .route("/_/oidc/audience", get(oidc::audience))
+        .route("/_/oidc/mint-token", post(oidc::mint_token))
+        .route(
+            "/api/v1/ingest/:forge/*repository",
+            post(ingest::release_webhook),
+        )
+        .nest_service("/docs", docs);
+    let router = router.layer(middleware::from_fn_with_state(
+        state.clone(),
+        auth::authorize,
+    ));
+    let router = match capture {
+        Some(capture) => router.layer(middleware::from_fn_with_state(capture, capture::record)),
+        None => router,
+    };
+    router.layer(TraceLayer::new_for_http()).with_state(state)
+}
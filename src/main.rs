@@ -1,291 +1,989 @@
-use axum::{
-    extract::{Multipart, Path, State},
-    http::StatusCode,
-    response::{Html, IntoResponse},
-    routing::{get, post},
-    Router,
+use clap::{Args, Parser, Subcommand};
+use pippy::{
+    auth::{self, Credentials, Scope, TokenStore},
+    backend::{open_backend, FileSystemBackend, StorageBackend},
+    bench::{self, BenchOptions, Scenario},
+    bundle::{write_bundle, BundleLimits, BundleSelection, Pin},
+    capture::{self, Capture, ReplayOptions},
+    compat::{CompatibilityQuery, TargetEnvironment},
+    dependencies::{DependencyCheck, DependencyPolicy},
+    diff::{IndexDiff, Manifest, Side},
+    fsck,
+    gc::{self, GcOptions},
+    import::{self, LinkMode},
+    ingest::{self, IngestSource},
+    migrate,
+    oidc::{TrustedPublisher, TrustedPublishing},
+    quota::Quotas,
+    reindex,
+    retention::{self, RetentionPolicy},
+    router_with_config,
+    server::{self, ConnectionSettings, HostCertificate, TlsConfig},
+    signing::ServerKey,
+    stats::{CapacityReport, StatsRetention},
+    tenants::{HostRouter, Tenant},
+    AppError, Channel, Config, DistFilename, InstanceLock, OverwritePolicy, PackageIndex,
+    PackageName, PackageStorage, UploadLimits,
 };
-use chrono::{DateTime, Utc};
-use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, path::PathBuf, sync::Arc};
-use thiserror::Error;
-use tokio::sync::RwLock;
-use tower_http::trace::TraceLayer;
-use tracing::{error, info};
-
-#[derive(Debug, Serialize, Deserialize, Clone)]
-struct Package {
-    name: String,
-    releases: Vec<Release>,
+use std::{net::IpAddr, path::PathBuf, sync::Arc, time::Duration};
+
+#[derive(Parser)]
+#[command(version, about = "A simple PyPI-compatible package index")]
+#[command(args_conflicts_with_subcommands = true)]
+struct Cli {
+    /// Data directory of the index; for the admin commands, it may be a
+    /// tenant's instead
+    #[arg(long, global = true, env = "PIPPY_DATA_DIR", default_value = "data")]
+    data_dir: PathBuf,
+    /// Most detailed log level shown: error, warn, info, debug or trace
+    #[arg(long, global = true, env = "PIPPY_LOG_LEVEL", default_value = "info")]
+    log_level: tracing::Level,
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Running without a subcommand serves with these arguments
+    #[command(flatten)]
+    serve: ServeArgs,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-struct Release {
-    version: String,
-    filename: String,
-    upload_time: DateTime<Utc>,
+#[derive(Args)]
+struct ServeArgs {
+    /// Address to listen on; 0.0.0.0 or :: serves other machines too
+    #[arg(long, env = "PIPPY_LISTEN", default_value = "127.0.0.1")]
+    listen: IpAddr,
+    #[arg(long, env = "PIPPY_PORT", default_value_t = 3000)]
+    port: u16,
+    /// PEM certificate chain to serve HTTPS with, leaf first
+    #[arg(long, env = "PIPPY_TLS_CERT", requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+    /// PEM private key of --tls-cert
+    #[arg(long, env = "PIPPY_TLS_KEY", requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+    /// PEM bundle of CAs that clients must present a certificate from; the
+    /// certificate's common name is the user a client acts as
+    #[arg(long, env = "PIPPY_TLS_CLIENT_CA", requires = "tls_cert")]
+    tls_client_ca: Option<PathBuf>,
+    /// Path prefix a reverse proxy serves the index under, used in generated links
+    #[arg(long, default_value = "")]
+    path_prefix: String,
+    /// Scheme and host clients reach the index at, e.g. https://pkg.example.com,
+    /// making generated links absolute; tenants get their own host under the
+    /// same scheme
+    #[arg(long, value_parser = parse_base_url)]
+    base_url: Option<String>,
+    /// Seconds an upload result is replayed for retries with the same Idempotency-Key
+    #[arg(long, default_value_t = 24 * 60 * 60)]
+    idempotency_window: u64,
+    /// Ingest wheels from releases of a repository, as `github:owner/repo=project`
+    /// or `gitlab:group/repo=project`
+    #[arg(long = "ingest")]
+    ingest_sources: Vec<IngestSource>,
+    /// Seconds between polls of the ingest sources
+    #[arg(long, default_value_t = 300)]
+    ingest_interval: u64,
+    #[arg(long, env = "GITHUB_TOKEN", hide_env_values = true)]
+    github_token: Option<String>,
+    #[arg(long, env = "GITLAB_TOKEN", hide_env_values = true)]
+    gitlab_token: Option<String>,
+    #[arg(long, default_value = "https://gitlab.com")]
+    gitlab_url: String,
+    /// Close HTTP/1.1 connections after each response
+    #[arg(long)]
+    no_http1_keep_alive: bool,
+    #[arg(long, default_value_t = 256)]
+    http2_max_concurrent_streams: u32,
+    /// Seconds between HTTP/2 keep-alive pings; unset disables them
+    #[arg(long)]
+    http2_keep_alive_interval: Option<u64>,
+    /// Seconds to wait for a keep-alive ping acknowledgement
+    #[arg(long, default_value_t = 20)]
+    http2_keep_alive_timeout: u64,
+    /// Seconds given on SIGINT or SIGTERM for open connections to finish,
+    /// and then again for background work and buffered stats to be saved
+    #[arg(long, default_value_t = 30)]
+    shutdown_timeout: u64,
+    /// Allow other pippy processes started with this flag to serve the same
+    /// data directory, e.g. during a blue/green deploy
+    #[arg(long)]
+    shared_storage: bool,
+    /// Milliseconds between checks for index writes by other processes
+    #[arg(long, default_value_t = 2000)]
+    change_poll_interval: u64,
+    /// Ed25519 private key (PKCS#8 PEM) used to sign checksum manifests and
+    /// upload receipts
+    #[arg(long)]
+    signing_key: Option<PathBuf>,
+    /// Append anonymized simple-API and download requests to this file, for
+    /// later use with `pippy replay`
+    #[arg(long)]
+    capture: Option<PathBuf>,
+    /// Refuse uploads that would give a project more than this many versions
+    #[arg(long)]
+    max_versions_per_project: Option<usize>,
+    /// Refuse more than this many uploads per project in any hour
+    #[arg(long)]
+    max_uploads_per_hour: Option<usize>,
+    /// Bytes of distribution files any one project may have stored;
+    /// uploads that would go over are refused
+    #[arg(long)]
+    project_quota: Option<u64>,
+    /// Bytes of distribution files the whole index may have stored
+    #[arg(long)]
+    total_quota: Option<u64>,
+    /// Keep only this many of each project's most recent versions, pruning
+    /// older ones on upload; the latest stable release is always kept
+    #[arg(long)]
+    keep_versions: Option<usize>,
+    /// Prune versions last uploaded more than this many days ago, other
+    /// than each project's latest stable release
+    #[arg(long)]
+    max_release_age: Option<u64>,
+    /// Seconds between passes applying --keep-versions and --max-release-age
+    /// to every project
+    #[arg(long, default_value_t = 60 * 60)]
+    retention_interval: u64,
+    /// Largest distribution file accepted, in bytes; larger uploads are
+    /// refused with 413 Payload Too Large
+    #[arg(long)]
+    max_upload_size: Option<u64>,
+    /// Most bytes an uploaded docs archive may unpack to; larger ones are
+    /// refused with 422 Unprocessable Entity
+    #[arg(long, default_value_t = 1024 * 1024 * 1024)]
+    max_docs_size: u64,
+    /// Most projects a bundle downloaded from the API may hold; larger
+    /// selections are refused with 400 Bad Request
+    #[arg(long, default_value_t = 100)]
+    max_bundle_projects: usize,
+    /// Most bytes of files a bundle downloaded from the API may hold;
+    /// larger ones are refused with 413 Payload Too Large
+    #[arg(long, default_value_t = 1024 * 1024 * 1024)]
+    max_bundle_size: u64,
+    /// Most projects kept in memory; the least recently used others are
+    /// read from their metadata files again when next needed
+    #[arg(long, default_value_t = 10_000)]
+    cached_projects: usize,
+    /// Which published files may be replaced by uploading different
+    /// contents under the same name: deny, allow, allow-prereleases-only
+    /// (also local versions), or a regular expression the whole version
+    /// must match
+    #[arg(long, default_value = "deny")]
+    overwrite: OverwritePolicy,
+    /// Hourly download and upload counts kept, in hours
+    #[arg(long, default_value_t = 48)]
+    stats_hourly_retention: u32,
+    /// Daily counts kept, in days
+    #[arg(long, default_value_t = 90)]
+    stats_daily_retention: u32,
+    /// Monthly counts kept, in months
+    #[arg(long, default_value_t = 36)]
+    stats_monthly_retention: u32,
+    /// Seconds between saves of the download and upload counts
+    #[arg(long, default_value_t = 60)]
+    stats_flush_interval: u64,
+    /// Directory distribution files are kept in by content, shared by the
+    /// default index and tenants so identical files are stored once; it
+    /// should be on the same filesystem as the data directories. Defaults
+    /// to `blobs` in each data directory
+    #[arg(long)]
+    blob_dir: Option<PathBuf>,
+    /// Where the default index keeps distribution files instead, as for
+    /// `migrate-storage --to`; tenants keep theirs in their data
+    /// directories
+    #[arg(long)]
+    storage: Option<String>,
+    /// Seconds between garbage collection passes, which remove stored files
+    /// no release refers to, releases whose file is gone and unlinked
+    /// blobs; unset disables them. With --scan-interval, wheels and sdists
+    /// copied in by hand are left for the scan to register
+    #[arg(long)]
+    gc_interval: Option<u64>,
+    /// Seconds since a stored file was last modified before garbage
+    /// collection may remove it
+    #[arg(long, default_value_t = 60 * 60)]
+    gc_min_age: u64,
+    /// Seconds between scans of storage for wheels and sdists copied in by
+    /// hand, which are then registered; unset disables scanning
+    #[arg(long)]
+    scan_interval: Option<u64>,
+    /// What to do with uploads whose dependencies are not on this index or
+    /// a --dependency-upstream
+    #[arg(long, value_enum, default_value_t = DependencyPolicy::Off)]
+    dependency_check: DependencyPolicy,
+    /// Simple index consulted by --dependency-check for projects not
+    /// hosted here, e.g. https://pypi.org/simple
+    #[arg(long = "dependency-upstream")]
+    dependency_upstreams: Vec<String>,
+    /// Serve a separate index to requests for a host name, as
+    /// `host=data-dir`; other hosts get the index in `data`
+    #[arg(long = "tenant")]
+    tenants: Vec<Tenant>,
+    /// Certificate to serve a --tenant host over HTTPS, as
+    /// `host=cert,key`; other hosts get --tls-cert
+    #[arg(long = "tenant-cert", requires = "tls_cert")]
+    tenant_certs: Vec<HostCertificate>,
+    /// Require an API token, from `pippy token create`, for uploads and
+    /// every other change to the index
+    #[arg(long, env = "PIPPY_REQUIRE_TOKEN")]
+    require_token: bool,
+    /// Refuse downloads of yanked files, unless forced with `?force=true`
+    /// by an owner, an admin or a token with the yank scope
+    #[arg(long, env = "PIPPY_REFUSE_YANKED_DOWNLOADS")]
+    refuse_yanked_downloads: bool,
+    /// Require Basic auth for reads, from the users in this file, one
+    /// `username:password-hash` line from `pippy hash-password` each;
+    /// token holders and write users may read too
+    #[arg(long, value_name = "FILE", env = "PIPPY_READ_USERS")]
+    read_users: Option<PathBuf>,
+    /// Accept Basic auth from the users in this file, in the same form as
+    /// --read-users, for uploads and every other change to the index, and
+    /// require it or a token
+    #[arg(long, value_name = "FILE", env = "PIPPY_WRITE_USERS")]
+    write_users: Option<PathBuf>,
+    /// Let a GitHub Actions workflow trade its OIDC token for a 15-minute
+    /// upload token, as `owner/repo:workflow.yml`, optionally followed by
+    /// `@environment`
+    #[arg(long = "trusted-publisher")]
+    trusted_publishers: Vec<TrustedPublisher>,
+    /// Audience trusted publishers request their OIDC token for
+    #[arg(long, default_value = "pippy")]
+    oidc_audience: String,
 }
 
-#[derive(Error, Debug)]
-enum AppError {
-    #[error("IO error: {0}")]
-    Io(#[from] std::io::Error),
-    #[error("JSON error: {0}")]
-    Json(#[from] serde_json::Error),
-    #[error("Package not found: {0}")]
-    NotFound(String),
-    #[error("Invalid package format: {0}")]
-    InvalidFormat(String),
-    #[error("Multipart error: {0}")]
-    Multipart(#[from] axum::extract::multipart::MultipartError),
+#[derive(Subcommand)]
+enum Command {
+    /// Run the index server (the default)
+    Serve(Box<ServeArgs>),
+    /// Drive synthetic load and report throughput and latency percentiles
+    Bench {
+        /// Base URL of a running instance; omit to benchmark an in-process server
+        #[arg(long)]
+        target: Option<String>,
+        /// Requests issued per scenario
+        #[arg(long, default_value_t = 1000)]
+        requests: usize,
+        #[arg(long, default_value_t = 16)]
+        concurrency: usize,
+        /// Packages uploaded before measuring
+        #[arg(long, default_value_t = 20)]
+        seed_packages: usize,
+        #[arg(
+            long,
+            value_enum,
+            value_delimiter = ',',
+            default_value = "simple,download,upload"
+        )]
+        scenarios: Vec<Scenario>,
+    },
+    /// Write an offline bundle of wheels plus a static simple index
+    Bundle {
+        /// `name==version` pins; omit to bundle the latest version of every project
+        pins: Vec<Pin>,
+        #[arg(long, short, default_value = "pippy-bundle.tar")]
+        output: PathBuf,
+        /// Only include files installable on this Python version (e.g. 3.11)
+        #[arg(long)]
+        python: Option<String>,
+        /// Only include files installable on this platform tag
+        #[arg(long)]
+        platform: Option<String>,
+    },
+    /// Replay requests recorded with `serve --capture` against an instance
+    Replay {
+        capture: PathBuf,
+        /// Base URL of the instance to replay against
+        #[arg(long)]
+        target: String,
+        /// Multiplier for the recorded pacing; 0 sends requests back to back
+        #[arg(long, default_value_t = 1.0)]
+        speed: f64,
+    },
+    /// Move a project to a new name, leaving a redirect at the old one
+    Rename {
+        from: PackageName,
+        to: PackageName,
+        /// Required while servers started with --shared-storage are running
+        #[arg(long)]
+        shared_storage: bool,
+    },
+    /// Check the index against stored files, docs, the journal and snapshots,
+    /// printing a JSON report; exits non-zero if problems remain
+    Fsck {
+        /// Apply the fixes that lose no data
+        #[arg(long)]
+        repair: bool,
+        /// Required while servers started with --shared-storage are running
+        #[arg(long)]
+        shared_storage: bool,
+    },
+    /// Report stored files no release refers to, releases whose file is
+    /// gone and blobs nothing links to
+    Gc {
+        /// Remove what is found
+        #[arg(long)]
+        remove: bool,
+        /// Seconds since a stored file was last modified before it may be
+        /// collected, so uploads in progress are left alone
+        #[arg(long, default_value_t = 60 * 60)]
+        min_age: u64,
+        /// Required while servers started with --shared-storage are running
+        #[arg(long)]
+        shared_storage: bool,
+    },
+    /// Rebuild the index from the distribution files in storage, rehashing
+    /// them and extracting their metadata again
+    Reindex {
+        /// Required while servers started with --shared-storage are running
+        #[arg(long)]
+        shared_storage: bool,
+    },
+    /// Add every wheel and sdist under a local directory to the index
+    Import {
+        dir: PathBuf,
+        /// How files get into the store; linking needs the directory on the
+        /// same filesystem as the data directory
+        #[arg(long, value_enum, default_value_t = LinkMode::Auto)]
+        link_mode: LinkMode,
+        /// Channel for every imported file, instead of one by version
+        #[arg(long)]
+        channel: Option<Channel>,
+        /// Required while servers started with --shared-storage are running
+        #[arg(long)]
+        shared_storage: bool,
+    },
+    /// Add wheels and sdists to the index without going through the server
+    Add {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        /// How files get into the store, as for `import`
+        #[arg(long, value_enum, default_value_t = LinkMode::Auto)]
+        link_mode: LinkMode,
+        /// Channel for every added file, instead of one by version
+        #[arg(long)]
+        channel: Option<Channel>,
+        /// Required while servers started with --shared-storage are running
+        #[arg(long)]
+        shared_storage: bool,
+    },
+    /// List projects with their file counts and newest version, or the
+    /// files of one project
+    List { project: Option<PackageName> },
+    /// Delete a project, or only the named files of it, from the index and
+    /// storage
+    Remove {
+        project: PackageName,
+        files: Vec<DistFilename>,
+        /// Required while servers started with --shared-storage are running
+        #[arg(long)]
+        shared_storage: bool,
+    },
+    /// Copy every stored file to another storage backend, checking each
+    /// copy by its SHA-256; files already copied are skipped, so running it
+    /// again catches up with uploads made meanwhile. Only reads the current
+    /// backend, so servers can keep running
+    MigrateStorage {
+        /// Backend to copy from; defaults to `packages` in the data
+        /// directory
+        #[arg(long)]
+        from: Option<String>,
+        /// Backend to copy to, e.g. `file:///mnt/packages`,
+        /// `gs://<bucket>/<prefix>` or `az://<account>/<container>/<prefix>`,
+        /// then passed to `serve --storage`
+        #[arg(long)]
+        to: String,
+    },
+    /// Compare the projects, files and digests of two index states; exits
+    /// non-zero if they differ
+    Diff {
+        /// `live`, a snapshot name, or an instance URL, optionally with
+        /// `#snapshot`
+        from: Side,
+        to: Side,
+        /// Hash every stored file instead of trusting recorded digests;
+        /// instances report the digests they recorded
+        #[arg(long)]
+        rehash: bool,
+        /// Print the differences as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Summarize the stats kept by the server
+    #[command(subcommand)]
+    Report(Report),
+    /// Manage the API tokens checked by --require-token
+    Token {
+        #[command(subcommand)]
+        command: TokenCommand,
+    },
+    /// Print a --read-users or --write-users line for `username`, hashing
+    /// the password read from stdin
+    HashPassword { username: String },
 }
 
-impl IntoResponse for AppError {
-    fn into_response(self) -> axum::response::Response {
-        let status = match &self {
-            AppError::NotFound(_) => StatusCode::NOT_FOUND,
-            _ => StatusCode::INTERNAL_SERVER_ERROR,
-        };
-        error!("Error: {}", self);
-        status.into_response()
-    }
+#[derive(Subcommand)]
+enum TokenCommand {
+    /// Create a token and print it; it cannot be shown again
+    Create {
+        /// What the token is for, e.g. `ci`
+        name: String,
+        /// Days until the token stops working; unset never expires
+        #[arg(long)]
+        expires_in_days: Option<i64>,
+        /// User the token acts for, limited to the projects they own;
+        /// unset makes an admin token
+        #[arg(long)]
+        user: Option<String>,
+        /// Limits an admin token to this capability; repeat for several
+        #[arg(long = "scope", value_enum, conflicts_with = "user")]
+        scopes: Vec<Scope>,
+    },
+    /// List tokens, without their values
+    List,
+    /// Revoke a token by id
+    Revoke { id: String },
 }
 
-#[derive(Clone)]
-struct PackageIndex {
-    packages: Arc<RwLock<HashMap<String, Package>>>,
-    storage: PackageStorage,
+#[derive(Subcommand)]
+enum Report {
+    /// Stored size, upload growth and file size distributions, for
+    /// forecasting storage and bandwidth
+    Capacity {
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
 }
 
-impl PackageIndex {
-    async fn new(base_path: PathBuf) -> Result<Self, AppError> {
-        let storage = PackageStorage::new(base_path.clone())?;
-        let packages = Arc::new(RwLock::new(storage.load_index().await?.unwrap_or_default()));
+#[tokio::main]
+async fn main() -> Result<(), AppError> {
+    let cli = Cli::parse();
+    tracing_subscriber::fmt()
+        .with_max_level(cli.log_level)
+        .with_file(true)
+        .with_line_number(true)
+        .with_thread_ids(true)
+        .with_target(false)
+        .init();
 
-        Ok(Self { packages, storage })
+    let data_dir = cli.data_dir;
+    match cli.command.unwrap_or(Command::Serve(Box::new(cli.serve))) {
+        Command::Serve(args) => serve(*args, data_dir).await,
+        Command::Bench {
+            target,
+            requests,
+            concurrency,
+            seed_packages,
+            scenarios,
+        } => {
+            let reports = bench::run(BenchOptions {
+                target,
+                requests,
+                concurrency,
+                scenarios,
+                seed_packages,
+            })
+            .await?;
+            for report in reports {
+                println!("{report}");
+            }
+            Ok(())
+        }
+        Command::Bundle {
+            pins,
+            output,
+            python,
+            platform,
+        } => {
+            let index = PackageIndex::new(data_dir.clone()).await?;
+            let selection = if pins.is_empty() {
+                BundleSelection::Latest
+            } else {
+                BundleSelection::Pins(pins)
+            };
+            let target = TargetEnvironment::from_query(&CompatibilityQuery { python, platform })?;
+            let output_file = std::fs::File::create(&output)?;
+            write_bundle(
+                &index,
+                &selection,
+                &target,
+                BundleLimits::UNLIMITED,
+                output_file,
+            )
+            .await?;
+            println!("wrote {}", output.display());
+            Ok(())
+        }
+        Command::Replay {
+            capture,
+            target,
+            speed,
+        } => {
+            let report = capture::replay(ReplayOptions {
+                capture,
+                target,
+                speed,
+            })
+            .await?;
+            println!("{report}");
+            Ok(())
+        }
+        Command::Rename {
+            from,
+            to,
+            shared_storage,
+        } => {
+            let index = PackageIndex::new(data_dir.clone()).await?;
+            let _claim = index.storage().claim(shared_storage)?;
+            index.rename(&from, to.clone()).await?;
+            println!("renamed {from} to {to}");
+            Ok(())
+        }
+        Command::Fsck {
+            repair,
+            shared_storage,
+        } => {
+            let index = PackageIndex::new(data_dir.clone()).await?;
+            let _claim = index.storage().claim(shared_storage)?;
+            let report = fsck::check(&index, repair).await?;
+            println!("{}", serde_json::to_string_pretty(&report)?);
+            if !report.is_clean() {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        Command::Gc {
+            remove,
+            min_age,
+            shared_storage,
+        } => {
+            let index = PackageIndex::new(data_dir.clone()).await?;
+            let _claim = index.storage().claim(shared_storage)?;
+            let options = GcOptions {
+                remove,
+                min_age: Duration::from_secs(min_age),
+                ..GcOptions::default()
+            };
+            let report = gc::collect(&index, options).await?;
+            print!("{report}");
+            Ok(())
+        }
+        Command::Reindex { shared_storage } => {
+            let index = PackageIndex::new(data_dir.clone()).await?;
+            let _claim = index.storage().claim(shared_storage)?;
+            let report = reindex::rebuild(&index).await?;
+            print!("{report}");
+            Ok(())
+        }
+        Command::Import {
+            dir,
+            link_mode,
+            channel,
+            shared_storage,
+        } => {
+            let index = PackageIndex::new(data_dir.clone()).await?;
+            let _claim = index.storage().claim(shared_storage)?;
+            let report = import::import_dir(&index, &dir, link_mode, channel).await?;
+            index.shutdown(Duration::from_secs(30)).await;
+            print!("{report}");
+            if !report.failed.is_empty() {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        Command::Add {
+            files,
+            link_mode,
+            channel,
+            shared_storage,
+        } => {
+            let index = PackageIndex::new(data_dir.clone()).await?;
+            let _claim = index.storage().claim(shared_storage)?;
+            let report = import::import_files(&index, files, link_mode, channel).await?;
+            index.shutdown(Duration::from_secs(30)).await;
+            print!("{report}");
+            if !report.failed.is_empty() {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        Command::List { project } => {
+            let index = PackageIndex::new(data_dir.clone()).await?;
+            let packages = index.packages().await?;
+            match project {
+                Some(name) => {
+                    let package = packages
+                        .into_iter()
+                        .find(|p| p.name == name)
+                        .ok_or_else(|| AppError::NotFound(name.to_string()))?;
+                    for release in package.releases {
+                        let yanked = if release.yanked { "\tyanked" } else { "" };
+                        println!(
+                            "{}\t{}\t{}{yanked}",
+                            release.version, release.filename, release.upload_time
+                        );
+                    }
+                }
+                None => {
+                    for package in packages.iter().filter(|p| p.renamed_to.is_none()) {
+                        let latest = package.releases.first().map_or("-", |r| r.version.as_str());
+                        println!(
+                            "{}\t{} files\t{latest}",
+                            package.name,
+                            package.releases.len()
+                        );
+                    }
+                }
+            }
+            Ok(())
+        }
+        Command::Remove {
+            project,
+            files,
+            shared_storage,
+        } => {
+            let index = PackageIndex::new(data_dir.clone()).await?;
+            let _claim = index.storage().claim(shared_storage)?;
+            if files.is_empty() {
+                index.delete_project(&project).await?;
+                println!("removed {project}");
+            }
+            for file in files {
+                index.delete_file(&project, &file).await?;
+                println!("removed {file}");
+            }
+            index.shutdown(Duration::from_secs(30)).await;
+            Ok(())
+        }
+        Command::MigrateStorage { from, to } => {
+            let from: Arc<dyn StorageBackend> = match from {
+                Some(from) => open_backend(&from)?,
+                None => Arc::new(FileSystemBackend::new(data_dir.join("packages"))?),
+            };
+            let report = migrate::migrate(from.as_ref(), open_backend(&to)?.as_ref()).await?;
+            print!("{report}");
+            Ok(())
+        }
+        Command::Diff {
+            from,
+            to,
+            rehash,
+            json,
+        } => {
+            let index = PackageIndex::new(data_dir.clone()).await?;
+            let a = Manifest::of(&index, &from, rehash).await?;
+            let b = Manifest::of(&index, &to, rehash).await?;
+            let diff = IndexDiff::compare(from, to, &a, &b);
+            if json {
+                println!("{}", serde_json::to_string_pretty(&diff)?);
+            } else {
+                print!("{diff}");
+            }
+            if !diff.is_identical() {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        Command::Token { command } => {
+            let tokens = TokenStore::new(PackageStorage::new(data_dir)?);
+            match command {
+                TokenCommand::Create {
+                    name,
+                    expires_in_days,
+                    user,
+                    scopes,
+                } => {
+                    let expires = expires_in_days
+                        .map(|days| chrono::Utc::now() + chrono::Duration::days(days));
+                    let (token, secret) = match &user {
+                        Some(user) => tokens.create_for(user, &name, expires).await?,
+                        None if !scopes.is_empty() => {
+                            tokens.create_scoped(&name, scopes, expires).await?
+                        }
+                        None => tokens.create(&name, expires).await?,
+                    };
+                    eprintln!("created token {} ({})", token.id, token.name);
+                    println!("{secret}");
+                }
+                TokenCommand::List => {
+                    for token in tokens.list().await? {
+                        let expires = match token.expires {
+                            Some(expires) if token.is_expired() => format!("expired {expires}"),
+                            Some(expires) => format!("expires {expires}"),
+                            None => "never expires".to_string(),
+                        };
+                        let user = match (&token.user, &token.scopes) {
+                            (Some(user), _) => user.clone(),
+                            (None, Some(scopes)) => format!("admin ({})", auth::scope_list(scopes)),
+                            (None, None) => "admin".to_string(),
+                        };
+                        println!(
+                            "{}\t{}\t{}\tcreated {}\t{}",
+                            token.id, token.name, user, token.created, expires
+                        );
+                    }
+                }
+                TokenCommand::Revoke { id } => {
+                    let token = tokens.revoke(&id).await?;
+                    println!("revoked token {} ({})", token.id, token.name);
+                }
+            }
+            Ok(())
+        }
+        Command::HashPassword { username } => {
+            let mut password = String::new();
+            std::io::stdin().read_line(&mut password)?;
+            let password = password.trim_end_matches(['\r', '\n']);
+            if password.is_empty() {
+                return Err(AppError::InvalidFormat("No password on stdin".into()));
+            }
+            println!("{}", auth::hash_password(&username, password)?);
+            Ok(())
+        }
+        Command::Report(Report::Capacity { json }) => {
+            let index = PackageIndex::new(data_dir.clone()).await?;
+            let report = CapacityReport::build(index.storage()).await?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                print!("{report}");
+            }
+            Ok(())
+        }
     }
+}
 
-    async fn add_release(
-        &self,
-        name: String,
-        version: String,
-        filename: String,
-    ) -> Result<(), AppError> {
-        let mut packages = self.packages.write().await;
-        let package = packages.entry(name.clone()).or_insert_with(|| Package {
-            name: name.clone(),
-            releases: Vec::new(),
-        });
-
-        package.releases.push(Release {
-            version,
-            filename,
-            upload_time: Utc::now(),
+async fn serve(args: ServeArgs, data_dir: PathBuf) -> Result<(), AppError> {
+    if let Some(unknown) = args
+        .tenant_certs
+        .iter()
+        .find(|cert| !args.tenants.iter().any(|tenant| tenant.host == cert.host))
+    {
+        return Err(AppError::InvalidFormat(format!(
+            "--tenant-cert for {}, which is not a --tenant",
+            unknown.host
+        )));
+    }
+    let config = Config {
+        path_prefix: args.path_prefix.clone(),
+        base_url: args.base_url.clone(),
+        idempotency_window: Duration::from_secs(args.idempotency_window),
+        ingest_sources: args.ingest_sources.clone(),
+        ingest_interval: Duration::from_secs(args.ingest_interval),
+        github_token: args.github_token.clone(),
+        gitlab_token: args.gitlab_token.clone(),
+        gitlab_url: args.gitlab_url.clone(),
+        connections: ConnectionSettings {
+            http1_keep_alive: !args.no_http1_keep_alive,
+            http2_max_concurrent_streams: args.http2_max_concurrent_streams,
+            http2_keep_alive_interval: args.http2_keep_alive_interval.map(Duration::from_secs),
+            http2_keep_alive_timeout: Duration::from_secs(args.http2_keep_alive_timeout),
+            drain_timeout: Duration::from_secs(args.shutdown_timeout),
+            tls: match (&args.tls_cert, &args.tls_key) {
+                (Some(cert), Some(key)) => Some(TlsConfig::with_host_certificates(
+                    cert,
+                    key,
+                    args.tls_client_ca.as_deref(),
+                    &args.tenant_certs,
+                )?),
+                _ => None,
+            },
+        },
+        shared_storage: args.shared_storage,
+        change_poll_interval: Duration::from_millis(args.change_poll_interval),
+        signing_key: args
+            .signing_key
+            .as_deref()
+            .map(ServerKey::load)
+            .transpose()?,
+        capture: match &args.capture {
+            Some(path) => Some(Capture::create(path).await?),
+            None => None,
+        },
+        dependencies: DependencyCheck::new(
+            args.dependency_check,
+            args.dependency_upstreams.clone(),
+        ),
+        require_token: args.require_token,
+        read_credentials: args
+            .read_users
+            .as_deref()
+            .map(Credentials::from_file)
+            .transpose()?,
+        write_credentials: args
+            .write_users
+            .as_deref()
+            .map(Credentials::from_file)
+            .transpose()?,
+        trusted_publishing: TrustedPublishing {
+            publishers: args.trusted_publishers.clone(),
+            audience: args.oidc_audience.clone(),
+            ..TrustedPublishing::default()
+        },
+        max_upload_size: args.max_upload_size,
+        max_docs_size: args.max_docs_size,
+        bundle_limits: BundleLimits {
+            max_projects: Some(args.max_bundle_projects),
+            max_bytes: Some(args.max_bundle_size),
+        },
+        refuse_yanked_downloads: args.refuse_yanked_downloads,
+    };
+
+    let backend = args.storage.as_deref().map(open_backend).transpose()?;
+    let (index, claim) = open_index(&args, data_dir, backend, &config).await?;
+    if !config.ingest_sources.is_empty() {
+        tokio::spawn(ingest::poll(index.clone(), config.clone()));
+    }
+    let mut hosts = HostRouter::new(router_with_config(index.clone(), config.clone()));
+    let mut indexes = vec![index];
+    let mut claims = vec![claim];
+    for tenant in &args.tenants {
+        let (index, claim) = open_index(&args, tenant.data_dir.clone(), None, &config).await?;
+        claims.push(claim);
+        let mut tenant_config = config.clone();
+        tenant_config.base_url = config.base_url.as_deref().map(|base_url| {
+            let scheme = base_url
+                .split_once("://")
+                .map_or("https", |(scheme, _)| scheme);
+            format!("{scheme}://{}", tenant.host)
         });
-
-        package
-            .releases
-            .sort_by(|a, b| b.upload_time.cmp(&a.upload_time));
-        self.storage.save_index(&packages).await?;
-        Ok(())
+        hosts = hosts.host(
+            &tenant.host,
+            router_with_config(index.clone(), tenant_config),
+        );
+        indexes.push(index);
+    }
+    let connections = config.connections.clone();
+
+    let listener = tokio::net::TcpListener::bind((args.listen, args.port)).await?;
+    let scheme = if connections.tls.is_some() {
+        "https"
+    } else {
+        "http"
+    };
+    tracing::info!("Listening on {scheme}://{}", listener.local_addr()?);
+    server::serve(
+        listener,
+        hosts.into_router(),
+        &connections,
+        shutdown_signal(),
+    )
+    .await?;
+    for index in indexes {
+        index
+            .shutdown(Duration::from_secs(args.shutdown_timeout))
+            .await;
     }
-}
 
-#[derive(Debug, Clone)]
-struct PackageStorage {
-    base_path: PathBuf,
-    packages_dir: PathBuf,
+    Ok(())
 }
 
-impl PackageStorage {
-    fn new(base_path: PathBuf) -> Result<Self, AppError> {
-        let packages_dir = base_path.join("packages");
-        std::fs::create_dir_all(&packages_dir)?;
-        std::fs::create_dir_all(&base_path)?;
-
-        Ok(Self {
-            base_path,
-            packages_dir,
+/// Opens and claims the index in `data_dir`, keeping files in `backend`
+/// if given, and starts its background work.
+async fn open_index(
+    args: &ServeArgs,
+    data_dir: PathBuf,
+    backend: Option<Arc<dyn StorageBackend>>,
+    config: &Config,
+) -> Result<(PackageIndex, InstanceLock), AppError> {
+    let storage = match (backend, &args.blob_dir) {
+        (Some(backend), _) => PackageStorage::with_backend(data_dir.clone(), backend)?,
+        (None, Some(blobs)) => {
+            let backend = FileSystemBackend::new(data_dir.join("packages"))?
+                .content_addressed(blobs.clone())?;
+            PackageStorage::with_backend(data_dir.clone(), Arc::new(backend))?
+        }
+        (None, None) => PackageStorage::new(data_dir.clone())?,
+    };
+    let index = PackageIndex::with_storage(storage)
+        .await?
+        .with_limits(UploadLimits {
+            max_versions: args.max_versions_per_project,
+            max_uploads_per_hour: args.max_uploads_per_hour,
+        })
+        .with_overwrite_policy(args.overwrite.clone())
+        .with_quotas(Quotas {
+            project_bytes: args.project_quota,
+            total_bytes: args.total_quota,
         })
+        .with_retention(RetentionPolicy {
+            max_versions: args.keep_versions,
+            max_age: args
+                .max_release_age
+                .map(|days| Duration::from_secs(days * 24 * 60 * 60)),
+        })
+        .with_stats_retention(StatsRetention {
+            hours: args.stats_hourly_retention,
+            days: args.stats_daily_retention,
+            months: args.stats_monthly_retention,
+        })
+        .with_cached_projects(args.cached_projects);
+    let claim = index.storage().claim(config.shared_storage)?;
+    if config.require_token
+        && TokenStore::new(index.storage().clone())
+            .list()
+            .await?
+            .is_empty()
+    {
+        tracing::warn!(
+            "{} has no API tokens, so nothing can be uploaded until one is created with `pippy token create`",
+            data_dir.display()
+        );
     }
-
-    async fn load_index(&self) -> Result<Option<HashMap<String, Package>>, AppError> {
-        let index_path = self.base_path.join("index.json");
-        if !index_path.exists() {
-            return Ok(None);
-        }
-
-        let content = tokio::fs::read_to_string(index_path).await?;
-        Ok(Some(serde_json::from_str(&content)?))
+    if config.shared_storage {
+        tokio::spawn(index.clone().follow_changes(config.change_poll_interval));
     }
-
-    async fn save_index(&self, packages: &HashMap<String, Package>) -> Result<(), AppError> {
-        let content = serde_json::to_string_pretty(packages)?;
-        tokio::fs::write(self.base_path.join("index.json"), content).await?;
-        Ok(())
+    tokio::spawn(index.clone().deliver_webhooks());
+    tokio::spawn(
+        index
+            .clone()
+            .flush_stats(Duration::from_secs(args.stats_flush_interval)),
+    );
+    if !index.retention().is_unlimited() {
+        tokio::spawn(retention::enforce(
+            index.clone(),
+            Duration::from_secs(args.retention_interval),
+        ));
     }
-
-    async fn store_package(
-        &self,
-        name: &str,
-        filename: &str,
-        contents: Vec<u8>,
-    ) -> Result<(), AppError> {
-        let package_dir = self.packages_dir.join(name);
-        tokio::fs::create_dir_all(&package_dir).await?;
-        tokio::fs::write(package_dir.join(filename), contents).await?;
-        Ok(())
+    if let Some(interval) = args.gc_interval {
+        let options = GcOptions {
+            remove: true,
+            min_age: Duration::from_secs(args.gc_min_age),
+            spare_distributions: args.scan_interval.is_some(),
+        };
+        tokio::spawn(gc::run(
+            index.clone(),
+            Duration::from_secs(interval),
+            options,
+        ));
     }
+    if let Some(interval) = args.scan_interval {
+        tokio::spawn(reindex::watch(index.clone(), Duration::from_secs(interval)));
+    }
+    Ok((index, claim))
 }
 
-async fn render_html(title: &str, content: String) -> Html<String> {
-    Html(format!(
-        r#"<!DOCTYPE html>
-<html>
-<style>
-    body {{
-        background-color: #1e1e1e;
-        color: #d4d4d4;
-        font-family: Arial, sans-serif;
-        margin: 0;
-        padding: 0;
-    }}
-</style>
-<head><title>{title}</title></head>
-<body>
-    <h1>{title}</h1>
-    {content}
-</body>
-</html>"#
-    ))
-}
-
-async fn list_packages(State(index): State<PackageIndex>) -> Result<Html<String>, AppError> {
-    let packages = index.packages.read().await;
-    let links = packages
-        .keys()
-        .map(|name| format!("<a href='/simple/{0}/'>{0}</a><br>\n", name))
-        .collect();
-
-    Ok(render_html("Package Index", links).await)
-}
-
-async fn package_details(
-    State(index): State<PackageIndex>,
-    Path(name): Path<String>,
-) -> Result<Html<String>, AppError> {
-    let packages = index.packages.read().await;
-    let package = packages
-        .get(&name)
-        .ok_or_else(|| AppError::NotFound(name.clone()))?;
-
-    let links = package
-        .releases
-        .iter()
-        .map(|r| {
-            format!(
-                "<a href='/packages/{0}/{1}'>{1}</a> (uploaded: {}) Uploaded: {2}<br>\n",
-                package.name,
-                r.filename,
-                r.upload_time.format("%Y-%m-%d %H:%M:%S UTC")
-            )
-        })
-        .collect();
-
-    Ok(render_html(&format!("{} Versions", name), links).await)
-}
-
-async fn upload_package(
-    State(index): State<PackageIndex>,
-    mut multipart: Multipart,
-) -> Result<StatusCode, AppError> {
-    while let Some(field) = multipart.next_field().await? {
-        // Now this will use From<MultipartError>
-        if let Some(filename) = field.file_name() {
-            if !filename.ends_with(".whl") {
-                continue;
-            }
-
-            let parts: Vec<&str> = filename.split('-').collect();
-            if parts.len() < 2 {
-                return Err(AppError::InvalidFormat(
-                    "Invalid package filename format".into(),
-                ));
-            }
-
-            let package_name = parts[0].to_string();
-            let version = parts[1].to_string();
-            // let contents = field.bytes().await?; // This will now use From<MultipartError> too
-            let contents = vec![];
-
-            index
-                .storage
-                .store_package(&package_name, filename, contents.to_vec())
-                .await?;
-            index
-                .add_release(package_name.clone(), version, filename.to_string())
-                .await?;
-
-            info!("Successfully uploaded package: {}", package_name);
+/// Completes on the first SIGINT or SIGTERM.
+async fn shutdown_signal() {
+    let interrupt = tokio::signal::ctrl_c();
+    #[cfg(unix)]
+    {
+        let mut terminate =
+            tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+                .expect("installing a SIGTERM handler");
+        tokio::select! {
+            _ = interrupt => {}
+            _ = terminate.recv() => {}
         }
     }
-
-    Ok(StatusCode::OK)
+    #[cfg(not(unix))]
+    let _ = interrupt.await;
 }
 
-#[tokio::main]
-async fn main() -> Result<(), AppError> {
-    tracing_subscriber::fmt()
-        .with_file(true)
-        .with_line_number(true)
-        .with_thread_ids(true)
-        .with_target(false)
-        .init();
-
-    // default to show all logs
-    let filter = std::env::var("RUST_LOG").unwrap_or_else(|_| "info".to_string());
-    std::env::set_var("RUST_LOG", &filter);
-
-    let index = PackageIndex::new(PathBuf::from("data")).await?;
-
-    let app = Router::new()
-        .route(
-            "/",
-            get(|| async {
-                Html(
-                    r#"<!DOCTYPE html>
-<html>
-<style>
-    body {
-        background-color: #1e1e1e;
-        color: #d4d4d4;
-        font-family: Arial, sans-serif;
-        margin: 0;
-        padding: 0;
+fn parse_base_url(url: &str) -> Result<String, String> {
+    let rest = url
+        .strip_prefix("https://")
+        .or_else(|| url.strip_prefix("http://"))
+        .ok_or("expected an http:// or https:// URL")?;
+    if rest.trim_end_matches('/').is_empty() || rest.contains(['?', '#']) {
+        return Err("expected a scheme and host, e.g. https://pkg.example.com".into());
     }
-</style>
-<head><title>{title}</title></head>
-<body>
-    <h1>Simple PyPI Server</h1>
-    <p>Use /simple/ for package listing</p>
-    <p>Upload packages using POST to /upload</p>
-</body>
-</html>
-"#,
-                )
-            }),
-        )
-        .route("/simple/", get(list_packages))
-        .route("/simple/:package/", get(package_details))
-        .route("/upload", post(upload_package))
-        .layer(TraceLayer::new_for_http())
-        .with_state(index);
-
-    let listener = tokio::net::TcpListener::bind("127.0.0.1:3000")
-        .await
-        .unwrap();
-    tracing::debug!("listening on {}", listener.local_addr().unwrap());
-    axum::serve(listener, app).await.unwrap();
-
-    Ok(())
+    Ok(url.trim_end_matches('/').to_string())
 }
@@ -0,0 +1,172 @@
+//! Edits to the mutable metadata of existing projects and releases, and the
+//! audit log every edit is recorded in.
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{AppError, Channel, PackageName, Version};
+
+/// Longest summary accepted, matching PyPI's limit.
+const MAX_SUMMARY_LEN: usize = 512;
+const MAX_PROJECT_URLS: usize = 32;
+const MAX_URL_LABEL_LEN: usize = 32;
+const MAX_URL_LEN: usize = 2048;
+const MAX_DEPRECATION_LEN: usize = 1024;
+const MAX_YANK_REASON_LEN: usize = 1024;
+const MAX_OWNERS: usize = 64;
+const MAX_OWNER_LEN: usize = 128;
+
+/// Fields of a project that can be changed after upload. Absent fields are
+/// left alone; an empty summary clears it and `project_urls` replaces the
+/// whole set.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ProjectUpdate {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub project_urls: Option<BTreeMap<String, String>>,
+}
+
+impl ProjectUpdate {
+    pub fn validate(&self) -> Result<(), AppError> {
+        if let Some(summary) = &self.summary {
+            if summary.chars().count() > MAX_SUMMARY_LEN {
+                return Err(AppError::InvalidFormat(format!(
+                    "Summary is longer than {MAX_SUMMARY_LEN} characters"
+                )));
+            }
+            if summary.contains(['\r', '\n']) {
+                return Err(AppError::InvalidFormat(
+                    "Summary must be a single line".into(),
+                ));
+            }
+        }
+        if let Some(urls) = &self.project_urls {
+            if urls.len() > MAX_PROJECT_URLS {
+                return Err(AppError::InvalidFormat(format!(
+                    "At most {MAX_PROJECT_URLS} project URLs are allowed"
+                )));
+            }
+            for (label, url) in urls {
+                if label.trim().is_empty() || label.chars().count() > MAX_URL_LABEL_LEN {
+                    return Err(AppError::InvalidFormat(format!(
+                        "Project URL labels must be 1 to {MAX_URL_LABEL_LEN} characters"
+                    )));
+                }
+                let is_web = url.starts_with("https://") || url.starts_with("http://");
+                if !is_web
+                    || url.len() > MAX_URL_LEN
+                    || url.chars().any(|c| c.is_whitespace() || c.is_control())
+                {
+                    return Err(AppError::InvalidFormat(format!(
+                        "Project URL {label:?} must be an http(s) URL"
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Fields shared by every file of a release that can be changed after
+/// upload. An empty deprecation note clears it.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ReleaseUpdate {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deprecated: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub channel: Option<Channel>,
+}
+
+impl ReleaseUpdate {
+    pub fn validate(&self) -> Result<(), AppError> {
+        if let Some(note) = &self.deprecated {
+            if note.chars().count() > MAX_DEPRECATION_LEN {
+                return Err(AppError::InvalidFormat(format!(
+                    "Deprecation notes are limited to {MAX_DEPRECATION_LEN} characters"
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Fields of a single file that can be changed after upload. Unyanking
+/// clears the reason, and an empty reason clears it too.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct FileUpdate {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub yanked: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub yanked_reason: Option<String>,
+}
+
+impl FileUpdate {
+    pub fn validate(&self) -> Result<(), AppError> {
+        if let Some(reason) = &self.yanked_reason {
+            if self.yanked == Some(false) && !reason.is_empty() {
+                return Err(AppError::InvalidFormat(
+                    "A yank reason cannot be given when unyanking".into(),
+                ));
+            }
+            if reason.chars().count() > MAX_YANK_REASON_LEN {
+                return Err(AppError::InvalidFormat(format!(
+                    "Yank reasons are limited to {MAX_YANK_REASON_LEN} characters"
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The users allowed to change a project, replacing the current set.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct OwnersUpdate {
+    pub owners: Vec<String>,
+}
+
+impl OwnersUpdate {
+    pub fn validate(&self) -> Result<(), AppError> {
+        if self.owners.len() > MAX_OWNERS {
+            return Err(AppError::InvalidFormat(format!(
+                "At most {MAX_OWNERS} owners are allowed"
+            )));
+        }
+        for owner in &self.owners {
+            if owner.is_empty()
+                || owner.chars().count() > MAX_OWNER_LEN
+                || owner.chars().any(|c| c.is_whitespace() || c.is_control())
+            {
+                return Err(AppError::InvalidFormat(format!(
+                    "Owners must be user names of 1 to {MAX_OWNER_LEN} characters: {owner:?}"
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// One metadata edit, written as a line of `data/audit.jsonl`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct AuditEntry {
+    pub time: DateTime<Utc>,
+    pub project: PackageName,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<Version>,
+    /// The fields as they were before the edit.
+    pub before: Value,
+    /// The edit as requested.
+    pub changes: Value,
+}
+
+/// `Some(text)`, or `None` for an empty string.
+pub(crate) fn non_empty(text: &str) -> Option<String> {
+    (!text.is_empty()).then(|| text.to_string())
+}
@@ -0,0 +1,107 @@
+//! Copying stored files from one storage backend to another, as
+//! `pippy migrate-storage` does. The index names files by key alone, so
+//! once the copy is complete the server only needs starting with the new
+//! backend.
+
+use std::{fmt, io};
+
+use futures_util::StreamExt;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tokio_util::io::ReaderStream;
+use tracing::info;
+
+use crate::{backend::StorageBackend, AppError};
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MigrationReport {
+    /// Keys copied in this run.
+    pub copied: Vec<String>,
+    /// Keys the destination already held with the same bytes, as copied by
+    /// an earlier run.
+    pub skipped: usize,
+    /// Bytes copied in this run.
+    pub bytes: u64,
+}
+
+impl fmt::Display for MigrationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "copied {} objects, {} bytes; {} already copied",
+            self.copied.len(),
+            self.bytes,
+            self.skipped
+        )?;
+        for key in &self.copied {
+            writeln!(f, "copied: {key}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The hex SHA-256 of `key` in `backend`, read through, or `None` if there
+/// is no such object.
+async fn digest(backend: &dyn StorageBackend, key: &str) -> io::Result<Option<String>> {
+    let Some((reader, _)) = backend.open(key).await? else {
+        return Ok(None);
+    };
+    let mut chunks = ReaderStream::new(reader);
+    let mut hasher = Sha256::new();
+    while let Some(chunk) = chunks.next().await {
+        hasher.update(chunk?);
+    }
+    Ok(Some(format!("{:x}", hasher.finalize())))
+}
+
+/// Copies every object of `from` into `to`, checking each copy against the
+/// SHA-256 of what was read. Objects `to` already holds with the same bytes
+/// are skipped, so an interrupted migration, or one overtaken by uploads,
+/// is finished by running it again. Only reads `from`, so servers using it
+/// can keep running; nothing is removed from `to`.
+pub async fn migrate(
+    from: &dyn StorageBackend,
+    to: &dyn StorageBackend,
+) -> Result<MigrationReport, AppError> {
+    let copied: std::collections::BTreeMap<String, u64> = to
+        .list("")
+        .await?
+        .into_iter()
+        .map(|object| (object.key, object.size))
+        .collect();
+    let mut report = MigrationReport::default();
+    for object in from.list("").await? {
+        let key = object.key;
+        if copied.get(&key) == Some(&object.size)
+            && digest(to, &key).await? == digest(from, &key).await?
+        {
+            report.skipped += 1;
+            continue;
+        }
+        let Some((reader, _)) = from.open(&key).await? else {
+            // Deleted since it was listed.
+            continue;
+        };
+        let mut hasher = Sha256::new();
+        let chunks = ReaderStream::new(reader).inspect(|chunk| {
+            if let Ok(chunk) = chunk {
+                hasher.update(chunk);
+            }
+        });
+        let size = to.store(&key, chunks.boxed()).await?;
+        let expected = format!("{:x}", hasher.finalize());
+        let stored = digest(to, &key).await?;
+        if stored.as_ref() != Some(&expected) {
+            to.delete(&key).await?;
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{key} was stored with SHA-256 {stored:?}, not {expected}"),
+            )
+            .into());
+        }
+        info!("Copied {} ({} bytes)", key, size);
+        report.bytes += size;
+        report.copied.push(key);
+    }
+    Ok(report)
+}
@@ -0,0 +1,316 @@
+//! Trusted publishing: CI jobs trade an OIDC token from GitHub Actions for
+//! a short-lived API token, at the same `/_/oidc/` endpoints as PyPI so
+//! `pypa/gh-action-pypi-publish` works unchanged.
+
+use std::{fmt, str::FromStr};
+
+use axum::{extract::State, Json};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{Duration, Utc};
+use reqwest::Client;
+use ring::signature::{RsaPublicKeyComponents, RSA_PKCS1_2048_8192_SHA256};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::info;
+
+use crate::{auth::TokenStore, AppError, AppState};
+
+/// The issuer of GitHub Actions OIDC tokens.
+pub const GITHUB_ISSUER: &str = "https://token.actions.githubusercontent.com";
+
+/// How long a minted token lasts, as on PyPI.
+const MINTED_TOKEN_LIFETIME: Duration = Duration::minutes(15);
+
+/// Clock skew tolerated on a token's validity period, in seconds.
+const LEEWAY: i64 = 60;
+
+/// A workflow allowed to publish, written as `owner/repo:workflow.yml`,
+/// or `owner/repo:workflow.yml@environment` to also require a deployment
+/// environment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrustedPublisher {
+    pub repository: String,
+    /// File name of the workflow under `.github/workflows/`.
+    pub workflow: String,
+    pub environment: Option<String>,
+}
+
+impl FromStr for TrustedPublisher {
+    type Err = AppError;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            AppError::InvalidFormat(format!(
+                "Expected owner/repo:workflow.yml or owner/repo:workflow.yml@environment: {spec}"
+            ))
+        };
+        let (repository, rest) = spec.split_once(':').ok_or_else(invalid)?;
+        let (workflow, environment) = match rest.split_once('@') {
+            Some((workflow, environment)) => (workflow, Some(environment)),
+            None => (rest, None),
+        };
+        let valid_repository = repository.split_once('/').is_some_and(|(owner, repo)| {
+            !owner.is_empty() && !repo.is_empty() && !repo.contains('/')
+        });
+        if !valid_repository
+            || workflow.is_empty()
+            || workflow.contains('/')
+            || environment.is_some_and(str::is_empty)
+        {
+            return Err(invalid());
+        }
+        Ok(Self {
+            repository: repository.to_string(),
+            workflow: workflow.to_string(),
+            environment: environment.map(str::to_string),
+        })
+    }
+}
+
+impl fmt::Display for TrustedPublisher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.repository, self.workflow)?;
+        if let Some(environment) = &self.environment {
+            write!(f, "@{environment}")?;
+        }
+        Ok(())
+    }
+}
+
+impl TrustedPublisher {
+    /// The user minted tokens act for, `github:owner/repo`, which owns the
+    /// projects the workflow publishes first and can be made an owner of
+    /// others.
+    pub fn user(&self) -> String {
+        format!("github:{}", self.repository)
+    }
+
+    /// Whether the verified `claims` come from this workflow. Repository
+    /// names are compared as GitHub does, ignoring case.
+    fn matches(&self, claims: &Claims) -> bool {
+        let workflow = format!("{}/.github/workflows/{}", self.repository, self.workflow);
+        let workflow_path = claims
+            .workflow_ref
+            .split_once('@')
+            .map_or(claims.workflow_ref.as_str(), |(path, _)| path);
+        claims.repository.eq_ignore_ascii_case(&self.repository)
+            && workflow_path.eq_ignore_ascii_case(&workflow)
+            && self
+                .environment
+                .as_ref()
+                .is_none_or(|environment| claims.environment.as_ref() == Some(environment))
+    }
+}
+
+/// Trusted publishing settings. Nothing can be minted until a publisher is
+/// configured.
+#[derive(Debug, Clone)]
+pub struct TrustedPublishing {
+    pub publishers: Vec<TrustedPublisher>,
+    /// Issuer whose signing keys verify OIDC tokens, found through its
+    /// OpenID discovery document.
+    pub issuer: String,
+    /// The `aud` claim tokens must carry.
+    pub audience: String,
+}
+
+impl Default for TrustedPublishing {
+    fn default() -> Self {
+        Self {
+            publishers: Vec::new(),
+            issuer: GITHUB_ISSUER.to_string(),
+            audience: "pippy".to_string(),
+        }
+    }
+}
+
+/// The claims checked, of the many GitHub puts in its tokens.
+#[derive(Debug, Deserialize)]
+struct Claims {
+    iss: String,
+    aud: Audience,
+    exp: i64,
+    #[serde(default)]
+    nbf: Option<i64>,
+    repository: String,
+    /// `owner/repo/.github/workflows/<file>@<ref>`.
+    workflow_ref: String,
+    #[serde(default)]
+    environment: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Audience {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Audience {
+    fn contains(&self, audience: &str) -> bool {
+        match self {
+            Audience::One(one) => one == audience,
+            Audience::Many(many) => many.iter().any(|a| a == audience),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Header {
+    alg: String,
+    kid: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Discovery {
+    jwks_uri: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct KeySet {
+    keys: Vec<Key>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Key {
+    kty: String,
+    #[serde(default)]
+    kid: Option<String>,
+    #[serde(default)]
+    n: Option<String>,
+    #[serde(default)]
+    e: Option<String>,
+}
+
+fn rejected(reason: &str) -> AppError {
+    AppError::Forbidden(format!("OIDC token rejected: {reason}"))
+}
+
+fn decode_part<T: for<'de> Deserialize<'de>>(part: &str) -> Result<T, AppError> {
+    let bytes = URL_SAFE_NO_PAD
+        .decode(part)
+        .map_err(|_| AppError::InvalidFormat("OIDC token is not a JWT".into()))?;
+    Ok(serde_json::from_slice(&bytes)?)
+}
+
+/// The claims of `token` once its RS256 signature checks out against the
+/// issuer's published keys and it is current and meant for this index.
+async fn verify(
+    client: &Client,
+    settings: &TrustedPublishing,
+    token: &str,
+) -> Result<Claims, AppError> {
+    let mut parts = token.split('.');
+    let (Some(header), Some(payload), Some(signature), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(AppError::InvalidFormat("OIDC token is not a JWT".into()));
+    };
+    let header: Header = decode_part(header)?;
+    if header.alg != "RS256" {
+        return Err(rejected("unsupported signature algorithm"));
+    }
+    let signature = URL_SAFE_NO_PAD
+        .decode(signature)
+        .map_err(|_| AppError::InvalidFormat("OIDC token is not a JWT".into()))?;
+
+    let issuer = settings.issuer.trim_end_matches('/');
+    let discovery: Discovery = client
+        .get(format!("{issuer}/.well-known/openid-configuration"))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    let keys: KeySet = client
+        .get(&discovery.jwks_uri)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    let (signed, _) = token.rsplit_once('.').expect("a JWT has three parts");
+    let verified = keys
+        .keys
+        .iter()
+        .filter(|key| key.kty == "RSA" && (header.kid.is_none() || key.kid == header.kid))
+        .filter_map(|key| {
+            let n = URL_SAFE_NO_PAD.decode(key.n.as_deref()?).ok()?;
+            let e = URL_SAFE_NO_PAD.decode(key.e.as_deref()?).ok()?;
+            Some(RsaPublicKeyComponents { n, e })
+        })
+        .any(|key| {
+            key.verify(&RSA_PKCS1_2048_8192_SHA256, signed.as_bytes(), &signature)
+                .is_ok()
+        });
+    if !verified {
+        return Err(rejected("signature does not match the issuer's keys"));
+    }
+
+    let claims: Claims = decode_part(payload)?;
+    let now = Utc::now().timestamp();
+    if claims.iss.trim_end_matches('/') != issuer {
+        return Err(rejected("unexpected issuer"));
+    }
+    if !claims.aud.contains(&settings.audience) {
+        return Err(rejected("unexpected audience"));
+    }
+    if claims.exp + LEEWAY <= now || claims.nbf.is_some_and(|nbf| nbf - LEEWAY > now) {
+        return Err(rejected("expired or not yet valid"));
+    }
+    Ok(claims)
+}
+
+/// The audience CI jobs should request their OIDC token for.
+pub(crate) async fn audience(State(state): State<AppState>) -> Json<Value> {
+    Json(json!({ "audience": state.config.trusted_publishing.audience }))
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct MintRequest {
+    token: String,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct Minted {
+    success: bool,
+    token: String,
+    /// Unix time the token stops working.
+    expires: i64,
+}
+
+/// Trades an OIDC token from a trusted publisher for an API token that
+/// expires after fifteen minutes and acts for [`TrustedPublisher::user`].
+pub(crate) async fn mint_token(
+    State(state): State<AppState>,
+    Json(request): Json<MintRequest>,
+) -> Result<Json<Minted>, AppError> {
+    let settings = &state.config.trusted_publishing;
+    if settings.publishers.is_empty() {
+        return Err(AppError::NotFound("Trusted publishing".into()));
+    }
+    let claims = verify(&Client::new(), settings, &request.token).await?;
+    let publisher = settings
+        .publishers
+        .iter()
+        .find(|publisher| publisher.matches(&claims))
+        .ok_or_else(|| rejected("no trusted publisher matches this workflow"))?;
+
+    let expires = Utc::now() + MINTED_TOKEN_LIFETIME;
+    let (token, secret) = TokenStore::new(state.index.storage.clone())
+        .create_for(
+            &publisher.user(),
+            &format!("trusted publisher {publisher}"),
+            Some(expires),
+        )
+        .await?;
+    info!(
+        "Minted token {} for {} ({})",
+        token.id, publisher, claims.workflow_ref
+    );
+    Ok(Json(Minted {
+        success: true,
+        token: secret,
+        expires: expires.timestamp(),
+    }))
+}
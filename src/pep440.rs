@@ -0,0 +1,442 @@
+//! PEP 440 version parsing and ordering, so releases sort the way
+//! installers compare them: `1.10` after `1.9`, pre-releases before their
+//! final release and post-releases after it.
+
+use std::{cmp::Ordering, str::FromStr};
+
+use crate::AppError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PreRelease {
+    Alpha,
+    Beta,
+    Candidate,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum LocalSegment {
+    Number(u64),
+    Text(String),
+}
+
+impl Ord for LocalSegment {
+    /// Numeric segments sort after alphanumeric ones.
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Self::Number(a), Self::Number(b)) => a.cmp(b),
+            (Self::Text(a), Self::Text(b)) => a.cmp(b),
+            (Self::Number(_), Self::Text(_)) => Ordering::Greater,
+            (Self::Text(_), Self::Number(_)) => Ordering::Less,
+        }
+    }
+}
+
+impl PartialOrd for LocalSegment {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// A version broken into its PEP 440 parts. Equality follows the
+/// ordering, so `1.0` equals `1.0.0` and `1.0ALPHA1` equals `1.0a1`.
+#[derive(Debug, Clone)]
+pub struct ParsedVersion {
+    pub epoch: u64,
+    pub release: Vec<u64>,
+    pub pre: Option<(PreRelease, u64)>,
+    pub post: Option<u64>,
+    pub dev: Option<u64>,
+    local: Vec<LocalSegment>,
+}
+
+impl ParsedVersion {
+    pub fn is_prerelease(&self) -> bool {
+        self.pre.is_some() || self.dev.is_some()
+    }
+
+    /// Where the pre-release part sorts: a bare development release (such
+    /// as `1.0.dev1`) comes before any pre-release of its version, and a
+    /// version without a pre-release after them all.
+    fn pre_key(&self) -> (u8, Option<(PreRelease, u64)>) {
+        match (self.pre, self.post, self.dev) {
+            (None, None, Some(_)) => (0, None),
+            (Some(pre), _, _) => (1, Some(pre)),
+            (None, _, _) => (2, None),
+        }
+    }
+}
+
+impl Ord for ParsedVersion {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let len = self.release.len().max(other.release.len());
+        let segment = |release: &[u64], i: usize| release.get(i).copied().unwrap_or(0);
+        self.epoch
+            .cmp(&other.epoch)
+            .then_with(|| {
+                (0..len)
+                    .map(|i| segment(&self.release, i).cmp(&segment(&other.release, i)))
+                    .find(|ordering| ordering.is_ne())
+                    .unwrap_or(Ordering::Equal)
+            })
+            .then_with(|| self.pre_key().cmp(&other.pre_key()))
+            .then_with(|| self.post.cmp(&other.post))
+            // Development releases come before the release they lead to.
+            .then_with(|| (self.dev.is_none(), self.dev).cmp(&(other.dev.is_none(), other.dev)))
+            .then_with(|| self.local.cmp(&other.local))
+    }
+}
+
+impl PartialOrd for ParsedVersion {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for ParsedVersion {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for ParsedVersion {}
+
+/// What remains of a version string as it is parsed.
+struct Cursor<'a> {
+    rest: &'a str,
+}
+
+impl Cursor<'_> {
+    fn number(&mut self) -> Option<u64> {
+        let end = self
+            .rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(self.rest.len());
+        let number = self.rest[..end].parse().ok()?;
+        self.rest = &self.rest[end..];
+        Some(number)
+    }
+
+    fn separator(&mut self) -> bool {
+        match self.rest.strip_prefix(['.', '-', '_']) {
+            Some(rest) => {
+                self.rest = rest;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// An optional separator followed by one of `words`, consuming
+    /// nothing if none follows.
+    fn word(&mut self, words: &[&'static str]) -> Option<&'static str> {
+        let start = self.rest;
+        self.separator();
+        if let Some(word) = words.iter().find(|word| self.rest.starts_with(**word)) {
+            self.rest = &self.rest[word.len()..];
+            return Some(word);
+        }
+        self.rest = start;
+        None
+    }
+
+    /// The number after a pre-, post- or dev-release word, which may be
+    /// separated from it and defaults to 0.
+    fn implicit_number(&mut self) -> u64 {
+        let start = self.rest;
+        self.separator();
+        self.number().unwrap_or_else(|| {
+            self.rest = start;
+            0
+        })
+    }
+}
+
+impl FromStr for ParsedVersion {
+    type Err = AppError;
+
+    fn from_str(version: &str) -> Result<Self, Self::Err> {
+        let invalid = || AppError::InvalidFormat(format!("Not a PEP 440 version: {version:?}"));
+        let lowered = version.trim().to_ascii_lowercase();
+        let lowered = lowered.strip_prefix('v').unwrap_or(&lowered);
+        let (public, local) = match lowered.split_once('+') {
+            Some((public, local)) => (public, Some(local)),
+            None => (lowered, None),
+        };
+
+        let mut cursor = Cursor { rest: public };
+        let epoch = match public.split_once('!') {
+            Some((epoch, rest)) => {
+                cursor.rest = rest;
+                epoch.parse().map_err(|_| invalid())?
+            }
+            None => 0,
+        };
+        let mut release = vec![cursor.number().ok_or_else(invalid)?];
+        while cursor.rest.starts_with('.')
+            && cursor.rest[1..].starts_with(|c: char| c.is_ascii_digit())
+        {
+            cursor.rest = &cursor.rest[1..];
+            release.push(cursor.number().ok_or_else(invalid)?);
+        }
+
+        let pre = cursor
+            .word(&["alpha", "beta", "preview", "pre", "rc", "a", "b", "c"])
+            .map(|word| {
+                let kind = match word {
+                    "alpha" | "a" => PreRelease::Alpha,
+                    "beta" | "b" => PreRelease::Beta,
+                    _ => PreRelease::Candidate,
+                };
+                (kind, cursor.implicit_number())
+            });
+        let post = if cursor.rest.starts_with('-')
+            && cursor.rest[1..].starts_with(|c: char| c.is_ascii_digit())
+        {
+            cursor.rest = &cursor.rest[1..];
+            cursor.number()
+        } else {
+            cursor
+                .word(&["post", "rev", "r"])
+                .map(|_| cursor.implicit_number())
+        };
+        let dev = cursor.word(&["dev"]).map(|_| cursor.implicit_number());
+        if !cursor.rest.is_empty() {
+            return Err(invalid());
+        }
+
+        let local = match local {
+            None => Vec::new(),
+            Some(local) => local
+                .split(['.', '-', '_'])
+                .map(|segment| {
+                    if segment.is_empty() || !segment.bytes().all(|b| b.is_ascii_alphanumeric()) {
+                        Err(invalid())
+                    } else if let Ok(number) = segment.parse() {
+                        Ok(LocalSegment::Number(number))
+                    } else {
+                        Ok(LocalSegment::Text(segment.to_string()))
+                    }
+                })
+                .collect::<Result<_, _>>()?,
+        };
+
+        Ok(Self {
+            epoch,
+            release,
+            pre,
+            post,
+            dev,
+            local,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(version: &str) -> ParsedVersion {
+        version
+            .parse()
+            .unwrap_or_else(|e| panic!("{version}: {e:?}"))
+    }
+
+    /// Each version sorts strictly before the next.
+    fn assert_ascending(versions: &[&str]) {
+        for pair in versions.windows(2) {
+            assert!(parse(pair[0]) < parse(pair[1]), "{} < {}", pair[0], pair[1]);
+            assert!(parse(pair[1]) > parse(pair[0]), "{} > {}", pair[1], pair[0]);
+        }
+    }
+
+    #[test]
+    fn versions_parse_into_their_parts() {
+        use PreRelease::*;
+        // Version, epoch, release, pre, post and dev.
+        type Case = (
+            &'static str,
+            u64,
+            &'static [u64],
+            Option<(PreRelease, u64)>,
+            Option<u64>,
+            Option<u64>,
+        );
+        let cases: &[Case] = &[
+            ("1", 0, &[1], None, None, None),
+            ("1.2.3", 0, &[1, 2, 3], None, None, None),
+            ("v1.0", 0, &[1, 0], None, None, None),
+            ("1!2.0", 1, &[2, 0], None, None, None),
+            ("2013!1.0a1", 2013, &[1, 0], Some((Alpha, 1)), None, None),
+            ("1.0a1", 0, &[1, 0], Some((Alpha, 1)), None, None),
+            ("1.0alpha", 0, &[1, 0], Some((Alpha, 0)), None, None),
+            ("1.0.b2", 0, &[1, 0], Some((Beta, 2)), None, None),
+            ("1.0-beta-3", 0, &[1, 0], Some((Beta, 3)), None, None),
+            ("1.0rc1", 0, &[1, 0], Some((Candidate, 1)), None, None),
+            ("1.0c1", 0, &[1, 0], Some((Candidate, 1)), None, None),
+            ("1.0pre2", 0, &[1, 0], Some((Candidate, 2)), None, None),
+            ("1.0preview_2", 0, &[1, 0], Some((Candidate, 2)), None, None),
+            ("1.0.post1", 0, &[1, 0], None, Some(1), None),
+            ("1.0post", 0, &[1, 0], None, Some(0), None),
+            ("1.0-1", 0, &[1, 0], None, Some(1), None),
+            ("1.0.rev2", 0, &[1, 0], None, Some(2), None),
+            ("1.0r3", 0, &[1, 0], None, Some(3), None),
+            ("1.0.dev1", 0, &[1, 0], None, None, Some(1)),
+            ("1.0dev", 0, &[1, 0], None, None, Some(0)),
+            (
+                "1.0rc1.post2.dev3",
+                0,
+                &[1, 0],
+                Some((Candidate, 1)),
+                Some(2),
+                Some(3),
+            ),
+            ("1.0RC1", 0, &[1, 0], Some((Candidate, 1)), None, None),
+        ];
+        for &(version, epoch, release, pre, post, dev) in cases {
+            let parsed = parse(version);
+            assert_eq!(parsed.epoch, epoch, "{version}");
+            assert_eq!(parsed.release, release, "{version}");
+            assert_eq!(parsed.pre, pre, "{version}");
+            assert_eq!(parsed.post, post, "{version}");
+            assert_eq!(parsed.dev, dev, "{version}");
+            assert_eq!(
+                parsed.is_prerelease(),
+                pre.is_some() || dev.is_some(),
+                "{version}"
+            );
+        }
+    }
+
+    #[test]
+    fn local_versions_parse_into_segments() {
+        let cases: &[(&str, &[LocalSegment])] = &[
+            ("1.0", &[]),
+            ("1.0+5", &[LocalSegment::Number(5)]),
+            (
+                "1.0+ubuntu-1.Abc_7",
+                &[
+                    LocalSegment::Text("ubuntu".into()),
+                    LocalSegment::Number(1),
+                    LocalSegment::Text("abc".into()),
+                    LocalSegment::Number(7),
+                ],
+            ),
+        ];
+        for &(version, local) in cases {
+            assert_eq!(parse(version).local, local, "{version}");
+        }
+    }
+
+    #[test]
+    fn invalid_versions_are_rejected() {
+        for version in [
+            "",
+            "a",
+            "1.0.x",
+            "1..0",
+            "1.0+",
+            "1.0+a..b",
+            "1.0+a$",
+            "1.0-",
+            "x!1.0",
+            "1.0 rc1",
+            "1.0rc1rc2",
+            "1.0.post1.post2",
+            "1.0dev1dev2",
+        ] {
+            assert!(version.parse::<ParsedVersion>().is_err(), "{version:?}");
+        }
+    }
+
+    #[test]
+    fn equivalent_spellings_are_equal() {
+        for (a, b) in [
+            ("1.0", "1.0.0"),
+            ("1.0", "v1.0.0.0"),
+            ("0!1.0", "1.0"),
+            ("1.0ALPHA1", "1.0a1"),
+            ("1.0-c1", "1.0rc1"),
+            ("1.0-1", "1.0.post1"),
+            ("1.0.dev0", "1.0dev"),
+            ("1.0+Abc.5", "1.0+abc-5"),
+        ] {
+            assert_eq!(parse(a), parse(b), "{a} == {b}");
+        }
+    }
+
+    #[test]
+    fn releases_sort_numerically_by_segment() {
+        assert_ascending(&["0.9", "1.0", "1.0.1", "1.1", "1.9", "1.10", "2"]);
+    }
+
+    #[test]
+    fn epochs_sort_before_everything_else() {
+        assert_ascending(&["2024.1", "1!0.1", "1!2.0", "2!0.0.dev1"]);
+    }
+
+    #[test]
+    fn pre_releases_sort_before_their_release() {
+        assert_ascending(&[
+            "1.0a1", "1.0a2", "1.0a10", "1.0b1", "1.0rc1", "1.0rc2", "1.0",
+        ]);
+    }
+
+    #[test]
+    fn post_releases_sort_after_their_release() {
+        assert_ascending(&["1.0", "1.0.post0", "1.0.post1", "1.0.post10", "1.0.1"]);
+        assert_ascending(&["1.0rc1", "1.0rc1.post1", "1.0rc2"]);
+    }
+
+    #[test]
+    fn dev_releases_sort_before_what_they_lead_to() {
+        assert_ascending(&[
+            "1.0.dev1",
+            "1.0.dev2",
+            "1.0a1.dev1",
+            "1.0a1",
+            "1.0.post1.dev1",
+            "1.0.post1",
+        ]);
+        assert!(parse("1.0.dev1") < parse("1.0"));
+    }
+
+    #[test]
+    fn local_versions_sort_after_their_public_version() {
+        assert_ascending(&[
+            "1.0",
+            "1.0+abc",
+            "1.0+abc.5",
+            "1.0+abc.7",
+            "1.0+abd",
+            "1.0+5",
+            "1.0+5.1",
+            "1.0+6",
+            "1.0.post1",
+        ]);
+    }
+
+    #[test]
+    fn versions_sort_as_the_pep_440_example_does() {
+        assert_ascending(&[
+            "1.0.dev456",
+            "1.0a1",
+            "1.0a2.dev456",
+            "1.0a12.dev456",
+            "1.0a12",
+            "1.0b1.dev456",
+            "1.0b2",
+            "1.0b2.post345.dev456",
+            "1.0b2.post345",
+            "1.0rc1.dev456",
+            "1.0rc1",
+            "1.0",
+            "1.0+abc.5",
+            "1.0+abc.7",
+            "1.0+5",
+            "1.0.post456.dev34",
+            "1.0.post456",
+            "1.0.15",
+            "1.1.dev1",
+        ]);
+    }
+}
@@ -0,0 +1,243 @@
+//! The PyPI JSON API, `/pypi/<name>/json` and `/pypi/<name>/<version>/json`,
+//! for tools written against pypi.org rather than the simple API. Fields
+//! the index does not know are sent as `null`, as PyPI does.
+
+use std::collections::BTreeMap;
+
+use axum::{
+    extract::{Path, State},
+    response::{IntoResponse, Redirect, Response},
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{
+    inspect, AppError, DistFilename, PackageIndex, PackageName, Release, UrlBuilder, Version,
+    WheelTags,
+};
+
+#[derive(Debug, Serialize)]
+struct ProjectJson {
+    info: Info,
+    /// The index's latest journal serial.
+    last_serial: u64,
+    /// Every version's files; only on the project endpoint.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    releases: Option<BTreeMap<Version, Vec<File>>>,
+    /// The files of the version described by `info`.
+    urls: Vec<File>,
+    vulnerabilities: Vec<Value>,
+}
+
+#[derive(Debug, Serialize)]
+struct Info {
+    name: PackageName,
+    version: Version,
+    summary: Option<String>,
+    author: Option<String>,
+    author_email: Option<String>,
+    license: Option<String>,
+    home_page: Option<String>,
+    keywords: Option<String>,
+    classifiers: Vec<String>,
+    requires_python: Option<String>,
+    requires_dist: Option<Vec<String>>,
+    project_urls: Option<BTreeMap<String, String>>,
+    package_url: String,
+    project_url: String,
+    release_url: String,
+    /// Whether every file of the version is yanked.
+    yanked: bool,
+    yanked_reason: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct File {
+    filename: DistFilename,
+    url: String,
+    digests: BTreeMap<String, String>,
+    packagetype: &'static str,
+    /// The wheel's Python tag, or `source` for sdists.
+    python_version: String,
+    requires_python: Option<String>,
+    size: u64,
+    /// In UTC, without an offset, as PyPI sends it.
+    upload_time: String,
+    upload_time_iso_8601: DateTime<Utc>,
+    yanked: bool,
+    yanked_reason: Option<String>,
+}
+
+/// The latest version of a project, with every version's files.
+pub(crate) async fn project(
+    State(index): State<PackageIndex>,
+    State(urls): State<UrlBuilder>,
+    Path(name): Path<PackageName>,
+) -> Result<Response, AppError> {
+    describe(&index, &urls, &name, None).await
+}
+
+/// One version of a project.
+pub(crate) async fn release(
+    State(index): State<PackageIndex>,
+    State(urls): State<UrlBuilder>,
+    Path((name, version)): Path<(PackageName, Version)>,
+) -> Result<Response, AppError> {
+    describe(&index, &urls, &name, Some(version)).await
+}
+
+async fn describe(
+    index: &PackageIndex,
+    urls: &UrlBuilder,
+    name: &PackageName,
+    version: Option<Version>,
+) -> Result<Response, AppError> {
+    let package = index
+        .project(name)
+        .await?
+        .get(name.as_str())
+        .cloned()
+        .ok_or_else(|| AppError::NotFound(name.to_string()))?;
+    if let Some(new_name) = &package.renamed_to {
+        let url = match &version {
+            Some(version) => urls.release_json(new_name.as_str(), version.as_str()),
+            None => urls.project_json(new_name.as_str()),
+        };
+        return Ok(Redirect::permanent(&url).into_response());
+    }
+
+    let all_versions = version.is_none();
+    // Releases are kept newest version first; as on PyPI, the latest is
+    // the newest final release when there is one.
+    let version = match version {
+        Some(version) => version,
+        None => package
+            .releases
+            .iter()
+            .find(|r| !r.version.is_prerelease() && !r.yanked)
+            .or(package.releases.first())
+            .map(|r| r.version.clone())
+            .ok_or_else(|| AppError::NotFound(format!("No releases of {name}")))?,
+    };
+    let files: Vec<&Release> = package
+        .releases
+        .iter()
+        .filter(|r| r.version == version)
+        .collect();
+    if files.is_empty() {
+        return Err(AppError::NotFound(format!("{name}=={version}")));
+    }
+
+    let described = files.iter().find(|r| r.core_metadata.is_some());
+    let metadata = match described {
+        Some(release) => index
+            .storage
+            .read_core_metadata(name, &release.filename)
+            .await
+            .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+            .unwrap_or_default(),
+        None => String::new(),
+    };
+    let field = |field| {
+        inspect::metadata_values(&metadata, field)
+            .next()
+            .map(str::to_string)
+    };
+    let all = |field| {
+        inspect::metadata_values(&metadata, field)
+            .map(str::to_string)
+            .collect::<Vec<_>>()
+    };
+    let mut project_urls: BTreeMap<String, String> = all("Project-URL")
+        .iter()
+        .filter_map(|entry| entry.split_once(','))
+        .map(|(label, url)| (label.trim().to_string(), url.trim().to_string()))
+        .collect();
+    // Links edited on the index win over those the files were built with.
+    project_urls.extend(package.project_urls.clone());
+    let requires_dist = all("Requires-Dist");
+    let yanked = files.iter().all(|r| r.yanked);
+    let info = Info {
+        name: package.name.clone(),
+        summary: package.summary.clone().or_else(|| field("Summary")),
+        author: field("Author"),
+        author_email: field("Author-email"),
+        license: field("License"),
+        home_page: field("Home-page"),
+        keywords: field("Keywords"),
+        classifiers: all("Classifier"),
+        requires_python: files.iter().find_map(|r| r.requires_python.clone()),
+        requires_dist: (!requires_dist.is_empty()).then_some(requires_dist),
+        project_urls: (!project_urls.is_empty()).then_some(project_urls),
+        package_url: urls.project(name.as_str()),
+        project_url: urls.project(name.as_str()),
+        release_url: urls.release_json(name.as_str(), version.as_str()),
+        yanked,
+        yanked_reason: if yanked {
+            files.iter().find_map(|r| r.yanked_reason.clone())
+        } else {
+            None
+        },
+        version: version.clone(),
+    };
+
+    let mut by_version: BTreeMap<Version, Vec<File>> = BTreeMap::new();
+    for release in package
+        .releases
+        .iter()
+        .filter(|r| all_versions || r.version == version)
+    {
+        by_version
+            .entry(release.version.clone())
+            .or_default()
+            .push(file(index, urls, name, release).await?);
+    }
+    let current = by_version.get(&version).cloned().unwrap_or_default();
+
+    Ok(Json(ProjectJson {
+        info,
+        last_serial: index.serial().await,
+        releases: all_versions.then_some(by_version),
+        urls: current,
+        vulnerabilities: Vec::new(),
+    })
+    .into_response())
+}
+
+async fn file(
+    index: &PackageIndex,
+    urls: &UrlBuilder,
+    name: &PackageName,
+    release: &Release,
+) -> Result<File, AppError> {
+    let filename = release.filename.as_str();
+    let (packagetype, python_version) = match WheelTags::from_filename(filename) {
+        Some(tags) => ("bdist_wheel", tags.python.join(".")),
+        None => ("sdist", "source".to_string()),
+    };
+    let size = index
+        .storage
+        .stat(name, &release.filename)
+        .await?
+        .ok_or_else(|| AppError::NotFound(release.filename.to_string()))?
+        .size;
+    Ok(File {
+        filename: release.filename.clone(),
+        url: urls.file(name.as_str(), filename),
+        digests: release
+            .provenance
+            .as_ref()
+            .map(|p| p.digests.clone())
+            .unwrap_or_default(),
+        packagetype,
+        python_version,
+        requires_python: release.requires_python.clone(),
+        size,
+        upload_time: release.upload_time.format("%Y-%m-%dT%H:%M:%S").to_string(),
+        upload_time_iso_8601: release.upload_time,
+        yanked: release.yanked,
+        yanked_reason: release.yanked_reason.clone(),
+    })
+}
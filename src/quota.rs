@@ -0,0 +1,97 @@
+//! Accounting of the space distribution files take up, per project and in
+//! total, and the quotas uploads are checked against.
+
+use std::{collections::BTreeMap, sync::Arc};
+
+use serde::Serialize;
+use tokio::sync::Mutex;
+
+use crate::{AppError, PackageName, PackageStorage};
+
+/// Caps on stored bytes. `None` means unlimited.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct Quotas {
+    /// Bytes of distribution files any one project may have stored.
+    pub project_bytes: Option<u64>,
+    /// Bytes of distribution files the whole index may have stored.
+    pub total_bytes: Option<u64>,
+}
+
+impl Quotas {
+    /// Fails if a project, or the index, would be over quota holding
+    /// `project` and `total` bytes.
+    pub(crate) fn check(
+        &self,
+        name: &PackageName,
+        project: u64,
+        total: u64,
+    ) -> Result<(), AppError> {
+        if let Some(quota) = self.project_bytes.filter(|quota| project > *quota) {
+            return Err(AppError::QuotaExceeded {
+                message: format!("{name} would take up {project} bytes, over its quota of {quota}"),
+                quota,
+                used: project,
+            });
+        }
+        if let Some(quota) = self.total_bytes.filter(|quota| total > *quota) {
+            return Err(AppError::QuotaExceeded {
+                message: format!(
+                    "the index would take up {total} bytes, over its quota of {quota}"
+                ),
+                quota,
+                used: total,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Bytes stored by project directory, counted from storage on first use
+/// and recounted for a project whenever files of it are stored or
+/// removed.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct DiskUsage {
+    projects: Arc<Mutex<Option<BTreeMap<String, u64>>>>,
+}
+
+impl DiskUsage {
+    /// Counts every project again.
+    pub(crate) async fn rescan(
+        &self,
+        storage: &PackageStorage,
+    ) -> Result<BTreeMap<String, u64>, AppError> {
+        let mut projects = self.projects.lock().await;
+        let counted = storage.stored_bytes().await?;
+        *projects = Some(counted.clone());
+        Ok(counted)
+    }
+
+    /// Counts `name` again, returning its bytes and the total.
+    pub(crate) async fn refresh(
+        &self,
+        storage: &PackageStorage,
+        name: &PackageName,
+    ) -> Result<(u64, u64), AppError> {
+        let mut projects = self.projects.lock().await;
+        let projects = match &mut *projects {
+            Some(projects) => projects,
+            None => projects.insert(storage.stored_bytes().await?),
+        };
+        let bytes = storage.project_bytes(name).await?;
+        if bytes == 0 {
+            projects.remove(name.as_str());
+        } else {
+            projects.insert(name.to_string(), bytes);
+        }
+        Ok((bytes, projects.values().sum()))
+    }
+}
+
+/// Current usage against the quotas, as served at `/api/v1/usage`.
+#[derive(Debug, Clone, Serialize)]
+pub struct UsageReport {
+    pub total_bytes: u64,
+    pub quotas: Quotas,
+    /// Bytes by project, for projects with files stored.
+    pub projects: BTreeMap<String, u64>,
+}
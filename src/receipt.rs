@@ -0,0 +1,60 @@
+//! Receipts returned for uploads, recording what was published and when,
+//! optionally signed so CI can archive them as verifiable proof.
+
+use std::collections::BTreeMap;
+
+use axum::{
+    http::header,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::{signing::ServerKey, DistFilename, PackageName, Version};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReceiptFile {
+    pub name: PackageName,
+    pub version: Version,
+    pub filename: DistFilename,
+    /// Digests of the stored bytes, keyed by algorithm.
+    pub digests: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Receipt {
+    pub files: Vec<ReceiptFile>,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// A receipt serialized once, so the signature covers exactly the bytes
+/// sent and replays of the upload return the same document.
+#[derive(Debug, Clone)]
+pub struct SignedReceipt {
+    json: String,
+    /// Base64 Ed25519 signature over `json`, when the server has a key.
+    signature: Option<String>,
+}
+
+impl SignedReceipt {
+    pub fn new(receipt: &Receipt, key: Option<&ServerKey>) -> Self {
+        let json = serde_json::to_string(receipt).expect("receipts always serialize");
+        let signature = key.map(|key| key.sign(json.as_bytes()));
+        Self { json, signature }
+    }
+}
+
+impl IntoResponse for SignedReceipt {
+    fn into_response(self) -> Response {
+        let mut response =
+            ([(header::CONTENT_TYPE, "application/json")], self.json).into_response();
+        if let Some(signature) = self.signature {
+            response.headers_mut().insert(
+                "x-receipt-signature",
+                header::HeaderValue::from_str(&format!("ed25519={signature}"))
+                    .expect("base64 is a valid header value"),
+            );
+        }
+        response
+    }
+}
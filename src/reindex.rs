@@ -0,0 +1,246 @@
+//! Rebuilding the index from the distribution files in storage, for when
+//! the metadata files are lost or no longer match what is stored, and
+//! registering files copied into storage by hand as they appear.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::{
+    backend::StoredObject, filename::parse_dist_filename, index::Operation, AppError, DistFilename,
+    Package, PackageIndex, PackageName, Provenance, ProvenanceSource, Release,
+};
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ReindexReport {
+    pub projects: usize,
+    pub files: usize,
+    /// Stored files the index had no release for.
+    pub added: usize,
+    /// Releases whose file is no longer stored.
+    pub dropped: usize,
+    /// Releases whose recorded digest was missing or differed from the
+    /// stored bytes.
+    pub rehashed: usize,
+    /// Stored files that are not a wheel or sdist of the project they are
+    /// stored under, as `<project>/<filename>`.
+    pub skipped: Vec<String>,
+    /// Projects whose index entry changed.
+    pub changed: Vec<PackageName>,
+}
+
+impl fmt::Display for ReindexReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "reindexed {} files in {} projects: {} added, {} dropped, {} rehashed, {} projects changed",
+            self.files,
+            self.projects,
+            self.added,
+            self.dropped,
+            self.rehashed,
+            self.changed.len()
+        )?;
+        for key in &self.skipped {
+            writeln!(f, "skipped: {key}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Regenerates every project's releases from the files in storage: each
+/// file is parsed for its project and version, hashed, and has its core
+/// metadata extracted again. Releases that are still stored keep their
+/// upload time, channel, yank and other annotations; files without one are
+/// registered as found, dated by their modification time. Owners, docs,
+/// webhooks and redirects are left alone.
+///
+/// Holds the index write lock throughout, so uploads wait for the rebuild.
+pub async fn rebuild(index: &PackageIndex) -> Result<ReindexReport, AppError> {
+    let (mut packages, _lock) = index.write_all().await?;
+    let storage = index.storage();
+    let mut report = ReindexReport::default();
+
+    let mut found: BTreeMap<PackageName, Vec<(DistFilename, DateTime<Utc>)>> = BTreeMap::new();
+    for (project, filenames) in storage.stored_files().await? {
+        for filename in filenames {
+            let key = format!("{project}/{filename}");
+            let Some((name, filename)) = distribution(&key) else {
+                warn!("Not reindexing {}: not a distribution of its project", key);
+                report.skipped.push(key);
+                continue;
+            };
+            let Some(stored) = storage.stat(&name, &filename).await? else {
+                continue;
+            };
+            found
+                .entry(name)
+                .or_default()
+                .push((filename, stored.modified));
+        }
+    }
+
+    let names: BTreeSet<PackageName> = packages.keys().chain(found.keys()).cloned().collect();
+    for name in names {
+        let files = found.remove(&name).unwrap_or_default();
+        let package = packages
+            .entry(name.clone())
+            .or_insert_with(|| Package::new(name.clone()));
+        if package.renamed_to.is_some() && files.is_empty() {
+            continue;
+        }
+        let before = serde_json::to_value(&*package)?;
+
+        let mut previous: BTreeMap<DistFilename, Release> = BTreeMap::new();
+        for release in package.releases.drain(..) {
+            // Entries are kept newest first, so the first of a name wins.
+            previous.entry(release.filename.clone()).or_insert(release);
+        }
+        for (filename, modified) in files {
+            report.files += 1;
+            let mut release = match previous.remove(&filename) {
+                Some(release) => release,
+                None => {
+                    report.added += 1;
+                    found_release(&filename, modified)?
+                }
+            };
+            let sha256 = storage.sha256(&name, &filename).await?;
+            match &mut release.provenance {
+                Some(provenance) => {
+                    if provenance.digests.get("sha256") != Some(&sha256) {
+                        provenance.digests.insert("sha256".to_string(), sha256);
+                        report.rehashed += 1;
+                    }
+                }
+                None => {
+                    release.provenance = Some(Provenance::new(ProvenanceSource::Reindex, sha256));
+                    report.rehashed += 1;
+                }
+            }
+            index.extract_core_metadata(&name, &mut release).await;
+            package.releases.push(release);
+        }
+        report.dropped += previous.len();
+        package.sort_releases();
+
+        if serde_json::to_value(&*package)? != before {
+            report.changed.push(name);
+        }
+    }
+    report.projects = packages.len();
+
+    if !report.changed.is_empty() {
+        let projects: Vec<&PackageName> = report.changed.iter().collect();
+        index
+            .commit(&packages, &projects, Operation::Reindex)
+            .await?;
+    }
+    info!(
+        "Reindexed {} files, {} projects changed",
+        report.files,
+        report.changed.len()
+    );
+    Ok(report)
+}
+
+/// A release for a file found in storage, dated by its modification time.
+fn found_release(filename: &DistFilename, modified: DateTime<Utc>) -> Result<Release, AppError> {
+    let (_, version) = parse_dist_filename(filename.as_str())?;
+    let mut release = Release::new(version, filename.clone());
+    release.upload_time = modified;
+    Ok(release)
+}
+
+/// The project and filename of a stored object, if it is a wheel or sdist
+/// of the project it is stored under.
+pub(crate) fn distribution(key: &str) -> Option<(PackageName, DistFilename)> {
+    let (project, filename) = key.split_once('/')?;
+    let name = project.parse::<PackageName>().ok()?;
+    let filename = filename.parse::<DistFilename>().ok()?;
+    let (parsed, _) = parse_dist_filename(filename.as_str()).ok()?;
+    (parsed == name).then_some((name, filename))
+}
+
+/// Finds distribution files put into storage without going through the
+/// index, such as wheels rsynced into `packages/<project>/`, and registers
+/// them as releases. A file is only registered once two scans in a row
+/// saw it with the same size and modification time, so copies still in
+/// progress are left alone.
+#[derive(Debug, Default)]
+pub struct StorageScanner {
+    /// Untracked files from the last scan, with whether registering them
+    /// has been tried since they last changed.
+    seen: BTreeMap<String, (StoredObject, bool)>,
+}
+
+impl StorageScanner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Scans storage once, returning how many files were registered.
+    pub async fn scan(&mut self, index: &PackageIndex) -> Result<usize, AppError> {
+        let objects = index.storage().backend().list("").await?;
+        let mut seen = BTreeMap::new();
+        let mut registered = 0;
+        for object in objects {
+            let Some((name, filename)) = distribution(&object.key) else {
+                continue;
+            };
+            if index.has_file(&name, &filename).await? {
+                continue;
+            }
+            let tried = match self.seen.remove(&object.key) {
+                Some((previous, tried)) if previous == object => tried,
+                _ => {
+                    seen.insert(object.key.clone(), (object, false));
+                    continue;
+                }
+            };
+            if !tried {
+                match register(index, name, &filename, object.modified).await {
+                    Ok(()) => {
+                        info!("Registered {} found in storage", object.key);
+                        registered += 1;
+                        continue;
+                    }
+                    Err(e) => warn!("Not registering {}: {}", object.key, e),
+                }
+            }
+            seen.insert(object.key.clone(), (object, true));
+        }
+        self.seen = seen;
+        Ok(registered)
+    }
+}
+
+async fn register(
+    index: &PackageIndex,
+    name: PackageName,
+    filename: &DistFilename,
+    modified: DateTime<Utc>,
+) -> Result<(), AppError> {
+    let sha256 = index.storage().sha256(&name, filename).await?;
+    let release = found_release(filename, modified)?
+        .with_provenance(Provenance::new(ProvenanceSource::Reindex, sha256));
+    index.add_release(name, release, None).await
+}
+
+/// Scans storage every `interval` forever, registering the files that
+/// appear in it.
+pub async fn watch(index: PackageIndex, interval: Duration) {
+    let mut scanner = StorageScanner::new();
+    loop {
+        if let Err(e) = scanner.scan(&index).await {
+            warn!("Scanning storage for new files failed: {}", e);
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
@@ -0,0 +1,100 @@
+//! Pruning old releases, so that projects publishing on every commit do
+//! not keep every build forever.
+
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use tracing::{info, warn};
+
+use crate::{AppError, DistFilename, Package, PackageIndex, PackageName, Release, Version};
+
+/// Which releases of each project are kept. `None` means unlimited. A
+/// version is pruned once either limit says so, except that a project's
+/// latest stable release is always kept, as are files a snapshot keeps.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RetentionPolicy {
+    /// Most recent versions kept of each project.
+    pub max_versions: Option<usize>,
+    /// How long a version is kept after its last upload.
+    pub max_age: Option<Duration>,
+}
+
+impl RetentionPolicy {
+    pub fn is_unlimited(&self) -> bool {
+        self.max_versions.is_none() && self.max_age.is_none()
+    }
+
+    /// Files of `package` the policy no longer keeps at `now`, never
+    /// including those of `keep`.
+    pub(crate) fn expired(
+        &self,
+        package: &Package,
+        now: DateTime<Utc>,
+        keep: Option<&Version>,
+    ) -> Vec<DistFilename> {
+        let oldest = self
+            .max_age
+            .and_then(|age| chrono::Duration::from_std(age).ok())
+            .and_then(|age| now.checked_sub_signed(age));
+        // Releases are kept newest version first.
+        let latest_stable = package
+            .releases
+            .iter()
+            .find(|r| !r.version.is_prerelease() && !r.yanked)
+            .map(|r| &r.version);
+        let mut versions: Vec<(&Version, DateTime<Utc>)> = Vec::new();
+        for release in &package.releases {
+            match versions.iter_mut().find(|(v, _)| *v == &release.version) {
+                Some((_, uploaded)) => *uploaded = (*uploaded).max(release.upload_time),
+                None => versions.push((&release.version, release.upload_time)),
+            }
+        }
+
+        let mut expired = Vec::new();
+        for (position, (version, uploaded)) in versions.into_iter().enumerate() {
+            let too_many = self.max_versions.is_some_and(|max| position >= max);
+            let too_old = oldest.is_some_and(|oldest| uploaded < oldest);
+            if !(too_many || too_old) || Some(version) == latest_stable || Some(version) == keep {
+                continue;
+            }
+            expired.extend(
+                package
+                    .releases
+                    .iter()
+                    .filter(|r| r.version == *version)
+                    .map(|r| r.filename.clone()),
+            );
+        }
+        expired
+    }
+}
+
+/// Applies the index's retention policy to every project, returning the
+/// releases removed.
+pub async fn prune_all(index: &PackageIndex) -> Result<Vec<(PackageName, Release)>, AppError> {
+    let mut pruned = Vec::new();
+    if index.retention().is_unlimited() {
+        return Ok(pruned);
+    }
+    let names: Vec<PackageName> = index.catalog().await?.keys().cloned().collect();
+    for name in names {
+        for release in index.apply_retention(&name, None).await? {
+            pruned.push((name.clone(), release));
+        }
+    }
+    if !pruned.is_empty() {
+        info!("Retention pruned {} files", pruned.len());
+    }
+    Ok(pruned)
+}
+
+/// Applies the retention policy every `interval` forever, so releases are
+/// pruned for age even when nothing new is uploaded.
+pub async fn enforce(index: PackageIndex, interval: Duration) {
+    loop {
+        if let Err(e) = prune_all(&index).await {
+            warn!("Applying the retention policy failed: {}", e);
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
@@ -0,0 +1,426 @@
+//! Accept loop serving HTTP/1.1 and HTTP/2 on one port, cleartext (h2c)
+//! or over TLS, with the connection settings exposed in `Config`.
+
+use std::{
+    fmt,
+    fs::File,
+    future::Future,
+    io::BufReader,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
+
+use axum::{body::Body, extract::Request, Router};
+use hyper::body::Incoming;
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::conn::auto::Builder,
+    service::TowerToHyperService,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpListener,
+    sync::watch,
+};
+use tokio_rustls::{
+    rustls::{
+        crypto::{ring::default_provider, CryptoProvider},
+        pki_types::{CertificateDer, PrivateKeyDer},
+        server::{
+            ClientHello, ResolvesServerCert, ResolvesServerCertUsingSni, ServerConnection,
+            WebPkiClientVerifier,
+        },
+        sign::CertifiedKey,
+        RootCertStore, ServerConfig,
+    },
+    TlsAcceptor,
+};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+use tower::ServiceExt;
+use tracing::{debug, info, warn};
+
+use crate::AppError;
+
+/// How long a client gets to complete the TLS handshake.
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A connection, over TLS or not.
+trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+/// A certificate chain and key to serve HTTPS with.
+#[derive(Clone)]
+pub struct TlsConfig {
+    server: Arc<ServerConfig>,
+    verifies_clients: bool,
+}
+
+impl fmt::Debug for TlsConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TlsConfig").finish_non_exhaustive()
+    }
+}
+
+fn invalid(what: &str, path: &Path, e: &dyn fmt::Display) -> AppError {
+    AppError::InvalidFormat(format!("{what} {}: {e}", path.display()))
+}
+
+/// Every certificate in a PEM file, which must have at least one.
+fn read_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>, AppError> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(path)?))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| invalid("Unreadable certificate", path, &e))?;
+    if certs.is_empty() {
+        return Err(invalid("No certificate in", path, &"expected PEM"));
+    }
+    Ok(certs)
+}
+
+/// The PEM private key in a file.
+fn read_key(path: &Path) -> Result<PrivateKeyDer<'static>, AppError> {
+    rustls_pemfile::private_key(&mut BufReader::new(File::open(path)?))
+        .map_err(|e| invalid("Unreadable key", path, &e))?
+        .ok_or_else(|| invalid("No private key in", path, &"expected PEM"))
+}
+
+fn certified_key(
+    cert: &Path,
+    key: &Path,
+    provider: &CryptoProvider,
+) -> Result<CertifiedKey, AppError> {
+    CertifiedKey::from_der(read_certs(cert)?, read_key(key)?, provider)
+        .map_err(|e| invalid("Unusable certificate", cert, &e))
+}
+
+/// The certificate served to clients asking for `host`, one of the
+/// `--tenant` hosts, over SNI.
+#[derive(Debug, Clone)]
+pub struct HostCertificate {
+    pub host: String,
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+impl FromStr for HostCertificate {
+    type Err = AppError;
+
+    /// Parses `host=cert,key`, e.g. `ml.pkg.corp=ml.pem,ml-key.pem`.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (host, (cert, key)) = value
+            .split_once('=')
+            .and_then(|(host, files)| Some((host, files.split_once(',')?)))
+            .filter(|(host, (cert, key))| !host.is_empty() && !cert.is_empty() && !key.is_empty())
+            .ok_or_else(|| AppError::InvalidFormat(format!("Expected host=cert,key: {value}")))?;
+        Ok(Self {
+            host: host.to_ascii_lowercase(),
+            cert: PathBuf::from(cert),
+            key: PathBuf::from(key),
+        })
+    }
+}
+
+/// Picks the certificate of the host a client asked for, falling back to
+/// the one from `--tls-cert` for other names and clients without SNI.
+#[derive(Debug)]
+struct HostCertificates {
+    hosts: ResolvesServerCertUsingSni,
+    default: Arc<CertifiedKey>,
+}
+
+impl ResolvesServerCert for HostCertificates {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        self.hosts
+            .resolve(client_hello)
+            .or_else(|| Some(self.default.clone()))
+    }
+}
+
+impl TlsConfig {
+    /// Loads a PEM certificate chain, leaf first, and its PEM private key.
+    /// With `client_ca`, a PEM bundle of CA certificates, clients must
+    /// present a certificate issued by one of them.
+    pub fn from_pem_files(
+        cert: &Path,
+        key: &Path,
+        client_ca: Option<&Path>,
+    ) -> Result<Self, AppError> {
+        Self::with_host_certificates(cert, key, client_ca, &[])
+    }
+
+    /// As [`from_pem_files`](Self::from_pem_files), also serving each of
+    /// `hosts` its own certificate to clients naming it.
+    pub fn with_host_certificates(
+        cert: &Path,
+        key: &Path,
+        client_ca: Option<&Path>,
+        hosts: &[HostCertificate],
+    ) -> Result<Self, AppError> {
+        let provider = Arc::new(default_provider());
+        let mut resolver = HostCertificates {
+            hosts: ResolvesServerCertUsingSni::new(),
+            default: Arc::new(certified_key(cert, key, &provider)?),
+        };
+        for host in hosts {
+            let certified = certified_key(&host.cert, &host.key, &provider)?;
+            resolver.hosts.add(&host.host, certified).map_err(|e| {
+                invalid(
+                    &format!("Unusable certificate for {} in", host.host),
+                    &host.cert,
+                    &e,
+                )
+            })?;
+        }
+        let builder = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .map_err(|e| invalid("Unusable certificate", cert, &e))?;
+        let builder = match client_ca {
+            None => builder.with_no_client_auth(),
+            Some(client_ca) => {
+                let mut roots = RootCertStore::empty();
+                for ca in read_certs(client_ca)? {
+                    roots
+                        .add(ca)
+                        .map_err(|e| invalid("Unusable CA certificate in", client_ca, &e))?;
+                }
+                let verifier = WebPkiClientVerifier::builder_with_provider(roots.into(), provider)
+                    .build()
+                    .map_err(|e| invalid("Unusable CA certificates in", client_ca, &e))?;
+                builder.with_client_cert_verifier(verifier)
+            }
+        };
+        let mut server = builder.with_cert_resolver(Arc::new(resolver));
+        server.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        Ok(Self {
+            server: Arc::new(server),
+            verifies_clients: client_ca.is_some(),
+        })
+    }
+
+    /// Whether clients must present a certificate, and are known by it.
+    pub fn verifies_clients(&self) -> bool {
+        self.verifies_clients
+    }
+}
+
+/// The verified certificate a client connected with, added to the
+/// extensions of each request on the connection.
+#[derive(Debug, Clone)]
+pub struct ClientCertificate {
+    /// The subject's common name, which the client acts as.
+    pub common_name: String,
+}
+
+impl ClientCertificate {
+    fn of(connection: &ServerConnection) -> Option<Self> {
+        let leaf = connection.peer_certificates()?.first()?;
+        common_name(leaf).map(|common_name| Self { common_name })
+    }
+}
+
+/// Splits a DER value off the front of `der`, returning its tag, its
+/// contents and what follows it.
+fn der_value(der: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, rest) = der.split_first()?;
+    let (&first, rest) = rest.split_first()?;
+    let (length, rest) = if first < 0x80 {
+        (usize::from(first), rest)
+    } else {
+        let octets = usize::from(first & 0x7f);
+        if octets == 0 || octets > 4 || rest.len() < octets {
+            return None;
+        }
+        let (length, rest) = rest.split_at(octets);
+        let length = length
+            .iter()
+            .fold(0usize, |length, &b| length << 8 | usize::from(b));
+        (length, rest)
+    };
+    (rest.len() >= length).then(|| (tag, &rest[..length], &rest[length..]))
+}
+
+/// The last common name in a certificate's subject. The certificate has
+/// already been verified, so only its layout is trusted here.
+fn common_name(cert: &[u8]) -> Option<String> {
+    const SEQUENCE: u8 = 0x30;
+    const SET: u8 = 0x31;
+    const VERSION: u8 = 0xa0;
+    /// 2.5.4.3, id-at-commonName.
+    const COMMON_NAME: &[u8] = &[0x55, 0x04, 0x03];
+
+    let (SEQUENCE, cert, _) = der_value(cert)? else {
+        return None;
+    };
+    let (SEQUENCE, mut tbs, _) = der_value(cert)? else {
+        return None;
+    };
+    if tbs.first() == Some(&VERSION) {
+        tbs = der_value(tbs)?.2;
+    }
+    // Serial number, signature algorithm, issuer and validity.
+    for _ in 0..4 {
+        tbs = der_value(tbs)?.2;
+    }
+    let (SEQUENCE, mut subject, _) = der_value(tbs)? else {
+        return None;
+    };
+    let mut found = None;
+    while !subject.is_empty() {
+        let (SET, mut names, rest) = der_value(subject)? else {
+            return None;
+        };
+        subject = rest;
+        while !names.is_empty() {
+            let (SEQUENCE, name, rest) = der_value(names)? else {
+                return None;
+            };
+            names = rest;
+            let (_, oid, value) = der_value(name)?;
+            if oid == COMMON_NAME {
+                let (_, value, _) = der_value(value)?;
+                found = Some(String::from_utf8(value.to_vec()).ok()?);
+            }
+        }
+    }
+    found
+}
+
+#[derive(Debug, Clone)]
+pub struct ConnectionSettings {
+    /// Whether HTTP/1.1 connections are kept open between requests.
+    pub http1_keep_alive: bool,
+    pub http2_max_concurrent_streams: u32,
+    /// Interval of HTTP/2 keep-alive pings; `None` disables them.
+    pub http2_keep_alive_interval: Option<Duration>,
+    pub http2_keep_alive_timeout: Duration,
+    /// How long open connections get to finish at shutdown.
+    pub drain_timeout: Duration,
+    /// Serves HTTPS instead of cleartext HTTP, when set.
+    pub tls: Option<TlsConfig>,
+}
+
+impl Default for ConnectionSettings {
+    fn default() -> Self {
+        Self {
+            http1_keep_alive: true,
+            http2_max_concurrent_streams: 256,
+            http2_keep_alive_interval: None,
+            http2_keep_alive_timeout: Duration::from_secs(20),
+            drain_timeout: Duration::from_secs(30),
+            tls: None,
+        }
+    }
+}
+
+/// Serves until `shutdown` completes, then stops accepting, asks open
+/// connections to finish their requests, and waits up to the drain timeout
+/// for them to close before dropping the rest.
+pub async fn serve(
+    listener: TcpListener,
+    app: Router,
+    settings: &ConnectionSettings,
+    shutdown: impl Future<Output = ()>,
+) -> std::io::Result<()> {
+    let mut builder = Builder::new(TokioExecutor::new());
+    builder.http1().keep_alive(settings.http1_keep_alive);
+    builder
+        .http2()
+        .max_concurrent_streams(settings.http2_max_concurrent_streams)
+        .keep_alive_interval(settings.http2_keep_alive_interval)
+        .keep_alive_timeout(settings.http2_keep_alive_timeout);
+
+    let tls = settings
+        .tls
+        .as_ref()
+        .map(|tls| TlsAcceptor::from(tls.server.clone()));
+    let (stop, stopped) = watch::channel(());
+    let connections = TaskTracker::new();
+    let dropped = CancellationToken::new();
+    tokio::pin!(shutdown);
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            () = &mut shutdown => break,
+        };
+        let (stream, peer) = match accepted {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!("Failed to accept connection: {}", e);
+                continue;
+            }
+        };
+        let _ = stream.set_nodelay(true);
+
+        let builder = builder.clone();
+        let app = app.clone();
+        let mut stopped = stopped.clone();
+        let tls = tls.clone();
+        let dropped = dropped.clone();
+        let connection = async move {
+            let (stream, client): (Box<dyn Stream>, _) = match tls {
+                None => (Box::new(stream), None),
+                Some(tls) => {
+                    match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, tls.accept(stream)).await {
+                        Ok(Ok(stream)) => {
+                            let client = ClientCertificate::of(stream.get_ref().1);
+                            (Box::new(stream), client)
+                        }
+                        Ok(Err(e)) => {
+                            debug!("TLS handshake with {} failed: {}", peer, e);
+                            return;
+                        }
+                        Err(_) => {
+                            debug!("TLS handshake with {} timed out", peer);
+                            return;
+                        }
+                    }
+                }
+            };
+            let service =
+                TowerToHyperService::new(app.map_request(move |request: Request<Incoming>| {
+                    let mut request = request.map(Body::new);
+                    if let Some(client) = &client {
+                        request.extensions_mut().insert(client.clone());
+                    }
+                    request
+                }));
+            let connection = builder.serve_connection_with_upgrades(TokioIo::new(stream), service);
+            tokio::pin!(connection);
+            let result = tokio::select! {
+                result = connection.as_mut() => result,
+                _ = stopped.changed() => {
+                    connection.as_mut().graceful_shutdown();
+                    connection.await
+                }
+            };
+            if let Err(e) = result {
+                debug!("Connection from {} closed with error: {}", peer, e);
+            }
+        };
+        connections.spawn(async move {
+            tokio::select! {
+                () = connection => {}
+                () = dropped.cancelled() => {}
+            }
+        });
+    }
+
+    info!("Shutting down, draining {} connections", connections.len());
+    stop.send_replace(());
+    connections.close();
+    if tokio::time::timeout(settings.drain_timeout, connections.wait())
+        .await
+        .is_err()
+    {
+        warn!(
+            "{} connections were still open after {:?}, dropping them",
+            connections.len(),
+            settings.drain_timeout
+        );
+        dropped.cancel();
+        connections.wait().await;
+    }
+    Ok(())
+}
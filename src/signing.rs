@@ -0,0 +1,48 @@
+//! The optional Ed25519 key the server signs documents it vouches for
+//! with, so they can be verified after leaving the server.
+
+use std::{fmt, path::Path, sync::Arc};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ed25519_dalek::{
+    pkcs8::{spki::der::pem::LineEnding, DecodePrivateKey, EncodePublicKey},
+    Signer, SigningKey,
+};
+
+use crate::AppError;
+
+#[derive(Clone)]
+pub struct ServerKey {
+    key: Arc<SigningKey>,
+}
+
+impl ServerKey {
+    /// Loads a PKCS#8 PEM private key, as written by
+    /// `openssl genpkey -algorithm ed25519`.
+    pub fn load(path: &Path) -> Result<Self, AppError> {
+        let pem = std::fs::read_to_string(path)?;
+        let key = SigningKey::from_pkcs8_pem(&pem).map_err(|e| {
+            AppError::InvalidFormat(format!("Invalid signing key {}: {e}", path.display()))
+        })?;
+        Ok(Self { key: Arc::new(key) })
+    }
+
+    /// A base64 Ed25519 signature over `message`.
+    pub fn sign(&self, message: &[u8]) -> String {
+        STANDARD.encode(self.key.sign(message).to_bytes())
+    }
+
+    /// The verifying key as an SPKI PEM document.
+    pub fn public_key_pem(&self) -> String {
+        self.key
+            .verifying_key()
+            .to_public_key_pem(LineEnding::LF)
+            .expect("an Ed25519 public key always encodes")
+    }
+}
+
+impl fmt::Debug for ServerKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ServerKey").finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,200 @@
+//! The PEP 691 JSON form of the simple API, chosen over the HTML pages by
+//! the request's `Accept` header.
+
+use std::collections::BTreeMap;
+
+use axum::{
+    http::{header, HeaderMap, HeaderValue},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::{compat::TargetEnvironment, Package, PackageName, Release, UrlBuilder};
+
+const JSON_V1: &str = "application/vnd.pypi.simple.v1+json";
+const JSON_LATEST: &str = "application/vnd.pypi.simple.latest+json";
+const HTML_V1: &str = "application/vnd.pypi.simple.v1+html";
+const HTML_LATEST: &str = "application/vnd.pypi.simple.latest+html";
+
+/// API version reported in the `meta` of every JSON response.
+const API_VERSION: &str = "1.0";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SimpleFormat {
+    Html,
+    Json,
+}
+
+impl SimpleFormat {
+    /// The format the client prefers by `Accept` quality, with explicit
+    /// types beating `*/*` and the first listed winning other ties. Absent
+    /// headers and unsupported types get HTML, which every installer reads.
+    pub fn negotiate(headers: &HeaderMap) -> Self {
+        let mut best = (Self::Html, 0.0, false);
+        for value in headers.get_all(header::ACCEPT) {
+            let Ok(value) = value.to_str() else {
+                continue;
+            };
+            for range in value.split(',') {
+                let mut params = range.split(';');
+                let media_type = params.next().unwrap_or_default().trim();
+                let quality = params
+                    .filter_map(|param| param.trim().strip_prefix("q="))
+                    .find_map(|q| q.trim().parse::<f32>().ok())
+                    .unwrap_or(1.0);
+                let (format, explicit) = match media_type.to_ascii_lowercase().as_str() {
+                    JSON_V1 | JSON_LATEST => (Self::Json, true),
+                    HTML_V1 | HTML_LATEST | "text/html" => (Self::Html, true),
+                    "*/*" => (Self::Html, false),
+                    _ => continue,
+                };
+                if quality <= 0.0 {
+                    continue;
+                }
+                if quality > best.1 || (quality == best.1 && explicit && !best.2) {
+                    best = (format, quality, explicit);
+                }
+            }
+        }
+        best.0
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Meta {
+    #[serde(rename = "api-version")]
+    api_version: &'static str,
+    /// Projects in a listing. Keys starting with `_` are private
+    /// extensions, which PEP 691 clients ignore.
+    #[serde(rename = "_project-count", skip_serializing_if = "Option::is_none")]
+    project_count: Option<usize>,
+}
+
+const META: Meta = Meta {
+    api_version: API_VERSION,
+    project_count: None,
+};
+
+#[derive(Debug, Serialize)]
+struct ProjectList {
+    meta: Meta,
+    projects: Vec<ListedProject>,
+}
+
+/// A project in a listing, with how many files it lists and when the
+/// newest arrived, so mirrors can tell which project pages to refetch.
+#[derive(Debug, Serialize)]
+pub(crate) struct ListedProject {
+    pub name: PackageName,
+    #[serde(rename = "_files")]
+    pub files: usize,
+    #[serde(rename = "_last-upload", skip_serializing_if = "Option::is_none")]
+    pub last_upload: Option<DateTime<Utc>>,
+}
+
+impl ListedProject {
+    /// `package` as listed, counting only the releases `listed` accepts.
+    pub fn new(package: &Package, listed: impl Fn(&Release) -> bool) -> Self {
+        let releases = package.releases.iter().filter(|r| listed(r));
+        let (files, last_upload) = releases.fold((0, None), |(files, last), r| {
+            (files + 1, last.max(Some(r.upload_time)))
+        });
+        Self {
+            name: package.name.clone(),
+            files,
+            last_upload,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ProjectDetail {
+    meta: Meta,
+    name: PackageName,
+    files: Vec<ProjectFile>,
+}
+
+#[derive(Debug, Serialize)]
+struct ProjectFile {
+    filename: String,
+    url: String,
+    /// Digests keyed by algorithm; empty for files registered before
+    /// digests were recorded.
+    hashes: BTreeMap<String, String>,
+    #[serde(rename = "requires-python", skip_serializing_if = "Option::is_none")]
+    requires_python: Option<String>,
+    /// Digests of the PEP 658 metadata at `<url>.metadata`, under both
+    /// PEP 714 names; absent when there is none.
+    #[serde(rename = "core-metadata", skip_serializing_if = "Option::is_none")]
+    core_metadata: Option<BTreeMap<String, String>>,
+    #[serde(rename = "dist-info-metadata", skip_serializing_if = "Option::is_none")]
+    dist_info_metadata: Option<BTreeMap<String, String>>,
+    /// Absent unless the file is yanked (PEP 592).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    yanked: Option<Yanked>,
+}
+
+/// `true`, or the reason the file was yanked.
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+enum Yanked {
+    Flag(bool),
+    Reason(String),
+}
+
+fn json_response(body: &impl Serialize) -> Response {
+    (
+        [
+            (header::CONTENT_TYPE, HeaderValue::from_static(JSON_V1)),
+            (header::VARY, HeaderValue::from_static("Accept")),
+        ],
+        serde_json::to_string(body).expect("simple API documents always serialize"),
+    )
+        .into_response()
+}
+
+/// The project list, for `/simple/` and its channel and snapshot variants.
+pub(crate) fn project_list(projects: Vec<ListedProject>) -> Response {
+    json_response(&ProjectList {
+        meta: Meta {
+            project_count: Some(projects.len()),
+            ..META
+        },
+        projects,
+    })
+}
+
+/// The files of one project installable in `target`.
+pub(crate) fn project_detail(
+    package: Package,
+    urls: &UrlBuilder,
+    target: &TargetEnvironment,
+) -> Response {
+    let files = package
+        .releases
+        .into_iter()
+        .filter(|r| target.accepts(r.filename.as_str()))
+        .map(|r| {
+            let core_metadata = r
+                .core_metadata
+                .map(|digest| BTreeMap::from([("sha256".to_string(), digest)]));
+            ProjectFile {
+                url: urls.file(package.name.as_str(), r.filename.as_str()),
+                filename: r.filename.into(),
+                hashes: r.provenance.map(|p| p.digests).unwrap_or_default(),
+                requires_python: r.requires_python,
+                dist_info_metadata: core_metadata.clone(),
+                core_metadata,
+                yanked: r
+                    .yanked
+                    .then(|| r.yanked_reason.map_or(Yanked::Flag(true), Yanked::Reason)),
+            }
+        })
+        .collect();
+    json_response(&ProjectDetail {
+        meta: META,
+        name: package.name,
+        files,
+    })
+}
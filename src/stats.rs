@@ -0,0 +1,462 @@
+//! Upload and download counts per project, kept in generations of
+//! decreasing resolution: hourly buckets for the recent past, daily and
+//! monthly ones for long-term trends. Each generation is pruned to its own
+//! retention, so the stats stay bounded however long the server runs.
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    ops::AddAssign,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use chrono::{DateTime, Datelike, Months, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::{AppError, PackageName, PackageStorage};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatKind {
+    Download,
+    Upload,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Counts {
+    #[serde(default)]
+    pub downloads: u64,
+    #[serde(default)]
+    pub uploads: u64,
+    #[serde(default)]
+    pub download_bytes: u64,
+    #[serde(default)]
+    pub upload_bytes: u64,
+}
+
+impl Counts {
+    fn of(kind: StatKind, bytes: u64) -> Self {
+        match kind {
+            StatKind::Download => Self {
+                downloads: 1,
+                download_bytes: bytes,
+                ..Self::default()
+            },
+            StatKind::Upload => Self {
+                uploads: 1,
+                upload_bytes: bytes,
+                ..Self::default()
+            },
+        }
+    }
+}
+
+impl AddAssign for Counts {
+    fn add_assign(&mut self, other: Self) {
+        self.downloads += other.downloads;
+        self.uploads += other.uploads;
+        self.download_bytes += other.download_bytes;
+        self.upload_bytes += other.upload_bytes;
+    }
+}
+
+/// Upper bounds of the size histogram buckets, in bytes; a final bucket
+/// takes everything larger.
+const SIZE_BOUNDS: [u64; 4] = [64 << 10, 1 << 20, 16 << 20, 256 << 20];
+const SIZE_LABELS: [&str; 5] = ["<64KiB", "<1MiB", "<16MiB", "<256MiB", ">=256MiB"];
+
+/// How many files fell in each size range, over the project's lifetime.
+/// Serialized as a map from range label to count, smallest first.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SizeHistogram {
+    pub counts: [u64; 5],
+}
+
+impl SizeHistogram {
+    fn add(&mut self, bytes: u64) {
+        let bucket = SIZE_BOUNDS
+            .iter()
+            .position(|bound| bytes < *bound)
+            .unwrap_or(SIZE_BOUNDS.len());
+        self.counts[bucket] += 1;
+    }
+
+    fn merge(&mut self, other: &SizeHistogram) {
+        for (mine, theirs) in self.counts.iter_mut().zip(other.counts) {
+            *mine += theirs;
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.counts.iter().all(|count| *count == 0)
+    }
+}
+
+impl Serialize for SizeHistogram {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(SIZE_LABELS.iter().zip(self.counts))
+    }
+}
+
+impl<'de> Deserialize<'de> for SizeHistogram {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let labelled = BTreeMap::<String, u64>::deserialize(deserializer)?;
+        let mut histogram = Self::default();
+        for (label, count) in labelled {
+            let bucket = SIZE_LABELS
+                .iter()
+                .position(|known| *known == label)
+                .ok_or_else(|| serde::de::Error::custom(format!("unknown size range {label}")))?;
+            histogram.counts[bucket] = count;
+        }
+        Ok(histogram)
+    }
+}
+
+impl fmt::Display for SizeHistogram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ranges: Vec<String> = SIZE_LABELS
+            .iter()
+            .zip(self.counts)
+            .map(|(label, count)| format!("{label} {count}"))
+            .collect();
+        write!(f, "{}", ranges.join("  "))
+    }
+}
+
+/// How many buckets of each generation are kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StatsRetention {
+    pub hours: u32,
+    pub days: u32,
+    pub months: u32,
+}
+
+impl Default for StatsRetention {
+    fn default() -> Self {
+        Self {
+            hours: 48,
+            days: 90,
+            months: 36,
+        }
+    }
+}
+
+/// Counts of one project, keyed by the start of each bucket:
+/// `2026-10-14T09`, `2026-10-14` and `2026-10`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ProjectStats {
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub hourly: BTreeMap<String, Counts>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub daily: BTreeMap<String, Counts>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub monthly: BTreeMap<String, Counts>,
+    #[serde(default, skip_serializing_if = "SizeHistogram::is_empty")]
+    pub upload_sizes: SizeHistogram,
+    #[serde(default, skip_serializing_if = "SizeHistogram::is_empty")]
+    pub download_sizes: SizeHistogram,
+}
+
+impl ProjectStats {
+    fn record(&mut self, at: DateTime<Utc>, kind: StatKind, bytes: u64) {
+        let counts = Counts::of(kind, bytes);
+        *self.hourly.entry(hour_key(at)).or_default() += counts;
+        *self.daily.entry(day_key(at)).or_default() += counts;
+        *self.monthly.entry(month_key(at)).or_default() += counts;
+        match kind {
+            StatKind::Download => self.download_sizes.add(bytes),
+            StatKind::Upload => self.upload_sizes.add(bytes),
+        }
+    }
+
+    fn merge(&mut self, other: &ProjectStats) {
+        self.upload_sizes.merge(&other.upload_sizes);
+        self.download_sizes.merge(&other.download_sizes);
+        for (generation, theirs) in [
+            (&mut self.hourly, &other.hourly),
+            (&mut self.daily, &other.daily),
+            (&mut self.monthly, &other.monthly),
+        ] {
+            for (bucket, counts) in theirs {
+                *generation.entry(bucket.clone()).or_default() += *counts;
+            }
+        }
+    }
+
+    /// Drops buckets older than each generation's retention. Keys sort
+    /// chronologically, so everything before the oldest kept key goes.
+    fn prune(&mut self, retention: &StatsRetention, now: DateTime<Utc>) {
+        let oldest_hour = hour_key(now - chrono::Duration::hours(retention.hours.into()));
+        let oldest_day = day_key(now - chrono::Duration::days(retention.days.into()));
+        let oldest_month = now
+            .checked_sub_months(Months::new(retention.months))
+            .map_or_else(String::new, month_key);
+        self.hourly.retain(|bucket, _| *bucket > oldest_hour);
+        self.daily.retain(|bucket, _| *bucket > oldest_day);
+        self.monthly.retain(|bucket, _| *bucket > oldest_month);
+    }
+
+    fn is_empty(&self) -> bool {
+        self.hourly.is_empty()
+            && self.daily.is_empty()
+            && self.monthly.is_empty()
+            && self.upload_sizes.is_empty()
+            && self.download_sizes.is_empty()
+    }
+}
+
+fn hour_key(at: DateTime<Utc>) -> String {
+    at.format("%Y-%m-%dT%H").to_string()
+}
+
+fn day_key(at: DateTime<Utc>) -> String {
+    at.format("%Y-%m-%d").to_string()
+}
+
+fn month_key(at: DateTime<Utc>) -> String {
+    format!("{:04}-{:02}", at.year(), at.month())
+}
+
+/// Counts events in memory and periodically folds them into
+/// `data/stats.json`, so recording never touches the disk.
+#[derive(Debug, Clone)]
+pub(crate) struct StatsRecorder {
+    storage: PackageStorage,
+    retention: StatsRetention,
+    pending: Arc<Mutex<BTreeMap<PackageName, ProjectStats>>>,
+}
+
+impl StatsRecorder {
+    pub fn new(storage: PackageStorage) -> Self {
+        Self {
+            storage,
+            retention: StatsRetention::default(),
+            pending: Arc::default(),
+        }
+    }
+
+    pub fn set_retention(&mut self, retention: StatsRetention) {
+        self.retention = retention;
+    }
+
+    /// Counts one file of `bytes` uploaded or downloaded.
+    pub fn record(&self, project: &PackageName, kind: StatKind, bytes: u64) {
+        self.pending
+            .lock()
+            .unwrap()
+            .entry(project.clone())
+            .or_default()
+            .record(Utc::now(), kind, bytes);
+    }
+
+    /// Projects with counts not yet saved.
+    pub fn pending_projects(&self) -> usize {
+        self.pending.lock().unwrap().len()
+    }
+
+    /// Adds the counts recorded since the last flush to the stats file,
+    /// under a lock so processes sharing the data directory each add
+    /// their own.
+    pub async fn flush(&self) -> Result<(), AppError> {
+        let pending = std::mem::take(&mut *self.pending.lock().unwrap());
+        let _lock = self.storage.lock_stats().await?;
+        let mut stats = self.storage.load_stats().await?;
+        for (project, counts) in &pending {
+            stats.entry(project.clone()).or_default().merge(counts);
+        }
+        let now = Utc::now();
+        for project in stats.values_mut() {
+            project.prune(&self.retention, now);
+        }
+        stats.retain(|_, project| !project.is_empty());
+        if let Err(e) = self.storage.save_stats(&stats).await {
+            // Keep the counts for the next flush rather than losing them.
+            let mut unsaved = self.pending.lock().unwrap();
+            for (project, counts) in pending {
+                unsaved.entry(project).or_default().merge(&counts);
+            }
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Flushes every `interval`, forever.
+    pub async fn run(self, interval: Duration) {
+        loop {
+            tokio::time::sleep(interval).await;
+            if let Err(e) = self.flush().await {
+                warn!("Saving stats failed: {}", e);
+            }
+        }
+    }
+
+    /// A project's stats, including counts not yet flushed.
+    pub async fn project(&self, name: &PackageName) -> Result<ProjectStats, AppError> {
+        let mut stats = self
+            .storage
+            .load_stats()
+            .await?
+            .remove(name.as_str())
+            .unwrap_or_default();
+        if let Some(pending) = self.pending.lock().unwrap().get(name.as_str()) {
+            stats.merge(pending);
+        }
+        stats.prune(&self.retention, Utc::now());
+        Ok(stats)
+    }
+}
+
+/// Storage growth and traffic trends, as printed by `pippy report capacity`.
+#[derive(Debug, Clone, Serialize)]
+pub struct CapacityReport {
+    /// Bytes of distribution files stored now.
+    pub stored_bytes: u64,
+    pub stored_files: usize,
+    /// Average bytes uploaded per month over the last complete months.
+    pub monthly_growth: Option<u64>,
+    /// Totals across projects per month.
+    pub monthly: BTreeMap<String, Counts>,
+    pub upload_sizes: SizeHistogram,
+    pub download_sizes: SizeHistogram,
+    /// Busiest projects by bytes uploaded and downloaded in the kept months.
+    pub top_projects: Vec<ProjectUsage>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ProjectUsage {
+    pub name: PackageName,
+    #[serde(flatten)]
+    pub counts: Counts,
+}
+
+/// Projects listed in the capacity report.
+const TOP_PROJECTS: usize = 10;
+/// Complete months averaged for the growth projection.
+const TREND_MONTHS: usize = 3;
+
+impl CapacityReport {
+    /// Builds the report from the saved stats and the files in storage.
+    pub async fn build(storage: &PackageStorage) -> Result<Self, AppError> {
+        let (stored_files, stored_bytes) = storage.stored_usage().await?;
+        let stats = storage.load_stats().await?;
+        let mut monthly: BTreeMap<String, Counts> = BTreeMap::new();
+        let mut upload_sizes = SizeHistogram::default();
+        let mut download_sizes = SizeHistogram::default();
+        let mut top_projects = Vec::new();
+        for (name, project) in &stats {
+            let mut total = Counts::default();
+            for (month, counts) in &project.monthly {
+                *monthly.entry(month.clone()).or_default() += *counts;
+                total += *counts;
+            }
+            upload_sizes.merge(&project.upload_sizes);
+            download_sizes.merge(&project.download_sizes);
+            top_projects.push(ProjectUsage {
+                name: name.clone(),
+                counts: total,
+            });
+        }
+        top_projects.sort_by_key(|usage| {
+            std::cmp::Reverse(usage.counts.upload_bytes + usage.counts.download_bytes)
+        });
+        top_projects.truncate(TOP_PROJECTS);
+        Ok(Self {
+            stored_bytes,
+            stored_files,
+            monthly_growth: monthly_growth(&monthly, Utc::now()),
+            monthly,
+            upload_sizes,
+            download_sizes,
+            top_projects,
+        })
+    }
+}
+
+/// Average bytes uploaded over the last complete months, or `None` without
+/// any.
+fn monthly_growth(monthly: &BTreeMap<String, Counts>, now: DateTime<Utc>) -> Option<u64> {
+    let current = month_key(now);
+    let complete: Vec<&Counts> = monthly
+        .iter()
+        .filter(|(month, _)| **month < current)
+        .rev()
+        .take(TREND_MONTHS)
+        .map(|(_, counts)| counts)
+        .collect();
+    if complete.is_empty() {
+        return None;
+    }
+    Some(complete.iter().map(|c| c.upload_bytes).sum::<u64>() / complete.len() as u64)
+}
+
+fn human_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
+}
+
+impl fmt::Display for CapacityReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "stored: {} in {} files",
+            human_bytes(self.stored_bytes),
+            self.stored_files
+        )?;
+        match self.monthly_growth {
+            Some(growth) => writeln!(
+                f,
+                "growth: {}/month, projected {} in 6 months, {} in 12",
+                human_bytes(growth),
+                human_bytes(self.stored_bytes + 6 * growth),
+                human_bytes(self.stored_bytes + 12 * growth)
+            )?,
+            None => writeln!(f, "growth: no complete month of stats yet")?,
+        }
+        writeln!(f)?;
+        writeln!(
+            f,
+            "{:<8} {:>8} {:>12} {:>10} {:>12}",
+            "month", "uploads", "uploaded", "downloads", "downloaded"
+        )?;
+        for (month, counts) in &self.monthly {
+            writeln!(
+                f,
+                "{:<8} {:>8} {:>12} {:>10} {:>12}",
+                month,
+                counts.uploads,
+                human_bytes(counts.upload_bytes),
+                counts.downloads,
+                human_bytes(counts.download_bytes)
+            )?;
+        }
+        writeln!(f)?;
+        writeln!(f, "upload sizes:   {}", self.upload_sizes)?;
+        writeln!(f, "download sizes: {}", self.download_sizes)?;
+        if !self.top_projects.is_empty() {
+            writeln!(f)?;
+            writeln!(f, "busiest projects:")?;
+            for usage in &self.top_projects {
+                writeln!(
+                    f,
+                    "  {:<30} {:>12} up {:>12} down",
+                    usage.name.as_str(),
+                    human_bytes(usage.counts.upload_bytes),
+                    human_bytes(usage.counts.download_bytes)
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
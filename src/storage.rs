@@ -0,0 +1,1516 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::{File, OpenOptions, TryLockError},
+    future::Future,
+    io::{self, BufReader, Read},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use axum::body::Bytes;
+use chrono::{DateTime, Utc};
+use futures_util::{stream, Stream, StreamExt};
+use serde::de::DeserializeOwned;
+use sha2::{Digest, Sha256};
+use tempfile::TempPath;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio_util::io::ReaderStream;
+use tracing::{info, warn};
+
+use crate::{
+    auth::{ApiToken, User},
+    backend::{FileSystemBackend, ObjectReader, StorageBackend, StoredObject},
+    import::{self, LinkMode},
+    index::{Change, Snapshot},
+    metadata::AuditEntry,
+    stats::ProjectStats,
+    webhooks::{Delivery, PendingDelivery},
+    AppError, DistFilename, Package, PackageName, SnapshotName, Version,
+};
+
+/// Attempts made for an operation failing with a transient error before
+/// the storage is reported unavailable.
+const RETRY_ATTEMPTS: u32 = 4;
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(50);
+/// `Retry-After` advertised to clients once retries are exhausted.
+const UNAVAILABLE_RETRY_AFTER: Duration = Duration::from_secs(30);
+/// How much of a large JSON file is read between progress reports.
+const LOAD_PROGRESS_STEP: u64 = 256 * 1024 * 1024;
+/// Project metadata files read at once while loading the index.
+const PROJECT_LOADS: usize = 16;
+/// How much of the change journal is read at a time from its end.
+const JOURNAL_TAIL_BLOCK: u64 = 64 * 1024;
+/// Compacted journal entries kept as history, for mirrors following
+/// `/api/v1/changes` and the hourly upload limit.
+const HISTORY_ENTRIES: usize = 100_000;
+
+/// Whether an I/O failure is likely to clear up on its own (a busy or
+/// briefly unreachable volume) rather than indicating a real fault.
+pub fn is_transient(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::Interrupted
+            | io::ErrorKind::WouldBlock
+            | io::ErrorKind::TimedOut
+            | io::ErrorKind::ResourceBusy
+            | io::ErrorKind::StaleNetworkFileHandle
+            | io::ErrorKind::NetworkDown
+            | io::ErrorKind::NetworkUnreachable
+            | io::ErrorKind::HostUnreachable
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+    )
+}
+
+/// Deserializes a JSON file as it is read rather than from one string, so
+/// a large index does not need twice its size in memory to load, logging
+/// progress through files big enough to take a while. Missing and empty
+/// files are `None`.
+async fn load_json<T: DeserializeOwned + Send + 'static>(
+    path: PathBuf,
+) -> Result<Option<T>, AppError> {
+    tokio::task::spawn_blocking(move || {
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let total = file.metadata()?.len();
+        if total == 0 {
+            return Ok(None);
+        }
+        let reader = ProgressReader {
+            inner: BufReader::with_capacity(1024 * 1024, file),
+            path: &path,
+            read: 0,
+            total,
+            next_report: LOAD_PROGRESS_STEP,
+        };
+        Ok(Some(serde_json::from_reader(reader)?))
+    })
+    .await
+    .map_err(|e| AppError::Io(io::Error::other(e)))?
+}
+
+struct ProgressReader<'a, R> {
+    inner: R,
+    path: &'a Path,
+    read: u64,
+    total: u64,
+    next_report: u64,
+}
+
+impl<R: Read> Read for ProgressReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.read += n as u64;
+        if self.read >= self.next_report {
+            info!(
+                "Loading {}: {} of {} MiB read",
+                self.path.display(),
+                self.read >> 20,
+                self.total >> 20
+            );
+            self.next_report += LOAD_PROGRESS_STEP;
+        }
+        Ok(n)
+    }
+}
+
+/// Syncs a directory, making the renames and removals in it durable.
+/// Reads the journal entries in the file at `path` back from its end, a
+/// block at a time, handing each block's entries in order to `visit` until
+/// it returns false. Unreadable lines are left for `pippy fsck`.
+async fn read_back(
+    path: &Path,
+    mut visit: impl FnMut(Vec<Change>) -> bool,
+) -> Result<(), AppError> {
+    let mut file = match tokio::fs::File::open(path).await {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut start = file.metadata().await?.len();
+    let mut tail: Vec<u8> = Vec::new();
+    while start > 0 {
+        let from = start.saturating_sub(JOURNAL_TAIL_BLOCK);
+        let mut block = vec![0; (start - from) as usize];
+        file.seek(io::SeekFrom::Start(from)).await?;
+        file.read_exact(&mut block).await?;
+        block.append(&mut tail);
+        start = from;
+        // Unless the start is reached, the first line may be cut short,
+        // so it is kept for the next block.
+        let complete = match start {
+            0 => 0,
+            _ => match block.iter().position(|b| *b == b'\n') {
+                Some(newline) => newline + 1,
+                None => {
+                    tail = block;
+                    continue;
+                }
+            },
+        };
+        tail = block[..complete].to_vec();
+        let changes = block[complete..]
+            .split(|b| *b == b'\n')
+            .filter_map(|line| serde_json::from_slice::<Change>(line).ok())
+            .collect();
+        if !visit(changes) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+async fn sync_dir(dir: &Path) -> io::Result<()> {
+    tokio::fs::File::open(dir).await?.sync_all().await
+}
+
+/// Runs `attempt` until it succeeds, fails permanently, or exhausts the
+/// retry budget with exponential backoff between transient failures.
+async fn with_retry<T, F, Fut>(operation: &str, mut attempt: F) -> Result<T, AppError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = io::Result<T>>,
+{
+    let mut delay = RETRY_BASE_DELAY;
+    for tries in 1.. {
+        match attempt().await {
+            Ok(value) => return Ok(value),
+            Err(e) if is_transient(&e) && tries < RETRY_ATTEMPTS => {
+                warn!(
+                    "Transient storage error during {}, retrying: {}",
+                    operation, e
+                );
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+            Err(e) if is_transient(&e) => {
+                warn!("Storage unavailable during {}: {}", operation, e);
+                return Err(AppError::StorageUnavailable {
+                    operation: operation.to_string(),
+                    retry_after: UNAVAILABLE_RETRY_AFTER,
+                });
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+    unreachable!("the retry loop only exits by returning")
+}
+
+/// Held for as long as a process serves from a data directory.
+#[derive(Debug)]
+pub struct InstanceLock(#[allow(dead_code)] File);
+
+/// Held while the index is read, modified and written back, so writes from
+/// several processes sharing a data directory do not interleave.
+#[derive(Debug)]
+pub(crate) struct IndexLock(#[allow(dead_code)] File);
+
+/// A stored file on the local filesystem: the file itself where the backend
+/// keeps it there, else a temporary copy removed when this is dropped.
+#[derive(Debug)]
+pub struct LocalFile {
+    path: PathBuf,
+    _copy: Option<TempPath>,
+}
+
+impl LocalFile {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// An upload received in full but not yet stored, removed when dropped
+/// unless [`PackageStorage::store_spooled`] moved it into place.
+#[derive(Debug)]
+pub(crate) struct SpooledPackage {
+    path: TempPath,
+    sha256: String,
+    size: u64,
+}
+
+impl SpooledPackage {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Hex SHA-256 of the bytes received.
+    pub fn sha256(&self) -> &str {
+        &self.sha256
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+/// Key of a project's file in the storage backend.
+fn object_key(name: &PackageName, filename: &str) -> String {
+    format!("{name}/{filename}")
+}
+
+#[derive(Debug, Clone)]
+pub struct PackageStorage {
+    base_path: PathBuf,
+    /// Where the filesystem backend keeps files. Scanned for leftovers of
+    /// interrupted writes, and of layouts older than the backend.
+    packages_dir: PathBuf,
+    /// One metadata file per project, listed in `projects.json`.
+    projects_dir: PathBuf,
+    docs_dir: PathBuf,
+    snapshots_dir: PathBuf,
+    backend: Arc<dyn StorageBackend>,
+}
+
+impl PackageStorage {
+    /// Storage in `base_path`, distribution files included.
+    pub fn new(base_path: PathBuf) -> Result<Self, AppError> {
+        let backend = FileSystemBackend::new(base_path.join("packages"))?
+            .content_addressed(base_path.join("blobs"))?;
+        Self::with_backend(base_path, Arc::new(backend))
+    }
+
+    /// Storage in `base_path` that keeps distribution files in `backend`.
+    pub fn with_backend(
+        base_path: PathBuf,
+        backend: Arc<dyn StorageBackend>,
+    ) -> Result<Self, AppError> {
+        let packages_dir = base_path.join("packages");
+        let projects_dir = base_path.join("projects");
+        let docs_dir = base_path.join("docs");
+        let snapshots_dir = base_path.join("snapshots");
+        std::fs::create_dir_all(&packages_dir)?;
+        std::fs::create_dir_all(&projects_dir)?;
+        std::fs::create_dir_all(&docs_dir)?;
+        std::fs::create_dir_all(&snapshots_dir)?;
+        std::fs::create_dir_all(&base_path)?;
+
+        Ok(Self {
+            base_path,
+            packages_dir,
+            projects_dir,
+            docs_dir,
+            snapshots_dir,
+            backend,
+        })
+    }
+
+    pub fn backend(&self) -> &Arc<dyn StorageBackend> {
+        &self.backend
+    }
+
+    /// Claims the data directory for this process. An exclusive claim fails
+    /// while any other process holds one; shared claims only coexist with
+    /// other shared claims, so every process must opt in to sharing.
+    pub fn claim(&self, shared: bool) -> Result<InstanceLock, AppError> {
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(self.base_path.join("instance.lock"))?;
+        let claimed = if shared {
+            file.try_lock_shared()
+        } else {
+            file.try_lock()
+        };
+        match claimed {
+            Ok(()) => Ok(InstanceLock(file)),
+            Err(TryLockError::WouldBlock) => Err(AppError::Conflict(format!(
+                "{} is in use by another pippy process; start every process with \
+                 --shared-storage to serve it from several at once",
+                self.base_path.display()
+            ))),
+            Err(TryLockError::Error(e)) => Err(e.into()),
+        }
+    }
+
+    pub(crate) async fn lock_index(&self) -> Result<IndexLock, AppError> {
+        self.lock_file("index.lock").await
+    }
+
+    /// Serializes changes to the webhook delivery queue across processes.
+    pub(crate) async fn lock_webhook_queue(&self) -> Result<IndexLock, AppError> {
+        self.lock_file("webhook-queue.lock").await
+    }
+
+    pub(crate) async fn lock_tokens(&self) -> Result<IndexLock, AppError> {
+        self.lock_file("tokens.lock").await
+    }
+
+    pub(crate) async fn lock_stats(&self) -> Result<IndexLock, AppError> {
+        self.lock_file("stats.lock").await
+    }
+
+    async fn lock_file(&self, name: &str) -> Result<IndexLock, AppError> {
+        let path = self.base_path.join(name);
+        tokio::task::spawn_blocking(move || {
+            let file = OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(path)?;
+            file.lock()?;
+            Ok(IndexLock(file))
+        })
+        .await
+        .map_err(|e| AppError::Io(io::Error::other(e)))?
+    }
+
+    /// Where one project's metadata is kept.
+    fn project_path(&self, name: &PackageName) -> PathBuf {
+        self.projects_dir.join(format!("{name}.json"))
+    }
+
+    /// The index, from the name list and each project's metadata file;
+    /// `None` before anything was written.
+    pub async fn load_index(&self) -> Result<Option<BTreeMap<PackageName, Package>>, AppError> {
+        match self.load_project_names().await? {
+            Some(names) => Ok(Some(self.load_projects(names).await?)),
+            None => Ok(None),
+        }
+    }
+
+    /// The names listed in `projects.json`; `None` before anything was
+    /// written.
+    pub(crate) async fn load_project_names(&self) -> Result<Option<Vec<PackageName>>, AppError> {
+        load_json(self.base_path.join("projects.json")).await
+    }
+
+    /// The metadata of `names`, read a few files at a time. Listed projects
+    /// without a file are left out.
+    pub(crate) async fn load_projects(
+        &self,
+        names: impl IntoIterator<Item = PackageName>,
+    ) -> Result<BTreeMap<PackageName, Package>, AppError> {
+        let mut loaded = stream::iter(names)
+            .map(|name| async move {
+                let package: Option<Package> = load_json(self.project_path(&name)).await?;
+                Ok::<_, AppError>((name, package))
+            })
+            .buffer_unordered(PROJECT_LOADS);
+        let mut packages = BTreeMap::new();
+        while let Some(loaded) = loaded.next().await {
+            match loaded? {
+                (name, Some(mut package)) => {
+                    // Written before releases were ordered by version.
+                    package.sort_releases();
+                    packages.insert(name, package);
+                }
+                (name, None) => warn!("{} is listed, but its metadata file is missing", name),
+            }
+        }
+        Ok(packages)
+    }
+
+    /// Splits an `index.json` written before each project had its own
+    /// metadata file, keying entries by normalized name and merging those
+    /// that now share one. Returns how many projects it held, if there was
+    /// one.
+    pub(crate) async fn split_legacy_index(&self) -> Result<Option<usize>, AppError> {
+        let _lock = self.lock_index().await?;
+        let legacy = self.base_path.join("index.json");
+        let raw: Option<BTreeMap<String, Package>> = load_json(legacy.clone()).await?;
+        let Some(raw) = raw else {
+            return Ok(None);
+        };
+        let mut merged: BTreeMap<PackageName, Package> = BTreeMap::new();
+        for (key, package) in raw {
+            let name = PackageName::new(key)?;
+            match merged.get_mut(name.as_str()) {
+                Some(existing) => existing.absorb(package),
+                None => {
+                    merged.insert(name, package);
+                }
+            }
+        }
+        self.save_index(&merged).await?;
+        tokio::fs::remove_file(&legacy).await?;
+        Ok(Some(merged.len()))
+    }
+
+    /// Brings project directories created before project names were
+    /// normalized up to date, moving them to match. Returns how many moved.
+    pub(crate) async fn normalize_project_names(&self) -> Result<usize, AppError> {
+        let _lock = self.lock_index().await?;
+        let mut moved = 0;
+        for dir in [&self.packages_dir, &self.docs_dir] {
+            let mut entries = tokio::fs::read_dir(dir).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                let raw = entry.file_name().to_string_lossy().into_owned();
+                let Ok(name) = PackageName::new(raw.as_str()) else {
+                    continue;
+                };
+                if name.as_str() == raw || !entry.file_type().await?.is_dir() {
+                    continue;
+                }
+                let target = dir.join(name.as_str());
+                tokio::fs::create_dir_all(&target).await?;
+                let mut files = tokio::fs::read_dir(entry.path()).await?;
+                while let Some(file) = files.next_entry().await? {
+                    let dest = target.join(file.file_name());
+                    if tokio::fs::try_exists(&dest).await? {
+                        warn!(
+                            "Leaving {} in place, {} already exists",
+                            file.path().display(),
+                            dest.display()
+                        );
+                        continue;
+                    }
+                    tokio::fs::rename(file.path(), dest).await?;
+                }
+                // Fails, leaving the directory for fsck, unless all moved.
+                let _ = tokio::fs::remove_dir(entry.path()).await;
+                moved += 1;
+            }
+        }
+        Ok(moved)
+    }
+
+    /// Replaces the whole index: every project's metadata file and the
+    /// name list. Callers hold the index lock.
+    pub async fn save_index(
+        &self,
+        packages: &BTreeMap<PackageName, Package>,
+    ) -> Result<(), AppError> {
+        for (name, package) in packages {
+            self.save_project(name, package).await?;
+        }
+        self.save_project_names(packages.keys()).await?;
+        let mut entries = tokio::fs::read_dir(&self.projects_dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let file_name = entry.file_name().to_string_lossy().into_owned();
+            let listed = file_name
+                .strip_suffix(".json")
+                .is_some_and(|name| packages.contains_key(name));
+            if !listed && !file_name.starts_with('.') {
+                tokio::fs::remove_file(entry.path()).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the metadata of `projects` as they are in `packages`, and
+    /// removes that of those no longer in it. `packages` need not hold
+    /// any other project. The name list is only rewritten when a project
+    /// is added or removed, so most writes touch one small file. Callers
+    /// hold the index lock.
+    pub(crate) async fn save_projects(
+        &self,
+        packages: &BTreeMap<PackageName, Package>,
+        projects: &[&PackageName],
+    ) -> Result<(), AppError> {
+        let mut added = Vec::new();
+        let mut removed = Vec::new();
+        for &name in projects {
+            match packages.get(name.as_str()) {
+                Some(package) => {
+                    if !tokio::fs::try_exists(self.project_path(name)).await? {
+                        added.push(name);
+                    }
+                    self.save_project(name, package).await?;
+                }
+                None => removed.push(name),
+            }
+        }
+        if added.is_empty() && removed.is_empty() {
+            return Ok(());
+        }
+        let mut names: BTreeSet<PackageName> = self
+            .load_project_names()
+            .await?
+            .unwrap_or_default()
+            .into_iter()
+            .collect();
+        names.extend(added.iter().map(|&name| name.clone()));
+        for name in &removed {
+            names.remove(*name);
+        }
+        // New projects are written before they are listed, and removed
+        // ones unlisted before their file goes, so every listed project has
+        // a file to load.
+        if let Err(e) = self.save_project_names(&names).await {
+            for name in added {
+                let _ = tokio::fs::remove_file(self.project_path(name)).await;
+            }
+            return Err(e);
+        }
+        for path in removed.into_iter().map(|name| self.project_path(name)) {
+            match tokio::fs::remove_file(&path).await {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Syncs the metadata files of `projects`, and the name list, to disk.
+    pub(crate) async fn sync_projects(&self, projects: &[&PackageName]) -> Result<(), AppError> {
+        let names = self.base_path.join("projects.json");
+        let files = projects.iter().map(|name| self.project_path(name));
+        for path in files.chain([names]) {
+            // Removed projects have no file left to sync.
+            match tokio::fs::File::open(path).await {
+                Ok(file) => file.sync_all().await?,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        sync_dir(&self.projects_dir).await?;
+        Ok(sync_dir(&self.base_path).await?)
+    }
+
+    async fn save_project(&self, name: &PackageName, package: &Package) -> Result<(), AppError> {
+        let content = serde_json::to_string_pretty(package)?;
+        let path = self.project_path(name);
+        let partial = self.projects_dir.join(format!(".{name}.json.partial"));
+        // Readers in other processes load the index without locking, so
+        // each file is replaced in one rename rather than rewritten in place.
+        with_retry("project save", || async {
+            tokio::fs::write(&partial, &content).await?;
+            tokio::fs::rename(&partial, &path).await
+        })
+        .await
+    }
+
+    async fn save_project_names(
+        &self,
+        names: impl IntoIterator<Item = &PackageName>,
+    ) -> Result<(), AppError> {
+        let content = serde_json::to_string_pretty(&names.into_iter().collect::<Vec<_>>())?;
+        let path = self.base_path.join("projects.json");
+        let partial = self.base_path.join("projects.json.partial");
+        with_retry("project list save", || async {
+            tokio::fs::write(&partial, &content).await?;
+            tokio::fs::rename(&partial, &path).await
+        })
+        .await
+    }
+
+    fn journal_path(&self) -> PathBuf {
+        self.base_path.join("changes.jsonl")
+    }
+
+    /// Length of the change journal, which grows with every index write.
+    pub(crate) async fn journal_len(&self) -> Result<u64, AppError> {
+        match tokio::fs::metadata(self.journal_path()).await {
+            Ok(metadata) => Ok(metadata.len()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(0),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Reads the journal entries written after byte `offset`, returning them
+    /// with the offset the next read should start from.
+    pub(crate) async fn read_changes(&self, offset: u64) -> Result<(Vec<Change>, u64), AppError> {
+        let mut file = match tokio::fs::File::open(self.journal_path()).await {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok((Vec::new(), 0)),
+            Err(e) => return Err(e.into()),
+        };
+        file.seek(io::SeekFrom::Start(offset)).await?;
+        let mut content = String::new();
+        file.read_to_string(&mut content).await?;
+        // Every change makes followers reload the whole index, so a
+        // damaged line costs nothing beyond its serial; `pippy fsck` reports
+        // and removes them.
+        let changes = content
+            .lines()
+            .filter_map(|line| match serde_json::from_str(line) {
+                Ok(change) => Some(change),
+                Err(e) => {
+                    warn!("Skipping unreadable change journal line: {}", e);
+                    None
+                }
+            })
+            .collect();
+        Ok((changes, offset + content.len() as u64))
+    }
+
+    /// The journal entries written after `since`, compacted ones included.
+    pub(crate) async fn changes_since(
+        &self,
+        since: DateTime<Utc>,
+    ) -> Result<Vec<Change>, AppError> {
+        self.changes_while(|change| change.time > since).await
+    }
+
+    /// The journal entries with a serial above `serial`, compacted ones
+    /// included, as far back as the history goes.
+    pub(crate) async fn changes_after(&self, serial: u64) -> Result<Vec<Change>, AppError> {
+        self.changes_while(|change| change.serial > serial).await
+    }
+
+    /// The latest journal entries, up to the first one `keep` rejects.
+    /// Entries are appended in serial and time order, so the journal and
+    /// then its history are read back from their ends only as far as
+    /// that entry, however long the history.
+    async fn changes_while(&self, keep: impl Fn(&Change) -> bool) -> Result<Vec<Change>, AppError> {
+        let mut changes = Vec::new();
+        let mut older = false;
+        for path in [self.journal_path(), self.history_path()] {
+            read_back(&path, |block| {
+                let mut lines = Vec::new();
+                for change in block {
+                    if keep(&change) {
+                        lines.push(change);
+                    } else {
+                        older = true;
+                    }
+                }
+                lines.append(&mut changes);
+                changes = lines;
+                !older
+            })
+            .await?;
+            if older {
+                break;
+            }
+        }
+        Ok(changes)
+    }
+
+    fn history_path(&self) -> PathBuf {
+        self.base_path.join("changes.history.jsonl")
+    }
+
+    /// Serial of the latest compacted journal entry, or 0 without any.
+    pub(crate) async fn history_serial(&self) -> Result<u64, AppError> {
+        let mut serial = 0;
+        read_back(&self.history_path(), |block| match block.last() {
+            Some(change) => {
+                serial = change.serial;
+                false
+            }
+            None => true,
+        })
+        .await?;
+        Ok(serial)
+    }
+
+    /// Adds compacted journal entries to the history, leaving out those it
+    /// already holds, as after a compaction interrupted before the journal
+    /// was rewritten, and dropping the oldest beyond `HISTORY_ENTRIES`.
+    /// Callers hold the index lock.
+    pub(crate) async fn append_history(&self, changes: &[Change]) -> Result<(), AppError> {
+        let path = self.history_path();
+        let history = match tokio::fs::read_to_string(&path).await {
+            Ok(history) => history,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let mut lines: Vec<String> = history.lines().map(str::to_owned).collect();
+        let serial = lines
+            .iter()
+            .rev()
+            .find_map(|line| serde_json::from_str::<Change>(line).ok())
+            .map_or(0, |change| change.serial);
+        for change in changes.iter().filter(|c| c.serial > serial) {
+            lines.push(serde_json::to_string(change)?);
+        }
+        let dropped = lines.len().saturating_sub(HISTORY_ENTRIES);
+        let mut content = lines[dropped..].join("\n");
+        content.push('\n');
+        let partial = self.base_path.join("changes.history.jsonl.partial");
+        with_retry("journal history rewrite", || async {
+            let mut file = tokio::fs::File::create(&partial).await?;
+            file.write_all(content.as_bytes()).await?;
+            file.sync_all().await?;
+            tokio::fs::rename(&partial, &path).await?;
+            sync_dir(&self.base_path).await
+        })
+        .await
+    }
+
+    /// The whole journal as written, for checking it line by line.
+    pub(crate) async fn read_journal(&self) -> Result<String, AppError> {
+        match tokio::fs::read_to_string(self.journal_path()).await {
+            Ok(content) => Ok(content),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(String::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Replaces the journal in one rename, so appends never interleave
+    /// with the rewrite. The new journal is synced first, since it may
+    /// hold the only copy of recent changes. Callers hold the index lock.
+    pub(crate) async fn replace_journal(&self, content: String) -> Result<(), AppError> {
+        let path = self.journal_path();
+        let partial = self.base_path.join("changes.jsonl.partial");
+        with_retry("journal rewrite", || async {
+            let mut file = tokio::fs::File::create(&partial).await?;
+            file.write_all(content.as_bytes()).await?;
+            file.sync_all().await?;
+            tokio::fs::rename(&partial, &path).await?;
+            sync_dir(&self.base_path).await
+        })
+        .await
+    }
+
+    /// Appends `changes` to the journal and syncs it to disk, so they
+    /// survive a crash once this returns.
+    pub(crate) async fn append_changes(&self, changes: &[Change]) -> Result<(), AppError> {
+        let mut lines = Vec::new();
+        for change in changes {
+            serde_json::to_writer(&mut lines, change)?;
+            lines.push(b'\n');
+        }
+        let path = self.journal_path();
+        with_retry("journal append", || async {
+            let mut file = tokio::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .await?;
+            file.write_all(&lines).await?;
+            file.sync_data().await
+        })
+        .await
+    }
+
+    /// Cuts the journal back to `len` bytes, dropping a failed append.
+    pub(crate) async fn truncate_journal(&self, len: u64) -> Result<(), AppError> {
+        let file = tokio::fs::OpenOptions::new()
+            .write(true)
+            .open(self.journal_path())
+            .await?;
+        file.set_len(len).await?;
+        Ok(file.sync_data().await?)
+    }
+
+    pub(crate) async fn append_audit(&self, entry: &AuditEntry) -> Result<(), AppError> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        let path = self.base_path.join("audit.jsonl");
+        with_retry("audit append", || async {
+            let mut file = tokio::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .await?;
+            file.write_all(&line).await?;
+            // Writes land in the background until flushed.
+            file.flush().await
+        })
+        .await
+    }
+
+    /// Deliveries waiting for their next attempt. Callers hold the queue
+    /// lock.
+    pub(crate) async fn load_webhook_queue(&self) -> Result<Vec<PendingDelivery>, AppError> {
+        match tokio::fs::read_to_string(self.base_path.join("webhook-queue.json")).await {
+            Ok(content) => Ok(serde_json::from_str(&content)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub(crate) async fn save_webhook_queue(
+        &self,
+        queue: &[PendingDelivery],
+    ) -> Result<(), AppError> {
+        let content = serde_json::to_string_pretty(queue)?;
+        let path = self.base_path.join("webhook-queue.json");
+        let partial = self.base_path.join("webhook-queue.json.partial");
+        with_retry("webhook queue save", || async {
+            tokio::fs::write(&partial, &content).await?;
+            tokio::fs::rename(&partial, &path).await
+        })
+        .await
+    }
+
+    pub(crate) async fn load_tokens(&self) -> Result<Vec<ApiToken>, AppError> {
+        match tokio::fs::read_to_string(self.base_path.join("tokens.json")).await {
+            Ok(content) => Ok(serde_json::from_str(&content)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Replaces the token list. Callers hold the token lock.
+    pub(crate) async fn save_tokens(&self, tokens: &[ApiToken]) -> Result<(), AppError> {
+        let content = serde_json::to_string_pretty(tokens)?;
+        let path = self.base_path.join("tokens.json");
+        let partial = self.base_path.join("tokens.json.partial");
+        with_retry("token save", || async {
+            tokio::fs::write(&partial, &content).await?;
+            tokio::fs::rename(&partial, &path).await
+        })
+        .await
+    }
+
+    pub(crate) async fn load_users(&self) -> Result<Vec<User>, AppError> {
+        match tokio::fs::read_to_string(self.base_path.join("users.json")).await {
+            Ok(content) => Ok(serde_json::from_str(&content)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Replaces the user list. Callers hold the token lock.
+    pub(crate) async fn save_users(&self, users: &[User]) -> Result<(), AppError> {
+        let content = serde_json::to_string_pretty(users)?;
+        let path = self.base_path.join("users.json");
+        let partial = self.base_path.join("users.json.partial");
+        with_retry("user save", || async {
+            tokio::fs::write(&partial, &content).await?;
+            tokio::fs::rename(&partial, &path).await
+        })
+        .await
+    }
+
+    pub(crate) async fn append_delivery(&self, delivery: &Delivery) -> Result<(), AppError> {
+        let mut line = serde_json::to_vec(delivery)?;
+        line.push(b'\n');
+        let path = self.base_path.join("webhook-deliveries.jsonl");
+        with_retry("delivery log append", || async {
+            let mut file = tokio::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .await?;
+            file.write_all(&line).await?;
+            file.flush().await
+        })
+        .await
+    }
+
+    /// Every recorded delivery attempt, oldest first.
+    pub(crate) async fn read_deliveries(&self) -> Result<Vec<Delivery>, AppError> {
+        let content = match tokio::fs::read_to_string(
+            self.base_path.join("webhook-deliveries.jsonl"),
+        )
+        .await
+        {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(content
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect())
+    }
+
+    pub(crate) async fn load_stats(&self) -> Result<BTreeMap<PackageName, ProjectStats>, AppError> {
+        match tokio::fs::read_to_string(self.base_path.join("stats.json")).await {
+            Ok(content) => Ok(serde_json::from_str(&content)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub(crate) async fn save_stats(
+        &self,
+        stats: &BTreeMap<PackageName, ProjectStats>,
+    ) -> Result<(), AppError> {
+        let content = serde_json::to_string(stats)?;
+        let path = self.base_path.join("stats.json");
+        let partial = self.base_path.join("stats.json.partial");
+        with_retry("stats save", || async {
+            tokio::fs::write(&partial, &content).await?;
+            tokio::fs::rename(&partial, &path).await
+        })
+        .await
+    }
+
+    /// The size and modification time of a stored file, if it is stored.
+    pub async fn stat(
+        &self,
+        name: &PackageName,
+        filename: &DistFilename,
+    ) -> Result<Option<StoredObject>, AppError> {
+        let key = object_key(name, filename.as_str());
+        let listed = with_retry("package stat", || self.backend.list(&key)).await?;
+        Ok(listed.into_iter().find(|object| object.key == key))
+    }
+
+    pub async fn has_package(
+        &self,
+        name: &PackageName,
+        filename: &DistFilename,
+    ) -> Result<bool, AppError> {
+        let key = object_key(name, filename.as_str());
+        with_retry("package lookup", || self.backend.exists(&key)).await
+    }
+
+    /// Opens a stored file for reading, with its size.
+    pub async fn open_package(
+        &self,
+        name: &PackageName,
+        filename: &DistFilename,
+    ) -> Result<(ObjectReader, u64), AppError> {
+        self.open_object(object_key(name, filename.as_str()), filename.to_string())
+            .await
+    }
+
+    /// Opens the `METADATA` extracted from a wheel, with its size.
+    pub(crate) async fn open_core_metadata(
+        &self,
+        name: &PackageName,
+        filename: &DistFilename,
+    ) -> Result<(ObjectReader, u64), AppError> {
+        let what = format!("{filename}.metadata");
+        self.open_object(object_key(name, &what), what).await
+    }
+
+    async fn open_object(
+        &self,
+        key: String,
+        what: String,
+    ) -> Result<(ObjectReader, u64), AppError> {
+        with_retry("package read", || self.backend.open(&key))
+            .await?
+            .ok_or(AppError::NotFound(what))
+    }
+
+    /// The `METADATA` extracted from a wheel.
+    pub(crate) async fn read_core_metadata(
+        &self,
+        name: &PackageName,
+        filename: &DistFilename,
+    ) -> Result<Vec<u8>, AppError> {
+        let (mut reader, size) = self.open_core_metadata(name, filename).await?;
+        let mut contents = Vec::with_capacity(size as usize);
+        reader.read_to_end(&mut contents).await?;
+        Ok(contents)
+    }
+
+    /// A stored file as a local path, for what can only read files from
+    /// disk, such as archive readers.
+    pub async fn local_file(
+        &self,
+        name: &PackageName,
+        filename: &DistFilename,
+    ) -> Result<LocalFile, AppError> {
+        let key = object_key(name, filename.as_str());
+        if let Some(path) = self.backend.local_path(&key) {
+            return Ok(LocalFile { path, _copy: None });
+        }
+        let (mut reader, _) = self.open_package(name, filename).await?;
+        let copy = tempfile::NamedTempFile::new()?.into_temp_path();
+        let mut file = tokio::fs::File::create(&copy).await?;
+        tokio::io::copy(&mut reader, &mut file).await?;
+        file.flush().await?;
+        Ok(LocalFile {
+            path: copy.to_path_buf(),
+            _copy: Some(copy),
+        })
+    }
+
+    /// Writes a wheel's `METADATA` next to it, returning its SHA-256.
+    pub(crate) async fn store_core_metadata(
+        &self,
+        name: &PackageName,
+        filename: &DistFilename,
+        contents: Vec<u8>,
+    ) -> Result<String, AppError> {
+        let key = object_key(name, &format!("{filename}.metadata"));
+        let sha256 = format!("{:x}", Sha256::digest(&contents));
+        let contents = Bytes::from(contents);
+        with_retry("core metadata write", || {
+            let chunk = Ok(contents.clone());
+            self.backend
+                .store(&key, stream::once(async { chunk }).boxed())
+        })
+        .await?;
+        Ok(sha256)
+    }
+
+    pub(crate) async fn remove_core_metadata(
+        &self,
+        name: &PackageName,
+        filename: &DistFilename,
+    ) -> Result<(), AppError> {
+        let key = object_key(name, &format!("{filename}.metadata"));
+        with_retry("core metadata removal", || self.backend.delete(&key)).await
+    }
+
+    /// Writes a new snapshot, refusing to replace an existing one. Callers
+    /// hold the index lock, so the existence check cannot race.
+    pub(crate) async fn save_snapshot(&self, snapshot: &Snapshot) -> Result<(), AppError> {
+        let path = self.snapshots_dir.join(format!("{}.json", snapshot.name));
+        if tokio::fs::try_exists(&path).await? {
+            return Err(AppError::Conflict(format!(
+                "Snapshot {} already exists",
+                snapshot.name
+            )));
+        }
+        let content = serde_json::to_string_pretty(snapshot)?;
+        let partial = self
+            .snapshots_dir
+            .join(format!(".{}.partial", snapshot.name));
+        with_retry("snapshot save", || async {
+            tokio::fs::write(&partial, &content).await?;
+            tokio::fs::rename(&partial, &path).await
+        })
+        .await
+    }
+
+    pub(crate) async fn load_snapshot(
+        &self,
+        name: &SnapshotName,
+    ) -> Result<Option<Snapshot>, AppError> {
+        let path = self.snapshots_dir.join(format!("{name}.json"));
+        match tokio::fs::read_to_string(path).await {
+            Ok(content) => Ok(Some(serde_json::from_str(&content)?)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub(crate) async fn snapshot_names(&self) -> Result<Vec<SnapshotName>, AppError> {
+        let mut names = Vec::new();
+        let mut entries = tokio::fs::read_dir(&self.snapshots_dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let file_name = entry.file_name();
+            let Some(stem) = file_name.to_str().and_then(|f| f.strip_suffix(".json")) else {
+                continue;
+            };
+            if let Ok(name) = stem.parse() {
+                names.push(name);
+            }
+        }
+        Ok(names)
+    }
+
+    /// Hex SHA-256 digest of a stored distribution file.
+    pub async fn sha256(
+        &self,
+        name: &PackageName,
+        filename: &DistFilename,
+    ) -> Result<String, AppError> {
+        let (reader, _) = self.open_package(name, filename).await?;
+        let mut chunks = ReaderStream::with_capacity(reader, 64 * 1024);
+        let mut hasher = Sha256::new();
+        while let Some(chunk) = chunks.next().await {
+            hasher.update(chunk?);
+        }
+        Ok(format!("{:x}", hasher.finalize()))
+    }
+
+    /// Streams a distribution file into the store, returning the SHA-256 of
+    /// its bytes. The backend only makes the file visible once complete, so
+    /// readers never see a truncated file and a failed upload leaves
+    /// nothing behind.
+    pub async fn store_package<S, E>(
+        &self,
+        name: &PackageName,
+        filename: &DistFilename,
+        chunks: S,
+    ) -> Result<String, AppError>
+    where
+        S: Stream<Item = Result<Bytes, E>> + Send,
+        AppError: From<E>,
+    {
+        let mut hasher = Sha256::new();
+        // The backend only sees I/O errors; the upload's own error, such as
+        // a size limit, is kept to be returned instead.
+        let mut failure = None;
+        let hashed = chunks.map(|chunk| match chunk {
+            Ok(chunk) => {
+                hasher.update(&chunk);
+                Ok(chunk)
+            }
+            Err(e) => {
+                failure = Some(AppError::from(e));
+                Err(io::Error::other("upload interrupted"))
+            }
+        });
+        let key = object_key(name, filename.as_str());
+        let stored = self.backend.store(&key, hashed.boxed()).await;
+        match (stored, failure) {
+            (Ok(_), _) => Ok(format!("{:x}", hasher.finalize())),
+            (Err(_), Some(e)) => Err(e),
+            (Err(e), None) => Err(e.into()),
+        }
+    }
+
+    /// Streams an upload into a temporary file, at the root of the backend
+    /// when it keeps files locally, hashing it on the way,
+    /// so it can be checked before [`store_spooled`](Self::store_spooled)
+    /// moves it into place.
+    pub(crate) async fn spool_package<S, E>(
+        &self,
+        name: &PackageName,
+        filename: &DistFilename,
+        chunks: S,
+    ) -> Result<SpooledPackage, AppError>
+    where
+        S: Stream<Item = Result<Bytes, E>> + Send,
+        AppError: From<E>,
+    {
+        let path = self.spool_path(name, filename)?;
+        let mut file = tokio::fs::File::create(&path).await?;
+        let mut hasher = Sha256::new();
+        let mut size = 0;
+        let mut chunks = std::pin::pin!(chunks);
+        while let Some(chunk) = chunks.next().await {
+            let chunk = chunk?;
+            size += chunk.len() as u64;
+            hasher.update(&chunk);
+            file.write_all(&chunk).await?;
+        }
+        file.flush().await?;
+        Ok(SpooledPackage {
+            path,
+            sha256: format!("{:x}", hasher.finalize()),
+            size,
+        })
+    }
+
+    /// Spools the stored bytes with hex SHA-256 `sha256` as an upload of
+    /// `filename` of `name`, for an upload naming its file by digest alone,
+    /// or `None` if no stored file has those bytes. The bytes are linked
+    /// rather than copied where the backend allows.
+    pub(crate) async fn spool_blob(
+        &self,
+        name: &PackageName,
+        filename: &DistFilename,
+        sha256: &str,
+    ) -> Result<Option<SpooledPackage>, AppError> {
+        let Some(blob) = self.backend.local_blob(sha256).await? else {
+            return Ok(None);
+        };
+        let path = self.spool_path(name, filename)?;
+        let spool = path.to_path_buf();
+        let size = tokio::task::spawn_blocking(move || {
+            std::fs::remove_file(&spool)?;
+            std::fs::hard_link(&blob, &spool)
+                .or_else(|_| std::fs::copy(&blob, &spool).map(|_| ()))?;
+            Ok::<_, std::io::Error>(std::fs::metadata(&spool)?.len())
+        })
+        .await
+        .map_err(std::io::Error::other)??;
+        Ok(Some(SpooledPackage {
+            path,
+            sha256: sha256.to_string(),
+            size,
+        }))
+    }
+
+    /// A new, empty file to spool an upload of `filename` of `name` to.
+    fn spool_path(
+        &self,
+        name: &PackageName,
+        filename: &DistFilename,
+    ) -> Result<TempPath, AppError> {
+        let key = object_key(name, filename.as_str());
+        // Above the project directory, so a refused upload of a new project
+        // leaves no directory behind.
+        let dir = match self.backend.local_path(&key) {
+            Some(path) => path
+                .parent()
+                .and_then(Path::parent)
+                .unwrap_or(&self.packages_dir)
+                .to_path_buf(),
+            None => std::env::temp_dir(),
+        };
+        // The leading dot keeps it out of listings, and the suffix lets fsck
+        // find it if the process dies before it is moved or removed.
+        let prefix = format!(".{filename}.");
+        let mut builder = tempfile::Builder::new();
+        builder.prefix(&prefix).suffix(".partial");
+        #[cfg(unix)]
+        builder.permissions(std::os::unix::fs::PermissionsExt::from_mode(0o666));
+        Ok(builder.tempfile_in(&dir)?.into_temp_path())
+    }
+
+    /// Moves a spooled upload into place as `filename` of `name`.
+    pub(crate) async fn store_spooled(
+        &self,
+        name: &PackageName,
+        filename: &DistFilename,
+        spooled: SpooledPackage,
+    ) -> Result<(), AppError> {
+        let key = object_key(name, filename.as_str());
+        self.backend
+            .store_file(&key, &spooled.path, &spooled.sha256)
+            .await?;
+        Ok(())
+    }
+
+    /// Places the local file `source` in the store as `filename`, linking
+    /// rather than copying where `mode` allows and the backend keeps files
+    /// locally. Returns how it was placed.
+    pub(crate) async fn import_package(
+        &self,
+        name: &PackageName,
+        filename: &DistFilename,
+        source: PathBuf,
+        mode: LinkMode,
+    ) -> Result<LinkMode, AppError> {
+        let key = object_key(name, filename.as_str());
+        let Some(path) = self.backend.local_path(&key) else {
+            let file = tokio::fs::File::open(&source).await?;
+            self.backend
+                .store(&key, ReaderStream::new(file).boxed())
+                .await?;
+            return Ok(LinkMode::Copy);
+        };
+        let package_dir = path.parent().unwrap_or(&self.packages_dir).to_path_buf();
+        let partial = package_dir.join(format!(".{filename}.partial"));
+        tokio::task::spawn_blocking(move || {
+            std::fs::create_dir_all(&package_dir)?;
+            match std::fs::remove_file(&partial) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+            let used = import::place(&source, &partial, mode)?;
+            std::fs::rename(&partial, &path)?;
+            Ok(used)
+        })
+        .await
+        .map_err(|e| AppError::Io(io::Error::other(e)))?
+    }
+
+    pub(crate) async fn remove_package(
+        &self,
+        name: &PackageName,
+        filename: &DistFilename,
+    ) -> Result<(), AppError> {
+        let key = object_key(name, filename.as_str());
+        with_retry("package removal", || self.backend.delete(&key)).await
+    }
+
+    /// Names in `dir`, grouped by subdirectory: stored files per project
+    /// directory, or docs versions per project.
+    async fn list_tree(dir: &Path) -> Result<BTreeMap<String, Vec<String>>, AppError> {
+        let mut tree = BTreeMap::new();
+        let mut projects = tokio::fs::read_dir(dir).await?;
+        while let Some(project) = projects.next_entry().await? {
+            if !project.file_type().await?.is_dir() {
+                continue;
+            }
+            let mut names = Vec::new();
+            let mut entries = tokio::fs::read_dir(project.path()).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                names.push(entry.file_name().to_string_lossy().into_owned());
+            }
+            names.sort();
+            tree.insert(project.file_name().to_string_lossy().into_owned(), names);
+        }
+        Ok(tree)
+    }
+
+    /// Every stored distribution file, by project directory.
+    pub(crate) async fn stored_files(&self) -> Result<BTreeMap<String, Vec<String>>, AppError> {
+        let mut files: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for object in with_retry("package listing", || self.backend.list("")).await? {
+            let Some((project, filename)) = object.key.split_once('/') else {
+                continue;
+            };
+            if !filename.ends_with(".metadata") {
+                files
+                    .entry(project.to_string())
+                    .or_default()
+                    .push(filename.to_string());
+            }
+        }
+        Ok(files)
+    }
+
+    /// Number and total size of the stored distribution files.
+    pub(crate) async fn stored_usage(&self) -> Result<(usize, u64), AppError> {
+        let objects = with_retry("package listing", || self.backend.list("")).await?;
+        let files = objects
+            .iter()
+            .filter(|object| !object.key.ends_with(".metadata"));
+        Ok(files.fold((0, 0), |(count, bytes), object| {
+            (count + 1, bytes + object.size)
+        }))
+    }
+
+    /// Total size of the stored distribution files, by project directory.
+    pub(crate) async fn stored_bytes(&self) -> Result<BTreeMap<String, u64>, AppError> {
+        let mut bytes: BTreeMap<String, u64> = BTreeMap::new();
+        for object in with_retry("package listing", || self.backend.list("")).await? {
+            if let Some((project, filename)) = object.key.split_once('/') {
+                if !filename.ends_with(".metadata") {
+                    *bytes.entry(project.to_string()).or_default() += object.size;
+                }
+            }
+        }
+        Ok(bytes)
+    }
+
+    /// Total size of one project's stored distribution files.
+    pub(crate) async fn project_bytes(&self, name: &PackageName) -> Result<u64, AppError> {
+        let prefix = format!("{name}/");
+        let objects = with_retry("package listing", || self.backend.list(&prefix)).await?;
+        Ok(objects
+            .iter()
+            .filter(|object| !object.key.ends_with(".metadata"))
+            .map(|object| object.size)
+            .sum())
+    }
+
+    /// Every unpacked docs directory, by project.
+    pub(crate) async fn stored_docs(&self) -> Result<BTreeMap<String, Vec<String>>, AppError> {
+        Self::list_tree(&self.docs_dir).await
+    }
+
+    /// Leftovers of writes interrupted before their final rename.
+    pub(crate) async fn partial_writes(&self) -> Result<Vec<PathBuf>, AppError> {
+        let mut partial = Vec::new();
+        for file in [
+            "index.json.partial",
+            "projects.json.partial",
+            "changes.jsonl.partial",
+            "changes.history.jsonl.partial",
+            "webhook-queue.json.partial",
+            "tokens.json.partial",
+            "users.json.partial",
+            "stats.json.partial",
+        ] {
+            let path = self.base_path.join(file);
+            if tokio::fs::try_exists(&path).await? {
+                partial.push(path);
+            }
+        }
+        for dir in [&self.projects_dir, &self.snapshots_dir, &self.packages_dir] {
+            let mut entries = tokio::fs::read_dir(dir).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                if entry.file_name().to_string_lossy().ends_with(".partial") {
+                    partial.push(entry.path());
+                }
+            }
+        }
+        for (project, filenames) in Self::list_tree(&self.packages_dir).await? {
+            for filename in filenames.iter().filter(|f| f.ends_with(".partial")) {
+                partial.push(self.packages_dir.join(&project).join(filename));
+            }
+        }
+        for (project, versions) in self.stored_docs().await? {
+            for version in versions.iter().filter(|v| v.ends_with(".partial")) {
+                partial.push(self.docs_dir.join(&project).join(version));
+            }
+        }
+        Ok(partial)
+    }
+
+    pub(crate) async fn remove_path(&self, path: &Path) -> Result<(), AppError> {
+        if tokio::fs::metadata(path).await?.is_dir() {
+            tokio::fs::remove_dir_all(path).await?;
+        } else {
+            tokio::fs::remove_file(path).await?;
+        }
+        Ok(())
+    }
+
+    /// Removes the stored files and docs of a project.
+    pub(crate) async fn remove_project(&self, name: &PackageName) -> Result<(), AppError> {
+        let prefix = format!("{name}/");
+        for object in self.backend.list(&prefix).await? {
+            self.backend.delete(&object.key).await?;
+        }
+        let docs = self.docs_dir.join(name.as_str());
+        if tokio::fs::try_exists(&docs).await? {
+            tokio::fs::remove_dir_all(&docs).await?;
+        }
+        Ok(())
+    }
+
+    /// Moves the stored files and docs of a project to a new name.
+    pub async fn rename_project(
+        &self,
+        from: &PackageName,
+        to: &PackageName,
+    ) -> Result<(), AppError> {
+        let prefix = format!("{from}/");
+        for object in self.backend.list(&prefix).await? {
+            let filename = &object.key[prefix.len()..];
+            self.backend
+                .rename(&object.key, &object_key(to, filename))
+                .await?;
+        }
+        let docs = self.docs_dir.join(from.as_str());
+        if tokio::fs::try_exists(&docs).await? {
+            tokio::fs::rename(&docs, self.docs_dir.join(to.as_str())).await?;
+        }
+        Ok(())
+    }
+
+    pub fn docs_dir(&self) -> &Path {
+        &self.docs_dir
+    }
+
+    /// Unpacks a zip of built HTML documentation to `docs/<name>/<version>/`,
+    /// replacing any docs previously stored for that version. Archives
+    /// unpacking to more than `max_size` bytes are refused, whatever their
+    /// entries claim, so a small archive cannot fill the disk.
+    pub async fn store_docs(
+        &self,
+        name: &PackageName,
+        version: &Version,
+        archive: std::fs::File,
+        max_size: u64,
+    ) -> Result<(), AppError> {
+        let project_dir = self.docs_dir.join(name.as_str());
+        let staging = project_dir.join(format!(".{version}.partial"));
+        let target = project_dir.join(version.as_str());
+        let too_large = format!("Docs for {name} {version} unpack to more than {max_size} bytes");
+
+        tokio::task::spawn_blocking(move || {
+            let zip = zip::ZipArchive::new(archive)
+                .map_err(|e| AppError::InvalidFormat(format!("Invalid docs archive: {e}")))?;
+            if staging.exists() {
+                std::fs::remove_dir_all(&staging)?;
+            }
+            std::fs::create_dir_all(&staging)?;
+            if let Err(e) = unpack_docs(zip, &staging, max_size, &too_large) {
+                std::fs::remove_dir_all(&staging)?;
+                return Err(e);
+            }
+            if target.exists() {
+                std::fs::remove_dir_all(&target)?;
+            }
+            std::fs::rename(&staging, &target)?;
+            Ok(())
+        })
+        .await
+        .map_err(|e| AppError::Io(std::io::Error::other(e)))?
+    }
+}
+
+/// Unpacks the entries of a docs archive into `staging`, failing with
+/// `too_large` once more than `max_size` bytes have been written.
+fn unpack_docs(
+    mut zip: zip::ZipArchive<std::fs::File>,
+    staging: &Path,
+    max_size: u64,
+    too_large: &str,
+) -> Result<(), AppError> {
+    let mut remaining = max_size;
+    for i in 0..zip.len() {
+        let entry = zip
+            .by_index(i)
+            .map_err(|e| AppError::InvalidFormat(format!("Invalid docs archive: {e}")))?;
+        // Entries escaping the target directory and symlinks, which would
+        // let the docs route serve arbitrary files, are skipped.
+        let Some(relative) = entry.enclosed_name() else {
+            continue;
+        };
+        if entry.is_symlink() {
+            continue;
+        }
+        let path = staging.join(relative);
+        if entry.is_dir() {
+            std::fs::create_dir_all(&path)?;
+        } else {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let written = std::io::copy(
+                &mut entry.take(remaining.saturating_add(1)),
+                &mut std::fs::File::create(&path)?,
+            )?;
+            remaining = remaining
+                .checked_sub(written)
+                .ok_or_else(|| AppError::Unprocessable(too_large.to_string()))?;
+        }
+    }
+    Ok(())
+}
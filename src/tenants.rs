@@ -0,0 +1,108 @@
+//! Several indexes served from one instance, chosen by the host name a
+//! request was sent to, so `ml.pkg.corp` and `web.pkg.corp` can be
+//! separate repositories behind one listener.
+
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    path::PathBuf,
+    str::FromStr,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use axum::{
+    body::Body,
+    http::{header, Request},
+    response::Response,
+    Router,
+};
+use tower::Service;
+
+use crate::AppError;
+
+/// A host name and the data directory of the index it serves.
+#[derive(Debug, Clone)]
+pub struct Tenant {
+    pub host: String,
+    pub data_dir: PathBuf,
+}
+
+impl FromStr for Tenant {
+    type Err = AppError;
+
+    /// Parses `host=data-dir`, e.g. `ml.pkg.corp=/srv/pippy/ml`.
+    fn from_str(tenant: &str) -> Result<Self, Self::Err> {
+        let (host, data_dir) = tenant
+            .split_once('=')
+            .filter(|(host, dir)| !host.is_empty() && !dir.is_empty())
+            .ok_or_else(|| AppError::InvalidFormat(format!("Expected host=dir: {tenant}")))?;
+        Ok(Self {
+            host: normalize_host(host),
+            data_dir: PathBuf::from(data_dir),
+        })
+    }
+}
+
+/// The host name without port or trailing dot, lowercased.
+fn normalize_host(host: &str) -> String {
+    let name = match host.strip_prefix('[') {
+        // A bracketed IPv6 literal, whose colons are not a port separator.
+        Some(literal) => literal.split(']').next().unwrap_or(literal),
+        None => host.split(':').next().unwrap_or(host),
+    };
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// Routes each request to the router of its host, falling back to a
+/// default for hosts without their own.
+#[derive(Clone)]
+pub struct HostRouter {
+    hosts: Arc<HashMap<String, Router>>,
+    default: Router,
+}
+
+impl HostRouter {
+    pub fn new(default: Router) -> Self {
+        Self {
+            hosts: Arc::new(HashMap::new()),
+            default,
+        }
+    }
+
+    /// Serves requests for `host` with `router`.
+    pub fn host(mut self, host: &str, router: Router) -> Self {
+        Arc::make_mut(&mut self.hosts).insert(normalize_host(host), router);
+        self
+    }
+
+    pub fn into_router(self) -> Router {
+        Router::new().fallback_service(self)
+    }
+
+    fn route(&self, request: &Request<Body>) -> &Router {
+        // HTTP/2 requests carry the host in the URI rather than a header.
+        let host = request
+            .headers()
+            .get(header::HOST)
+            .and_then(|host| host.to_str().ok())
+            .or_else(|| request.uri().authority().map(|a| a.as_str()));
+        host.and_then(|host| self.hosts.get(&normalize_host(host)))
+            .unwrap_or(&self.default)
+    }
+}
+
+impl Service<Request<Body>> for HostRouter {
+    type Response = Response;
+    type Error = Infallible;
+    type Future = <Router as Service<Request<Body>>>::Future;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        // Routers are always ready.
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        self.route(&request).clone().call(request)
+    }
+}
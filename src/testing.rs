@@ -0,0 +1,330 @@
+//! Support for integration tests of pippy and of servers embedding it: a
+//! throwaway index in a temporary directory, generated wheels, and upload
+//! requests that go through the router without a network.
+//!
+//! ```no_run
+//! # async fn demo() -> Result<(), pippy::AppError> {
+//! use pippy::testing::{SampleWheel, TestIndex};
+//!
+//! let index = TestIndex::builder()
+//!     .wheel(SampleWheel::new("demo", "1.0"))
+//!     .build()
+//!     .await?;
+//! let url = index.spawn().await?;
+//! # Ok(()) }
+//! ```
+
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use axum::{
+    body::Body,
+    http::{header, Request, StatusCode},
+    response::Response,
+    Router,
+};
+use tempfile::TempDir;
+use tower::ServiceExt;
+
+use crate::{
+    auth::TokenStore, backend::StorageBackend, quota::Quotas, retention::RetentionPolicy,
+    router_with_config, AppError, Config, OverwritePolicy, PackageIndex, PackageStorage,
+    UploadLimits,
+};
+
+/// A minimal pure-Python wheel, with the `METADATA`, `WHEEL` and `RECORD`
+/// members installers expect plus any added with [`SampleWheel::member`].
+#[derive(Debug, Clone)]
+pub struct SampleWheel {
+    name: String,
+    version: String,
+    tag: String,
+    metadata: Vec<(String, String)>,
+    members: Vec<(String, Vec<u8>)>,
+}
+
+impl SampleWheel {
+    pub fn new(name: impl Into<String>, version: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            version: version.into(),
+            tag: "py3-none-any".to_string(),
+            metadata: Vec::new(),
+            members: Vec::new(),
+        }
+    }
+
+    /// Replaces the `py3-none-any` compatibility tag.
+    pub fn tag(mut self, tag: impl Into<String>) -> Self {
+        self.tag = tag.into();
+        self
+    }
+
+    /// Adds a header to `METADATA`, e.g. `Requires-Python: >=3.9`.
+    pub fn metadata(mut self, field: impl Into<String>, value: impl Into<String>) -> Self {
+        self.metadata.push((field.into(), value.into()));
+        self
+    }
+
+    /// Adds a file to the wheel, e.g. `demo/__init__.py`.
+    pub fn member(mut self, path: impl Into<String>, contents: impl Into<Vec<u8>>) -> Self {
+        self.members.push((path.into(), contents.into()));
+        self
+    }
+
+    pub fn filename(&self) -> String {
+        format!("{}-{}-{}.whl", self.name, self.version, self.tag)
+    }
+
+    /// The wheel archive.
+    pub fn bytes(&self) -> Vec<u8> {
+        let dist_info = format!("{}-{}.dist-info", self.name, self.version);
+        let mut metadata = format!(
+            "Metadata-Version: 2.1\nName: {}\nVersion: {}\n",
+            self.name, self.version
+        );
+        for (field, value) in &self.metadata {
+            metadata.push_str(&format!("{field}: {value}\n"));
+        }
+        let wheel = format!(
+            "Wheel-Version: 1.0\nGenerator: pippy-testing\nRoot-Is-Purelib: true\nTag: {}\n",
+            self.tag
+        );
+        let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        let options = zip::write::SimpleFileOptions::default();
+        let generated = [
+            (format!("{dist_info}/METADATA"), metadata.into_bytes()),
+            (format!("{dist_info}/WHEEL"), wheel.into_bytes()),
+        ];
+        for (path, contents) in self.members.iter().chain(&generated) {
+            zip.start_file(path.as_str(), options)
+                .expect("writing to memory cannot fail");
+            zip.write_all(contents)
+                .expect("writing to memory cannot fail");
+        }
+        zip.start_file(format!("{dist_info}/RECORD"), options)
+            .expect("writing to memory cannot fail");
+        zip.finish()
+            .expect("writing to memory cannot fail")
+            .into_inner()
+    }
+
+    /// Writes the wheel into `dir` under its filename.
+    pub fn write_to(&self, dir: &Path) -> std::io::Result<PathBuf> {
+        let path = dir.join(self.filename());
+        std::fs::write(&path, self.bytes())?;
+        Ok(path)
+    }
+}
+
+const BOUNDARY: &str = "pippy-testing-boundary";
+
+/// A multipart upload as publishing clients send it: any form fields
+/// followed by the distribution files.
+#[derive(Debug, Clone, Default)]
+pub struct UploadForm {
+    fields: Vec<(String, String)>,
+    files: Vec<(String, Vec<u8>)>,
+}
+
+impl UploadForm {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a plain form field, such as `channel`.
+    pub fn field(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.fields.push((name.into(), value.into()));
+        self
+    }
+
+    pub fn wheel(self, wheel: &SampleWheel) -> Self {
+        self.file(wheel.filename(), wheel.bytes())
+    }
+
+    /// Adds a distribution file with arbitrary contents.
+    pub fn file(mut self, filename: impl Into<String>, contents: Vec<u8>) -> Self {
+        self.files.push((filename.into(), contents));
+        self
+    }
+
+    /// The encoded body, to be sent with [`UploadForm::content_type`].
+    pub fn body(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (name, value) in &self.fields {
+            body.extend_from_slice(
+                format!(
+                    "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"{name}\"\r\n\r\n{value}\r\n"
+                )
+                .as_bytes(),
+            );
+        }
+        for (filename, contents) in &self.files {
+            body.extend_from_slice(
+                format!(
+                    "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"content\"; filename=\"{filename}\"\r\nContent-Type: application/octet-stream\r\n\r\n"
+                )
+                .as_bytes(),
+            );
+            body.extend_from_slice(contents);
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(format!("--{BOUNDARY}--\r\n").as_bytes());
+        body
+    }
+
+    pub fn content_type(&self) -> String {
+        format!("multipart/form-data; boundary={BOUNDARY}")
+    }
+
+    /// A `POST` of the form to `uri`, e.g. `/upload`.
+    pub fn request(&self, uri: &str) -> Request<Body> {
+        Request::post(uri)
+            .header(header::CONTENT_TYPE, self.content_type())
+            .body(Body::from(self.body()))
+            .expect("the request is well formed")
+    }
+}
+
+/// Builds a [`TestIndex`], optionally seeded with uploaded wheels.
+#[derive(Debug, Default)]
+pub struct TestIndexBuilder {
+    config: Config,
+    limits: UploadLimits,
+    overwrite: OverwritePolicy,
+    quotas: Quotas,
+    retention: RetentionPolicy,
+    wheels: Vec<SampleWheel>,
+    backend: Option<Arc<dyn StorageBackend>>,
+}
+
+impl TestIndexBuilder {
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn limits(mut self, limits: UploadLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn overwrite(mut self, policy: OverwritePolicy) -> Self {
+        self.overwrite = policy;
+        self
+    }
+
+    pub fn quotas(mut self, quotas: Quotas) -> Self {
+        self.quotas = quotas;
+        self
+    }
+
+    pub fn retention(mut self, retention: RetentionPolicy) -> Self {
+        self.retention = retention;
+        self
+    }
+
+    /// Uploads `wheel` through the router once the index is built.
+    pub fn wheel(mut self, wheel: SampleWheel) -> Self {
+        self.wheels.push(wheel);
+        self
+    }
+
+    /// Keeps distribution files in `backend` instead of the data directory.
+    pub fn backend(mut self, backend: Arc<dyn StorageBackend>) -> Self {
+        self.backend = Some(backend);
+        self
+    }
+
+    pub async fn build(self) -> Result<TestIndex, AppError> {
+        let dir = tempfile::tempdir()?;
+        let storage = match self.backend {
+            Some(backend) => PackageStorage::with_backend(dir.path().to_path_buf(), backend)?,
+            None => PackageStorage::new(dir.path().to_path_buf())?,
+        };
+        let index = PackageIndex::with_storage(storage)
+            .await?
+            .with_limits(self.limits)
+            .with_overwrite_policy(self.overwrite)
+            .with_quotas(self.quotas)
+            .with_retention(self.retention);
+        let test_index = TestIndex {
+            dir,
+            index,
+            config: self.config,
+        };
+        for wheel in &self.wheels {
+            let response = test_index
+                .send(UploadForm::new().wheel(wheel).request("/upload"))
+                .await;
+            if response.status() != StatusCode::OK {
+                return Err(AppError::InvalidFormat(format!(
+                    "seeding {} failed with {}",
+                    wheel.filename(),
+                    response.status()
+                )));
+            }
+        }
+        Ok(test_index)
+    }
+}
+
+/// An index in a temporary directory, removed when this is dropped.
+pub struct TestIndex {
+    dir: TempDir,
+    index: PackageIndex,
+    config: Config,
+}
+
+impl TestIndex {
+    pub fn builder() -> TestIndexBuilder {
+        TestIndexBuilder::default()
+    }
+
+    /// An empty index with default settings.
+    pub async fn new() -> Result<Self, AppError> {
+        Self::builder().build().await
+    }
+
+    pub fn index(&self) -> &PackageIndex {
+        &self.index
+    }
+
+    /// The data directory.
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    pub fn router(&self) -> Router {
+        router_with_config(self.index.clone(), self.config.clone())
+    }
+
+    /// Creates an admin API token, for the endpoints only admins may use
+    /// even on an open index.
+    pub async fn admin_token(&self) -> Result<String, AppError> {
+        let tokens = TokenStore::new(self.index.storage().clone());
+        let (_, secret) = tokens.create("tests", None).await?;
+        Ok(secret)
+    }
+
+    /// Sends one request through a fresh router.
+    pub async fn send(&self, request: Request<Body>) -> Response {
+        self.router()
+            .oneshot(request)
+            .await
+            .expect("the router is infallible")
+    }
+
+    /// Serves the index on an ephemeral local port in the background,
+    /// returning its base URL.
+    pub async fn spawn(&self) -> Result<String, AppError> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let router = self.router();
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        Ok(format!("http://{addr}"))
+    }
+}
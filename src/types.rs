@@ -0,0 +1,334 @@
+//! Validated identifiers. Anything reaching the index or the filesystem
+//! goes through one of these, so handlers never join unchecked strings
+//! into paths. Project names are also normalized as PEP 503 describes, so
+//! `Foo_Bar` and `foo-bar` are the same project everywhere.
+
+use std::{borrow::Borrow, fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{pep440::ParsedVersion, AppError};
+
+macro_rules! string_newtype {
+    ($name:ident, $validate:path) => {
+        string_newtype!($name, $validate, String::from);
+    };
+    ($name:ident, $validate:path, $canonicalize:path) => {
+        #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+        #[serde(try_from = "String", into = "String")]
+        pub struct $name(String);
+
+        impl $name {
+            pub fn new(value: impl Into<String>) -> Result<Self, AppError> {
+                let value = value.into();
+                $validate(&value)?;
+                Ok(Self($canonicalize(value)))
+            }
+
+            pub fn as_str(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl TryFrom<String> for $name {
+            type Error = AppError;
+
+            fn try_from(value: String) -> Result<Self, Self::Error> {
+                Self::new(value)
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = AppError;
+
+            fn from_str(value: &str) -> Result<Self, Self::Err> {
+                Self::new(value)
+            }
+        }
+
+        impl From<$name> for String {
+            fn from(value: $name) -> Self {
+                value.0
+            }
+        }
+
+        impl AsRef<str> for $name {
+            fn as_ref(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl Borrow<str> for $name {
+            fn borrow(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl PartialEq<str> for $name {
+            fn eq(&self, other: &str) -> bool {
+                self.0 == other
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(&self.0)
+            }
+        }
+    };
+}
+
+string_newtype!(PackageName, validate_package_name, normalize_package_name);
+string_newtype!(Version, validate_version);
+string_newtype!(DistFilename, validate_filename);
+string_newtype!(SnapshotName, validate_snapshot_name);
+
+impl Version {
+    /// The PEP 440 parts of this version, or `None` for legacy versions
+    /// that do not follow it.
+    pub fn parsed(&self) -> Option<ParsedVersion> {
+        self.0.parse().ok()
+    }
+
+    /// Whether this is a PEP 440 pre-release or development release, such
+    /// as `2.0rc1` or `1.4.dev3`. Post-releases and local versions are not.
+    pub fn is_prerelease(&self) -> bool {
+        let public = self.0.split('+').next().unwrap_or_default();
+        public
+            .split(|c: char| !c.is_ascii_alphabetic())
+            .any(|word| {
+                matches!(
+                    word.to_ascii_lowercase().as_str(),
+                    "a" | "alpha" | "b" | "beta" | "c" | "rc" | "pre" | "preview" | "dev"
+                )
+            })
+    }
+
+    /// Whether this has a local version label, such as `1.0+build.7`.
+    pub fn is_local(&self) -> bool {
+        self.0.contains('+')
+    }
+
+    /// Whether this is a PEP 440 development release, such as `1.4.dev3`.
+    pub fn is_dev_release(&self) -> bool {
+        let public = self.0.split('+').next().unwrap_or_default();
+        public
+            .split(|c: char| !c.is_ascii_alphabetic())
+            .any(|word| word.eq_ignore_ascii_case("dev"))
+    }
+}
+
+/// ASCII letters and digits, with `.`, `_` and `-` permitted only between
+/// them.
+fn is_identifier(name: &str) -> bool {
+    let bytes = name.as_bytes();
+    !bytes.is_empty()
+        && bytes[0].is_ascii_alphanumeric()
+        && bytes[bytes.len() - 1].is_ascii_alphanumeric()
+        && bytes
+            .iter()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'.' | b'_' | b'-'))
+}
+
+/// Project names as allowed by PEP 508.
+fn validate_package_name(name: &str) -> Result<(), AppError> {
+    if !is_identifier(name) {
+        return Err(AppError::InvalidFormat(format!(
+            "Invalid package name: {name:?}"
+        )));
+    }
+    Ok(())
+}
+
+impl PackageName {
+    /// Whether `name` is already in the form project names are kept in.
+    pub fn is_normalized(name: &str) -> bool {
+        normalize_package_name(name.to_string()) == name
+    }
+}
+
+/// Lowercase, with each run of `-`, `_` and `.` collapsed to one `-`.
+fn normalize_package_name(name: String) -> String {
+    let mut normalized = String::with_capacity(name.len());
+    for c in name.chars() {
+        if matches!(c, '-' | '_' | '.') {
+            if !normalized.ends_with('-') {
+                normalized.push('-');
+            }
+        } else {
+            normalized.push(c.to_ascii_lowercase());
+        }
+    }
+    normalized
+}
+
+/// The characters PEP 440 versions are spelled with. Full parsing happens
+/// elsewhere; this only guarantees the value is safe to use as a path
+/// segment.
+fn validate_version(version: &str) -> Result<(), AppError> {
+    let valid = version
+        .as_bytes()
+        .first()
+        .is_some_and(u8::is_ascii_alphanumeric)
+        && version
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'.' | b'!' | b'+' | b'_' | b'-'));
+    if !valid {
+        return Err(AppError::InvalidFormat(format!(
+            "Invalid version: {version:?}"
+        )));
+    }
+    Ok(())
+}
+
+/// A single path component naming a distribution file.
+fn validate_filename(filename: &str) -> Result<(), AppError> {
+    let valid = filename
+        .as_bytes()
+        .first()
+        .is_some_and(u8::is_ascii_alphanumeric)
+        && !filename.contains(['/', '\\', '\0'])
+        && filename.len() <= 255;
+    if !valid {
+        return Err(AppError::InvalidFormat(format!(
+            "Invalid distribution filename: {filename:?}"
+        )));
+    }
+    Ok(())
+}
+
+/// Snapshot names follow the same rules as project names, e.g.
+/// `release-2024-06`.
+fn validate_snapshot_name(name: &str) -> Result<(), AppError> {
+    if !is_identifier(name) {
+        return Err(AppError::InvalidFormat(format!(
+            "Invalid snapshot name: {name:?}"
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filename::parse_dist_filename;
+
+    #[test]
+    fn package_names_are_normalized_as_pep_503_describes() {
+        for (name, normalized) in [
+            ("requests", "requests"),
+            ("Django", "django"),
+            ("Foo_Bar", "foo-bar"),
+            ("foo.bar", "foo-bar"),
+            ("foo-_.bar", "foo-bar"),
+            ("FOO__BAR--baz", "foo-bar-baz"),
+            ("zope.interface", "zope-interface"),
+            ("a", "a"),
+            ("1password", "1password"),
+        ] {
+            let parsed = PackageName::new(name).unwrap();
+            assert_eq!(parsed.as_str(), normalized, "{name}");
+            assert!(PackageName::is_normalized(normalized));
+        }
+        assert!(!PackageName::is_normalized("Foo_Bar"));
+        assert_eq!(
+            PackageName::new("Foo.Bar").unwrap(),
+            PackageName::new("foo_bar").unwrap()
+        );
+    }
+
+    #[test]
+    fn invalid_package_names_are_rejected() {
+        for name in [
+            "", "-foo", "foo-", ".foo", "foo_", "foo bar", "foo/bar", "../etc", "foo\\bar",
+            "naïve", "foo@1", "foo\0",
+        ] {
+            assert!(
+                matches!(PackageName::new(name), Err(AppError::InvalidFormat(_))),
+                "{name:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn names_deserialize_through_validation() {
+        let name: PackageName = serde_json::from_str("\"Foo_Bar\"").unwrap();
+        assert_eq!(name.as_str(), "foo-bar");
+        assert!(serde_json::from_str::<PackageName>("\"../x\"").is_err());
+        assert_eq!(serde_json::to_string(&name).unwrap(), "\"foo-bar\"");
+    }
+
+    #[test]
+    fn versions_are_kept_as_spelled_and_checked_for_path_safety() {
+        for version in [
+            "1.0",
+            "1!2.0",
+            "2.0rc1",
+            "1.0.post1",
+            "1.0.dev3",
+            "1.0+local_7-x",
+        ] {
+            assert_eq!(Version::new(version).unwrap().as_str(), version);
+        }
+        for version in ["", ".1", "1.0/..", "1.0 beta", "-1", "1.0\\"] {
+            assert!(Version::new(version).is_err(), "{version:?}");
+        }
+        let version = Version::new("2.0rc1").unwrap();
+        assert!(version.is_prerelease() && !version.is_local());
+        assert!(Version::new("1.0.dev3").unwrap().is_dev_release());
+        assert!(!Version::new("1.0.post1").unwrap().is_prerelease());
+        assert!(!Version::new("1.0+rc1").unwrap().is_prerelease());
+        assert!(Version::new("1.0+rc1").unwrap().is_local());
+    }
+
+    #[test]
+    fn dist_filenames_are_single_path_components() {
+        for filename in [
+            "demo-1.0-py3-none-any.whl",
+            "demo-1.0.tar.gz",
+            "Demo_Pkg-2.0rc1-cp311-abi3-manylinux_2_17_x86_64.whl",
+        ] {
+            assert_eq!(DistFilename::new(filename).unwrap().as_str(), filename);
+        }
+        let long = format!("{}.whl", "a".repeat(252));
+        for filename in [
+            "",
+            ".hidden.whl",
+            "../demo-1.0.tar.gz",
+            "demo/1.0.whl",
+            "demo\\1.0.whl",
+            "demo\0.whl",
+            long.as_str(),
+        ] {
+            assert!(DistFilename::new(filename).is_err(), "{filename:?}");
+        }
+    }
+
+    #[test]
+    fn dist_filenames_parse_into_normalized_names_and_versions() {
+        for (filename, name, version) in [
+            ("demo-1.0-py3-none-any.whl", "demo", "1.0"),
+            (
+                "Demo_Pkg-2.0rc1-cp311-abi3-manylinux_2_17_x86_64.whl",
+                "demo-pkg",
+                "2.0rc1",
+            ),
+            ("demo-1.0-1-py3-none-any.whl", "demo", "1.0"),
+            ("zope.interface-6.0.tar.gz", "zope-interface", "6.0"),
+            ("foo-bar-1.0.post1.tar.gz", "foo-bar", "1.0.post1"),
+        ] {
+            let filename = DistFilename::new(filename).unwrap();
+            let (parsed, parsed_version) = parse_dist_filename(filename.as_str()).unwrap();
+            assert_eq!(parsed.as_str(), name, "{filename}");
+            assert_eq!(parsed_version.as_str(), version, "{filename}");
+        }
+        for filename in [
+            "demo.whl",
+            "demo-1.0.zip",
+            "-1.0-py3-none-any.whl",
+            "demo-.tar.gz",
+        ] {
+            assert!(parse_dist_filename(filename).is_err(), "{filename}");
+        }
+    }
+}
@@ -0,0 +1,130 @@
+//! The one place links to index resources are built, so every page, API
+//! response, and redirect agrees on where things live.
+
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+
+/// Bytes escaped in a path segment: everything outside RFC 3986 `pchar`,
+/// plus `'` and `&` since links are emitted into single-quoted attributes.
+const SEGMENT: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'&')
+    .add(b'\'')
+    .add(b'/')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'[')
+    .add(b'\\')
+    .add(b']')
+    .add(b'^')
+    .add(b'`')
+    .add(b'{')
+    .add(b'|')
+    .add(b'}');
+
+#[derive(Debug, Clone, Default)]
+pub struct UrlBuilder {
+    /// Prepended to every path, without a trailing slash: empty when served
+    /// from the root, `/pypi` when a proxy mounts the index there, and
+    /// starting with a scheme and host when links are absolute.
+    prefix: String,
+}
+
+impl UrlBuilder {
+    pub fn new(path_prefix: &str) -> Self {
+        let prefix = path_prefix.trim_matches('/');
+        Self {
+            prefix: if prefix.is_empty() {
+                String::new()
+            } else {
+                format!("/{prefix}")
+            },
+        }
+    }
+
+    /// Makes every link absolute under `base_url`, e.g.
+    /// `https://pkg.example.com`, ahead of the path prefix.
+    pub fn with_base_url(self, base_url: &str) -> Self {
+        Self {
+            prefix: format!("{}{}", base_url.trim_end_matches('/'), self.prefix),
+        }
+    }
+
+    pub fn simple_index(&self) -> String {
+        format!("{}/simple/", self.prefix)
+    }
+
+    pub fn project(&self, name: &str) -> String {
+        format!("{}/simple/{}/", self.prefix, segment(name))
+    }
+
+    pub fn channel_project(&self, channel: &str, name: &str) -> String {
+        format!(
+            "{}/channels/{}/simple/{}/",
+            self.prefix,
+            segment(channel),
+            segment(name)
+        )
+    }
+
+    pub fn snapshot_index(&self, snapshot: &str) -> String {
+        format!("{}/snapshots/{}/simple/", self.prefix, segment(snapshot))
+    }
+
+    pub fn snapshot_project(&self, snapshot: &str, name: &str) -> String {
+        format!(
+            "{}/snapshots/{}/simple/{}/",
+            self.prefix,
+            segment(snapshot),
+            segment(name)
+        )
+    }
+
+    pub fn file(&self, name: &str, filename: &str) -> String {
+        format!(
+            "{}/packages/{}/{}",
+            self.prefix,
+            segment(name),
+            segment(filename)
+        )
+    }
+
+    /// The PyPI-style JSON metadata of a project.
+    pub fn project_json(&self, name: &str) -> String {
+        format!("{}/pypi/{}/json", self.prefix, segment(name))
+    }
+
+    pub fn release_json(&self, name: &str, version: &str) -> String {
+        format!(
+            "{}/pypi/{}/{}/json",
+            self.prefix,
+            segment(name),
+            segment(version)
+        )
+    }
+
+    /// The cache-busting URL of an embedded static asset.
+    pub fn asset(&self, path: &str) -> String {
+        format!(
+            "{}/static/{}",
+            self.prefix,
+            crate::assets::hashed_name(path)
+        )
+    }
+
+    pub fn docs(&self, name: &str, version: &str) -> String {
+        format!(
+            "{}/docs/{}/{}/",
+            self.prefix,
+            segment(name),
+            segment(version)
+        )
+    }
+}
+
+fn segment(value: &str) -> String {
+    utf8_percent_encode(value, SEGMENT).to_string()
+}
@@ -0,0 +1,383 @@
+//! Webhooks registered on a project, notified when it publishes. Each
+//! delivery is signed with the hook's own secret, queued durably, retried
+//! with backoff, and logged for the owner to inspect.
+
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::Sha256;
+use tokio::sync::Notify;
+use tokio_util::task::TaskTracker;
+use tracing::{info, warn};
+
+use crate::{AppError, PackageIndex, PackageName, PackageStorage};
+
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long a claimed delivery is hidden from other workers; a worker that
+/// dies mid-send has its claims retried after this.
+const DELIVERY_LEASE: chrono::Duration = chrono::Duration::seconds(60);
+/// Attempts made before a delivery is given up on; with the doubling
+/// delay, the last comes a little over an hour after the first.
+const MAX_ATTEMPTS: u32 = 8;
+const RETRY_BASE_DELAY: chrono::Duration = chrono::Duration::seconds(30);
+/// Upper bound on how long the queue goes unchecked, for deliveries
+/// queued by other processes.
+const QUEUE_POLL_INTERVAL: Duration = Duration::from_secs(5);
+/// Characters of the response body kept in the delivery log.
+const BODY_SNIPPET_LEN: usize = 256;
+/// How long after asking for wheels built from an sdist the hooks are
+/// asked again, however often it is downloaded meanwhile.
+const BUILD_REQUEST_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEvent {
+    /// A file was published to the project.
+    Release,
+    /// An sdist was downloaded, so a client found no wheel of its release
+    /// that it could install. A build farm can answer by uploading one.
+    BuildRequested,
+}
+
+impl WebhookEvent {
+    fn as_str(self) -> &'static str {
+        match self {
+            WebhookEvent::Release => "release",
+            WebhookEvent::BuildRequested => "build_requested",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Webhook {
+    pub id: String,
+    pub url: String,
+    pub events: Vec<WebhookEvent>,
+    /// Key of the `X-Pippy-Signature` HMAC, shown only when the hook is
+    /// created.
+    pub secret: String,
+    pub created: DateTime<Utc>,
+}
+
+impl Webhook {
+    /// A hook with a freshly generated id and secret.
+    pub fn new(url: String, events: Vec<WebhookEvent>) -> Result<Self, AppError> {
+        if !(url.starts_with("https://") || url.starts_with("http://")) {
+            return Err(AppError::InvalidFormat(
+                "Webhook URLs must be http(s)".into(),
+            ));
+        }
+        if events.is_empty() {
+            return Err(AppError::InvalidFormat(
+                "A webhook needs at least one event".into(),
+            ));
+        }
+        Ok(Self {
+            id: random_hex(8)?,
+            url,
+            events,
+            secret: random_hex(32)?,
+            created: Utc::now(),
+        })
+    }
+}
+
+fn random_hex(bytes: usize) -> Result<String, AppError> {
+    let mut buf = vec![0; bytes];
+    getrandom::getrandom(&mut buf).map_err(|e| AppError::Io(e.into()))?;
+    Ok(buf.iter().map(|b| format!("{b:02x}")).collect())
+}
+
+/// An event waiting to be delivered to one hook, persisted so deliveries
+/// survive restarts.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct PendingDelivery {
+    pub id: String,
+    pub hook: String,
+    pub project: PackageName,
+    pub event: WebhookEvent,
+    pub payload: Value,
+    /// Attempts made so far.
+    pub attempts: u32,
+    pub next_attempt: DateTime<Utc>,
+}
+
+/// One attempt to deliver an event to a hook, as kept in the delivery log.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Delivery {
+    /// Shared by every attempt at the same delivery.
+    pub id: String,
+    pub hook: String,
+    pub event: WebhookEvent,
+    pub attempt: u32,
+    pub time: DateTime<Utc>,
+    /// Response status, if the hook answered at all.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response_snippet: Option<String>,
+    pub duration_ms: u64,
+    /// When the next attempt is due, unless this one succeeded or was the
+    /// last.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_at: Option<DateTime<Utc>>,
+    pub payload: Value,
+}
+
+impl Delivery {
+    fn succeeded(&self) -> bool {
+        self.status
+            .is_some_and(|status| (200..300).contains(&status))
+    }
+}
+
+/// Sends queued events to hooks, retrying failures with exponential
+/// backoff. The queue lives in the data directory, so any process sharing
+/// it may deliver.
+#[derive(Debug, Clone)]
+pub(crate) struct WebhookDispatcher {
+    storage: PackageStorage,
+    client: Client,
+    wake: Arc<Notify>,
+    /// Tracks queueing, so shutdown can wait for it.
+    tasks: TaskTracker,
+    /// When builds were last requested, by project and sdist.
+    build_requests: Arc<Mutex<HashMap<(PackageName, String), Instant>>>,
+}
+
+impl WebhookDispatcher {
+    pub fn new(storage: PackageStorage, tasks: TaskTracker) -> Self {
+        Self {
+            storage,
+            client: Client::new(),
+            wake: Arc::default(),
+            tasks,
+            build_requests: Arc::default(),
+        }
+    }
+
+    /// Whether builds from `filename` may be requested now, at most once
+    /// per [`BUILD_REQUEST_INTERVAL`], noting that they were if so.
+    pub fn claim_build_request(&self, project: &PackageName, filename: &str) -> bool {
+        let now = Instant::now();
+        let mut requested = self.build_requests.lock().unwrap();
+        requested.retain(|_, at| now.duration_since(*at) < BUILD_REQUEST_INTERVAL);
+        match requested.entry((project.clone(), filename.to_string())) {
+            Entry::Occupied(_) => false,
+            Entry::Vacant(entry) => {
+                entry.insert(now);
+                true
+            }
+        }
+    }
+
+    /// Queues `event` for every hook subscribed to it, without waiting.
+    /// Callers may hold the index lock, so queueing happens in the
+    /// background.
+    pub fn dispatch(
+        &self,
+        project: &PackageName,
+        hooks: Vec<Webhook>,
+        event: WebhookEvent,
+        payload: Value,
+    ) {
+        let hooks: Vec<String> = hooks
+            .into_iter()
+            .filter(|hook| hook.events.contains(&event))
+            .map(|hook| hook.id)
+            .collect();
+        if hooks.is_empty() {
+            return;
+        }
+        let dispatcher = self.clone();
+        let project = project.clone();
+        self.tasks.spawn(async move {
+            for hook in hooks {
+                if let Err(e) = dispatcher
+                    .enqueue(&project, hook, event, payload.clone())
+                    .await
+                {
+                    warn!("Queueing a webhook delivery for {} failed: {}", project, e);
+                }
+            }
+        });
+    }
+
+    /// Adds a delivery to the queue, due now. Returns its id.
+    pub async fn enqueue(
+        &self,
+        project: &PackageName,
+        hook: String,
+        event: WebhookEvent,
+        payload: Value,
+    ) -> Result<String, AppError> {
+        let pending = PendingDelivery {
+            id: random_hex(8)?,
+            hook,
+            project: project.clone(),
+            event,
+            payload,
+            attempts: 0,
+            next_attempt: Utc::now(),
+        };
+        let id = pending.id.clone();
+        {
+            let _lock = self.storage.lock_webhook_queue().await?;
+            let mut queue = self.storage.load_webhook_queue().await?;
+            queue.push(pending);
+            self.storage.save_webhook_queue(&queue).await?;
+        }
+        self.wake.notify_one();
+        Ok(id)
+    }
+
+    /// Delivers queued events as they come due, forever.
+    pub async fn run(self, index: PackageIndex) {
+        loop {
+            let next = match self.deliver_due(&index).await {
+                Ok(next) => next,
+                Err(e) => {
+                    warn!("Processing the webhook queue failed: {}", e);
+                    None
+                }
+            };
+            let wait = next
+                .and_then(|at| (at - Utc::now()).to_std().ok())
+                .unwrap_or(QUEUE_POLL_INTERVAL)
+                .min(QUEUE_POLL_INTERVAL);
+            tokio::select! {
+                () = tokio::time::sleep(wait) => {}
+                () = self.wake.notified() => {}
+            }
+        }
+    }
+
+    /// Attempts every due delivery once, returning when the next one is due.
+    async fn deliver_due(&self, index: &PackageIndex) -> Result<Option<DateTime<Utc>>, AppError> {
+        // Claim due entries by pushing them past a lease, so other
+        // processes skip them while they are in flight, and a crash only
+        // delays them.
+        let due: Vec<PendingDelivery> = {
+            let _lock = self.storage.lock_webhook_queue().await?;
+            let mut queue = self.storage.load_webhook_queue().await?;
+            let now = Utc::now();
+            let due: Vec<PendingDelivery> = queue
+                .iter_mut()
+                .filter(|pending| pending.next_attempt <= now)
+                .map(|pending| {
+                    let claimed = pending.clone();
+                    pending.next_attempt = now + DELIVERY_LEASE;
+                    claimed
+                })
+                .collect();
+            if !due.is_empty() {
+                self.storage.save_webhook_queue(&queue).await?;
+            }
+            due
+        };
+
+        let mut outcomes = Vec::with_capacity(due.len());
+        for pending in due {
+            let hook = index
+                .project(&pending.project)
+                .await?
+                .get(pending.project.as_str())
+                .and_then(|p| p.webhooks.iter().find(|h| h.id == pending.hook).cloned());
+            let Some(hook) = hook else {
+                // The hook was removed since the event was queued.
+                outcomes.push((pending.id, None));
+                continue;
+            };
+            let delivery = self.send(&hook, &pending).await;
+            match (&delivery.status, &delivery.error) {
+                (Some(status), _) => info!("Webhook {} answered {}", hook.id, status),
+                (None, Some(e)) => warn!("Webhook {} failed: {}", hook.id, e),
+                (None, None) => {}
+            }
+            if let Err(e) = self.storage.append_delivery(&delivery).await {
+                warn!("Recording a webhook delivery failed: {}", e);
+            }
+            outcomes.push((pending.id, delivery.retry_at));
+        }
+
+        let _lock = self.storage.lock_webhook_queue().await?;
+        let mut queue = self.storage.load_webhook_queue().await?;
+        for (id, retry_at) in outcomes {
+            match retry_at {
+                Some(retry_at) => {
+                    if let Some(pending) = queue.iter_mut().find(|p| p.id == id) {
+                        pending.attempts += 1;
+                        pending.next_attempt = retry_at;
+                    }
+                }
+                None => queue.retain(|p| p.id != id),
+            }
+        }
+        self.storage.save_webhook_queue(&queue).await?;
+        Ok(queue.iter().map(|p| p.next_attempt).min())
+    }
+
+    async fn send(&self, hook: &Webhook, pending: &PendingDelivery) -> Delivery {
+        let body = serde_json::to_vec(&pending.payload).expect("payloads always serialize");
+        let mut mac = Hmac::<Sha256>::new_from_slice(hook.secret.as_bytes())
+            .expect("HMAC accepts keys of any length");
+        mac.update(&body);
+        let signature: String = mac
+            .finalize()
+            .into_bytes()
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect();
+
+        let time = Utc::now();
+        let started = Instant::now();
+        let sent = self
+            .client
+            .post(&hook.url)
+            .timeout(DELIVERY_TIMEOUT)
+            .header("content-type", "application/json")
+            .header("x-pippy-event", pending.event.as_str())
+            .header("x-pippy-delivery", &pending.id)
+            .header("x-pippy-signature", format!("sha256={signature}"))
+            .body(body)
+            .send()
+            .await;
+        let mut delivery = Delivery {
+            id: pending.id.clone(),
+            hook: hook.id.clone(),
+            event: pending.event,
+            attempt: pending.attempts + 1,
+            time,
+            status: None,
+            error: None,
+            response_snippet: None,
+            duration_ms: 0,
+            retry_at: None,
+            payload: pending.payload.clone(),
+        };
+        match sent {
+            Ok(response) => {
+                delivery.status = Some(response.status().as_u16());
+                if let Ok(text) = response.text().await {
+                    delivery.response_snippet = Some(text.chars().take(BODY_SNIPPET_LEN).collect());
+                }
+            }
+            Err(e) => delivery.error = Some(e.to_string()),
+        }
+        delivery.duration_ms = started.elapsed().as_millis() as u64;
+        if !delivery.succeeded() && delivery.attempt < MAX_ATTEMPTS {
+            delivery.retry_at = Some(Utc::now() + RETRY_BASE_DELAY * 2i32.pow(pending.attempts));
+        }
+        delivery
+    }
+}
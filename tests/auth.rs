@@ -0,0 +1,651 @@
+//! API tokens and Basic auth users guarding uploads and reads.
+
+use axum::{
+    body::Body,
+    http::{header, HeaderValue, Request, StatusCode},
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use pippy::{
+    auth::{self, Credentials, Scope, TokenStore},
+    testing::{SampleWheel, TestIndex, UploadForm},
+    Config,
+};
+
+fn upload(wheel: &SampleWheel, authorization: Option<String>) -> Request<Body> {
+    let mut request = UploadForm::new().wheel(wheel).request("/upload");
+    if let Some(value) = authorization {
+        request.headers_mut().insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_str(&value).unwrap(),
+        );
+    }
+    request
+}
+
+fn basic(username: &str, password: &str) -> Option<String> {
+    Some(format!(
+        "Basic {}",
+        STANDARD.encode(format!("{username}:{password}"))
+    ))
+}
+
+fn get(uri: &str, authorization: Option<String>) -> Request<Body> {
+    let mut request = Request::get(uri).body(Body::empty()).unwrap();
+    if let Some(value) = authorization {
+        request.headers_mut().insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_str(&value).unwrap(),
+        );
+    }
+    request
+}
+
+async fn guarded_index() -> TestIndex {
+    TestIndex::builder()
+        .config(Config {
+            require_token: true,
+            ..Config::default()
+        })
+        .build()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn uploads_need_a_token() {
+    let index = guarded_index().await;
+    let response = index
+        .send(upload(&SampleWheel::new("demo", "1.0"), None))
+        .await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert!(response.headers().contains_key(header::WWW_AUTHENTICATE));
+
+    let wrong = index
+        .send(upload(
+            &SampleWheel::new("demo", "1.0"),
+            Some("token pippy-not-a-real-token".into()),
+        ))
+        .await;
+    assert_eq!(wrong.status(), StatusCode::UNAUTHORIZED);
+
+    // Reads stay open.
+    let listing = index
+        .send(Request::get("/simple/").body(Body::empty()).unwrap())
+        .await;
+    assert_eq!(listing.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn tokens_are_accepted_in_either_form_until_revoked() {
+    let index = guarded_index().await;
+    let tokens = TokenStore::new(index.index().storage().clone());
+    let (token, secret) = tokens.create("ci", None).await.unwrap();
+
+    let response = index
+        .send(upload(
+            &SampleWheel::new("demo", "1.0"),
+            Some(format!("token {secret}")),
+        ))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // As twine sends it.
+    let basic = STANDARD.encode(format!("__token__:{secret}"));
+    let response = index
+        .send(upload(
+            &SampleWheel::new("demo", "1.1"),
+            Some(format!("Basic {basic}")),
+        ))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    tokens.revoke(&token.id).await.unwrap();
+    let response = index
+        .send(upload(
+            &SampleWheel::new("demo", "1.2"),
+            Some(format!("token {secret}")),
+        ))
+        .await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn read_and_write_users_are_kept_apart() {
+    let readers = "# mirrors\n".to_string() + &auth::hash_password("reader", "letmein").unwrap();
+    let index = TestIndex::builder()
+        .config(Config {
+            read_credentials: Some(readers.parse::<Credentials>().unwrap()),
+            write_credentials: Some(Credentials::default().user("writer", "hunter2")),
+            ..Config::default()
+        })
+        .build()
+        .await
+        .unwrap();
+    let response = index
+        .send(upload(
+            &SampleWheel::new("demo", "1.0"),
+            basic("writer", "hunter2"),
+        ))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    for uri in [
+        "/simple/",
+        "/simple/demo/",
+        "/packages/demo/demo-1.0-py3-none-any.whl",
+    ] {
+        let response = index.send(get(uri, None)).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "{uri}");
+        let response = index.send(get(uri, basic("reader", "letmein"))).await;
+        assert_eq!(response.status(), StatusCode::OK, "{uri}");
+    }
+    let wrong = index.send(get("/simple/", basic("reader", "guess"))).await;
+    assert_eq!(wrong.status(), StatusCode::UNAUTHORIZED);
+
+    // Writers may read, but readers may not write.
+    let response = index
+        .send(get("/simple/", basic("writer", "hunter2")))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = index
+        .send(upload(
+            &SampleWheel::new("demo", "1.1"),
+            basic("reader", "letmein"),
+        ))
+        .await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn read_users_alone_keep_anonymous_clients_from_writing() {
+    let index = TestIndex::builder()
+        .config(Config {
+            read_credentials: Some(Credentials::default().user("reader", "letmein")),
+            ..Config::default()
+        })
+        .build()
+        .await
+        .unwrap();
+    let response = index
+        .send(upload(&SampleWheel::new("demo", "1.0"), None))
+        .await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = index
+        .send(upload(
+            &SampleWheel::new("demo", "1.0"),
+            basic("reader", "letmein"),
+        ))
+        .await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let admin = index.admin_token().await.unwrap();
+    let response = index
+        .send(with_token(
+            upload(&SampleWheel::new("demo", "1.0"), None),
+            &admin,
+        ))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = index
+        .send(json_request(
+            "PATCH",
+            "/api/v1/projects/demo",
+            r#"{"summary": "taken over"}"#,
+        ))
+        .await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[test]
+fn malformed_user_files_are_rejected() {
+    assert!("reader:not-a-digest".parse::<Credentials>().is_err());
+    assert!("just-a-name".parse::<Credentials>().is_err());
+    assert!("reader:$pbkdf2-sha256$0$c2FsdA$aGFzaA"
+        .parse::<Credentials>()
+        .is_err());
+    // Unsalted digests are refused rather than trusted.
+    let legacy = format!(
+        "reader:{:x}",
+        <sha2::Sha256 as sha2::Digest>::digest(b"letmein")
+    );
+    let error = legacy.parse::<Credentials>().unwrap_err();
+    assert!(error.to_string().contains("hash-password"), "{error}");
+}
+
+fn with_token(mut request: Request<Body>, secret: &str) -> Request<Body> {
+    request.headers_mut().insert(
+        header::AUTHORIZATION,
+        HeaderValue::from_str(&format!("token {secret}")).unwrap(),
+    );
+    request
+}
+
+fn json_request(method: &str, uri: &str, body: &str) -> Request<Body> {
+    Request::builder()
+        .method(method)
+        .uri(uri)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+#[tokio::test]
+async fn projects_can_only_be_changed_by_their_owners() {
+    let index = guarded_index().await;
+    let tokens = TokenStore::new(index.index().storage().clone());
+    let (_, admin) = tokens.create("admin", None).await.unwrap();
+    let (_, alice) = tokens.create_for("alice", "laptop", None).await.unwrap();
+    let (_, bob) = tokens.create_for("bob", "laptop", None).await.unwrap();
+
+    // The first upload claims the name.
+    let response = index
+        .send(upload(
+            &SampleWheel::new("demo", "1.0"),
+            Some(format!("token {alice}")),
+        ))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let owners = index.send(get("/api/v1/projects/demo/owners", None)).await;
+    let owners = axum::body::to_bytes(owners.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    assert_eq!(&owners[..], br#"{"owners":["alice"]}"#);
+
+    let file = "/api/v1/projects/demo/files/demo-1.0-py3-none-any.whl";
+    let yank = r#"{"yanked": true}"#;
+    let response = index
+        .send(upload(
+            &SampleWheel::new("demo", "1.1"),
+            Some(format!("token {bob}")),
+        ))
+        .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = index
+        .send(with_token(json_request("PATCH", file, yank), &bob))
+        .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = index
+        .send(with_token(json_request("DELETE", file, ""), &bob))
+        .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = index
+        .send(with_token(json_request("PATCH", file, yank), &alice))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Only admins hand projects to others.
+    let share = r#"{"owners": ["alice", "bob"]}"#;
+    let response = index
+        .send(with_token(
+            json_request("PUT", "/api/v1/projects/demo/owners", share),
+            &alice,
+        ))
+        .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = index
+        .send(with_token(
+            json_request("PUT", "/api/v1/projects/demo/owners", share),
+            &admin,
+        ))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = index
+        .send(upload(
+            &SampleWheel::new("demo", "1.1"),
+            Some(format!("token {bob}")),
+        ))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn owners_are_only_set_by_admins_even_on_an_open_index() {
+    let index = TestIndex::builder()
+        .wheel(SampleWheel::new("demo", "1.0"))
+        .build()
+        .await
+        .unwrap();
+    let takeover = r#"{"owners": ["mallory"]}"#;
+    let response = index
+        .send(json_request(
+            "PUT",
+            "/api/v1/projects/demo/owners",
+            takeover,
+        ))
+        .await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let owners = index.send(get("/api/v1/projects/demo/owners", None)).await;
+    let owners = axum::body::to_bytes(owners.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    assert_eq!(&owners[..], br#"{"owners":[]}"#);
+}
+
+async fn body_json(response: axum::response::Response) -> serde_json::Value {
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    serde_json::from_slice(&bytes).unwrap()
+}
+
+#[tokio::test]
+async fn admins_manage_users_and_scoped_tokens() {
+    let index = guarded_index().await;
+    let (_, admin) = TokenStore::new(index.index().storage().clone())
+        .create("admin", None)
+        .await
+        .unwrap();
+
+    let response = index.send(get("/api/v1/tokens", None)).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let carol = r#"{"name": "carol"}"#;
+    let response = index
+        .send(with_token(
+            json_request("POST", "/api/v1/users", carol),
+            &admin,
+        ))
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let response = index
+        .send(with_token(
+            json_request("POST", "/api/v1/users", carol),
+            &admin,
+        ))
+        .await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+
+    let unknown = r#"{"name": "ci", "user": "nobody"}"#;
+    let response = index
+        .send(with_token(
+            json_request("POST", "/api/v1/tokens", unknown),
+            &admin,
+        ))
+        .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let scoped = r#"{"name": "ci", "user": "carol", "projects": ["demo"]}"#;
+    let response = index
+        .send(with_token(
+            json_request("POST", "/api/v1/tokens", scoped),
+            &admin,
+        ))
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let issued = body_json(response).await;
+    let secret = issued["secret"].as_str().unwrap().to_string();
+    let id = issued["id"].as_str().unwrap().to_string();
+
+    let response = index
+        .send(upload(
+            &SampleWheel::new("demo", "1.0"),
+            Some(format!("token {secret}")),
+        ))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = index
+        .send(upload(
+            &SampleWheel::new("other", "1.0"),
+            Some(format!("token {secret}")),
+        ))
+        .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = index
+        .send(with_token(get("/api/v1/tokens", None), &secret))
+        .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let response = index
+        .send(with_token(get("/api/v1/tokens?user=carol", None), &admin))
+        .await;
+    let listed = body_json(response).await;
+    assert_eq!(listed.as_array().unwrap().len(), 1);
+    assert_eq!(listed[0]["projects"][0], "demo");
+    assert!(listed[0]["last_used"].is_string());
+    assert!(listed[0].get("sha256").is_none());
+
+    let revoke = format!("/api/v1/tokens/{id}");
+    let response = index
+        .send(with_token(json_request("DELETE", &revoke, ""), &admin))
+        .await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let response = index
+        .send(upload(
+            &SampleWheel::new("demo", "1.1"),
+            Some(format!("token {secret}")),
+        ))
+        .await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn admin_endpoints_need_an_admin_even_on_an_open_index() {
+    let index = TestIndex::new().await.unwrap();
+    let tokens = TokenStore::new(index.index().storage().clone());
+    let (_, admin) = tokens.create("admin", None).await.unwrap();
+    let (_, alice) = tokens.create_for("alice", "laptop", None).await.unwrap();
+
+    let response = index.send(get("/api/v1/admin/fsck", None)).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = index
+        .send(with_token(get("/api/v1/admin/fsck", None), &alice))
+        .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = index
+        .send(with_token(get("/api/v1/admin/fsck", None), &admin))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(body_json(response).await["problems"].is_array());
+
+    let response = index.send(get("/api/v1/admin/capacity", None)).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = index
+        .send(with_token(get("/api/v1/admin/capacity", None), &alice))
+        .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = index
+        .send(with_token(get("/api/v1/admin/capacity", None), &admin))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Snapshots keep files of every project, so only admins take them.
+    let snapshot = || json_request("POST", "/api/v1/snapshots/release-1", "");
+    let response = index.send(snapshot()).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = index.send(with_token(snapshot(), &alice)).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = index.send(with_token(snapshot(), &admin)).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    // Manifests stay open, but rehashing reads every stored file.
+    let response = index.send(get("/api/v1/manifest", None)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    for uri in [
+        "/api/v1/manifest?rehash=true",
+        "/api/v1/diff?from=release-1&rehash=true",
+    ] {
+        let response = index.send(get(uri, None)).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "{uri}");
+        let response = index.send(with_token(get(uri, None), &alice)).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN, "{uri}");
+        let response = index.send(with_token(get(uri, None), &admin)).await;
+        assert_eq!(response.status(), StatusCode::OK, "{uri}");
+    }
+}
+
+#[tokio::test]
+async fn scoped_admin_tokens_do_only_what_they_are_scoped_to() {
+    let index = guarded_index().await;
+    let tokens = TokenStore::new(index.index().storage().clone());
+    let (_, alice) = tokens.create_for("alice", "laptop", None).await.unwrap();
+    let (_, yank) = tokens
+        .create_scoped("yank-bot", vec![Scope::Yank], None)
+        .await
+        .unwrap();
+    let (_, stats) = tokens
+        .create_scoped("dashboard", vec![Scope::StatsRead], None)
+        .await
+        .unwrap();
+    let (_, token_admin) = tokens
+        .create_scoped("onboarding", vec![Scope::TokenAdmin], None)
+        .await
+        .unwrap();
+    let response = index
+        .send(upload(
+            &SampleWheel::new("demo", "1.0"),
+            Some(format!("token {alice}")),
+        ))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // The yank bot yanks any project's files, but publishes nothing.
+    let file = "/api/v1/projects/demo/files/demo-1.0-py3-none-any.whl";
+    let response = index
+        .send(with_token(
+            json_request("PATCH", file, r#"{"yanked": true}"#),
+            &yank,
+        ))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = index
+        .send(upload(
+            &SampleWheel::new("demo", "1.1"),
+            Some(format!("token {yank}")),
+        ))
+        .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = index
+        .send(with_token(json_request("DELETE", file, ""), &yank))
+        .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = index
+        .send(with_token(get("/simple/demo/", None), &yank))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // The dashboard reads capacity, and nothing else admins see.
+    let response = index
+        .send(with_token(get("/api/v1/admin/capacity", None), &stats))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = index
+        .send(with_token(get("/api/v1/admin/fsck", None), &stats))
+        .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = index
+        .send(with_token(get("/api/v1/tokens", None), &stats))
+        .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    // Onboarding creates users and their tokens, never admin tokens.
+    let response = index
+        .send(with_token(
+            json_request("POST", "/api/v1/users", r#"{"name": "carol"}"#),
+            &token_admin,
+        ))
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let response = index
+        .send(with_token(
+            json_request(
+                "POST",
+                "/api/v1/tokens",
+                r#"{"name": "ci", "user": "carol"}"#,
+            ),
+            &token_admin,
+        ))
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    for new in [
+        r#"{"name": "root"}"#,
+        r#"{"name": "more", "scopes": ["token-admin"]}"#,
+    ] {
+        let response = index
+            .send(with_token(
+                json_request("POST", "/api/v1/tokens", new),
+                &token_admin,
+            ))
+            .await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+    let response = index
+        .send(with_token(get("/api/v1/tokens", None), &token_admin))
+        .await;
+    let listed = body_json(response).await;
+    let listed = listed.as_array().unwrap();
+    assert!(listed
+        .iter()
+        .any(|t| t["name"] == "yank-bot" && t["scopes"][0] == "yank"));
+    let stats_id = listed.iter().find(|t| t["name"] == "dashboard").unwrap()["id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    let response = index
+        .send(with_token(
+            json_request("DELETE", &format!("/api/v1/tokens/{stats_id}"), ""),
+            &token_admin,
+        ))
+        .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = index
+        .send(with_token(
+            json_request(
+                "PUT",
+                "/api/v1/projects/demo/owners",
+                r#"{"owners": ["carol"]}"#,
+            ),
+            &token_admin,
+        ))
+        .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn yanked_downloads_can_be_refused_unless_forced() {
+    let index = TestIndex::builder()
+        .config(Config {
+            refuse_yanked_downloads: true,
+            ..Config::default()
+        })
+        .wheel(SampleWheel::new("demo", "1.0"))
+        .wheel(SampleWheel::new("demo", "1.1"))
+        .build()
+        .await
+        .unwrap();
+    let tokens = TokenStore::new(index.index().storage().clone());
+    let (_, alice) = tokens.create_for("alice", "laptop", None).await.unwrap();
+    let (_, yank) = tokens
+        .create_scoped("yank-bot", vec![Scope::Yank], None)
+        .await
+        .unwrap();
+    let response = index
+        .send(json_request(
+            "PATCH",
+            "/api/v1/projects/demo/files/demo-1.0-py3-none-any.whl",
+            r#"{"yanked": true, "yanked_reason": "miscompiled"}"#,
+        ))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let file = "/packages/demo/demo-1.0-py3-none-any.whl";
+    let response = index.send(get(file, None)).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let explanation = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let explanation = String::from_utf8_lossy(&explanation);
+    assert!(explanation.contains("miscompiled"), "{explanation}");
+    assert!(explanation.contains("force=true"), "{explanation}");
+    let response = index
+        .send(get("/packages/demo/demo-1.1-py3-none-any.whl", None))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Forcing takes someone who may yank the file.
+    let forced = format!("{file}?force=true");
+    let response = index.send(get(&forced, None)).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = index.send(with_token(get(&forced, None), &alice)).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = index.send(with_token(get(&forced, None), &yank)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
@@ -0,0 +1,90 @@
+//! Offline bundles downloaded from the API.
+
+use axum::{
+    body::Body,
+    http::{header, Request, StatusCode},
+    response::Response,
+};
+use pippy::{
+    bundle::BundleLimits,
+    testing::{SampleWheel, TestIndex},
+    Config,
+};
+
+async fn demo_index(limits: BundleLimits) -> TestIndex {
+    TestIndex::builder()
+        .config(Config {
+            bundle_limits: limits,
+            ..Config::default()
+        })
+        .wheel(SampleWheel::new("alpha", "1.0"))
+        .wheel(SampleWheel::new("beta", "1.0"))
+        .build()
+        .await
+        .unwrap()
+}
+
+async fn bundle(index: &TestIndex, uri: &str) -> Response {
+    let admin = index.admin_token().await.unwrap();
+    index
+        .send(
+            Request::get(uri)
+                .header(header::AUTHORIZATION, format!("token {admin}"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+}
+
+#[tokio::test]
+async fn bundles_are_built_for_identified_callers_only() {
+    let index = demo_index(Config::default().bundle_limits).await;
+    let anonymous = index
+        .send(Request::get("/api/v1/bundle").body(Body::empty()).unwrap())
+        .await;
+    assert_eq!(anonymous.status(), StatusCode::UNAUTHORIZED);
+
+    let response = bundle(&index, "/api/v1/bundle").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let tar = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let mut archive = tar::Archive::new(tar.as_ref());
+    let mut paths: Vec<String> = archive
+        .entries()
+        .unwrap()
+        .map(|entry| entry.unwrap().path().unwrap().display().to_string())
+        .collect();
+    paths.sort();
+    assert_eq!(
+        paths,
+        [
+            "pippy-bundle/packages/alpha-1.0-py3-none-any.whl",
+            "pippy-bundle/packages/beta-1.0-py3-none-any.whl",
+            "pippy-bundle/simple/alpha/index.html",
+            "pippy-bundle/simple/beta/index.html",
+            "pippy-bundle/simple/index.html",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn bundles_over_the_limits_are_refused() {
+    let index = demo_index(BundleLimits {
+        max_projects: Some(1),
+        max_bytes: None,
+    })
+    .await;
+    let response = bundle(&index, "/api/v1/bundle").await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = bundle(&index, "/api/v1/bundle?pins=alpha==1.0").await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let index = demo_index(BundleLimits {
+        max_projects: None,
+        max_bytes: Some(100),
+    })
+    .await;
+    let response = bundle(&index, "/api/v1/bundle?pins=alpha==1.0").await;
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+}
@@ -0,0 +1,109 @@
+//! Capturing read traffic to a file and replaying it against another
+//! instance.
+
+use std::{path::Path, time::Duration};
+
+use axum::{
+    body::Body,
+    http::{header, Request},
+};
+use pippy::{
+    capture::{self, Capture, CaptureRecord, ReplayOptions},
+    testing::{SampleWheel, TestIndex, UploadForm},
+    Config,
+};
+
+/// The records in `path`, once there are `count` of them.
+async fn captured(path: &Path, count: usize) -> Vec<CaptureRecord> {
+    for _ in 0..100 {
+        let content = std::fs::read_to_string(path).unwrap_or_default();
+        let records: Vec<CaptureRecord> = content
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        if records.len() >= count {
+            return records;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("fewer than {count} requests were captured");
+}
+
+#[tokio::test]
+async fn captured_reads_replay_against_another_instance() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("capture.jsonl");
+    let wheel = SampleWheel::new("demo", "1.0");
+    let index = TestIndex::builder()
+        .config(Config {
+            capture: Some(Capture::create(&path).await.unwrap()),
+            ..Config::default()
+        })
+        .wheel(wheel.clone())
+        .build()
+        .await
+        .unwrap();
+
+    let reads = [
+        "/simple/",
+        "/simple/demo/",
+        "/simple/missing/",
+        &format!("/packages/demo/{}", wheel.filename()),
+        "/project/demo/latest?python=3.11",
+    ];
+    for uri in reads {
+        let request = Request::get(uri)
+            .header(header::USER_AGENT, "pip/24.0")
+            .header(header::AUTHORIZATION, "Basic c2VjcmV0")
+            .body(Body::empty())
+            .unwrap();
+        index.send(request).await;
+    }
+    // Neither uploads nor the admin API are read traffic.
+    index
+        .send(
+            UploadForm::new()
+                .wheel(&SampleWheel::new("other", "1.0"))
+                .request("/upload"),
+        )
+        .await;
+    index
+        .send(Request::get("/api/v1/stats").body(Body::empty()).unwrap())
+        .await;
+
+    let records = captured(&path, reads.len()).await;
+    let uris: Vec<_> = records.iter().map(|record| record.uri.as_str()).collect();
+    assert_eq!(uris, reads);
+    assert!(records.iter().all(|record| record.method == "GET"));
+    assert_eq!(records[2].status, 404);
+    assert_eq!(records[4].status, 302);
+    let content = std::fs::read_to_string(&path).unwrap();
+    assert!(!content.contains("pip/24.0") && !content.contains("c2VjcmV0"));
+
+    // An instance holding the same files answers alike.
+    let replica = TestIndex::builder().wheel(wheel).build().await.unwrap();
+    let report = capture::replay(ReplayOptions {
+        capture: path.clone(),
+        target: replica.spawn().await.unwrap(),
+        speed: 0.0,
+    })
+    .await
+    .unwrap();
+    assert_eq!(
+        (report.requests, report.errors, report.mismatches),
+        (5, 0, 0),
+        "{report}"
+    );
+
+    // One that is missing them does not.
+    let empty = TestIndex::new().await.unwrap();
+    let report = capture::replay(ReplayOptions {
+        capture: path,
+        target: empty.spawn().await.unwrap(),
+        speed: 0.0,
+    })
+    .await
+    .unwrap();
+    assert_eq!(report.errors, 0);
+    assert_eq!(report.mismatches, 3, "{report}");
+}
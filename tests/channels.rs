@@ -0,0 +1,101 @@
+//! Release channels: what each `/channels/<channel>/simple/` view lists,
+//! by the channel a release was tagged with or its version implies.
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+};
+use pippy::testing::{SampleWheel, TestIndex, UploadForm};
+
+async fn get(index: &TestIndex, uri: &str) -> (StatusCode, String) {
+    let response = index
+        .send(Request::get(uri).body(Body::empty()).unwrap())
+        .await;
+    let status = response.status();
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    (status, String::from_utf8(bytes.to_vec()).unwrap())
+}
+
+async fn upload(index: &TestIndex, form: UploadForm, uri: &str) {
+    let response = index.send(form.request(uri)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn channels_list_their_releases_and_more_stable_ones() {
+    let index = TestIndex::builder()
+        .wheel(SampleWheel::new("demo", "1.0"))
+        .wheel(SampleWheel::new("demo", "2.0b1"))
+        .wheel(SampleWheel::new("demo", "3.0.dev1"))
+        .wheel(SampleWheel::new("edge", "0.1.dev4"))
+        .build()
+        .await
+        .unwrap();
+    // Tagged explicitly, by query parameter or by a field before the file.
+    let tagged = SampleWheel::new("demo", "1.1");
+    upload(
+        &index,
+        UploadForm::new().wheel(&tagged),
+        "/upload?channel=nightly",
+    )
+    .await;
+    let form = UploadForm::new()
+        .field("channel", "beta")
+        .wheel(&SampleWheel::new("demo", "1.2"));
+    upload(&index, form, "/upload?channel=nightly").await;
+
+    let cases = [
+        (
+            "stable",
+            vec!["1.0"],
+            vec!["1.1", "1.2", "2.0b1", "3.0.dev1"],
+        ),
+        ("beta", vec!["1.0", "1.2", "2.0b1"], vec!["1.1", "3.0.dev1"]),
+        (
+            "nightly",
+            vec!["1.0", "1.1", "1.2", "2.0b1", "3.0.dev1"],
+            vec![],
+        ),
+    ];
+    for (channel, listed, unlisted) in cases {
+        let (status, page) = get(&index, &format!("/channels/{channel}/simple/demo/")).await;
+        assert_eq!(status, StatusCode::OK);
+        for version in listed {
+            assert!(
+                page.contains(&format!(">demo-{version}-")),
+                "{channel}: {page}"
+            );
+        }
+        for version in unlisted {
+            assert!(
+                !page.contains(&format!(">demo-{version}-")),
+                "{channel}: {page}"
+            );
+        }
+    }
+
+    // Projects with nothing in a channel are left out of its listing.
+    let (_, stable) = get(&index, "/channels/stable/simple/").await;
+    assert!(
+        stable.contains(">demo<") && !stable.contains(">edge<"),
+        "{stable}"
+    );
+    let (_, nightly) = get(&index, "/channels/nightly/simple/").await;
+    assert!(
+        nightly.contains(">demo<") && nightly.contains(">edge<"),
+        "{nightly}"
+    );
+
+    let (status, _) = get(&index, "/channels/canary/simple/demo/").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let response = index
+        .send(
+            UploadForm::new()
+                .wheel(&SampleWheel::new("demo", "1.3"))
+                .request("/upload?channel=canary"),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
@@ -0,0 +1,316 @@
+//! The object store backends, against stand-ins for the Google Cloud
+//! Storage and Azure Blob Storage REST APIs.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, Request, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use futures_util::StreamExt;
+use pippy::{
+    backend::{open_backend, StorageBackend},
+    cloud::{AzureBackend, GcsBackend},
+    testing::{SampleWheel, TestIndex},
+};
+use serde_json::json;
+use tokio::io::AsyncReadExt;
+
+type Objects = Arc<Mutex<BTreeMap<String, Bytes>>>;
+
+/// Serves `router` on a free local port, returning its base URL.
+async fn serve(router: Router) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, router).await });
+    format!("http://{address}")
+}
+
+/// One object per page, so listings have to follow the pages.
+fn page(objects: &Objects, prefix: &str, after: Option<&str>) -> (Vec<(String, usize)>, bool) {
+    let objects = objects.lock().unwrap();
+    let mut matching = objects
+        .iter()
+        .filter(|(name, _)| name.starts_with(prefix))
+        .filter(|(name, _)| after.is_none_or(|after| name.as_str() > after));
+    let listed = matching
+        .next()
+        .map(|(name, contents)| (name.clone(), contents.len()));
+    let more = matching.next().is_some();
+    (listed.into_iter().collect(), more)
+}
+
+async fn fake_gcs(objects: Objects) -> String {
+    async fn upload(
+        State(objects): State<Objects>,
+        Query(query): Query<HashMap<String, String>>,
+        body: Bytes,
+    ) -> Json<serde_json::Value> {
+        let name = query["name"].clone();
+        let size = body.len();
+        objects.lock().unwrap().insert(name.clone(), body);
+        Json(json!({ "name": name, "size": size.to_string() }))
+    }
+    async fn list(
+        State(objects): State<Objects>,
+        Query(query): Query<HashMap<String, String>>,
+    ) -> Json<serde_json::Value> {
+        let after = query.get("pageToken").map(String::as_str);
+        let (listed, more) = page(&objects, &query["prefix"], after);
+        let items: Vec<_> = listed
+            .iter()
+            .map(|(name, size)| {
+                json!({ "name": name, "size": size.to_string(), "updated": "2024-05-01T12:00:00.000Z" })
+            })
+            .collect();
+        let next = listed.last().filter(|_| more).map(|(name, _)| name);
+        Json(json!({ "items": items, "nextPageToken": next }))
+    }
+    async fn object(
+        State(objects): State<Objects>,
+        Path((_, name)): Path<(String, String)>,
+        Query(query): Query<HashMap<String, String>>,
+    ) -> Response {
+        match objects.lock().unwrap().get(&name) {
+            Some(contents) if query.get("alt").map(String::as_str) == Some("media") => {
+                contents.clone().into_response()
+            }
+            Some(contents) => {
+                Json(json!({ "name": name, "size": contents.len().to_string() })).into_response()
+            }
+            None => StatusCode::NOT_FOUND.into_response(),
+        }
+    }
+    async fn delete(
+        State(objects): State<Objects>,
+        Path((_, name)): Path<(String, String)>,
+    ) -> StatusCode {
+        match objects.lock().unwrap().remove(&name) {
+            Some(_) => StatusCode::NO_CONTENT,
+            None => StatusCode::NOT_FOUND,
+        }
+    }
+    async fn authorize(request: Request<Body>, next: Next) -> Response {
+        match request.headers().get(header::AUTHORIZATION) {
+            Some(value) if value == "Bearer gcs-token" => next.run(request).await,
+            _ => StatusCode::UNAUTHORIZED.into_response(),
+        }
+    }
+    serve(
+        Router::new()
+            .route("/upload/storage/v1/b/:bucket/o", post(upload))
+            .route("/storage/v1/b/:bucket/o", get(list))
+            .route("/storage/v1/b/:bucket/o/:name", get(object).delete(delete))
+            .layer(middleware::from_fn(authorize))
+            .with_state(objects),
+    )
+    .await
+}
+
+async fn fake_azure(objects: Objects) -> String {
+    async fn put(
+        State(objects): State<Objects>,
+        Path((_, _, blob)): Path<(String, String, String)>,
+        headers: HeaderMap,
+        body: Bytes,
+    ) -> StatusCode {
+        if headers.get("x-ms-blob-type").is_none() || headers.get(header::CONTENT_LENGTH).is_none()
+        {
+            return StatusCode::BAD_REQUEST;
+        }
+        objects.lock().unwrap().insert(blob, body);
+        StatusCode::CREATED
+    }
+    async fn list(
+        State(objects): State<Objects>,
+        Query(query): Query<HashMap<String, String>>,
+    ) -> Response {
+        if query.get("comp").map(String::as_str) != Some("list") {
+            return StatusCode::BAD_REQUEST.into_response();
+        }
+        let after = query.get("marker").map(String::as_str);
+        let (listed, more) = page(&objects, &query["prefix"], after);
+        let blobs: String = listed
+            .iter()
+            .map(|(name, size)| {
+                format!(
+                    "<Blob><Name>{name}</Name><Properties><Last-Modified>Wed, 01 May 2024 12:00:00 GMT</Last-Modified><Content-Length>{size}</Content-Length></Properties></Blob>"
+                )
+            })
+            .collect();
+        let next = listed
+            .last()
+            .filter(|_| more)
+            .map_or(String::new(), |(name, _)| name.clone());
+        format!(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?><EnumerationResults><Blobs>{blobs}</Blobs><NextMarker>{next}</NextMarker></EnumerationResults>"
+        )
+        .into_response()
+    }
+    async fn blob(
+        State(objects): State<Objects>,
+        Path((_, _, blob)): Path<(String, String, String)>,
+    ) -> Response {
+        match objects.lock().unwrap().get(&blob) {
+            Some(contents) => contents.clone().into_response(),
+            None => StatusCode::NOT_FOUND.into_response(),
+        }
+    }
+    async fn delete(
+        State(objects): State<Objects>,
+        Path((_, _, blob)): Path<(String, String, String)>,
+    ) -> StatusCode {
+        match objects.lock().unwrap().remove(&blob) {
+            Some(_) => StatusCode::ACCEPTED,
+            None => StatusCode::NOT_FOUND,
+        }
+    }
+    async fn authorize(request: Request<Body>, next: Next) -> Response {
+        let signed = request
+            .uri()
+            .query()
+            .is_some_and(|query| query.split('&').any(|pair| pair == "sig=azure-sas"));
+        match signed && request.headers().contains_key("x-ms-version") {
+            true => next.run(request).await,
+            false => StatusCode::FORBIDDEN.into_response(),
+        }
+    }
+    let base = serve(
+        Router::new()
+            .route("/:account/:container", get(list))
+            .route(
+                "/:account/:container/*blob",
+                get(blob).put(put).delete(delete),
+            )
+            .layer(middleware::from_fn(authorize))
+            .with_state(objects),
+    )
+    .await;
+    format!("{base}/account")
+}
+
+async fn read(backend: &dyn StorageBackend, key: &str) -> Option<Vec<u8>> {
+    let (mut reader, size) = backend.open(key).await.unwrap()?;
+    let mut contents = Vec::new();
+    reader.read_to_end(&mut contents).await.unwrap();
+    assert_eq!(contents.len() as u64, size);
+    Some(contents)
+}
+
+/// Stores, lists, reads and deletes objects under `mirror/`, leaving alone
+/// the object `objects` holds outside it.
+async fn exercise(backend: &dyn StorageBackend, objects: &Objects) {
+    objects
+        .lock()
+        .unwrap()
+        .insert("elsewhere/demo/x.whl".into(), Bytes::from_static(b"x"));
+    assert!(!backend.exists("demo/a.whl").await.unwrap());
+    assert!(read(backend, "demo/a.whl").await.is_none());
+
+    for key in ["demo/a.whl", "demo/b.whl", "other/c.whl"] {
+        let chunks = futures_util::stream::iter([Ok(Bytes::from(key.to_string()))]).boxed();
+        assert_eq!(backend.store(key, chunks).await.unwrap(), key.len() as u64);
+    }
+    assert!(objects.lock().unwrap().contains_key("mirror/demo/a.whl"));
+    assert!(backend.exists("demo/a.whl").await.unwrap());
+    assert_eq!(read(backend, "demo/b.whl").await.unwrap(), b"demo/b.whl");
+
+    let listed = backend.list("demo/").await.unwrap();
+    let keys: Vec<_> = listed.iter().map(|object| object.key.as_str()).collect();
+    assert_eq!(keys, ["demo/a.whl", "demo/b.whl"]);
+    assert_eq!(listed[0].size, 10);
+
+    backend.delete("demo/a.whl").await.unwrap();
+    backend.delete("demo/a.whl").await.unwrap();
+    let keys: Vec<_> = backend
+        .list("")
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|object| object.key)
+        .collect();
+    assert_eq!(keys, ["demo/b.whl", "other/c.whl"]);
+}
+
+#[tokio::test]
+async fn objects_are_kept_in_a_gcs_bucket() {
+    let objects = Objects::default();
+    let endpoint = fake_gcs(objects.clone()).await;
+    let backend = GcsBackend::new("bucket", "mirror")
+        .with_token("gcs-token".into())
+        .with_endpoint(&endpoint);
+    exercise(&backend, &objects).await;
+
+    let unauthorized = GcsBackend::new("bucket", "mirror")
+        .with_token("wrong".into())
+        .with_endpoint(&endpoint);
+    let error = unauthorized.exists("demo/b.whl").await.unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::PermissionDenied);
+}
+
+#[tokio::test]
+async fn objects_are_kept_in_an_azure_container() {
+    let objects = Objects::default();
+    let endpoint = fake_azure(objects.clone()).await;
+    let backend = AzureBackend::new(
+        "account",
+        "container",
+        "mirror",
+        "?sv=2021-08-06&sig=azure-sas",
+    )
+    .with_endpoint(&endpoint);
+    exercise(&backend, &objects).await;
+}
+
+#[tokio::test]
+async fn the_index_serves_files_from_an_object_store() {
+    let objects = Objects::default();
+    let endpoint = fake_gcs(objects.clone()).await;
+    let backend = GcsBackend::new("bucket", "")
+        .with_token("gcs-token".into())
+        .with_endpoint(&endpoint);
+    let wheel = SampleWheel::new("demo", "1.0");
+    let index = TestIndex::builder()
+        .backend(Arc::new(backend))
+        .wheel(wheel.clone())
+        .build()
+        .await
+        .unwrap();
+    let key = format!("demo/{}", wheel.filename());
+    assert!(objects.lock().unwrap().contains_key(&key));
+
+    let response = index
+        .send(
+            Request::get(format!("/packages/{key}"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    assert_eq!(body, wheel.bytes());
+}
+
+#[test]
+fn storage_locations_are_checked() {
+    assert!(open_backend("s3://bucket/prefix").is_err());
+    assert!(open_backend("gs://bucket/prefix?colour=red").is_err());
+    let dir = tempfile::tempdir().unwrap();
+    let location = format!(
+        "file://{}?blobs={}",
+        dir.path().join("packages").display(),
+        dir.path().join("blobs").display()
+    );
+    assert!(open_backend(&location).is_ok());
+    assert!(dir.path().join("blobs").is_dir());
+}
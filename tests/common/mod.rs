@@ -0,0 +1,46 @@
+#![allow(dead_code)]
+
+use std::path::{Path, PathBuf};
+
+use pippy::{router, testing::SampleWheel, PackageIndex};
+use tokio::process::Command;
+
+pub async fn spawn_server(data_dir: &Path) -> String {
+    let index = PackageIndex::new(data_dir.to_path_buf()).await.unwrap();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, router(index)).await });
+    format!("http://{addr}")
+}
+
+/// Fails the test unless `program` can be run. Tests driving a real client
+/// are `#[ignore]`d, so they run, and need the client, only when asked for
+/// with `cargo test -- --ignored`.
+pub async fn require_client(program: &str) {
+    let found = Command::new(program)
+        .arg("--version")
+        .output()
+        .await
+        .is_ok_and(|output| output.status.success());
+    assert!(found, "`{program}` is not installed");
+}
+
+pub fn write_wheel(dir: &Path, name: &str, version: &str) -> PathBuf {
+    SampleWheel::new(name, version).write_to(dir).unwrap()
+}
+
+pub async fn upload(url: &str, wheel: &Path) -> reqwest::StatusCode {
+    let filename = wheel.file_name().unwrap().to_str().unwrap().to_string();
+    let part = reqwest::multipart::Part::bytes(std::fs::read(wheel).unwrap()).file_name(filename);
+    reqwest::Client::new()
+        .post(format!("{url}/upload"))
+        .multipart(reqwest::multipart::Form::new().part("content", part))
+        .send()
+        .await
+        .unwrap()
+        .status()
+}
+
+pub async fn get_text(url: &str) -> String {
+    reqwest::get(url).await.unwrap().text().await.unwrap()
+}
@@ -0,0 +1,176 @@
+//! Removing uploads through the API, and what snapshots keep from being
+//! removed or moved.
+
+use axum::{
+    body::Body,
+    http::{header, Request, StatusCode},
+    response::Response,
+};
+use pippy::testing::{SampleWheel, TestIndex};
+
+/// Deletes with an admin token, as deleting always needs credentials.
+async fn delete(index: &TestIndex, uri: String) -> Response {
+    let request = Request::delete(uri)
+        .header(
+            header::AUTHORIZATION,
+            format!("token {}", index.admin_token().await.unwrap()),
+        )
+        .body(Body::empty())
+        .unwrap();
+    index.send(request).await
+}
+
+fn get(uri: String) -> Request<Body> {
+    Request::get(uri).body(Body::empty()).unwrap()
+}
+
+#[tokio::test]
+async fn deleted_files_are_gone_from_index_and_storage() {
+    let (kept, bad) = (
+        SampleWheel::new("demo", "1.0"),
+        SampleWheel::new("demo", "1.1"),
+    );
+    let index = TestIndex::builder()
+        .wheel(kept.clone())
+        .wheel(bad.clone())
+        .build()
+        .await
+        .unwrap();
+
+    let response = delete(
+        &index,
+        format!("/api/v1/projects/demo/files/{}", bad.filename()),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let page = index.send(get("/simple/demo/".into())).await;
+    let page = axum::body::to_bytes(page.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let page = String::from_utf8_lossy(&page);
+    assert!(page.contains(&kept.filename()));
+    assert!(!page.contains(&bad.filename()));
+
+    let download = index
+        .send(get(format!("/packages/demo/{}", bad.filename())))
+        .await;
+    assert_eq!(download.status(), StatusCode::NOT_FOUND);
+    let again = delete(
+        &index,
+        format!("/api/v1/projects/demo/files/{}", bad.filename()),
+    )
+    .await;
+    assert_eq!(again.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn files_kept_by_a_snapshot_are_not_deleted() {
+    let wheel = SampleWheel::new("demo", "1.0");
+    let index = TestIndex::builder()
+        .wheel(wheel.clone())
+        .build()
+        .await
+        .unwrap();
+    let snapshot = index
+        .send(
+            Request::post("/api/v1/snapshots/release-1")
+                .header(
+                    header::AUTHORIZATION,
+                    format!("token {}", index.admin_token().await.unwrap()),
+                )
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert!(snapshot.status().is_success());
+
+    let response = delete(
+        &index,
+        format!("/api/v1/projects/demo/files/{}", wheel.filename()),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    let download = index
+        .send(get(format!("/packages/demo/{}", wheel.filename())))
+        .await;
+    assert_eq!(download.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn deleted_projects_leave_nothing_behind() {
+    let wheel = SampleWheel::new("demo", "1.0");
+    let index = TestIndex::builder()
+        .wheel(wheel.clone())
+        .wheel(SampleWheel::new("other", "1.0"))
+        .build()
+        .await
+        .unwrap();
+
+    let response = delete(&index, "/api/v1/projects/demo".into()).await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let page = index.send(get("/simple/demo/".into())).await;
+    assert_eq!(page.status(), StatusCode::NOT_FOUND);
+    assert!(!index.path().join("packages/demo").exists());
+    assert!(index.path().join("packages/other").exists());
+    let audit = std::fs::read_to_string(index.path().join("audit.jsonl")).unwrap();
+    assert!(audit.contains(&wheel.filename()));
+}
+
+#[tokio::test]
+async fn projects_kept_by_a_snapshot_are_not_renamed() {
+    let wheel = SampleWheel::new("demo", "1.0");
+    let index = TestIndex::builder()
+        .wheel(wheel.clone())
+        .build()
+        .await
+        .unwrap();
+    let snapshot = index
+        .send(
+            Request::post("/api/v1/snapshots/release-1")
+                .header(
+                    header::AUTHORIZATION,
+                    format!("token {}", index.admin_token().await.unwrap()),
+                )
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert!(snapshot.status().is_success());
+
+    let renamed = index
+        .index()
+        .rename(&"demo".parse().unwrap(), "demo-renamed".parse().unwrap())
+        .await;
+    assert!(matches!(renamed, Err(pippy::AppError::Conflict(_))));
+    // Where the snapshot's pages link to.
+    let download = index
+        .send(get(format!("/packages/demo/{}", wheel.filename())))
+        .await;
+    assert_eq!(download.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn deleting_needs_credentials_even_on_an_open_index() {
+    let wheel = SampleWheel::new("demo", "1.0");
+    let index = TestIndex::builder()
+        .wheel(wheel.clone())
+        .build()
+        .await
+        .unwrap();
+
+    for uri in [
+        format!("/api/v1/projects/demo/files/{}", wheel.filename()),
+        "/api/v1/projects/demo".to_string(),
+    ] {
+        let response = index
+            .send(Request::delete(uri).body(Body::empty()).unwrap())
+            .await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+    let download = index
+        .send(get(format!("/packages/demo/{}", wheel.filename())))
+        .await;
+    assert_eq!(download.status(), StatusCode::OK);
+}
@@ -0,0 +1,76 @@
+//! Uploads can be refused when their dependencies are nowhere to be found.
+
+use axum::http::StatusCode;
+use pippy::{
+    dependencies::{DependencyCheck, DependencyPolicy},
+    testing::{SampleWheel, TestIndex, UploadForm},
+    Config,
+};
+
+#[tokio::test]
+async fn uploads_with_unpublished_dependencies_are_rejected() {
+    let index = TestIndex::builder()
+        .config(Config {
+            dependencies: DependencyCheck::new(DependencyPolicy::Reject, Vec::new()),
+            ..Config::default()
+        })
+        .wheel(SampleWheel::new("corp_core", "1.0"))
+        .build()
+        .await
+        .unwrap();
+
+    let app = SampleWheel::new("corp_app", "1.0")
+        .metadata("Requires-Dist", "Corp_Core>=1.0")
+        .metadata("Requires-Dist", "pytest; extra == \"test\"");
+    let response = index
+        .send(UploadForm::new().wheel(&app).request("/upload"))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let broken = SampleWheel::new("corp_tool", "1.0").metadata(
+        "Requires-Dist",
+        "corp-secret (>=2); python_version >= \"3.9\"",
+    );
+    let response = index
+        .send(UploadForm::new().wheel(&broken).request("/upload"))
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert!(!index
+        .index()
+        .has_file(
+            &"corp-tool".parse().unwrap(),
+            &broken.filename().parse().unwrap()
+        )
+        .await
+        .unwrap());
+}
+
+#[tokio::test]
+async fn dependencies_that_cannot_be_checked_fail_only_under_reject() {
+    // Nothing listens there, so the upstream cannot answer.
+    let upstreams = vec!["http://127.0.0.1:1/simple".to_string()];
+    let wheel = SampleWheel::new("corp_app", "1.0").metadata("Requires-Dist", "requests");
+    for (policy, expected) in [
+        (DependencyPolicy::Reject, StatusCode::BAD_REQUEST),
+        (DependencyPolicy::Warn, StatusCode::OK),
+    ] {
+        let index = TestIndex::builder()
+            .config(Config {
+                dependencies: DependencyCheck::new(policy, upstreams.clone()),
+                ..Config::default()
+            })
+            .build()
+            .await
+            .unwrap();
+        let response = index
+            .send(UploadForm::new().wheel(&wheel).request("/upload"))
+            .await;
+        assert_eq!(response.status(), expected, "{policy:?}");
+        if policy == DependencyPolicy::Reject {
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            assert!(String::from_utf8_lossy(&body).contains("could not be checked"));
+        }
+    }
+}
@@ -0,0 +1,157 @@
+//! Comparing index states by the projects, files and digests they serve.
+
+use axum::{
+    body::Body,
+    http::{header, Request, StatusCode},
+};
+use pippy::{
+    diff::{DifferenceKind, IndexDiff, Manifest, Side},
+    testing::{SampleWheel, TestIndex, UploadForm},
+};
+use serde_json::Value;
+
+async fn send(index: &TestIndex, request: Request<Body>) -> (StatusCode, Value) {
+    let response = index.send(request).await;
+    let status = response.status();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    (status, serde_json::from_slice(&body).unwrap_or_default())
+}
+
+fn admin(method: &str, uri: &str, token: &str) -> Request<Body> {
+    Request::builder()
+        .method(method)
+        .uri(uri)
+        .header(header::AUTHORIZATION, format!("token {token}"))
+        .body(Body::empty())
+        .unwrap()
+}
+
+fn kinds(diff: &Value) -> Vec<(String, String)> {
+    diff["differences"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|d| {
+            let file = d["filename"]
+                .as_str()
+                .unwrap_or(d["project"].as_str().unwrap());
+            (d["kind"].as_str().unwrap().to_string(), file.to_string())
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn snapshots_are_compared_with_the_live_index() {
+    let index = TestIndex::builder()
+        .wheel(SampleWheel::new("demo", "1.0"))
+        .wheel(SampleWheel::new("demo", "1.1"))
+        .build()
+        .await
+        .unwrap();
+    let token = index.admin_token().await.unwrap();
+    let (status, _) = send(&index, admin("POST", "/api/v1/snapshots/before", &token)).await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let (status, diff) = send(&index, admin("GET", "/api/v1/diff?from=before", &token)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        (diff["files_compared"].as_u64(), kinds(&diff)),
+        (Some(2), vec![])
+    );
+
+    let (newer, extra) = (
+        SampleWheel::new("demo", "1.2"),
+        SampleWheel::new("extra", "0.1"),
+    );
+    for wheel in [&newer, &extra] {
+        let response = index
+            .send(UploadForm::new().wheel(wheel).request("/upload"))
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    let (_, diff) = send(&index, admin("GET", "/api/v1/diff?from=before", &token)).await;
+    assert_eq!(diff["from"], "before");
+    assert_eq!(diff["to"], "live");
+    assert_eq!(
+        kinds(&diff),
+        [
+            ("file_added".to_string(), newer.filename()),
+            ("project_added".to_string(), "extra".to_string()),
+            ("file_added".to_string(), extra.filename()),
+        ]
+    );
+    let uri = "/api/v1/diff?from=live&to=before";
+    let (_, diff) = send(&index, admin("GET", uri, &token)).await;
+    assert_eq!(
+        kinds(&diff),
+        [
+            ("file_removed".to_string(), newer.filename()),
+            ("project_removed".to_string(), "extra".to_string()),
+            ("file_removed".to_string(), extra.filename()),
+        ]
+    );
+
+    // Other instances are only compared by `pippy diff` itself.
+    let uri = "/api/v1/diff?from=https://replica.example";
+    let (status, _) = send(&index, admin("GET", uri, &token)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn replicas_are_compared_byte_for_byte() {
+    let wheel = SampleWheel::new("demo", "1.0");
+    let source = TestIndex::builder()
+        .wheel(wheel.clone())
+        .wheel(SampleWheel::new("other", "2.0"))
+        .build()
+        .await
+        .unwrap();
+    let replica = TestIndex::builder()
+        .wheel(wheel.clone())
+        .build()
+        .await
+        .unwrap();
+    let remote: Side = replica.spawn().await.unwrap().parse().unwrap();
+    assert!(remote.is_remote());
+
+    let a = Manifest::of(source.index(), &Side::Live, false)
+        .await
+        .unwrap();
+    let b = Manifest::of(source.index(), &remote, false).await.unwrap();
+    let diff = IndexDiff::compare(Side::Live, &remote, &a, &b);
+    assert_eq!(diff.files_compared, 1);
+    assert_eq!(diff.unverified, 0);
+    let found: Vec<_> = diff
+        .differences
+        .iter()
+        .map(|d| (d.kind, d.project.to_string()))
+        .collect();
+    let other = "other".to_string();
+    assert_eq!(
+        found,
+        [
+            (DifferenceKind::ProjectRemoved, other.clone()),
+            (DifferenceKind::FileRemoved, other),
+        ]
+    );
+
+    // Bytes changed in storage since upload only show when rehashed.
+    let stored = replica.path().join("packages/demo").join(wheel.filename());
+    std::fs::write(&stored, b"not the wheel").unwrap();
+    let recorded = Manifest::of(replica.index(), &Side::Live, false)
+        .await
+        .unwrap();
+    let rehashed = Manifest::of(replica.index(), &Side::Live, true)
+        .await
+        .unwrap();
+    let diff = IndexDiff::compare("recorded", "rehashed", &recorded, &rehashed);
+    assert_eq!(diff.differences.len(), 1);
+    assert_eq!(diff.differences[0].kind, DifferenceKind::DigestChanged);
+    assert_eq!(
+        diff.differences[0].filename.as_ref().unwrap().as_str(),
+        wheel.filename()
+    );
+}
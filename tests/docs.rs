@@ -0,0 +1,192 @@
+//! Documentation uploaded for a project's versions and served under
+//! `/docs/`.
+
+use std::io::Write;
+
+use axum::{
+    body::Body,
+    http::{header, Request, StatusCode},
+    response::Response,
+};
+use pippy::{
+    testing::{SampleWheel, TestIndex, UploadForm},
+    Config,
+};
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
+
+/// A docs archive holding `files`, deflated.
+fn archive(files: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut zip = ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    for (path, contents) in files {
+        zip.start_file(*path, options).unwrap();
+        zip.write_all(contents).unwrap();
+    }
+    zip.finish().unwrap().into_inner()
+}
+
+async fn upload(index: &TestIndex, archive: Vec<u8>, token: Option<&str>) -> Response {
+    let mut request = UploadForm::new()
+        .file("docs.zip", archive)
+        .request("/api/v1/projects/demo/docs/1.0");
+    if let Some(token) = token {
+        request.headers_mut().insert(
+            header::AUTHORIZATION,
+            format!("token {token}").parse().unwrap(),
+        );
+    }
+    index.send(request).await
+}
+
+async fn get(index: &TestIndex, uri: &str) -> StatusCode {
+    index
+        .send(Request::get(uri).body(Body::empty()).unwrap())
+        .await
+        .status()
+}
+
+async fn demo_index(config: Config) -> TestIndex {
+    TestIndex::builder()
+        .config(config)
+        .wheel(SampleWheel::new("demo", "1.0"))
+        .build()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn docs_uploads_need_credentials_even_on_an_open_index() {
+    let index = demo_index(Config::default()).await;
+    let docs = archive(&[("index.html", b"<h1>demo</h1>")]);
+
+    let response = upload(&index, docs.clone(), None).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(
+        get(&index, "/docs/demo/1.0/index.html").await,
+        StatusCode::NOT_FOUND
+    );
+
+    let admin = index.admin_token().await.unwrap();
+    let response = upload(&index, docs, Some(&admin)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        get(&index, "/docs/demo/1.0/index.html").await,
+        StatusCode::OK
+    );
+}
+
+#[tokio::test]
+async fn docs_archives_are_held_to_the_upload_limit_not_the_default_body_limit() {
+    // Incompressible, so the archive is as large as its contents.
+    let mut noise = vec![0u8; 3 * 1024 * 1024];
+    let mut state = 0x2545_f491_4f6c_dd1d_u64;
+    for byte in &mut noise {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        *byte = state as u8;
+    }
+    let docs = archive(&[("index.html", b"<h1>demo</h1>"), ("search.js", &noise)]);
+    let index = demo_index(Config::default()).await;
+    let admin = index.admin_token().await.unwrap();
+
+    let response = upload(&index, docs.clone(), Some(&admin)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        get(&index, "/docs/demo/1.0/search.js").await,
+        StatusCode::OK
+    );
+
+    let limited = demo_index(Config {
+        max_upload_size: Some(1024 * 1024),
+        ..Config::default()
+    })
+    .await;
+    let admin = limited.admin_token().await.unwrap();
+    let response = upload(&limited, docs, Some(&admin)).await;
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+}
+
+#[tokio::test]
+async fn archives_unpacking_past_the_cap_are_refused() {
+    let index = demo_index(Config {
+        max_docs_size: 64 * 1024,
+        ..Config::default()
+    })
+    .await;
+    let admin = index.admin_token().await.unwrap();
+    // About a kilobyte once deflated.
+    let bomb = archive(&[("index.html", b"<h1>demo</h1>"), ("pad", &[0; 1024 * 1024])]);
+    assert!(bomb.len() < 64 * 1024);
+
+    let response = upload(&index, bomb, Some(&admin)).await;
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(
+        get(&index, "/docs/demo/1.0/index.html").await,
+        StatusCode::NOT_FOUND
+    );
+    let leftovers = std::fs::read_dir(index.path().join("docs/demo")).unwrap();
+    assert_eq!(leftovers.count(), 0);
+}
+
+#[tokio::test]
+async fn only_published_versions_are_served() {
+    let index = demo_index(Config::default()).await;
+    let admin = index.admin_token().await.unwrap();
+    let response = upload(
+        &index,
+        archive(&[("index.html", b"<h1>demo</h1>")]),
+        Some(&admin),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    // As an upload still being unpacked, and a version never published.
+    for dir in [".2.0.partial", "2.0"] {
+        let dir = index.path().join("docs/demo").join(dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("index.html"), "<h1>unpublished</h1>").unwrap();
+    }
+
+    assert_eq!(
+        get(&index, "/docs/demo/1.0/index.html").await,
+        StatusCode::OK
+    );
+    assert_eq!(
+        get(&index, "/docs/demo/.2.0.partial/index.html").await,
+        StatusCode::NOT_FOUND
+    );
+    assert_eq!(
+        get(&index, "/docs/demo/2.0/index.html").await,
+        StatusCode::NOT_FOUND
+    );
+}
+
+#[tokio::test]
+async fn docs_under_unnormalized_names_redirect() {
+    let index = demo_index(Config::default()).await;
+    let admin = index.admin_token().await.unwrap();
+    let response = upload(
+        &index,
+        archive(&[("api/index.html", b"<h1>demo</h1>")]),
+        Some(&admin),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = index
+        .send(
+            Request::get("/docs/Demo/1.0/api/index.html?q=1")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+    assert_eq!(
+        response.headers()[header::LOCATION],
+        "/docs/demo/1.0/api/index.html?q=1"
+    );
+    assert_eq!(
+        get(&index, "/docs/demo/1.0/api/index.html").await,
+        StatusCode::OK
+    );
+}
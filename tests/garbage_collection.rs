@@ -0,0 +1,123 @@
+//! Collecting stored files the index does not know about, and releases
+//! whose file is gone.
+
+use std::time::Duration;
+
+use futures_util::StreamExt;
+use pippy::{
+    gc::{self, GcOptions},
+    testing::{SampleWheel, TestIndex},
+};
+
+#[tokio::test]
+async fn orphans_are_reported_then_removed_in_both_directions() {
+    let (kept, lost) = (
+        SampleWheel::new("demo", "1.0"),
+        SampleWheel::new("demo", "1.1"),
+    );
+    let index = TestIndex::builder()
+        .wheel(kept.clone())
+        .wheel(lost.clone())
+        .build()
+        .await
+        .unwrap();
+    let dir = index.path().join("packages/demo");
+    std::fs::remove_file(dir.join(lost.filename())).unwrap();
+    let stray = SampleWheel::new("demo", "2.0").write_to(&dir).unwrap();
+    std::fs::write(dir.join("notes.txt"), "left behind").unwrap();
+
+    let options = GcOptions {
+        min_age: Duration::ZERO,
+        ..GcOptions::default()
+    };
+    let report = gc::collect(index.index(), options).await.unwrap();
+    let mut orphans: Vec<&str> = report
+        .orphaned_objects
+        .iter()
+        .map(|o| o.key.as_str())
+        .collect();
+    orphans.sort();
+    let stray_key = format!("demo/{}", SampleWheel::new("demo", "2.0").filename());
+    assert_eq!(orphans, [stray_key.as_str(), "demo/notes.txt"]);
+    assert_eq!(report.missing_files, [format!("demo/{}", lost.filename())]);
+    // The blob of the file removed by hand is no longer linked from storage.
+    #[cfg(unix)]
+    assert_eq!(report.orphaned_blobs.len(), 1);
+    assert!(stray.exists());
+
+    // Hand-copied distributions can be left for the storage scanner.
+    let spared = GcOptions {
+        remove: true,
+        spare_distributions: true,
+        ..options
+    };
+    let report = gc::collect(index.index(), spared).await.unwrap();
+    // The dropped release's core metadata goes with it.
+    assert_eq!(report.orphaned_objects.len(), 2);
+    assert!(stray.exists());
+    assert!(!dir.join("notes.txt").exists());
+    assert!(!dir.join(format!("{}.metadata", lost.filename())).exists());
+    let packages = index.index().packages().await.unwrap();
+    let filenames: Vec<String> = packages[0]
+        .releases
+        .iter()
+        .map(|r| r.filename.to_string())
+        .collect();
+    assert_eq!(filenames, [kept.filename()]);
+
+    let report = gc::collect(
+        index.index(),
+        GcOptions {
+            remove: true,
+            ..options
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(report.orphaned_objects.len(), 1);
+    assert!(!stray.exists());
+    assert!(dir.join(kept.filename()).exists());
+
+    let report = gc::collect(index.index(), options).await.unwrap();
+    assert!(report.is_empty(), "{report}");
+    let usage = index.index().usage().await.unwrap();
+    assert_eq!(usage.total_bytes, kept.bytes().len() as u64);
+}
+
+#[tokio::test]
+async fn files_linked_to_an_old_blob_count_as_just_written() {
+    let wheel = SampleWheel::new("demo", "1.0");
+    let index = TestIndex::builder()
+        .wheel(wheel.clone())
+        .build()
+        .await
+        .unwrap();
+    let published = index.path().join("packages/demo").join(wheel.filename());
+    let day_ago = std::time::SystemTime::now() - Duration::from_secs(24 * 60 * 60);
+    std::fs::File::options()
+        .write(true)
+        .open(&published)
+        .unwrap()
+        .set_modified(day_ago)
+        .unwrap();
+
+    // As an upload of the same bytes leaves them before it is indexed.
+    let key = "demo/demo-2.0-py3-none-any.whl";
+    index
+        .index()
+        .storage()
+        .backend()
+        .store(
+            key,
+            futures_util::stream::iter([Ok(axum::body::Bytes::from(wheel.bytes()))]).boxed(),
+        )
+        .await
+        .unwrap();
+    let report = gc::collect(index.index(), GcOptions::default())
+        .await
+        .unwrap();
+    assert!(
+        report.orphaned_objects.iter().all(|o| o.key != key),
+        "{report}"
+    );
+}
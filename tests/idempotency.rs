@@ -0,0 +1,124 @@
+//! Uploads retried under an `Idempotency-Key`.
+
+use axum::{
+    body::{Body, Bytes},
+    http::{header, HeaderValue, Request, StatusCode},
+    response::Response,
+    Router,
+};
+use pippy::{
+    auth::TokenStore,
+    testing::{SampleWheel, TestIndex, UploadForm},
+    Config,
+};
+use tower::ServiceExt;
+
+fn upload(form: &UploadForm, key: &str, token: Option<&str>) -> Request<Body> {
+    let mut request = form.request("/upload");
+    let headers = request.headers_mut();
+    headers.insert("idempotency-key", HeaderValue::from_str(key).unwrap());
+    if let Some(token) = token {
+        headers.insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_str(&format!("token {token}")).unwrap(),
+        );
+    }
+    request
+}
+
+/// Sends through `router`, whose state carries the keys seen, unlike
+/// [`TestIndex::send`] with its fresh router per request.
+async fn send(router: &Router, request: Request<Body>) -> Response {
+    router.clone().oneshot(request).await.unwrap()
+}
+
+async fn body(response: Response) -> Bytes {
+    axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn retries_get_the_original_receipt() {
+    let index = TestIndex::new().await.unwrap();
+    let router = index.router();
+    let form = UploadForm::new().wheel(&SampleWheel::new("demo", "1.0"));
+    let first = send(&router, upload(&form, "build-1", None)).await;
+    assert_eq!(first.status(), StatusCode::OK);
+    let first = body(first).await;
+
+    let retry = send(&router, upload(&form, "build-1", None)).await;
+    assert_eq!(retry.status(), StatusCode::OK);
+    // The same document, timestamp included.
+    assert_eq!(body(retry).await, first);
+
+    // Other files under the same key are not a retry.
+    let other = UploadForm::new().wheel(&SampleWheel::new("demo", "1.1"));
+    let response = send(&router, upload(&other, "build-1", None)).await;
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(index.index().packages().await.unwrap()[0].releases.len(), 1);
+}
+
+#[tokio::test]
+async fn keys_belong_to_the_caller_that_used_them() {
+    let index = TestIndex::builder()
+        .config(Config {
+            require_token: true,
+            ..Config::default()
+        })
+        .build()
+        .await
+        .unwrap();
+    let router = index.router();
+    let tokens = TokenStore::new(index.index().storage().clone());
+    let (_, alice) = tokens.create_for("alice", "ci", None).await.unwrap();
+    let (_, bob) = tokens.create_for("bob", "ci", None).await.unwrap();
+
+    let form = UploadForm::new().wheel(&SampleWheel::new("demo", "1.0"));
+    let response = send(&router, upload(&form, "build-1", Some(&alice))).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    // Bob's upload is his own, and refused: the project is Alice's.
+    let response = send(&router, upload(&form, "build-1", Some(&bob))).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn failed_and_abandoned_uploads_can_be_retried() {
+    let index = TestIndex::new().await.unwrap();
+    let router = index.router();
+    let wheel = SampleWheel::new("demo", "1.0");
+    let form = UploadForm::new().wheel(&wheel);
+
+    let broken = UploadForm::new()
+        .field("sha256_digest", "0".repeat(64))
+        .wheel(&wheel);
+    let response = send(&router, upload(&broken, "build-1", None)).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = send(&router, upload(&form, "build-1", None)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // An upload whose body stops arriving holds its key...
+    let body = form.body();
+    let (sender, receiver) = tokio::sync::mpsc::channel::<Result<Bytes, std::io::Error>>(1);
+    let mut request = upload(&form, "build-2", None);
+    *request.body_mut() = Body::from_stream(receiver_stream(receiver));
+    let stalled = tokio::spawn(router.clone().oneshot(request));
+    let (head, _) = body.split_at(body.len() / 2);
+    sender.send(Ok(Bytes::copy_from_slice(head))).await.unwrap();
+    // Taken once the handler reads the body, past claiming the key.
+    sender.send(Ok(Bytes::new())).await.unwrap();
+    let response = send(&router, upload(&form, "build-2", None)).await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+
+    // ...until the client goes away.
+    stalled.abort();
+    let _ = stalled.await;
+    let response = send(&router, upload(&form, "build-2", None)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+fn receiver_stream<T: Send + 'static>(
+    mut receiver: tokio::sync::mpsc::Receiver<T>,
+) -> impl futures_util::Stream<Item = T> + Send {
+    futures_util::stream::poll_fn(move |cx| receiver.poll_recv(cx))
+}
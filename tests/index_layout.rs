@@ -0,0 +1,444 @@
+//! How the index is laid out in the data directory.
+
+use axum::{body::Body, http::Request, http::StatusCode};
+use pippy::{
+    reindex::StorageScanner,
+    testing::{SampleWheel, TestIndex, UploadForm},
+    PackageIndex,
+};
+use serde_json::{json, Value};
+use std::time::Duration;
+
+fn read_json(path: &std::path::Path) -> Value {
+    serde_json::from_slice(&std::fs::read(path).unwrap()).unwrap()
+}
+
+async fn cached_projects(index: &PackageIndex) -> Vec<String> {
+    let names = index.cached_projects().await;
+    names.iter().map(ToString::to_string).collect()
+}
+
+#[tokio::test]
+async fn uploads_only_rewrite_their_own_project() {
+    let index = TestIndex::builder()
+        .wheel(SampleWheel::new("alpha", "1.0"))
+        .build()
+        .await
+        .unwrap();
+    let alpha = index.path().join("projects/alpha.json");
+    // Enrichment rewrites the project once after its upload.
+    for _ in 0..100 {
+        if read_json(&alpha)["releases"][0]["enrichments"]["size"].is_object() {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    let before = std::fs::metadata(&alpha).unwrap().modified().unwrap();
+
+    let response = index
+        .send(
+            UploadForm::new()
+                .wheel(&SampleWheel::new("beta", "1.0"))
+                .request("/upload"),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    assert_eq!(
+        std::fs::metadata(&alpha).unwrap().modified().unwrap(),
+        before
+    );
+    assert_eq!(
+        read_json(&index.path().join("projects.json")),
+        json!(["alpha", "beta"])
+    );
+    let beta = read_json(&index.path().join("projects/beta.json"));
+    assert_eq!(beta["releases"][0]["version"], "1.0");
+    assert!(!index.path().join("index.json").exists());
+}
+
+#[tokio::test]
+async fn a_single_index_file_is_split_on_startup() {
+    let seeded = TestIndex::builder()
+        .wheel(SampleWheel::new("demo", "1.0"))
+        .build()
+        .await
+        .unwrap();
+    let demo = read_json(&seeded.path().join("projects/demo.json"));
+
+    // As written before projects had their own files, under a name from
+    // before names were normalized.
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(
+        dir.path().join("index.json"),
+        serde_json::to_vec(&json!({ "Demo": demo })).unwrap(),
+    )
+    .unwrap();
+
+    let index = PackageIndex::new(dir.path().to_path_buf()).await.unwrap();
+    assert!(!dir.path().join("index.json").exists());
+    assert_eq!(
+        read_json(&dir.path().join("projects.json")),
+        json!(["demo"])
+    );
+    let router = pippy::router(index);
+    let response = tower::ServiceExt::oneshot(
+        router,
+        Request::get("/simple/demo/").body(Body::empty()).unwrap(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn journaled_changes_survive_lost_metadata_files() {
+    let index = TestIndex::builder()
+        .wheel(SampleWheel::new("demo", "1.0"))
+        .build()
+        .await
+        .unwrap();
+    let journal = std::fs::read_to_string(index.path().join("changes.jsonl")).unwrap();
+    let first: Value = serde_json::from_str(journal.lines().next().unwrap()).unwrap();
+    assert_eq!(first["operation"], "release");
+    assert_eq!(first["state"]["saved"]["name"], "demo");
+
+    // As if the process died before the metadata files reached the disk.
+    std::fs::remove_file(index.path().join("projects/demo.json")).unwrap();
+    std::fs::remove_file(index.path().join("projects.json")).unwrap();
+    let reopened = PackageIndex::new(index.path().to_path_buf()).await.unwrap();
+    assert_eq!(reopened.packages().await.unwrap().len(), 1);
+
+    // Compaction writes the files out and moves the entries to history.
+    reopened.compact_journal().await.unwrap();
+    assert!(index.path().join("projects/demo.json").exists());
+    let journal = std::fs::read_to_string(index.path().join("changes.jsonl")).unwrap();
+    assert!(journal.is_empty());
+    let history = std::fs::read_to_string(index.path().join("changes.history.jsonl")).unwrap();
+    assert!(!history.is_empty());
+    for line in history.lines() {
+        let change: Value = serde_json::from_str(line).unwrap();
+        assert!(change.get("state").is_none(), "{line}");
+        assert!(change["operation"].is_string());
+    }
+}
+
+#[tokio::test]
+async fn compacted_history_is_bounded_and_still_followed() {
+    let dir = tempfile::tempdir().unwrap();
+    let history = dir.path().join("changes.history.jsonl");
+    let mut lines = String::new();
+    for serial in 1..=100_000 {
+        lines.push_str(&format!(
+            r#"{{"serial":{serial},"project":"old","time":"2020-01-01T00:00:00Z","operation":"release"}}"#
+        ));
+        lines.push('\n');
+    }
+    std::fs::write(&history, lines).unwrap();
+
+    let index = PackageIndex::new(dir.path().to_path_buf()).await.unwrap();
+    assert_eq!(index.serial().await, 100_000);
+    let router = pippy::router(index.clone());
+    let response = tower::ServiceExt::oneshot(
+        router.clone(),
+        UploadForm::new()
+            .wheel(&SampleWheel::new("demo", "1.0"))
+            .request("/upload"),
+    )
+    .await
+    .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(index.shutdown(Duration::from_secs(10)).await);
+    index.compact_journal().await.unwrap();
+
+    // The oldest entries made room for the compacted ones.
+    let kept = std::fs::read_to_string(&history).unwrap();
+    assert_eq!(kept.lines().count(), 100_000);
+    let first: Value = serde_json::from_str(kept.lines().next().unwrap()).unwrap();
+    assert!(first["serial"].as_u64().unwrap() > 1);
+
+    let response = tower::ServiceExt::oneshot(
+        router,
+        Request::get("/api/v1/changes?since=99999")
+            .body(Body::empty())
+            .unwrap(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let changes: Value = serde_json::from_slice(&body).unwrap();
+    let names: Vec<&str> = changes["projects"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|p| p["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(names, ["old", "demo"]);
+    assert_eq!(changes["serial"], index.serial().await);
+}
+
+#[tokio::test]
+async fn metadata_files_are_read_on_first_access() {
+    let index = TestIndex::builder()
+        .wheel(SampleWheel::new("alpha", "1.0"))
+        .wheel(SampleWheel::new("beta", "1.0"))
+        .build()
+        .await
+        .unwrap();
+    // Once enrichment is done, leaves the files, rather than the journal,
+    // holding both projects.
+    assert!(index.index().shutdown(Duration::from_secs(10)).await);
+    index.index().compact_journal().await.unwrap();
+    std::fs::write(index.path().join("projects/beta.json"), "{").unwrap();
+
+    let reopened = PackageIndex::new(index.path().to_path_buf()).await.unwrap();
+    let router = pippy::router(reopened);
+    let get = |uri: &str| {
+        tower::ServiceExt::oneshot(
+            router.clone(),
+            Request::get(uri).body(Body::empty()).unwrap(),
+        )
+    };
+    assert_eq!(
+        get("/simple/alpha/").await.unwrap().status(),
+        StatusCode::OK
+    );
+    assert_eq!(
+        get("/simple/beta/").await.unwrap().status(),
+        StatusCode::INTERNAL_SERVER_ERROR
+    );
+    assert_eq!(
+        get("/simple/").await.unwrap().status(),
+        StatusCode::INTERNAL_SERVER_ERROR
+    );
+}
+
+#[tokio::test]
+async fn uploads_only_read_their_own_project() {
+    let index = TestIndex::builder()
+        .wheel(SampleWheel::new("alpha", "1.0"))
+        .wheel(SampleWheel::new("beta", "1.0"))
+        .build()
+        .await
+        .unwrap();
+    assert!(index.index().shutdown(Duration::from_secs(10)).await);
+    index.index().compact_journal().await.unwrap();
+
+    let reopened = PackageIndex::new(index.path().to_path_buf()).await.unwrap();
+    assert!(reopened.cached_projects().await.is_empty());
+    let router = pippy::router(reopened.clone());
+    for wheel in [
+        SampleWheel::new("gamma", "1.0"),
+        SampleWheel::new("alpha", "1.1"),
+    ] {
+        let response = tower::ServiceExt::oneshot(
+            router.clone(),
+            UploadForm::new().wheel(&wheel).request("/upload"),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+    assert_eq!(cached_projects(&reopened).await, ["alpha", "gamma"]);
+}
+
+#[tokio::test]
+async fn the_least_recently_used_projects_are_evicted() {
+    let index = TestIndex::builder()
+        .wheel(SampleWheel::new("alpha", "1.0"))
+        .wheel(SampleWheel::new("beta", "1.0"))
+        .build()
+        .await
+        .unwrap();
+    assert!(index.index().shutdown(Duration::from_secs(10)).await);
+    index.index().compact_journal().await.unwrap();
+
+    let reopened = PackageIndex::new(index.path().to_path_buf())
+        .await
+        .unwrap()
+        .with_cached_projects(1);
+    let router = pippy::router(reopened.clone());
+    for (uri, cached) in [
+        ("/simple/alpha/", "alpha"),
+        ("/simple/beta/", "beta"),
+        ("/simple/alpha/", "alpha"),
+    ] {
+        let response = tower::ServiceExt::oneshot(
+            router.clone(),
+            Request::get(uri).body(Body::empty()).unwrap(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(cached_projects(&reopened).await, [cached]);
+    }
+
+    // Listing every project reads them all, to be evicted first.
+    let response = tower::ServiceExt::oneshot(
+        router.clone(),
+        Request::get("/simple/").body(Body::empty()).unwrap(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(reopened.cached_projects().await.len(), 2);
+    let response = tower::ServiceExt::oneshot(
+        router,
+        Request::get("/simple/alpha/").body(Body::empty()).unwrap(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(cached_projects(&reopened).await, ["alpha"]);
+}
+
+#[tokio::test]
+async fn the_index_is_rebuilt_from_stored_files() {
+    let index = TestIndex::builder()
+        .wheel(SampleWheel::new("demo", "1.0").metadata("Requires-Python", ">=3.9"))
+        .build()
+        .await
+        .unwrap();
+    let response = index
+        .send(
+            Request::post("/api/v1/admin/reindex")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    // Metadata and journal lost, and a wheel dropped in by hand.
+    std::fs::remove_dir_all(index.path().join("projects")).unwrap();
+    std::fs::remove_file(index.path().join("projects.json")).unwrap();
+    std::fs::remove_file(index.path().join("changes.jsonl")).unwrap();
+    SampleWheel::new("demo", "2.0")
+        .write_to(&index.path().join("packages/demo"))
+        .unwrap();
+    std::fs::write(index.path().join("packages/demo/notes.txt"), "").unwrap();
+
+    let reopened = PackageIndex::new(index.path().to_path_buf()).await.unwrap();
+    assert!(reopened.packages().await.unwrap().is_empty());
+    let report = pippy::reindex::rebuild(&reopened).await.unwrap();
+    assert_eq!((report.files, report.added, report.rehashed), (2, 2, 2));
+    assert_eq!(report.skipped, ["demo/notes.txt"]);
+
+    let demo = read_json(&index.path().join("projects/demo.json"));
+    let releases = demo["releases"].as_array().unwrap();
+    assert_eq!(releases.len(), 2);
+    for release in releases {
+        assert_eq!(release["provenance"]["source"], "reindex");
+        assert!(release["provenance"]["digests"]["sha256"].is_string());
+        assert!(release["core_metadata"].is_string());
+    }
+    assert!(releases.iter().any(|r| r["requires_python"] == ">=3.9"));
+
+    // Nothing changes the second time.
+    let report = pippy::reindex::rebuild(&reopened).await.unwrap();
+    assert!(report.changed.is_empty());
+}
+
+#[tokio::test]
+async fn files_copied_into_storage_are_registered_once_settled() {
+    let index = TestIndex::new().await.unwrap();
+    let dir = index.path().join("packages/demo");
+    std::fs::create_dir_all(&dir).unwrap();
+    SampleWheel::new("demo", "1.0").write_to(&dir).unwrap();
+    std::fs::write(dir.join("README"), "").unwrap();
+
+    let mut scanner = StorageScanner::new();
+    assert_eq!(scanner.scan(index.index()).await.unwrap(), 0);
+    assert!(index.index().packages().await.unwrap().is_empty());
+    assert_eq!(scanner.scan(index.index()).await.unwrap(), 1);
+    assert_eq!(scanner.scan(index.index()).await.unwrap(), 0);
+
+    let response = index
+        .send(Request::get("/simple/demo/").body(Body::empty()).unwrap())
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let demo = read_json(&index.path().join("projects/demo.json"));
+    assert_eq!(demo["releases"][0]["provenance"]["source"], "reindex");
+    assert!(demo["releases"][0]["core_metadata"].is_string());
+}
+
+#[tokio::test]
+async fn indexes_sharing_a_data_directory_follow_each_other() {
+    let first = TestIndex::new().await.unwrap();
+    let second = PackageIndex::new(first.path().to_path_buf()).await.unwrap();
+    let _claims = [
+        first.index().storage().claim(true).unwrap(),
+        second.storage().claim(true).unwrap(),
+    ];
+    assert!(matches!(
+        second.storage().claim(false),
+        Err(pippy::AppError::Conflict(_))
+    ));
+    tokio::spawn(second.clone().follow_changes(Duration::from_millis(20)));
+
+    // Uploads through both at once are serialized by the index lock, each
+    // reloading what the other wrote before adding its own.
+    let uploads = (0..8).map(|i| {
+        let name = ["alpha", "beta", "gamma", "delta"][i % 4];
+        let request = UploadForm::new()
+            .wheel(&SampleWheel::new(name, format!("1.{i}")))
+            .request("/upload");
+        let router = match i % 2 {
+            0 => first.router(),
+            _ => pippy::router(second.clone()),
+        };
+        tower::ServiceExt::oneshot(router, request)
+    });
+    for response in futures_util::future::join_all(uploads).await {
+        assert_eq!(response.unwrap().status(), StatusCode::OK);
+    }
+
+    // An upload through the first alone reaches the second by polling.
+    let response = first
+        .send(
+            UploadForm::new()
+                .wheel(&SampleWheel::new("latecomer", "1.0"))
+                .request("/upload"),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let serial = first.index().serial().await;
+    for _ in 0..100 {
+        if second.serial().await >= serial {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert!(second.serial().await >= serial);
+
+    // No two writers took the same serial.
+    let journal = std::fs::read_to_string(first.path().join("changes.jsonl")).unwrap();
+    let serials: Vec<u64> = journal
+        .lines()
+        .map(|line| {
+            serde_json::from_str::<Value>(line).unwrap()["serial"]
+                .as_u64()
+                .unwrap()
+        })
+        .collect();
+    assert_eq!(serials, (1..=serials.len() as u64).collect::<Vec<_>>());
+
+    let releases = |packages: Vec<pippy::Package>| -> Vec<(String, usize)> {
+        packages
+            .iter()
+            .map(|p| (p.name.to_string(), p.releases.len()))
+            .collect()
+    };
+    let expected = [
+        ("alpha", 2),
+        ("beta", 2),
+        ("delta", 2),
+        ("gamma", 2),
+        ("latecomer", 1),
+    ]
+    .map(|(name, releases)| (name.to_string(), releases));
+    assert_eq!(releases(first.index().packages().await.unwrap()), expected);
+    assert_eq!(releases(second.packages().await.unwrap()), expected);
+}
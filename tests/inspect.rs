@@ -0,0 +1,52 @@
+//! Browsing the members of stored files.
+
+use axum::{
+    body::Body,
+    http::{header, Request, StatusCode},
+};
+use pippy::testing::{SampleWheel, TestIndex};
+use serde_json::Value;
+
+#[tokio::test]
+async fn file_contents_are_shown_to_identified_callers_only() {
+    let index = TestIndex::builder()
+        .wheel(SampleWheel::new("demo", "1.0"))
+        .build()
+        .await
+        .unwrap();
+    let files = "/api/v1/projects/demo/files/demo-1.0-py3-none-any.whl/contents";
+    let member = format!("{files}/demo-1.0.dist-info/METADATA");
+    for uri in [files, member.as_str()] {
+        let anonymous = index
+            .send(Request::get(uri).body(Body::empty()).unwrap())
+            .await;
+        assert_eq!(anonymous.status(), StatusCode::UNAUTHORIZED, "{uri}");
+    }
+
+    let admin = index.admin_token().await.unwrap();
+    let get = |uri: &str| {
+        Request::get(uri)
+            .header(header::AUTHORIZATION, format!("token {admin}"))
+            .body(Body::empty())
+            .unwrap()
+    };
+    let response = index.send(get(files)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let listing: Value = serde_json::from_slice(
+        &axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap(),
+    )
+    .unwrap();
+    let members = listing["members"].as_array().unwrap();
+    assert!(members
+        .iter()
+        .any(|m| m["name"] == "demo-1.0.dist-info/METADATA"));
+
+    let response = index.send(get(&member)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    assert!(body.starts_with(b"Metadata-Version: 2.1\nName: demo\n"));
+}
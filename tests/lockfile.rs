@@ -0,0 +1,67 @@
+//! Everything a lockfile pins (filenames, URLs, hashes) must survive a
+//! restart of the server unchanged.
+
+mod common;
+
+use common::{get_text, require_client, spawn_server, upload, write_wheel};
+use reqwest::StatusCode;
+use tokio::process::Command;
+
+#[tokio::test]
+async fn index_pages_are_stable_across_restarts() {
+    let data = tempfile::tempdir().unwrap();
+    let wheels = tempfile::tempdir().unwrap();
+    let url = spawn_server(data.path()).await;
+    for name in ["zeta", "alpha", "mid", "beta"] {
+        let wheel = write_wheel(wheels.path(), name, "1.0.0");
+        assert_eq!(upload(&url, &wheel).await, StatusCode::OK);
+    }
+    let root = get_text(&format!("{url}/simple/")).await;
+    let project = get_text(&format!("{url}/simple/alpha/")).await;
+
+    let restarted = spawn_server(data.path()).await;
+    assert_eq!(root, get_text(&format!("{restarted}/simple/")).await);
+    assert_eq!(
+        project,
+        get_text(&format!("{restarted}/simple/alpha/")).await
+    );
+}
+
+#[tokio::test]
+#[ignore = "needs `uv` installed"]
+async fn uv_lockfile_installs_after_restart() {
+    require_client("uv").await;
+    let data = tempfile::tempdir().unwrap();
+    let work = tempfile::tempdir().unwrap();
+    let url = spawn_server(data.path()).await;
+    let wheel = write_wheel(work.path(), "lockpkg", "1.0.0");
+    assert_eq!(upload(&url, &wheel).await, StatusCode::OK);
+    std::fs::write(work.path().join("requirements.in"), "lockpkg==1.0.0\n").unwrap();
+
+    let compiled = Command::new("uv")
+        .args(["pip", "compile", "requirements.in", "--generate-hashes"])
+        .args(["-o", "requirements.txt", "--index-url"])
+        .arg(format!("{url}/simple/"))
+        .current_dir(work.path())
+        .status()
+        .await
+        .unwrap();
+    assert!(compiled.success());
+
+    let restarted = spawn_server(data.path()).await;
+    let installed = Command::new("uv")
+        .args([
+            "pip",
+            "install",
+            "--require-hashes",
+            "-r",
+            "requirements.txt",
+        ])
+        .args(["--target", "site", "--index-url"])
+        .arg(format!("{restarted}/simple/"))
+        .current_dir(work.path())
+        .status()
+        .await
+        .unwrap();
+    assert!(installed.success());
+}
@@ -0,0 +1,219 @@
+//! Replacing published files under the overwrite policy.
+
+use axum::{
+    body::Body,
+    http::{header, Request, StatusCode},
+};
+use pippy::{
+    dependencies::{DependencyCheck, DependencyPolicy},
+    testing::{SampleWheel, TestIndex, UploadForm},
+    AppError, Config, DistFilename, OverwritePolicy, PackageName, Provenance, ProvenanceSource,
+    Release,
+};
+use sha2::{Digest, Sha256};
+use tower::ServiceExt;
+
+async fn upload(index: &TestIndex, wheel: &SampleWheel) -> StatusCode {
+    index
+        .send(UploadForm::new().wheel(wheel).request("/upload"))
+        .await
+        .status()
+}
+
+#[tokio::test]
+async fn only_prereleases_are_overwritten_when_so_configured() {
+    let index = TestIndex::builder()
+        .overwrite(OverwritePolicy::AllowPrereleases)
+        .build()
+        .await
+        .unwrap();
+    for version in ["1.0.dev1", "1.0+build.1", "1.0"] {
+        let wheel = SampleWheel::new("demo", version);
+        assert_eq!(upload(&index, &wheel).await, StatusCode::OK);
+        let rebuilt = wheel.metadata("Summary", "Rebuilt");
+        let expected = if version == "1.0" {
+            StatusCode::CONFLICT
+        } else {
+            StatusCode::OK
+        };
+        assert_eq!(upload(&index, &rebuilt).await, expected, "{version}");
+    }
+
+    let packages = index.index().packages().await.unwrap();
+    assert_eq!(packages[0].releases.len(), 3);
+    let dev = SampleWheel::new("demo", "1.0.dev1").metadata("Summary", "Rebuilt");
+    let stored = std::fs::read(index.path().join("packages/demo").join(dev.filename())).unwrap();
+    assert_eq!(stored, dev.bytes());
+}
+
+#[tokio::test]
+async fn a_refused_replacement_leaves_the_published_file_alone() {
+    let wheel = SampleWheel::new("demo", "1.0.dev1");
+    let index = TestIndex::builder()
+        .overwrite(OverwritePolicy::Allow)
+        .config(Config {
+            dependencies: DependencyCheck::new(DependencyPolicy::Reject, Vec::new()),
+            ..Config::default()
+        })
+        .wheel(wheel.clone())
+        .build()
+        .await
+        .unwrap();
+
+    let broken = wheel.clone().metadata("Requires-Dist", "never-published");
+    assert_eq!(upload(&index, &broken).await, StatusCode::BAD_REQUEST);
+    let download = index
+        .send(
+            Request::get(format!("/packages/demo/{}", wheel.filename()))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(download.status(), StatusCode::OK);
+    let body = axum::body::to_bytes(download.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    assert_eq!(body, wheel.bytes());
+    // Nor is the refused file left behind where it was received.
+    let leftovers: Vec<_> = std::fs::read_dir(index.path().join("packages"))
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    assert_eq!(leftovers, ["demo"]);
+}
+
+#[tokio::test]
+async fn files_kept_by_a_snapshot_are_not_overwritten() {
+    let wheel = SampleWheel::new("demo", "1.0.dev1");
+    let index = TestIndex::builder()
+        .overwrite(OverwritePolicy::Allow)
+        .wheel(wheel.clone())
+        .build()
+        .await
+        .unwrap();
+    let snapshot = index
+        .send(
+            Request::post("/api/v1/snapshots/release-1")
+                .header(
+                    header::AUTHORIZATION,
+                    format!("token {}", index.admin_token().await.unwrap()),
+                )
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert!(snapshot.status().is_success());
+
+    let rebuilt = wheel.clone().metadata("Summary", "Rebuilt");
+    assert_eq!(upload(&index, &rebuilt).await, StatusCode::CONFLICT);
+    let stored = std::fs::read(index.path().join("packages/demo").join(wheel.filename())).unwrap();
+    assert_eq!(stored, wheel.bytes());
+    // New versions are still accepted.
+    let next = SampleWheel::new("demo", "1.0.dev2");
+    assert_eq!(upload(&index, &next).await, StatusCode::OK);
+}
+
+#[test]
+fn policies_parse_from_their_names_or_a_pattern() {
+    let version = |v: &str| v.parse().unwrap();
+    let pattern: OverwritePolicy = r"\d+\.\d+\.dev\d+".parse().unwrap();
+    assert!(pattern.allows(&version("2.0.dev4")));
+    assert!(!pattern.allows(&version("2.0.dev4.post1")));
+    assert!(!"deny"
+        .parse::<OverwritePolicy>()
+        .unwrap()
+        .allows(&version("2.0.dev4")));
+    assert!("allow"
+        .parse::<OverwritePolicy>()
+        .unwrap()
+        .allows(&version("2.0")));
+    assert!("(".parse::<OverwritePolicy>().is_err());
+}
+
+#[tokio::test]
+async fn files_indexed_without_the_upload_checks_still_follow_the_policy() {
+    let wheel = SampleWheel::new("demo", "1.0");
+    let index = TestIndex::builder()
+        .wheel(wheel.clone())
+        .build()
+        .await
+        .unwrap();
+    let published = format!("{:x}", Sha256::digest(wheel.bytes()));
+    let release = |sha256: String| {
+        Release::new(
+            "1.0".parse().unwrap(),
+            DistFilename::new(wheel.filename()).unwrap(),
+        )
+        .with_provenance(Provenance::new(ProvenanceSource::Reindex, sha256))
+    };
+
+    let rebuilt = wheel.clone().metadata("Summary", "Rebuilt");
+    let refused = index
+        .index()
+        .add_release(
+            PackageName::new("demo").unwrap(),
+            release(format!("{:x}", Sha256::digest(rebuilt.bytes()))),
+            None,
+        )
+        .await;
+    assert!(matches!(refused, Err(AppError::Conflict(_))), "{refused:?}");
+    // The same contents again change nothing.
+    index
+        .index()
+        .add_release(
+            PackageName::new("demo").unwrap(),
+            release(published.clone()),
+            None,
+        )
+        .await
+        .unwrap();
+    let packages = index.index().packages().await.unwrap();
+    assert_eq!(packages[0].releases.len(), 1);
+    assert_eq!(packages[0].releases[0].sha256(), Some(published.as_str()));
+    assert_eq!(
+        packages[0].releases[0].provenance.as_ref().unwrap().source,
+        ProvenanceSource::Upload
+    );
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn only_one_of_racing_uploads_of_a_filename_is_published() {
+    let index = TestIndex::new().await.unwrap();
+    let builds: Vec<SampleWheel> = (0..8)
+        .map(|build| SampleWheel::new("demo", "1.0").metadata("Summary", format!("Build {build}")))
+        .collect();
+    let uploads = builds.iter().map(|wheel| {
+        let request = UploadForm::new().wheel(wheel).request("/upload");
+        tokio::spawn(index.router().oneshot(request))
+    });
+    let statuses: Vec<StatusCode> = futures_util::future::join_all(uploads)
+        .await
+        .into_iter()
+        .map(|response| response.unwrap().unwrap().status())
+        .collect();
+    let published: Vec<&SampleWheel> = builds
+        .iter()
+        .zip(&statuses)
+        .filter(|(_, status)| **status == StatusCode::OK)
+        .map(|(wheel, _)| wheel)
+        .collect();
+    assert_eq!(published.len(), 1, "{statuses:?}");
+    assert!(statuses
+        .iter()
+        .all(|s| *s == StatusCode::OK || *s == StatusCode::CONFLICT));
+
+    let stored = std::fs::read(
+        index
+            .path()
+            .join("packages/demo")
+            .join(published[0].filename()),
+    )
+    .unwrap();
+    assert_eq!(stored, published[0].bytes());
+    let packages = index.index().packages().await.unwrap();
+    assert_eq!(packages[0].releases.len(), 1);
+    assert_eq!(
+        packages[0].releases[0].sha256(),
+        Some(format!("{:x}", Sha256::digest(&stored)).as_str())
+    );
+}
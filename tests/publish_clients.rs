@@ -0,0 +1,294 @@
+//! Publishes to an in-process server the way twine, uv, poetry and pdm do.
+//! Tests driving a real client are skipped when it is not on `PATH`.
+
+mod common;
+
+use std::path::{Path, PathBuf};
+
+use common::{get_text, require_client, spawn_server, write_wheel};
+use reqwest::{multipart, StatusCode};
+use tokio::process::Command;
+
+/// Lays out a project directory with a built wheel under `dist/`, which is
+/// what poetry and pdm publish from.
+fn write_project(dir: &Path, name: &str, version: &str) -> PathBuf {
+    std::fs::write(
+        dir.join("pyproject.toml"),
+        format!(
+            "[project]\nname = \"{name}\"\nversion = \"{version}\"\n\n\
+             [tool.poetry]\nname = \"{name}\"\nversion = \"{version}\"\ndescription = \"\"\nauthors = []\n"
+        ),
+    )
+    .unwrap();
+    std::fs::create_dir_all(dir.join("dist")).unwrap();
+    write_wheel(&dir.join("dist"), name, version)
+}
+
+async fn assert_listed(url: &str, name: &str, wheel: &Path) {
+    let filename = wheel.file_name().unwrap().to_str().unwrap();
+    let page = get_text(&format!("{url}/simple/{name}/")).await;
+    assert!(page.contains(filename), "{filename} missing from {page}");
+}
+
+#[tokio::test]
+async fn legacy_form_upload_is_accepted_with_trailing_slash() {
+    let data = tempfile::tempdir().unwrap();
+    let url = spawn_server(data.path()).await;
+    let wheel = write_wheel(data.path(), "formpkg", "1.0.0");
+
+    let form = multipart::Form::new()
+        .text(":action", "file_upload")
+        .text("protocol_version", "1")
+        .text("name", "formpkg")
+        .text("version", "1.0.0")
+        .text("filetype", "bdist_wheel")
+        .part(
+            "content",
+            multipart::Part::bytes(std::fs::read(&wheel).unwrap())
+                .file_name("formpkg-1.0.0-py3-none-any.whl"),
+        );
+    let response = reqwest::Client::new()
+        .post(format!("{url}/legacy/"))
+        .multipart(form)
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_listed(&url, "formpkg", &wheel).await;
+}
+
+/// The form twine sends for one wheel, with the given declared digest.
+fn twine_form(wheel: &Path, name: &str, version: &str, sha256: &str) -> multipart::Form {
+    multipart::Form::new()
+        .text(":action", "file_upload")
+        .text("protocol_version", "1")
+        .text("name", name.to_string())
+        .text("version", version.to_string())
+        .text("filetype", "bdist_wheel")
+        .text("sha256_digest", sha256.to_string())
+        .part(
+            "content",
+            multipart::Part::bytes(std::fs::read(wheel).unwrap())
+                .file_name(wheel.file_name().unwrap().to_string_lossy().into_owned()),
+        )
+}
+
+#[tokio::test]
+async fn twine_form_fields_are_checked_against_the_file() {
+    use sha2::{Digest, Sha256};
+
+    let data = tempfile::tempdir().unwrap();
+    let url = spawn_server(data.path()).await;
+    let wheel = write_wheel(data.path(), "formpkg", "1.0.0");
+    let digest = format!("{:x}", Sha256::digest(std::fs::read(&wheel).unwrap()));
+    let client = reqwest::Client::new();
+
+    for (name, version, sha256) in [
+        ("otherpkg", "1.0.0", digest.as_str()),
+        ("formpkg", "2.0.0", digest.as_str()),
+        ("formpkg", "1.0.0", "0000"),
+    ] {
+        let response = client
+            .post(format!("{url}/"))
+            .multipart(twine_form(&wheel, name, version, sha256))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+    let page = get_text(&format!("{url}/simple/")).await;
+    assert!(!page.contains("formpkg"));
+
+    let response = client
+        .post(format!("{url}/"))
+        .multipart(twine_form(&wheel, "FormPkg", "1.0.0", &digest))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_listed(&url, "formpkg", &wheel).await;
+}
+
+#[tokio::test]
+async fn upload_without_distribution_is_rejected() {
+    let data = tempfile::tempdir().unwrap();
+    let url = spawn_server(data.path()).await;
+
+    let form = multipart::Form::new().text(":action", "file_upload");
+    let response = reqwest::Client::new()
+        .post(format!("{url}/upload/"))
+        .multipart(form)
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn project_page_without_trailing_slash_redirects() {
+    let data = tempfile::tempdir().unwrap();
+    let url = spawn_server(data.path()).await;
+
+    let response = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap()
+        .get(format!("{url}/simple/formpkg"))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+    assert_eq!(response.headers()["location"], "/simple/formpkg/");
+}
+
+#[tokio::test]
+#[ignore = "needs `twine` installed"]
+async fn twine_upload() {
+    require_client("twine").await;
+    let data = tempfile::tempdir().unwrap();
+    let url = spawn_server(data.path()).await;
+    let wheel = write_wheel(data.path(), "twinepkg", "1.0.0");
+
+    let status = Command::new("twine")
+        .args(["upload", "--non-interactive", "--repository-url"])
+        .arg(format!("{url}/legacy/"))
+        .args(["-u", "user", "-p", "pass"])
+        .arg(&wheel)
+        .status()
+        .await
+        .unwrap();
+
+    assert!(status.success());
+    assert_listed(&url, "twinepkg", &wheel).await;
+}
+
+#[tokio::test]
+#[ignore = "needs `uv` installed"]
+async fn uv_publish() {
+    require_client("uv").await;
+    let data = tempfile::tempdir().unwrap();
+    let url = spawn_server(data.path()).await;
+    let wheel = write_wheel(data.path(), "uvpkg", "1.0.0");
+
+    let status = Command::new("uv")
+        .args(["publish", "--publish-url"])
+        .arg(format!("{url}/legacy/"))
+        .args(["--username", "user", "--password", "pass"])
+        .arg(&wheel)
+        .status()
+        .await
+        .unwrap();
+
+    assert!(status.success());
+    assert_listed(&url, "uvpkg", &wheel).await;
+}
+
+#[tokio::test]
+#[ignore = "needs `poetry` installed"]
+async fn poetry_publish() {
+    require_client("poetry").await;
+    let data = tempfile::tempdir().unwrap();
+    let url = spawn_server(data.path()).await;
+    let project = tempfile::tempdir().unwrap();
+    let wheel = write_project(project.path(), "poetrypkg", "1.0.0");
+
+    let status = Command::new("poetry")
+        .args(["publish", "-r", "pippy", "-u", "user", "-p", "pass"])
+        .env("POETRY_REPOSITORIES_PIPPY_URL", format!("{url}/legacy/"))
+        .current_dir(project.path())
+        .status()
+        .await
+        .unwrap();
+
+    assert!(status.success());
+    assert_listed(&url, "poetrypkg", &wheel).await;
+}
+
+#[tokio::test]
+#[ignore = "needs `pdm` installed"]
+async fn pdm_publish() {
+    require_client("pdm").await;
+    let data = tempfile::tempdir().unwrap();
+    let url = spawn_server(data.path()).await;
+    let project = tempfile::tempdir().unwrap();
+    let wheel = write_project(project.path(), "pdmpkg", "1.0.0");
+
+    let status = Command::new("pdm")
+        .args(["publish", "--no-build", "-r"])
+        .arg(format!("{url}/legacy/"))
+        .args(["-u", "user", "-P", "pass"])
+        .current_dir(project.path())
+        .status()
+        .await
+        .unwrap();
+
+    assert!(status.success());
+    assert_listed(&url, "pdmpkg", &wheel).await;
+}
+
+#[tokio::test]
+async fn retried_uploads_of_the_same_file_are_not_recorded_twice() {
+    use sha2::{Digest, Sha256};
+
+    let data = tempfile::tempdir().unwrap();
+    let url = spawn_server(data.path()).await;
+    let wheel = write_wheel(data.path(), "formpkg", "1.0.0");
+    let digest = format!("{:x}", Sha256::digest(std::fs::read(&wheel).unwrap()));
+    let client = reqwest::Client::new();
+
+    for _ in 0..2 {
+        let response = client
+            .post(format!("{url}/"))
+            .multipart(twine_form(&wheel, "formpkg", "1.0.0", &digest))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let receipt: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(receipt["files"][0]["digests"]["sha256"], digest);
+    }
+    let project: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(data.path().join("projects/formpkg.json")).unwrap())
+            .unwrap();
+    assert_eq!(project["releases"].as_array().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn a_different_file_under_a_published_name_is_refused() {
+    let data = tempfile::tempdir().unwrap();
+    let url = spawn_server(data.path()).await;
+    let wheel = write_wheel(data.path(), "formpkg", "1.0.0");
+    let original = std::fs::read(&wheel).unwrap();
+    let client = reqwest::Client::new();
+    let upload = |contents: Vec<u8>| {
+        let form = multipart::Form::new().part(
+            "content",
+            multipart::Part::bytes(contents).file_name("formpkg-1.0.0-py3-none-any.whl"),
+        );
+        client.post(format!("{url}/")).multipart(form).send()
+    };
+
+    assert_eq!(
+        upload(original.clone()).await.unwrap().status(),
+        StatusCode::OK
+    );
+    let mut changed = original.clone();
+    changed.extend_from_slice(b"trailing bytes");
+    assert_eq!(
+        upload(changed).await.unwrap().status(),
+        StatusCode::CONFLICT
+    );
+
+    let served = reqwest::get(format!(
+        "{url}/packages/formpkg/formpkg-1.0.0-py3-none-any.whl"
+    ))
+    .await
+    .unwrap()
+    .bytes()
+    .await
+    .unwrap();
+    assert_eq!(served, original);
+}
@@ -0,0 +1,67 @@
+//! The PyPI-compatible JSON API.
+
+use axum::{body::Body, http::Request, http::StatusCode};
+use pippy::testing::{SampleWheel, TestIndex};
+use serde_json::Value;
+
+async fn get_json(index: &TestIndex, uri: &str) -> (StatusCode, Value) {
+    let response = index
+        .send(Request::get(uri).body(Body::empty()).unwrap())
+        .await;
+    let status = response.status();
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    (
+        status,
+        serde_json::from_slice(&bytes).unwrap_or(Value::Null),
+    )
+}
+
+#[tokio::test]
+async fn project_json_describes_the_latest_final_release() {
+    let index = TestIndex::builder()
+        .wheel(SampleWheel::new("demo", "1.0"))
+        .wheel(
+            SampleWheel::new("demo", "1.1")
+                .metadata("Summary", "A demo")
+                .metadata("Requires-Dist", "requests>=2")
+                .metadata("Requires-Python", ">=3.9"),
+        )
+        .wheel(SampleWheel::new("demo", "2.0rc1"))
+        .build()
+        .await
+        .unwrap();
+
+    let (status, json) = get_json(&index, "/pypi/demo/json").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(json["info"]["version"], "1.1");
+    assert_eq!(json["info"]["summary"], "A demo");
+    assert_eq!(json["info"]["requires_dist"][0], "requests>=2");
+    assert_eq!(json["info"]["requires_python"], ">=3.9");
+    assert_eq!(json["urls"][0]["filename"], "demo-1.1-py3-none-any.whl");
+    assert_eq!(json["urls"][0]["packagetype"], "bdist_wheel");
+    assert!(json["urls"][0]["digests"]["sha256"].is_string());
+    for version in ["1.0", "1.1", "2.0rc1"] {
+        assert_eq!(json["releases"][version].as_array().unwrap().len(), 1);
+    }
+}
+
+#[tokio::test]
+async fn release_json_describes_one_version() {
+    let index = TestIndex::builder()
+        .wheel(SampleWheel::new("demo", "1.0"))
+        .wheel(SampleWheel::new("demo", "1.1"))
+        .build()
+        .await
+        .unwrap();
+
+    let (status, json) = get_json(&index, "/pypi/demo/1.0/json").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(json["info"]["version"], "1.0");
+    assert!(json.get("releases").is_none());
+    assert_eq!(json["urls"].as_array().unwrap().len(), 1);
+
+    let (status, _) = get_json(&index, "/pypi/demo/9.9/json").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
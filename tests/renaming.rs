@@ -0,0 +1,86 @@
+//! Renaming projects, and the tombstones left at their old names.
+
+use axum::{
+    body::Body,
+    http::{header, Request, StatusCode},
+};
+use pippy::testing::{SampleWheel, TestIndex, UploadForm};
+
+async fn renamed_index() -> TestIndex {
+    let index = TestIndex::builder()
+        .wheel(SampleWheel::new("demo", "1.0"))
+        .build()
+        .await
+        .unwrap();
+    index
+        .index()
+        .rename(&"demo".parse().unwrap(), "demo-core".parse().unwrap())
+        .await
+        .unwrap();
+    index
+}
+
+#[tokio::test]
+async fn renamed_projects_redirect_from_their_old_name() {
+    let index = renamed_index().await;
+
+    let response = index
+        .send(Request::get("/simple/demo/").body(Body::empty()).unwrap())
+        .await;
+    assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+    assert_eq!(response.headers()[header::LOCATION], "/simple/demo-core/");
+
+    let response = index
+        .send(
+            Request::get("/simple/demo-core/")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(index
+        .path()
+        .join("packages/demo-core")
+        .join(SampleWheel::new("demo", "1.0").filename())
+        .exists());
+}
+
+#[tokio::test]
+async fn old_names_stay_reserved_after_a_rename() {
+    let index = renamed_index().await;
+    let token = index.admin_token().await.unwrap();
+
+    let response = index
+        .send(
+            UploadForm::new()
+                .wheel(&SampleWheel::new("demo", "2.0"))
+                .request("/upload"),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+
+    // Deleting the tombstone would free the name for anyone to register.
+    let response = index
+        .send(
+            Request::delete("/api/v1/projects/demo")
+                .header(header::AUTHORIZATION, format!("token {token}"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    let response = index
+        .send(
+            UploadForm::new()
+                .wheel(&SampleWheel::new("Demo", "2.0"))
+                .request("/upload"),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+
+    let renamed = index
+        .index()
+        .rename(&"demo-core".parse().unwrap(), "demo".parse().unwrap())
+        .await;
+    assert!(matches!(renamed, Err(pippy::AppError::Conflict(_))));
+}
@@ -0,0 +1,130 @@
+//! Pruning old releases under a retention policy.
+
+use std::time::Duration;
+
+use axum::{
+    body::Body,
+    http::{header, Request, StatusCode},
+};
+use pippy::{
+    retention::{self, RetentionPolicy},
+    testing::{SampleWheel, TestIndex, UploadForm},
+    PackageIndex,
+};
+
+async fn files(index: &TestIndex) -> Vec<String> {
+    let packages = index.index().packages().await.unwrap();
+    packages[0]
+        .releases
+        .iter()
+        .map(|r| r.filename.to_string())
+        .collect()
+}
+
+#[tokio::test]
+async fn only_the_newest_versions_are_kept_on_upload() {
+    let index = TestIndex::builder()
+        .retention(RetentionPolicy {
+            max_versions: Some(2),
+            ..RetentionPolicy::default()
+        })
+        .wheel(SampleWheel::new("demo", "1.0"))
+        .wheel(SampleWheel::new("demo", "1.1"))
+        .wheel(SampleWheel::new("demo", "2.0rc1"))
+        .build()
+        .await
+        .unwrap();
+    let old = SampleWheel::new("demo", "1.0").filename();
+    assert!(!files(&index).await.contains(&old));
+    assert!(!index.path().join("packages/demo").join(&old).exists());
+    let download = index
+        .send(
+            Request::get(format!("/packages/demo/{old}"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(download.status(), StatusCode::NOT_FOUND);
+
+    // 1.1 is the latest stable release, so it outlives newer pre-releases.
+    for version in ["2.0rc2", "2.0rc3"] {
+        let response = index
+            .send(
+                UploadForm::new()
+                    .wheel(&SampleWheel::new("demo", version))
+                    .request("/upload"),
+            )
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+    assert_eq!(
+        files(&index).await,
+        [
+            SampleWheel::new("demo", "2.0rc3").filename(),
+            SampleWheel::new("demo", "2.0rc2").filename(),
+            SampleWheel::new("demo", "1.1").filename(),
+        ]
+    );
+    let audit = std::fs::read_to_string(index.path().join("audit.jsonl")).unwrap();
+    assert!(audit.contains(&old));
+
+    // A backport is accepted even though it is older than the versions kept.
+    let backport = SampleWheel::new("demo", "1.0.1");
+    let response = index
+        .send(UploadForm::new().wheel(&backport).request("/upload"))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(files(&index).await.contains(&backport.filename()));
+}
+
+#[tokio::test]
+async fn old_releases_are_pruned_unless_a_snapshot_keeps_them() {
+    let index = TestIndex::builder()
+        .wheel(SampleWheel::new("demo", "1.0"))
+        .build()
+        .await
+        .unwrap();
+    let snapshot = index
+        .send(
+            Request::post("/api/v1/snapshots/release-1")
+                .header(
+                    header::AUTHORIZATION,
+                    format!("token {}", index.admin_token().await.unwrap()),
+                )
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert!(snapshot.status().is_success());
+    for version in ["1.1", "2.0", "3.0b1"] {
+        let response = index
+            .send(
+                UploadForm::new()
+                    .wheel(&SampleWheel::new("demo", version))
+                    .request("/upload"),
+            )
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    // Everything is older than no time at all.
+    let reopened = PackageIndex::new(index.path().to_path_buf())
+        .await
+        .unwrap()
+        .with_retention(RetentionPolicy {
+            max_age: Some(Duration::ZERO),
+            ..RetentionPolicy::default()
+        });
+    let pruned = retention::prune_all(&reopened).await.unwrap();
+    let mut pruned: Vec<String> = pruned
+        .iter()
+        .map(|(_, release)| release.version.to_string())
+        .collect();
+    pruned.sort();
+    assert_eq!(pruned, ["1.1", "3.0b1"]);
+
+    let demo = &reopened.packages().await.unwrap()[0];
+    let kept: Vec<&str> = demo.releases.iter().map(|r| r.version.as_str()).collect();
+    assert_eq!(kept, ["2.0", "1.0"]);
+    assert!(retention::prune_all(&reopened).await.unwrap().is_empty());
+}
@@ -0,0 +1,101 @@
+//! An orderly shutdown: open requests are finished, then background work
+//! and buffered stats are saved before the process exits.
+
+use std::time::Duration;
+
+use axum::{routing::get, Router};
+use pippy::{
+    server::{self, ConnectionSettings},
+    testing::{SampleWheel, TestIndex, UploadForm},
+};
+use serde_json::Value;
+use tokio::{net::TcpListener, sync::oneshot, task::JoinHandle};
+
+/// Serves `router` with `/slow` added, taking `delay` to answer, until the
+/// returned sender fires.
+async fn serve(
+    router: Router,
+    delay: Duration,
+    settings: ConnectionSettings,
+) -> (String, oneshot::Sender<()>, JoinHandle<std::io::Result<()>>) {
+    let router = router.route(
+        "/slow",
+        get(move || async move {
+            tokio::time::sleep(delay).await;
+            "done"
+        }),
+    );
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+    let (stop, stopped) = oneshot::channel();
+    let server = tokio::spawn(async move {
+        server::serve(listener, router, &settings, async {
+            let _ = stopped.await;
+        })
+        .await
+    });
+    (base, stop, server)
+}
+
+#[tokio::test]
+async fn open_requests_finish_before_the_server_stops() {
+    let index = TestIndex::new().await.unwrap();
+    let (base, stop, server) = serve(
+        index.router(),
+        Duration::from_millis(300),
+        ConnectionSettings::default(),
+    )
+    .await;
+    let slow = tokio::spawn(reqwest::get(format!("{base}/slow")));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    stop.send(()).unwrap();
+    let response = slow.await.unwrap().unwrap();
+    assert_eq!(response.text().await.unwrap(), "done");
+    server.await.unwrap().unwrap();
+    assert!(reqwest::get(format!("{base}/simple/")).await.is_err());
+}
+
+#[tokio::test]
+async fn connections_still_open_after_the_drain_timeout_are_dropped() {
+    let index = TestIndex::new().await.unwrap();
+    let settings = ConnectionSettings {
+        drain_timeout: Duration::from_millis(100),
+        ..ConnectionSettings::default()
+    };
+    let (base, stop, server) = serve(index.router(), Duration::from_secs(60), settings).await;
+    let slow = tokio::spawn(reqwest::get(format!("{base}/slow")));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    stop.send(()).unwrap();
+    tokio::time::timeout(Duration::from_secs(5), server)
+        .await
+        .expect("the server stops at the drain timeout")
+        .unwrap()
+        .unwrap();
+    assert!(slow.await.unwrap().is_err());
+}
+
+#[tokio::test]
+async fn index_shutdown_saves_stats_and_finishes_enrichment() {
+    let index = TestIndex::new().await.unwrap();
+    let response = index
+        .send(
+            UploadForm::new()
+                .wheel(&SampleWheel::new("demo", "1.0"))
+                .request("/upload"),
+        )
+        .await;
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    assert!(!index.path().join("stats.json").exists());
+
+    assert!(index.index().shutdown(Duration::from_secs(5)).await);
+    let read = |path: &str| -> Value {
+        serde_json::from_slice(&std::fs::read(index.path().join(path)).unwrap()).unwrap()
+    };
+    let stats = read("stats.json");
+    let monthly = stats["demo"]["monthly"].as_object().unwrap();
+    assert_eq!(monthly.values().next().unwrap()["uploads"], 1);
+    let project = read("projects/demo.json");
+    assert!(project["releases"][0]["enrichments"]["size"].is_object());
+}
@@ -0,0 +1,399 @@
+//! What installers read from the simple index.
+
+use axum::{
+    body::Body,
+    http::{header, Request, StatusCode},
+};
+use pippy::{
+    testing::{SampleWheel, TestIndex, UploadForm},
+    Config,
+};
+use sha2::{Digest, Sha256};
+
+async fn body_text(response: axum::response::Response) -> String {
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    String::from_utf8(bytes.to_vec()).unwrap()
+}
+
+#[tokio::test]
+async fn file_links_carry_sha256_fragments() {
+    let wheel = SampleWheel::new("demo", "1.0");
+    let index = TestIndex::builder()
+        .wheel(wheel.clone())
+        .build()
+        .await
+        .unwrap();
+    let digest = format!("{:x}", Sha256::digest(wheel.bytes()));
+
+    let page = index
+        .send(Request::get("/simple/demo/").body(Body::empty()).unwrap())
+        .await;
+    assert!(body_text(page)
+        .await
+        .contains(&format!("{}#sha256={digest}'", wheel.filename())));
+}
+
+#[tokio::test]
+async fn checksums_list_recorded_digests_unless_an_admin_rehashes() {
+    let wheel = SampleWheel::new("demo", "1.0");
+    let index = TestIndex::builder()
+        .wheel(wheel.clone())
+        .build()
+        .await
+        .unwrap();
+    let recorded = format!("{:x}", Sha256::digest(wheel.bytes()));
+    // Replaced behind the index's back, as fsck would find.
+    let stored = index.path().join("packages/demo").join(wheel.filename());
+    std::fs::remove_file(&stored).unwrap();
+    std::fs::write(&stored, b"tampered").unwrap();
+    let tampered = format!("{:x}", Sha256::digest(b"tampered"));
+    let uri = "/api/v1/projects/demo/checksums";
+
+    let sums = index
+        .send(Request::get(uri).body(Body::empty()).unwrap())
+        .await;
+    assert_eq!(
+        body_text(sums).await,
+        format!("{recorded}  {}\n", wheel.filename())
+    );
+    let rehash = format!("{uri}?rehash=true");
+    let anonymous = index
+        .send(Request::get(&rehash).body(Body::empty()).unwrap())
+        .await;
+    assert_eq!(anonymous.status(), StatusCode::UNAUTHORIZED);
+    let admin = index
+        .send(
+            Request::get(&rehash)
+                .header(
+                    header::AUTHORIZATION,
+                    format!("token {}", index.admin_token().await.unwrap()),
+                )
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(
+        body_text(admin).await,
+        format!("{tampered}  {}\n", wheel.filename())
+    );
+}
+
+#[tokio::test]
+async fn wheel_metadata_is_served_alongside_the_wheel() {
+    let wheel = SampleWheel::new("demo", "1.0");
+    let index = TestIndex::builder()
+        .wheel(wheel.clone())
+        .build()
+        .await
+        .unwrap();
+
+    let metadata = index
+        .send(
+            Request::get(format!("/packages/demo/{}.metadata", wheel.filename()))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    let metadata = body_text(metadata).await;
+    assert!(metadata.contains("Name: demo\nVersion: 1.0\n"));
+    let digest = format!("{:x}", Sha256::digest(metadata.as_bytes()));
+
+    let page = index
+        .send(Request::get("/simple/demo/").body(Body::empty()).unwrap())
+        .await;
+    assert!(body_text(page)
+        .await
+        .contains(&format!("data-core-metadata='sha256={digest}'")));
+}
+
+#[tokio::test]
+async fn links_carry_requires_python() {
+    let wheel = SampleWheel::new("demo", "1.0").metadata("Requires-Python", ">=3.9");
+    let index = TestIndex::builder().wheel(wheel).build().await.unwrap();
+
+    let page = index
+        .send(Request::get("/simple/demo/").body(Body::empty()).unwrap())
+        .await;
+    assert!(body_text(page)
+        .await
+        .contains("data-requires-python='&gt;=3.9'"));
+}
+
+#[tokio::test]
+async fn sdists_are_listed() {
+    let index = TestIndex::new().await.unwrap();
+    let response = index
+        .send(
+            UploadForm::new()
+                .file("legacy-tool-2.0.tar.gz", b"not really gzip".to_vec())
+                .request("/upload"),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let page = index
+        .send(
+            Request::get("/simple/legacy-tool/")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    let page = body_text(page).await;
+    assert!(page.contains("/packages/legacy-tool/legacy-tool-2.0.tar.gz#sha256="));
+    assert!(!page.contains("data-core-metadata"));
+}
+
+#[tokio::test]
+async fn releases_are_listed_by_version_not_upload_order() {
+    let mut builder = TestIndex::builder();
+    for version in ["1.10", "2.0", "1.9", "1.10rc1", "1.10.post1"] {
+        builder = builder.wheel(SampleWheel::new("demo", version));
+    }
+    let index = builder.build().await.unwrap();
+
+    let page = index
+        .send(Request::get("/simple/demo/").body(Body::empty()).unwrap())
+        .await;
+    let page = body_text(page).await;
+    let positions: Vec<usize> = ["2.0", "1.10.post1", "1.10", "1.10rc1", "1.9"]
+        .iter()
+        .map(|v| page.find(&format!(">demo-{v}-py3-none-any.whl<")).unwrap())
+        .collect();
+    assert!(positions.windows(2).all(|w| w[0] < w[1]), "{page}");
+}
+
+#[tokio::test]
+async fn yanked_files_are_marked_and_not_latest() {
+    let (old, new) = (
+        SampleWheel::new("demo", "1.0"),
+        SampleWheel::new("demo", "2.0"),
+    );
+    let index = TestIndex::builder()
+        .wheel(old.clone())
+        .wheel(new.clone())
+        .build()
+        .await
+        .unwrap();
+
+    let response = index
+        .send(
+            Request::patch(format!("/api/v1/projects/demo/files/{}", new.filename()))
+                .header("content-type", "application/json")
+                .body(Body::from(
+                    r#"{"yanked": true, "yanked_reason": "broken <build>"}"#,
+                ))
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let page = index
+        .send(Request::get("/simple/demo/").body(Body::empty()).unwrap())
+        .await;
+    let page = body_text(page).await;
+    assert!(page.contains("data-yanked='broken &lt;build&gt;'>demo-2.0-"));
+    assert!(!page.contains("data-yanked='broken &lt;build&gt;'>demo-1.0-"));
+
+    let json = index
+        .send(
+            Request::get("/simple/demo/")
+                .header("accept", "application/vnd.pypi.simple.v1+json")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert!(body_text(json)
+        .await
+        .contains(r#""yanked":"broken <build>""#));
+
+    let latest = index
+        .send(
+            Request::get("/project/demo/latest")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    let location = latest.headers()["location"].to_str().unwrap();
+    assert!(location.ends_with(&old.filename()), "{location}");
+}
+
+#[tokio::test]
+async fn root_listing_counts_files() {
+    let index = TestIndex::builder()
+        .wheel(SampleWheel::new("demo", "1.0"))
+        .wheel(SampleWheel::new("demo", "1.1"))
+        .wheel(SampleWheel::new("other", "0.1"))
+        .build()
+        .await
+        .unwrap();
+
+    let json = index
+        .send(
+            Request::get("/simple/")
+                .header("accept", "application/vnd.pypi.simple.v1+json")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    let json: serde_json::Value = serde_json::from_str(&body_text(json).await).unwrap();
+    assert_eq!(json["meta"]["_project-count"], 2);
+    assert_eq!(json["projects"][0]["name"], "demo");
+    assert_eq!(json["projects"][0]["_files"], 2);
+    assert!(json["projects"][0]["_last-upload"].is_string());
+
+    let page = index
+        .send(Request::get("/simple/").body(Body::empty()).unwrap())
+        .await;
+    assert!(body_text(page).await.contains("data-files='1'"));
+}
+
+#[tokio::test]
+async fn links_use_the_configured_base_url() {
+    let index = TestIndex::builder()
+        .config(Config {
+            base_url: Some("https://pkg.example.com".into()),
+            path_prefix: "/pypi".into(),
+            ..Config::default()
+        })
+        .wheel(SampleWheel::new("demo", "1.0"))
+        .build()
+        .await
+        .unwrap();
+
+    let page = index
+        .send(Request::get("/simple/").body(Body::empty()).unwrap())
+        .await;
+    assert!(body_text(page)
+        .await
+        .contains("href='https://pkg.example.com/pypi/simple/demo/'"));
+
+    let json = index
+        .send(
+            Request::get("/simple/demo/")
+                .header("accept", "application/vnd.pypi.simple.v1+json")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert!(body_text(json).await.contains(
+        r#""url":"https://pkg.example.com/pypi/packages/demo/demo-1.0-py3-none-any.whl""#
+    ));
+}
+
+async fn get(index: &TestIndex, path: &str) -> axum::response::Response {
+    index
+        .send(Request::get(path).body(Body::empty()).unwrap())
+        .await
+}
+
+async fn yank(index: &TestIndex, wheel: &SampleWheel) {
+    let response = index
+        .send(
+            Request::patch(format!("/api/v1/projects/demo/files/{}", wheel.filename()))
+                .header("content-type", "application/json")
+                .body(Body::from(r#"{"yanked": true}"#))
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn latest_skips_prereleases_and_yanked_files() {
+    let mut builder = TestIndex::builder();
+    for version in ["1.0", "1.1", "2.0rc1", "2.0.dev3"] {
+        builder = builder.wheel(SampleWheel::new("demo", version));
+    }
+    let index = builder.build().await.unwrap();
+
+    for path in ["/project/demo/latest", "/packages/demo/latest.whl"] {
+        let response = get(&index, path).await;
+        assert_eq!(response.status(), StatusCode::FOUND);
+        let location = response.headers()["location"].to_str().unwrap();
+        assert!(
+            location.ends_with("/demo-1.1-py3-none-any.whl"),
+            "{location}"
+        );
+    }
+
+    yank(&index, &SampleWheel::new("demo", "1.1")).await;
+    let response = get(&index, "/project/demo/latest").await;
+    let location = response.headers()["location"].to_str().unwrap();
+    assert!(
+        location.ends_with("/demo-1.0-py3-none-any.whl"),
+        "{location}"
+    );
+
+    // Only prereleases are left unyanked, and those are never latest.
+    yank(&index, &SampleWheel::new("demo", "1.0")).await;
+    for path in ["/project/demo/latest", "/packages/demo/latest.whl"] {
+        assert_eq!(get(&index, path).await.status(), StatusCode::NOT_FOUND);
+    }
+}
+
+#[tokio::test]
+async fn json_or_html_is_negotiated_by_accept() {
+    let wheel = SampleWheel::new("demo", "1.0");
+    let index = TestIndex::builder()
+        .wheel(wheel.clone())
+        .build()
+        .await
+        .unwrap();
+    let json = "application/vnd.pypi.simple.v1+json";
+    let cases = [
+        // As pip and uv send it.
+        (
+            "application/vnd.pypi.simple.v1+json, application/vnd.pypi.simple.v1+html; q=0.1, text/html; q=0.01",
+            json,
+        ),
+        ("application/vnd.pypi.simple.latest+json", json),
+        ("text/html;q=0.5, application/vnd.pypi.simple.v1+json;q=0.9", json),
+        ("*/*, application/vnd.pypi.simple.v1+json", json),
+        ("application/vnd.pypi.simple.v1+json;q=0, text/html", "text/html"),
+        ("application/vnd.pypi.simple.v1+html", "text/html"),
+        ("*/*", "text/html"),
+        ("application/xml", "text/html"),
+        ("", "text/html"),
+    ];
+    for path in ["/simple/", "/simple/demo/", "/channels/stable/simple/demo/"] {
+        for (accept, expected) in cases {
+            let mut request = Request::get(path);
+            if !accept.is_empty() {
+                request = request.header(header::ACCEPT, accept);
+            }
+            let response = index.send(request.body(Body::empty()).unwrap()).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            let content_type = response.headers()[header::CONTENT_TYPE].to_str().unwrap();
+            assert!(
+                content_type.starts_with(expected),
+                "{path} {accept:?}: {content_type}"
+            );
+        }
+    }
+
+    let detail = index
+        .send(
+            Request::get("/simple/demo/")
+                .header(header::ACCEPT, json)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(detail.headers()[header::VARY], "Accept");
+    let detail: serde_json::Value = serde_json::from_str(&body_text(detail).await).unwrap();
+    let digest = format!("{:x}", Sha256::digest(wheel.bytes()));
+    assert_eq!(detail["meta"]["api-version"], "1.0");
+    assert_eq!(detail["name"], "demo");
+    let file = &detail["files"][0];
+    assert_eq!(file["filename"], wheel.filename());
+    assert_eq!(file["hashes"]["sha256"], digest);
+    assert!(file["url"]
+        .as_str()
+        .unwrap()
+        .ends_with(&format!("/packages/demo/{}", wheel.filename())));
+    assert!(file["core-metadata"]["sha256"].is_string());
+    assert_eq!(file["core-metadata"], file["dist-info-metadata"]);
+    assert!(file.get("yanked").is_none());
+}
@@ -0,0 +1,119 @@
+//! Download and upload counts, kept hourly, daily and monthly, each
+//! generation pruned to its own retention.
+
+use std::time::Duration;
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+};
+use chrono::{Months, Utc};
+use pippy::{
+    stats::StatsRetention,
+    testing::{SampleWheel, TestIndex},
+    PackageIndex,
+};
+use serde_json::{json, Value};
+
+async fn stats(router: axum::Router) -> Value {
+    let response = tower::ServiceExt::oneshot(
+        router,
+        Request::get("/api/v1/projects/demo/stats")
+            .body(Body::empty())
+            .unwrap(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    serde_json::from_slice(&body).unwrap()
+}
+
+fn buckets(generation: &Value) -> Vec<String> {
+    generation.as_object().unwrap().keys().cloned().collect()
+}
+
+#[tokio::test]
+async fn counts_are_downsampled_and_pruned_per_generation() {
+    let wheel = SampleWheel::new("demo", "1.0");
+    let index = TestIndex::builder()
+        .wheel(wheel.clone())
+        .build()
+        .await
+        .unwrap();
+    let now = Utc::now();
+    let hour = |hours: i64| (now - chrono::Duration::hours(hours)).format("%Y-%m-%dT%H");
+    let day = |days: i64| (now - chrono::Duration::days(days)).format("%Y-%m-%d");
+    let month = |months: u32| {
+        now.checked_sub_months(Months::new(months))
+            .unwrap()
+            .format("%Y-%m")
+    };
+    let (this_hour, today, this_month) = (hour(0), day(0), month(0));
+    let old = json!({ "downloads": 5, "download_bytes": 500 });
+    let saved = json!({
+        "demo": {
+            "hourly": { hour(1).to_string(): old, hour(72).to_string(): old },
+            "daily": { day(1).to_string(): old, day(120).to_string(): old },
+            "monthly": { month(1).to_string(): old, month(48).to_string(): old },
+        }
+    });
+    std::fs::write(index.path().join("stats.json"), saved.to_string()).unwrap();
+
+    let response = index
+        .send(
+            Request::get(format!("/packages/demo/{}", wheel.filename()))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Unsaved counts are reported too, once in each generation.
+    let reported = stats(index.router()).await;
+    assert_eq!(
+        buckets(&reported["hourly"]),
+        [hour(1).to_string(), this_hour.to_string()]
+    );
+    assert_eq!(
+        buckets(&reported["daily"]),
+        [day(1).to_string(), today.to_string()]
+    );
+    assert_eq!(
+        buckets(&reported["monthly"]),
+        [month(1).to_string(), this_month.to_string()]
+    );
+    let size = wheel.bytes().len() as u64;
+    for bucket in [
+        &reported["hourly"][this_hour.to_string()],
+        &reported["daily"][today.to_string()],
+        &reported["monthly"][this_month.to_string()],
+    ] {
+        assert_eq!(bucket["uploads"], 1);
+        assert_eq!(bucket["downloads"], 1);
+        assert_eq!(bucket["download_bytes"], size);
+    }
+
+    // Saving prunes the file as well.
+    assert!(index.index().shutdown(Duration::from_secs(5)).await);
+    let saved: Value =
+        serde_json::from_slice(&std::fs::read(index.path().join("stats.json")).unwrap()).unwrap();
+    assert_eq!(saved["demo"]["hourly"], reported["hourly"]);
+    assert_eq!(saved["demo"]["monthly"], reported["monthly"]);
+
+    // A shorter retention drops more on the next read.
+    let reopened = PackageIndex::new(index.path().to_path_buf())
+        .await
+        .unwrap()
+        .with_stats_retention(StatsRetention {
+            hours: 1,
+            days: 1,
+            months: 1,
+        });
+    let reported = stats(pippy::router(reopened)).await;
+    assert_eq!(buckets(&reported["hourly"]), [this_hour.to_string()]);
+    assert_eq!(buckets(&reported["daily"]), [today.to_string()]);
+    assert_eq!(buckets(&reported["monthly"]), [this_month.to_string()]);
+}
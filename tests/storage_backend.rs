@@ -0,0 +1,440 @@
+//! Where distribution files are kept: in a storage backend of the
+//! embedder's own, or by content in the data directory, moving them from
+//! one backend to another, and riding out a backend's transient failures.
+
+use std::{
+    collections::BTreeMap,
+    io,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use async_trait::async_trait;
+use axum::{
+    body::{Body, Bytes},
+    http::{header, Request, StatusCode},
+};
+use chrono::{DateTime, Utc};
+use futures_util::{stream::BoxStream, StreamExt};
+use pippy::{
+    backend::{FileSystemBackend, ObjectReader, StorageBackend, StoredObject},
+    testing::{SampleWheel, TestIndex, UploadForm},
+};
+use sha2::{Digest, Sha256};
+
+/// Objects in memory, as an object store would keep them.
+#[derive(Debug, Default)]
+struct MemoryBackend {
+    objects: Mutex<BTreeMap<String, (Bytes, DateTime<Utc>)>>,
+}
+
+#[async_trait]
+impl StorageBackend for MemoryBackend {
+    async fn store(
+        &self,
+        key: &str,
+        mut chunks: BoxStream<'_, io::Result<Bytes>>,
+    ) -> io::Result<u64> {
+        let mut contents = Vec::new();
+        while let Some(chunk) = chunks.next().await {
+            contents.extend_from_slice(&chunk?);
+        }
+        let size = contents.len() as u64;
+        self.objects
+            .lock()
+            .unwrap()
+            .insert(key.to_string(), (contents.into(), Utc::now()));
+        Ok(size)
+    }
+
+    async fn open(&self, key: &str) -> io::Result<Option<(ObjectReader, u64)>> {
+        let objects = self.objects.lock().unwrap();
+        Ok(objects.get(key).map(|(contents, _)| {
+            let reader: ObjectReader = Box::pin(io::Cursor::new(contents.clone()));
+            (reader, contents.len() as u64)
+        }))
+    }
+
+    async fn delete(&self, key: &str) -> io::Result<()> {
+        self.objects.lock().unwrap().remove(key);
+        Ok(())
+    }
+
+    async fn exists(&self, key: &str) -> io::Result<bool> {
+        Ok(self.objects.lock().unwrap().contains_key(key))
+    }
+
+    async fn list(&self, prefix: &str) -> io::Result<Vec<StoredObject>> {
+        let objects = self.objects.lock().unwrap();
+        Ok(objects
+            .iter()
+            .filter(|(key, _)| key.starts_with(prefix))
+            .map(|(key, (contents, modified))| StoredObject {
+                key: key.clone(),
+                size: contents.len() as u64,
+                modified: *modified,
+            })
+            .collect())
+    }
+}
+
+/// A `MemoryBackend` whose reads fail with `error` while `failures` lasts.
+#[derive(Debug)]
+struct FlakyBackend {
+    inner: MemoryBackend,
+    error: io::ErrorKind,
+    failures: AtomicU32,
+    opens: AtomicU32,
+}
+
+impl FlakyBackend {
+    fn new(error: io::ErrorKind) -> Self {
+        Self {
+            inner: MemoryBackend::default(),
+            error,
+            failures: AtomicU32::new(0),
+            opens: AtomicU32::new(0),
+        }
+    }
+
+    /// Fails the next `count` reads.
+    fn fail(&self, count: u32) {
+        self.failures.store(count, Ordering::SeqCst);
+        self.opens.store(0, Ordering::SeqCst);
+    }
+}
+
+#[async_trait]
+impl StorageBackend for FlakyBackend {
+    async fn store(&self, key: &str, chunks: BoxStream<'_, io::Result<Bytes>>) -> io::Result<u64> {
+        self.inner.store(key, chunks).await
+    }
+
+    async fn open(&self, key: &str) -> io::Result<Option<(ObjectReader, u64)>> {
+        self.opens.fetch_add(1, Ordering::SeqCst);
+        let failing = self
+            .failures
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |left| {
+                left.checked_sub(1)
+            })
+            .is_ok();
+        match failing {
+            true => Err(io::Error::new(self.error, "volume hiccup")),
+            false => self.inner.open(key).await,
+        }
+    }
+
+    async fn delete(&self, key: &str) -> io::Result<()> {
+        self.inner.delete(key).await
+    }
+
+    async fn exists(&self, key: &str) -> io::Result<bool> {
+        self.inner.exists(key).await
+    }
+
+    async fn list(&self, prefix: &str) -> io::Result<Vec<StoredObject>> {
+        self.inner.list(prefix).await
+    }
+}
+
+/// Waits for the enrichment that follows the upload of `demo`, which
+/// reads the stored file too.
+async fn wait_for_enrichment(index: &TestIndex) {
+    let project = index.path().join("projects/demo.json");
+    for _ in 0..100 {
+        let json: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(&project).unwrap()).unwrap();
+        if json["releases"][0]["enrichments"]["size"].is_object() {
+            return;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    panic!("demo was never enriched");
+}
+
+async fn get(index: &TestIndex, uri: &str) -> (StatusCode, Bytes) {
+    let response = index
+        .send(Request::get(uri).body(Body::empty()).unwrap())
+        .await;
+    let status = response.status();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    (status, body)
+}
+
+#[tokio::test]
+async fn files_are_served_from_a_custom_backend() {
+    let backend = Arc::new(MemoryBackend::default());
+    let wheel = SampleWheel::new("demo", "1.0").metadata("Summary", "In memory");
+    let index = TestIndex::builder()
+        .backend(backend.clone())
+        .wheel(wheel.clone())
+        .build()
+        .await
+        .unwrap();
+    let filename = wheel.filename();
+    assert!(backend.exists(&format!("demo/{filename}")).await.unwrap());
+    assert!(!index.path().join("packages/demo").exists());
+
+    let (status, body) = get(&index, &format!("/packages/demo/{filename}")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, wheel.bytes());
+
+    // Core metadata is extracted from a local copy and stored alongside.
+    let (status, body) = get(&index, &format!("/packages/demo/{filename}.metadata")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(String::from_utf8_lossy(&body).contains("Summary: In memory"));
+
+    let response = index
+        .send(
+            Request::delete(format!("/api/v1/projects/demo/files/{filename}"))
+                .header(
+                    header::AUTHORIZATION,
+                    format!("token {}", index.admin_token().await.unwrap()),
+                )
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert!(backend.list("demo/").await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn identical_files_share_one_blob() {
+    let wheel = SampleWheel::new("demo", "1.0");
+    let index = TestIndex::builder()
+        .wheel(wheel.clone())
+        .build()
+        .await
+        .unwrap();
+    let storage = index.index().storage();
+    let filename = wheel.filename();
+    let sha256 = format!("{:x}", Sha256::digest(wheel.bytes()));
+    let blob = index
+        .path()
+        .join("blobs")
+        .join(&sha256[..2])
+        .join(&sha256[2..4])
+        .join(&sha256);
+    assert_eq!(std::fs::read(&blob).unwrap(), wheel.bytes());
+
+    let copy = "copy/demo-1.0-py3-none-any.whl";
+    storage
+        .backend()
+        .store(
+            copy,
+            futures_util::stream::iter([Ok(Bytes::from(wheel.bytes()))]).boxed(),
+        )
+        .await
+        .unwrap();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        // The blob, the uploaded file and the copy.
+        assert_eq!(std::fs::metadata(&blob).unwrap().nlink(), 3);
+    }
+    let (status, body) = get(&index, &format!("/packages/demo/{filename}")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, wheel.bytes());
+}
+
+/// An upload naming its file by digest alone, with the file sent empty.
+fn by_digest(filename: &str, sha256: &str) -> UploadForm {
+    UploadForm::new()
+        .field("sha256_digest", sha256)
+        .file(filename, Vec::new())
+}
+
+#[tokio::test]
+async fn listed_files_are_published_by_digest_alone() {
+    let wheel = SampleWheel::new("demo", "1.0");
+    let index = TestIndex::builder()
+        .wheel(wheel.clone())
+        .build()
+        .await
+        .unwrap();
+    let sha256 = format!("{:x}", Sha256::digest(wheel.bytes()));
+    // The same artifact, under another project.
+    let filename = "demo_copy-1.0-py3-none-any.whl";
+
+    let unknown = by_digest(filename, &"0".repeat(64));
+    let response = index.send(unknown.request("/upload")).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let response = index
+        .send(by_digest(filename, &sha256).request("/upload"))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let (status, body) = get(&index, &format!("/packages/demo-copy/{filename}")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, wheel.bytes());
+    // Indexed like any upload, metadata included.
+    let (status, _) = get(&index, &format!("/packages/demo-copy/{filename}.metadata")).await;
+    assert_eq!(status, StatusCode::OK);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let blob = index
+            .path()
+            .join("blobs")
+            .join(&sha256[..2])
+            .join(&sha256[2..4])
+            .join(&sha256);
+        // The blob and the file of each project.
+        assert_eq!(std::fs::metadata(&blob).unwrap().nlink(), 3);
+    }
+}
+
+#[tokio::test]
+async fn deleted_files_are_not_published_by_digest() {
+    let wheel = SampleWheel::new("demo", "1.0");
+    let index = TestIndex::builder()
+        .wheel(wheel.clone())
+        .build()
+        .await
+        .unwrap();
+    let filename = wheel.filename();
+    let sha256 = format!("{:x}", Sha256::digest(wheel.bytes()));
+    let response = index
+        .send(
+            Request::delete(format!("/api/v1/projects/demo/files/{filename}"))
+                .header(
+                    header::AUTHORIZATION,
+                    format!("token {}", index.admin_token().await.unwrap()),
+                )
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    // The blob lingers until garbage collection.
+    assert!(index.path().join("blobs").join(&sha256[..2]).exists());
+
+    let response = index
+        .send(by_digest(&filename, &sha256).request("/upload"))
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let (status, _) = get(&index, &format!("/packages/demo/{filename}")).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn files_of_other_indexes_sharing_blobs_are_not_published_by_digest() {
+    let wheel = SampleWheel::new("demo", "1.0");
+    let first = TestIndex::builder()
+        .wheel(wheel.clone())
+        .build()
+        .await
+        .unwrap();
+    // Another index, as of another tenant, sharing the first one's blobs.
+    let blobs = first.path().join("blobs");
+    let backend = FileSystemBackend::new(first.path().join("other"))
+        .unwrap()
+        .content_addressed(blobs)
+        .unwrap();
+    let second = TestIndex::builder()
+        .backend(Arc::new(backend))
+        .build()
+        .await
+        .unwrap();
+    let filename = wheel.filename();
+    let sha256 = format!("{:x}", Sha256::digest(wheel.bytes()));
+
+    let response = second
+        .send(by_digest(&filename, &sha256).request("/upload"))
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let (status, _) = get(&second, &format!("/packages/demo/{filename}")).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn files_are_migrated_to_another_backend_once() {
+    let wheel = SampleWheel::new("demo", "1.0");
+    let index = TestIndex::builder()
+        .wheel(wheel.clone())
+        .wheel(SampleWheel::new("other", "1.0"))
+        .build()
+        .await
+        .unwrap();
+    let from = FileSystemBackend::new(index.path().join("packages")).unwrap();
+    let to = MemoryBackend::default();
+
+    let report = pippy::migrate::migrate(&from, &to).await.unwrap();
+    let stored = from.list("").await.unwrap();
+    assert_eq!(report.copied.len(), stored.len());
+    assert_eq!(report.skipped, 0);
+    let key = format!("demo/{}", wheel.filename());
+    let (mut reader, _) = to.open(&key).await.unwrap().unwrap();
+    let mut copied = Vec::new();
+    tokio::io::AsyncReadExt::read_to_end(&mut reader, &mut copied)
+        .await
+        .unwrap();
+    assert_eq!(copied, wheel.bytes());
+
+    // A copy gone bad is made again; the rest are left alone.
+    let mut damaged = copied.clone();
+    damaged[0] ^= 0xff;
+    to.store(
+        &key,
+        futures_util::stream::iter([Ok(Bytes::from(damaged))]).boxed(),
+    )
+    .await
+    .unwrap();
+    let report = pippy::migrate::migrate(&from, &to).await.unwrap();
+    assert_eq!(report.copied, [key]);
+    assert_eq!(report.skipped, stored.len() - 1);
+}
+
+#[tokio::test]
+async fn transient_storage_errors_are_retried() {
+    let backend = Arc::new(FlakyBackend::new(io::ErrorKind::TimedOut));
+    let wheel = SampleWheel::new("demo", "1.0");
+    let index = TestIndex::builder()
+        .backend(backend.clone())
+        .wheel(wheel.clone())
+        .build()
+        .await
+        .unwrap();
+    let uri = format!("/packages/demo/{}", wheel.filename());
+    wait_for_enrichment(&index).await;
+
+    backend.fail(2);
+    let (status, body) = get(&index, &uri).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, wheel.bytes());
+    assert_eq!(backend.opens.load(Ordering::SeqCst), 3);
+
+    backend.fail(u32::MAX);
+    let response = index
+        .send(Request::get(&uri).body(Body::empty()).unwrap())
+        .await;
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(response.headers()[header::RETRY_AFTER], "30");
+    assert_eq!(backend.opens.load(Ordering::SeqCst), 4);
+
+    backend.fail(0);
+    assert_eq!(get(&index, &uri).await.0, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn other_storage_errors_are_not_retried() {
+    let backend = Arc::new(FlakyBackend::new(io::ErrorKind::PermissionDenied));
+    let wheel = SampleWheel::new("demo", "1.0");
+    let index = TestIndex::builder()
+        .backend(backend.clone())
+        .wheel(wheel.clone())
+        .build()
+        .await
+        .unwrap();
+    wait_for_enrichment(&index).await;
+
+    backend.fail(1);
+    let (status, _) = get(&index, &format!("/packages/demo/{}", wheel.filename())).await;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(backend.opens.load(Ordering::SeqCst), 1);
+}
@@ -0,0 +1,46 @@
+//! One listener serving separate indexes by host name.
+
+use axum::{body::Body, http::Request};
+use pippy::{
+    tenants::HostRouter,
+    testing::{SampleWheel, TestIndex},
+};
+use tower::ServiceExt;
+
+async fn listing(router: axum::Router, host: &str) -> String {
+    let response = router
+        .oneshot(
+            Request::get("/simple/")
+                .header("host", host)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    String::from_utf8(bytes.to_vec()).unwrap()
+}
+
+#[tokio::test]
+async fn requests_reach_the_index_of_their_host() {
+    let default = TestIndex::builder()
+        .wheel(SampleWheel::new("shared", "1.0"))
+        .build()
+        .await
+        .unwrap();
+    let ml = TestIndex::builder()
+        .wheel(SampleWheel::new("torchish", "1.0"))
+        .build()
+        .await
+        .unwrap();
+    let router = HostRouter::new(default.router())
+        .host("ml.pkg.corp", ml.router())
+        .into_router();
+
+    let page = listing(router.clone(), "ML.pkg.corp:8443").await;
+    assert!(page.contains("torchish") && !page.contains("shared"));
+    let page = listing(router, "web.pkg.corp").await;
+    assert!(page.contains("shared") && !page.contains("torchish"));
+}
@@ -0,0 +1,55 @@
+//! The public test-support module works for embedders without a network.
+
+use axum::{body::Body, http::Request};
+use pippy::testing::{SampleWheel, TestIndex, UploadForm};
+use reqwest::StatusCode;
+
+async fn body_text(response: axum::response::Response) -> String {
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    String::from_utf8(bytes.to_vec()).unwrap()
+}
+
+#[tokio::test]
+async fn seeded_wheels_are_listed_and_downloadable() {
+    let wheel = SampleWheel::new("demo", "1.0").member("demo/__init__.py", "VALUE = 1\n");
+    let index = TestIndex::builder()
+        .wheel(wheel.clone())
+        .build()
+        .await
+        .unwrap();
+
+    let page = index
+        .send(Request::get("/simple/demo/").body(Body::empty()).unwrap())
+        .await;
+    assert_eq!(page.status(), StatusCode::OK);
+    assert!(body_text(page).await.contains(&wheel.filename()));
+
+    let url = index.spawn().await.unwrap();
+    let download = reqwest::get(format!("{url}/packages/demo/{}", wheel.filename()))
+        .await
+        .unwrap();
+    assert_eq!(download.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn upload_forms_carry_fields() {
+    let index = TestIndex::new().await.unwrap();
+    let form = UploadForm::new()
+        .field("channel", "beta")
+        .wheel(&SampleWheel::new("demo", "2.0"));
+    let response = index.send(form.request("/upload")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let channel = index
+        .send(
+            Request::get("/channels/beta/simple/demo/")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert!(body_text(channel)
+        .await
+        .contains("demo-2.0-py3-none-any.whl"));
+}
@@ -0,0 +1,260 @@
+//! Serving HTTPS directly.
+
+use std::path::Path;
+
+use pippy::{
+    server::{self, ConnectionSettings, HostCertificate, TlsConfig},
+    testing::{SampleWheel, TestIndex, UploadForm},
+    Config,
+};
+
+fn fixture(name: &str) -> std::path::PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+}
+
+#[tokio::test]
+async fn serves_https_with_the_configured_certificate() {
+    let index = TestIndex::new().await.unwrap();
+    let settings = ConnectionSettings {
+        tls: Some(
+            TlsConfig::from_pem_files(&fixture("tls-cert.pem"), &fixture("tls-key.pem"), None)
+                .unwrap(),
+        ),
+        ..ConnectionSettings::default()
+    };
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let router = index.router();
+    tokio::spawn(async move {
+        server::serve(listener, router, &settings, std::future::pending()).await
+    });
+
+    let ca =
+        reqwest::Certificate::from_pem(&std::fs::read(fixture("tls-cert.pem")).unwrap()).unwrap();
+    let client = reqwest::Client::builder()
+        .add_root_certificate(ca)
+        .build()
+        .unwrap();
+    let response = client
+        .get(format!("https://localhost:{port}/simple/"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+    // Cleartext requests get nowhere.
+    let plain = reqwest::get(format!("http://localhost:{port}/simple/")).await;
+    assert!(plain.is_err());
+}
+
+#[tokio::test]
+async fn tenants_get_their_own_certificate() {
+    let index = TestIndex::new().await.unwrap();
+    let tenant = HostCertificate {
+        host: "ml.localhost".to_string(),
+        cert: fixture("tls-tenant-cert.pem"),
+        key: fixture("tls-tenant-key.pem"),
+    };
+    let settings = ConnectionSettings {
+        tls: Some(
+            TlsConfig::with_host_certificates(
+                &fixture("tls-cert.pem"),
+                &fixture("tls-key.pem"),
+                None,
+                &[tenant],
+            )
+            .unwrap(),
+        ),
+        ..ConnectionSettings::default()
+    };
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let router = index.router();
+    tokio::spawn(async move {
+        server::serve(listener, router, &settings, std::future::pending()).await
+    });
+
+    // Trusting only the tenant's certificate, a client reaches the tenant
+    // host but not the others, which keep the default one.
+    let ca =
+        reqwest::Certificate::from_pem(&std::fs::read(fixture("tls-tenant-cert.pem")).unwrap())
+            .unwrap();
+    let client = reqwest::Client::builder()
+        .add_root_certificate(ca)
+        .resolve("ml.localhost", address)
+        .build()
+        .unwrap();
+    let port = address.port();
+    let response = client
+        .get(format!("https://ml.localhost:{port}/simple/"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    let other = client
+        .get(format!("https://localhost:{port}/simple/"))
+        .send()
+        .await;
+    assert!(other.is_err());
+}
+
+#[test]
+fn tenant_certificates_must_name_their_host() {
+    let tenant = HostCertificate {
+        host: "web.localhost".to_string(),
+        cert: fixture("tls-tenant-cert.pem"),
+        key: fixture("tls-tenant-key.pem"),
+    };
+    let error = TlsConfig::with_host_certificates(
+        &fixture("tls-cert.pem"),
+        &fixture("tls-key.pem"),
+        None,
+        &[tenant],
+    )
+    .unwrap_err();
+    assert!(error.to_string().contains("web.localhost"), "{error}");
+
+    let parsed: HostCertificate = "Web.localhost=web.pem,web-key.pem".parse().unwrap();
+    assert_eq!(parsed.host, "web.localhost");
+    assert!("web.localhost=web.pem".parse::<HostCertificate>().is_err());
+}
+
+#[tokio::test]
+async fn client_certificates_authenticate_as_their_common_name() {
+    let index = TestIndex::builder()
+        .config(Config {
+            require_token: true,
+            ..Config::default()
+        })
+        .build()
+        .await
+        .unwrap();
+    let settings = ConnectionSettings {
+        tls: Some(
+            TlsConfig::from_pem_files(
+                &fixture("tls-cert.pem"),
+                &fixture("tls-key.pem"),
+                Some(&fixture("tls-client-ca.pem")),
+            )
+            .unwrap(),
+        ),
+        ..ConnectionSettings::default()
+    };
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base = format!(
+        "https://localhost:{}",
+        listener.local_addr().unwrap().port()
+    );
+    let router = index.router();
+    tokio::spawn(async move {
+        server::serve(listener, router, &settings, std::future::pending()).await
+    });
+    let ca =
+        reqwest::Certificate::from_pem(&std::fs::read(fixture("tls-cert.pem")).unwrap()).unwrap();
+
+    // Clients without a certificate fail the handshake.
+    let anonymous = reqwest::Client::builder()
+        .add_root_certificate(ca.clone())
+        .build()
+        .unwrap();
+    assert!(anonymous
+        .get(format!("{base}/simple/"))
+        .send()
+        .await
+        .is_err());
+
+    // The certificate's holder uploads as, and so owns the project as, alice.
+    let identity =
+        reqwest::Identity::from_pem(&std::fs::read(fixture("tls-client.pem")).unwrap()).unwrap();
+    let client = reqwest::Client::builder()
+        .add_root_certificate(ca)
+        .identity(identity)
+        .build()
+        .unwrap();
+    let form = UploadForm::new().wheel(&SampleWheel::new("demo", "1.0"));
+    let response = client
+        .post(format!("{base}/upload"))
+        .header(reqwest::header::CONTENT_TYPE, form.content_type())
+        .body(form.body())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    let owners = client
+        .get(format!("{base}/api/v1/projects/demo/owners"))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert_eq!(owners, r#"{"owners":["alice"]}"#);
+}
+
+#[tokio::test]
+async fn client_certificates_alone_identify_uploaders() {
+    let tls = TlsConfig::from_pem_files(
+        &fixture("tls-cert.pem"),
+        &fixture("tls-key.pem"),
+        Some(&fixture("tls-client-ca.pem")),
+    )
+    .unwrap();
+    let settings = ConnectionSettings {
+        tls: Some(tls),
+        ..ConnectionSettings::default()
+    };
+    // No tokens or users: the certificate is the only credential.
+    let index = TestIndex::builder()
+        .config(Config {
+            connections: settings.clone(),
+            ..Config::default()
+        })
+        .build()
+        .await
+        .unwrap();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base = format!(
+        "https://localhost:{}",
+        listener.local_addr().unwrap().port()
+    );
+    let router = index.router();
+    tokio::spawn(async move {
+        server::serve(listener, router, &settings, std::future::pending()).await
+    });
+    let ca =
+        reqwest::Certificate::from_pem(&std::fs::read(fixture("tls-cert.pem")).unwrap()).unwrap();
+    let identity =
+        reqwest::Identity::from_pem(&std::fs::read(fixture("tls-client.pem")).unwrap()).unwrap();
+    let client = reqwest::Client::builder()
+        .add_root_certificate(ca)
+        .identity(identity)
+        .build()
+        .unwrap();
+    let form = UploadForm::new().wheel(&SampleWheel::new("demo", "1.0"));
+    let response = client
+        .post(format!("{base}/upload"))
+        .header(reqwest::header::CONTENT_TYPE, form.content_type())
+        .body(form.body())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    let owners = client
+        .get(format!("{base}/api/v1/projects/demo/owners"))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert_eq!(owners, r#"{"owners":["alice"]}"#);
+}
+
+#[test]
+fn missing_keys_are_reported() {
+    let error = TlsConfig::from_pem_files(&fixture("tls-cert.pem"), &fixture("tls-cert.pem"), None)
+        .unwrap_err();
+    assert!(error.to_string().contains("No private key"), "{error}");
+}
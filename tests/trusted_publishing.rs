@@ -0,0 +1,158 @@
+//! Trading GitHub Actions OIDC tokens for upload tokens.
+
+use axum::{
+    body::Body,
+    http::{header, Request, StatusCode},
+    routing::get,
+    Json, Router,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use pippy::{
+    oidc::{TrustedPublisher, TrustedPublishing},
+    testing::{SampleWheel, TestIndex, UploadForm},
+    Config,
+};
+use ring::{
+    rand::SystemRandom,
+    signature::{RsaKeyPair, RsaPublicKeyComponents, RSA_PKCS1_SHA256},
+};
+use serde_json::{json, Value};
+
+const SIGNING_KEY: &[u8] = include_bytes!("fixtures/oidc-signing-key.der");
+
+/// An OIDC issuer publishing the fixture key, and its URL.
+async fn spawn_issuer() -> String {
+    let key = RsaKeyPair::from_pkcs8(SIGNING_KEY).unwrap();
+    let public = RsaPublicKeyComponents::<Vec<u8>>::from(key.public());
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let issuer = format!("http://{}", listener.local_addr().unwrap());
+    let jwks_uri = format!("{issuer}/jwks");
+    let keys = json!({ "keys": [{
+        "kty": "RSA",
+        "kid": "test",
+        "n": URL_SAFE_NO_PAD.encode(&public.n),
+        "e": URL_SAFE_NO_PAD.encode(&public.e),
+    }] });
+    let app = Router::new()
+        .route(
+            "/.well-known/openid-configuration",
+            get(move || async move { Json(json!({ "jwks_uri": jwks_uri })) }),
+        )
+        .route("/jwks", get(move || async move { Json(keys) }));
+    tokio::spawn(async move { axum::serve(listener, app).await });
+    issuer
+}
+
+fn sign(claims: &Value) -> String {
+    let key = RsaKeyPair::from_pkcs8(SIGNING_KEY).unwrap();
+    let header = URL_SAFE_NO_PAD.encode(json!({ "alg": "RS256", "kid": "test" }).to_string());
+    let payload = URL_SAFE_NO_PAD.encode(claims.to_string());
+    let signed = format!("{header}.{payload}");
+    let mut signature = vec![0; key.public().modulus_len()];
+    key.sign(
+        &RSA_PKCS1_SHA256,
+        &SystemRandom::new(),
+        signed.as_bytes(),
+        &mut signature,
+    )
+    .unwrap();
+    format!("{signed}.{}", URL_SAFE_NO_PAD.encode(signature))
+}
+
+fn claims(issuer: &str, workflow: &str) -> Value {
+    let now = chrono::Utc::now().timestamp();
+    json!({
+        "iss": issuer,
+        "aud": "pippy",
+        "iat": now,
+        "nbf": now,
+        "exp": now + 300,
+        "repository": "acme/widgets",
+        "workflow_ref": format!("acme/widgets/.github/workflows/{workflow}@refs/tags/v1.0"),
+        "environment": "release",
+    })
+}
+
+async fn mint(index: &TestIndex, token: &str) -> (StatusCode, Value) {
+    let response = index
+        .send(
+            Request::post("/_/oidc/mint-token")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(json!({ "token": token }).to_string()))
+                .unwrap(),
+        )
+        .await;
+    let status = response.status();
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    (
+        status,
+        serde_json::from_slice(&bytes).unwrap_or(Value::Null),
+    )
+}
+
+#[tokio::test]
+async fn trusted_workflows_get_an_upload_token() {
+    let issuer = spawn_issuer().await;
+    let index = TestIndex::builder()
+        .config(Config {
+            require_token: true,
+            trusted_publishing: TrustedPublishing {
+                publishers: vec!["acme/widgets:release.yml@release".parse().unwrap()],
+                issuer: issuer.clone(),
+                ..TrustedPublishing::default()
+            },
+            ..Config::default()
+        })
+        .build()
+        .await
+        .unwrap();
+
+    let (status, minted) = mint(&index, &sign(&claims(&issuer, "release.yml"))).await;
+    assert_eq!(status, StatusCode::OK, "{minted}");
+    let secret = minted["token"].as_str().unwrap();
+
+    let mut upload = UploadForm::new()
+        .wheel(&SampleWheel::new("widgets", "1.0"))
+        .request("/upload");
+    upload.headers_mut().insert(
+        header::AUTHORIZATION,
+        format!("token {secret}").parse().unwrap(),
+    );
+    assert_eq!(index.send(upload).await.status(), StatusCode::OK);
+
+    // Other workflows of the same repository are not trusted.
+    let (status, _) = mint(&index, &sign(&claims(&issuer, "test.yml"))).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    // Nor are tokens for another audience, or expired ones.
+    let mut other = claims(&issuer, "release.yml");
+    other["aud"] = json!("pypi");
+    assert_eq!(mint(&index, &sign(&other)).await.0, StatusCode::FORBIDDEN);
+    let mut expired = claims(&issuer, "release.yml");
+    expired["exp"] = json!(chrono::Utc::now().timestamp() - 600);
+    assert_eq!(mint(&index, &sign(&expired)).await.0, StatusCode::FORBIDDEN);
+
+    // A tampered payload no longer matches the signature.
+    let token = sign(&claims(&issuer, "release.yml"));
+    let mut parts: Vec<&str> = token.split('.').collect();
+    let forged = URL_SAFE_NO_PAD.encode(claims(&issuer, "release.yml").to_string() + " ");
+    parts[1] = &forged;
+    assert_eq!(
+        mint(&index, &parts.join(".")).await.0,
+        StatusCode::FORBIDDEN
+    );
+}
+
+#[test]
+fn publisher_specs_are_parsed() {
+    let publisher: TrustedPublisher = "acme/widgets:release.yml".parse().unwrap();
+    assert_eq!(publisher.repository, "acme/widgets");
+    assert_eq!(publisher.workflow, "release.yml");
+    assert_eq!(publisher.environment, None);
+    assert!("acme:release.yml".parse::<TrustedPublisher>().is_err());
+    assert!("acme/widgets:.github/workflows/release.yml"
+        .parse::<TrustedPublisher>()
+        .is_err());
+}
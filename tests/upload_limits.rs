@@ -0,0 +1,222 @@
+//! Limits on what uploads may bring in: the size of each file, quotas on
+//! the bytes stored, and how often a project may be published to.
+
+use axum::{
+    body::Body,
+    http::{header, Request, StatusCode},
+};
+use pippy::{
+    quota::Quotas,
+    testing::{SampleWheel, TestIndex, UploadForm},
+    Config, UploadLimits,
+};
+use serde_json::Value;
+
+async fn body_json(response: axum::response::Response) -> Value {
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    serde_json::from_slice(&body).unwrap()
+}
+
+#[tokio::test]
+async fn files_over_the_maximum_size_are_refused_with_413() {
+    let small = SampleWheel::new("demo", "1.0");
+    let limit = small.bytes().len() as u64 + 100;
+    let index = TestIndex::builder()
+        .config(Config {
+            max_upload_size: Some(limit),
+            ..Config::default()
+        })
+        .wheel(small)
+        .build()
+        .await
+        .unwrap();
+
+    let large = SampleWheel::new("demo", "2.0").member("demo/data.bin", vec![7; 4096]);
+    let response = index
+        .send(UploadForm::new().wheel(&large).request("/upload"))
+        .await;
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    let error = body_json(response).await;
+    assert_eq!(error["max_upload_size"], limit);
+    assert!(error["error"].as_str().unwrap().contains(&large.filename()));
+
+    assert!(!index
+        .path()
+        .join("packages/demo")
+        .join(large.filename())
+        .exists());
+    assert_eq!(index.index().packages().await.unwrap()[0].releases.len(), 1);
+}
+
+#[tokio::test]
+async fn bodies_over_the_limit_are_refused_before_the_file_is_reached() {
+    let index = TestIndex::builder()
+        .config(Config {
+            max_upload_size: Some(0),
+            ..Config::default()
+        })
+        .build()
+        .await
+        .unwrap();
+    // A description longer than the room left for form fields.
+    let form = UploadForm::new()
+        .field("description", "x".repeat(2 * 1024 * 1024))
+        .wheel(&SampleWheel::new("demo", "1.0"));
+    let response = index.send(form.request("/upload")).await;
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(body_json(response).await["max_upload_size"], 0);
+}
+
+#[tokio::test]
+async fn uploads_over_a_project_quota_are_refused_until_space_is_freed() {
+    let first = SampleWheel::new("demo", "1.0");
+    let size = first.bytes().len() as u64;
+    let index = TestIndex::builder()
+        .quotas(Quotas {
+            project_bytes: Some(size + size / 2),
+            total_bytes: None,
+        })
+        .wheel(first.clone())
+        .wheel(SampleWheel::new("other", "1.0"))
+        .build()
+        .await
+        .unwrap();
+
+    let second = SampleWheel::new("demo", "2.0");
+    let upload = || index.send(UploadForm::new().wheel(&second).request("/upload"));
+    let response = upload().await;
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    let error = body_json(response).await;
+    assert_eq!(error["quota"], size + size / 2);
+    assert!(!index
+        .path()
+        .join("packages/demo")
+        .join(second.filename())
+        .exists());
+
+    let usage = body_json(
+        index
+            .send(Request::get("/api/v1/usage").body(Body::empty()).unwrap())
+            .await,
+    )
+    .await;
+    assert_eq!(usage["projects"]["demo"], size);
+    assert!(usage["total_bytes"].as_u64().unwrap() > size);
+    assert_eq!(usage["quotas"]["project_bytes"], size + size / 2);
+
+    let response = index
+        .send(
+            Request::delete(format!("/api/v1/projects/demo/files/{}", first.filename()))
+                .header(
+                    header::AUTHORIZATION,
+                    format!("token {}", index.admin_token().await.unwrap()),
+                )
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert_eq!(upload().await.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn dry_runs_are_refused_whatever_the_upload_would_be() {
+    let published = SampleWheel::new("demo", "1.0");
+    let size = published.bytes().len() as u64;
+    let index = TestIndex::builder()
+        .config(Config {
+            max_upload_size: Some(size + 100),
+            ..Config::default()
+        })
+        .quotas(Quotas {
+            project_bytes: Some(size + 100),
+            ..Quotas::default()
+        })
+        .wheel(published.clone())
+        .build()
+        .await
+        .unwrap();
+    let dry_run = |form: UploadForm| index.send(form.request("/upload?dry_run=true"));
+
+    let response = dry_run(UploadForm::new().wheel(&published)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body_json(response).await["files"][0]["action"], "unchanged");
+
+    let rebuilt = published.clone().metadata("Summary", "Rebuilt");
+    let response = dry_run(UploadForm::new().wheel(&rebuilt)).await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    let large = SampleWheel::new("other", "1.0").member("other/data.bin", vec![7; 4096]);
+    let response = dry_run(UploadForm::new().wheel(&large)).await;
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    let response = dry_run(UploadForm::new().wheel(&SampleWheel::new("demo", "1.1"))).await;
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    assert!(body_json(response).await["quota"].is_u64());
+    let other = SampleWheel::new("other", "1.0");
+    let response = dry_run(
+        UploadForm::new()
+            .field("sha256_digest", "0".repeat(64))
+            .wheel(&other),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let response = dry_run(UploadForm::new().wheel(&other)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let plan = body_json(response).await;
+    assert_eq!(plan["files"][0]["action"], "store");
+    assert_eq!(plan["files"][0]["new_project"], true);
+    assert!(plan["files"][0]["sha256"].is_string());
+    assert_eq!(index.index().packages().await.unwrap().len(), 1);
+    assert!(!index.path().join("packages/other").exists());
+}
+
+#[tokio::test]
+async fn deleting_files_does_not_make_room_in_the_hourly_upload_limit() {
+    let index = TestIndex::builder()
+        .limits(UploadLimits {
+            max_uploads_per_hour: Some(2),
+            ..UploadLimits::default()
+        })
+        .wheel(SampleWheel::new("demo", "0.1"))
+        .wheel(SampleWheel::new("demo", "0.2"))
+        .build()
+        .await
+        .unwrap();
+    // Older history, so the journal is read back over several blocks.
+    let journal = index.path().join("changes.jsonl");
+    let mut history = String::new();
+    for serial in 0..2000 {
+        history.push_str(&format!(
+            r#"{{"serial":{serial},"project":"demo","time":"2020-01-01T00:00:00Z","operation":"release"}}"#
+        ));
+        history.push('\n');
+    }
+    history.push_str(&std::fs::read_to_string(&journal).unwrap());
+    std::fs::write(&journal, history).unwrap();
+    let upload = |version: &str| {
+        index.send(
+            UploadForm::new()
+                .wheel(&SampleWheel::new("demo", version))
+                .request("/upload"),
+        )
+    };
+    assert_eq!(upload("1.0").await.status(), StatusCode::TOO_MANY_REQUESTS);
+
+    let response = index
+        .send(
+            Request::delete("/api/v1/projects/demo/files/demo-0.1-py3-none-any.whl")
+                .header(
+                    header::AUTHORIZATION,
+                    format!("token {}", index.admin_token().await.unwrap()),
+                )
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let response = upload("1.0").await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert!(response.headers().contains_key(header::RETRY_AFTER));
+}
@@ -0,0 +1,181 @@
+//! Webhooks registered on a project.
+
+use std::time::Duration;
+
+use axum::{
+    body::Body,
+    http::{header, HeaderMap, Request, StatusCode},
+    routing::post,
+    Json, Router,
+};
+use pippy::{
+    auth::TokenStore,
+    testing::{SampleWheel, TestIndex, UploadForm},
+    Config,
+};
+use serde_json::Value;
+
+fn get(uri: &str, user_agent: &str) -> Request<Body> {
+    Request::get(uri)
+        .header(header::USER_AGENT, user_agent)
+        .body(Body::empty())
+        .unwrap()
+}
+
+#[tokio::test]
+async fn sdist_downloads_ask_build_hooks_for_wheels() {
+    // The build farm, recording the events it is sent.
+    let (sender, mut received) = tokio::sync::mpsc::unbounded_channel();
+    let farm = Router::new().route(
+        "/build",
+        post(move |headers: HeaderMap, Json(payload): Json<Value>| {
+            let event = headers["x-pippy-event"].to_str().unwrap().to_string();
+            sender.send((event, payload)).unwrap();
+            async { StatusCode::NO_CONTENT }
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let farm_url = format!("http://{}/build", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, farm).await });
+
+    let index = TestIndex::new().await.unwrap();
+    tokio::spawn(index.index().clone().deliver_webhooks());
+    let form = UploadForm::new()
+        .file("demo-1.0.tar.gz", b"not really gzip".to_vec())
+        .wheel(&SampleWheel::new("demo", "0.9"));
+    let response = index.send(form.request("/upload")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let hook = format!(r#"{{"url": "{farm_url}", "events": ["build_requested"]}}"#);
+    let admin = index.admin_token().await.unwrap();
+    let response = index
+        .send(
+            Request::post("/api/v1/projects/demo/webhooks")
+                .header(header::CONTENT_TYPE, "application/json")
+                .header(header::AUTHORIZATION, format!("token {admin}"))
+                .body(Body::from(hook))
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let pip = r#"pip/24.0 {"system":{"name":"Linux"},"cpu":"aarch64"}"#;
+    let wheel = index
+        .send(get("/packages/demo/demo-0.9-py3-none-any.whl", pip))
+        .await;
+    assert_eq!(wheel.status(), StatusCode::OK);
+    for _ in 0..2 {
+        let sdist = index.send(get("/packages/demo/demo-1.0.tar.gz", pip)).await;
+        assert_eq!(sdist.status(), StatusCode::OK);
+    }
+
+    let (event, payload) = tokio::time::timeout(Duration::from_secs(10), received.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(event, "build_requested");
+    assert_eq!(payload["project"], "demo");
+    assert_eq!(payload["version"], "1.0");
+    assert_eq!(payload["filename"], "demo-1.0.tar.gz");
+    assert_eq!(payload["url"], "/packages/demo/demo-1.0.tar.gz");
+    assert_eq!(payload["user_agent"], pip);
+    // Downloading the wheel asked for nothing, and the repeated download
+    // of the sdist did not ask again.
+    let again = tokio::time::timeout(Duration::from_millis(500), received.recv()).await;
+    assert!(again.is_err(), "{again:?}");
+}
+
+#[tokio::test]
+async fn hooks_and_deliveries_are_shown_to_owners_only() {
+    let index = TestIndex::builder()
+        .config(Config {
+            require_token: true,
+            ..Config::default()
+        })
+        .build()
+        .await
+        .unwrap();
+    let tokens = TokenStore::new(index.index().storage().clone());
+    let (_, alice) = tokens.create_for("alice", "laptop", None).await.unwrap();
+    let (_, bob) = tokens.create_for("bob", "laptop", None).await.unwrap();
+    let with_token = |request: axum::http::request::Builder, secret: &str| {
+        request
+            .header(header::AUTHORIZATION, format!("token {secret}"))
+            .body(Body::empty())
+            .unwrap()
+    };
+    let mut upload = UploadForm::new()
+        .wheel(&SampleWheel::new("demo", "1.0"))
+        .request("/upload");
+    upload.headers_mut().insert(
+        header::AUTHORIZATION,
+        format!("token {alice}").parse().unwrap(),
+    );
+    assert_eq!(index.send(upload).await.status(), StatusCode::OK);
+    let response = index
+        .send(
+            Request::post("/api/v1/projects/demo/webhooks")
+                .header(header::CONTENT_TYPE, "application/json")
+                .header(header::AUTHORIZATION, format!("token {alice}"))
+                .body(Body::from(
+                    r#"{"url": "https://hooks.example/notify?key=secret"}"#,
+                ))
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let hook: Value = serde_json::from_slice(
+        &axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap(),
+    )
+    .unwrap();
+    let deliveries = format!(
+        "/api/v1/projects/demo/webhooks/{}/deliveries",
+        hook["id"].as_str().unwrap()
+    );
+
+    for uri in ["/api/v1/projects/demo/webhooks", deliveries.as_str()] {
+        let anonymous = index
+            .send(Request::get(uri).body(Body::empty()).unwrap())
+            .await;
+        assert_eq!(anonymous.status(), StatusCode::UNAUTHORIZED);
+        let other = index.send(with_token(Request::get(uri), &bob)).await;
+        assert_eq!(other.status(), StatusCode::FORBIDDEN);
+        let owner = index.send(with_token(Request::get(uri), &alice)).await;
+        assert_eq!(owner.status(), StatusCode::OK);
+    }
+}
+
+#[tokio::test]
+async fn hooks_are_registered_by_owners_only_even_on_an_open_index() {
+    let index = TestIndex::builder()
+        .wheel(SampleWheel::new("demo", "1.0"))
+        .build()
+        .await
+        .unwrap();
+    let register = |authorization: Option<String>| {
+        let mut request = Request::post("/api/v1/projects/demo/webhooks")
+            .header(header::CONTENT_TYPE, "application/json");
+        if let Some(authorization) = authorization {
+            request = request.header(header::AUTHORIZATION, authorization);
+        }
+        request
+            .body(Body::from(r#"{"url": "http://169.254.169.254/latest"}"#))
+            .unwrap()
+    };
+
+    let anonymous = index.send(register(None)).await;
+    assert_eq!(anonymous.status(), StatusCode::UNAUTHORIZED);
+    let redelivery = index
+        .send(
+            Request::post("/api/v1/projects/demo/webhooks/any/deliveries/any/redeliver")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(redelivery.status(), StatusCode::UNAUTHORIZED);
+
+    let admin = index.admin_token().await.unwrap();
+    let owner = index.send(register(Some(format!("token {admin}")))).await;
+    assert_eq!(owner.status(), StatusCode::CREATED);
+}